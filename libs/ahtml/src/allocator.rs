@@ -1,20 +1,22 @@
 use std::{sync::{Mutex, atomic::AtomicBool, Arc},
-          cell::RefCell,
+          cell::{RefCell, Cell},
           collections::HashSet,
           marker::PhantomData,
           cmp::max,
           fmt::Display,
           panic::RefUnwindSafe,
-          ops::Deref, mem::ManuallyDrop};
+          ops::Deref, mem::ManuallyDrop,
+          borrow::Cow};
 
 use anyhow::{bail, Result, anyhow};
-use ahtml_html::meta::{MetaDb, ElementMeta};
+use ahtml_html::meta::{MetaDb, ElementMeta, GLOBAL_ATTRIBUTE_PREFIXES};
 use backtrace::Backtrace;
 use chj_util::{partialbacktrace::PartialBacktrace, warn};
 use kstring::KString;
 use lazy_static::lazy_static;
 
-use crate::{myfrom::MyFrom, arc_util::IntoArc, more_vec::MoreVec, stillvec::StillVec};
+use crate::{myfrom::MyFrom, arc_util::IntoArc, more_vec::MoreVec, stillvec::StillVec,
+            custom_elements::CustomElements, METADB};
 
 pub type Context = Arc<dyn Display + Sync + Send + RefUnwindSafe>;
 
@@ -59,6 +61,7 @@ pub struct HtmlAllocatorPool {
     allocator_max_use_count: u16,
     max_allocations: u32, // See HtmlAllocator
     metadb: Option<&'static MetaDb>, // See HtmlAllocator
+    custom_elements: Option<&'static CustomElements>, // See HtmlAllocator
     /// Information about the pool, e.g. where it was created or what
     /// document it is used for.
     context: Context,
@@ -79,20 +82,62 @@ impl HtmlAllocatorPool {
             allocator_max_use_count,
             max_allocations,
             metadb,
+            custom_elements: None,
             context,
             allocators: Mutex::new(Vec::new())
         }
     }
+
+    /// Have all allocators handed out by this pool accept the given
+    /// custom elements (see `HtmlAllocator::with_custom_elements`).
+    pub fn with_custom_elements(
+        mut self,
+        custom_elements: &'static CustomElements
+    ) -> Self {
+        self.custom_elements = Some(custom_elements);
+        self
+    }
+
+    fn new_allocator(&self) -> HtmlAllocator {
+        let a = HtmlAllocator::new_with_metadb(
+            self.max_allocations,
+            self.metadb,
+            self.context.clone()
+        );
+        if let Some(custom_elements) = self.custom_elements {
+            a.with_custom_elements(custom_elements)
+        } else {
+            a
+        }
+    }
+
+    /// Construct allocators until there are at least `count` idle
+    /// ones in the pool, so that the first requests don't have to
+    /// pay for the (large) allocations.
+    pub fn prewarm(&self, count: usize) {
+        loop {
+            if self.idle_count() >= count {
+                break;
+            }
+            // Allocate outside the lock
+            let a = self.new_allocator();
+            let mut l = self.allocators.lock().unwrap();
+            if l.len() >= count {
+                break;
+            }
+            l.push(a);
+        }
+    }
+
+    /// The number of allocators currently parked in the pool.
+    pub fn idle_count(&self) -> usize {
+        self.allocators.lock().unwrap().len()
+    }
+
     pub fn get<'p>(&'p self) -> HtmlAllocatorGuard<'p>
     {
         let mut l = self.allocators.lock().unwrap();
-        let a = l.pop().unwrap_or_else(|| {
-            HtmlAllocator::new_with_metadb(
-                self.max_allocations,
-                self.metadb.clone(),
-                self.context.clone()
-            )
-        });
+        let a = l.pop().unwrap_or_else(|| self.new_allocator());
         HtmlAllocatorGuard {
             pool: self,
             html_allocator: ManuallyDrop::new(a)
@@ -136,7 +181,9 @@ pub struct HtmlAllocator {
     // unused memory.
     regionid: RegionId,
     // If present, DOM structure validation is done (at runtime):
-    metadb: Option<&'static MetaDb>,
+    pub(crate) metadb: Option<&'static MetaDb>,
+    // Elements accepted in addition to those in metadb:
+    custom_elements: Option<&'static CustomElements>,
     // The top capacity value, as passed by the user
     max_allocations: usize,
     // Storage for attributes:
@@ -144,9 +191,28 @@ pub struct HtmlAllocator {
     // Storage for nodes:
     nodes: StillVec<Option<Node>>,
     // Storage for references to attributes and nodes:
-    ids: RefCell<Vec<u32>>, // for attribute or Node, depending on slot
+    pub(crate) ids: RefCell<Vec<u32>>, // for attribute or Node, depending on slot
     // Temporary storage for serialisation:
     pub(crate) html_escape_tmp: RefCell<Vec<u8>>,
+    // Serialisation option, see `set_canonical_attribute_order`:
+    canonical_attribute_order: Cell<bool>,
+    // Serialisation option, see `set_attribute_quoting`:
+    attribute_quoting: Cell<AttributeQuoting>,
+    // Whether `new_element` verifies, see `set_verify`:
+    verify: Cell<bool>,
+}
+
+/// How attribute values are quoted in serialized HTML.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AttributeQuoting {
+    /// Always in double quotes, with `"` (and `'`) escaped.
+    #[default]
+    Double,
+    /// For minification: without quotes if the value is non-empty
+    /// and contains none of whitespace, quotes, `=`, `<`, `>` or
+    /// backtick; in single quotes if it contains `"` but not `'`
+    /// (saving the escaping); otherwise like `Double`.
+    Minimal,
 }
 
 lazy_static!{
@@ -164,6 +230,55 @@ pub trait ToASlice<T> {
     fn to_aslice(self, allocator: &HtmlAllocator) -> Result<ASlice<T>>;
 }
 
+/// String types that can become the contents of a text node without
+/// copying, see `HtmlAllocator::text_nocopy`. There's deliberately no
+/// implementation for non-static `&str`, since Rust can't select
+/// `KString::from_static` for the static case otherwise.
+pub trait ToTextNode {
+    fn into_text_kstring(self) -> KString;
+}
+
+impl ToTextNode for &'static str {
+    fn into_text_kstring(self) -> KString {
+        KString::from_static(self)
+    }
+}
+
+impl ToTextNode for String {
+    fn into_text_kstring(self) -> KString {
+        KString::from_string(self)
+    }
+}
+
+impl ToTextNode for KString {
+    fn into_text_kstring(self) -> KString {
+        self
+    }
+}
+
+impl ToTextNode for &KString {
+    fn into_text_kstring(self) -> KString {
+        self.clone()
+    }
+}
+
+impl ToTextNode for Cow<'static, str> {
+    fn into_text_kstring(self) -> KString {
+        match self {
+            Cow::Borrowed(s) => KString::from_static(s),
+            Cow::Owned(s) => KString::from_string(s),
+        }
+    }
+}
+
+/// ISO 8601 format, like `MyFrom<NaiveDate>`.
+#[cfg(feature = "chrono")]
+impl ToTextNode for chrono::NaiveDate {
+    fn into_text_kstring(self) -> KString {
+        KString::myfrom(self)
+    }
+}
+
 pub static AHTML_TRACE: AtomicBool = AtomicBool::new(false);
 
 impl HtmlAllocator {
@@ -172,7 +287,7 @@ impl HtmlAllocator {
     /// memory errors are being returned for allocations (i.e. when
     /// creating new elements, attributes, or pushing to an
     /// `AVec`). `metadb`: if given, HTML structure is verified during
-    /// element allocation.
+    /// element allocation (can be changed via `set_verify`).
     pub fn new_with_metadb(max_allocations: u32, metadb: Option<&'static MetaDb>,
                            context: Context) -> Self {
         let max_allocations = max_allocations as usize;
@@ -192,15 +307,124 @@ impl HtmlAllocator {
             nodes: StillVec::with_capacity(max_allocations),
             ids: RefCell::new(Vec::with_capacity(max_allocations)),
             metadb,
+            custom_elements: None,
             max_allocations,
             html_escape_tmp: RefCell::new(Vec::new()),
+            canonical_attribute_order: Cell::new(false),
+            attribute_quoting: Cell::new(AttributeQuoting::default()),
+            verify: Cell::new(metadb.is_some()),
         }
     }
 
+    /// Accept the elements from `custom_elements` in addition to the
+    /// standard HTML ones, both in structure validation and in
+    /// `elementmeta` lookups.
+    pub fn with_custom_elements(
+        mut self,
+        custom_elements: &'static CustomElements
+    ) -> Self {
+        self.custom_elements = Some(custom_elements);
+        self
+    }
+
+    /// Look up the meta for the element with the given tag name, in
+    /// the standard HTML ones, then the custom elements if any.
+    pub fn elementmeta(&self, name: &str) -> Option<&'static ElementMeta> {
+        let metadb: &'static MetaDb = self.metadb.unwrap_or(&*METADB);
+        metadb.elementmeta.get(name).or_else(
+            || self.custom_elements?.get(name))
+    }
+
+    fn is_custom_element(&self, meta: &ElementMeta) -> bool {
+        self.custom_elements.is_some_and(|c| c.contains(meta))
+    }
+
+    /// Whether elements with `meta` are accepted without verification.
+    pub(crate) fn is_permissive_element(&self, meta: &ElementMeta) -> bool {
+        self.custom_elements.is_some_and(|c| c.is_permissive(meta))
+    }
+
+    /// Check that the attribute `name` (number `i`) is allowed on an
+    /// element with `meta`.
+    pub(crate) fn verify_attribute(
+        &self, global_meta: &MetaDb, meta: &ElementMeta, i: usize, name: &str
+    ) -> Result<()> {
+        let allowed = &meta.attributes;
+        if global_meta.is_global_attribute(name) {
+            // OK; XX verify attribute value, too, but
+            // don't have the data yet.
+        } else if let Some(_a) = allowed.get(name) {
+            // OK; XX: todo: verify attribute value, too
+        } else {
+            let mut allowednamesset =
+                allowed.keys().cloned().collect::<HashSet<KString>>();
+            allowednamesset.extend(global_meta.global_attribute_names.iter().cloned());
+            allowednamesset.extend(GLOBAL_ATTRIBUTE_PREFIXES.iter()
+                                   .map(|prefix| KString::from(format!("{prefix}*"))));
+            let mut allowednames: Vec<&str> =
+                allowednamesset.iter().map(
+                    |v| v.as_str()).collect();
+            allowednames.sort();
+            bail!("invalid attribute #{i} {:?} for element {:?} \
+                   (valid: {:?})",
+                  name,
+                  meta.tag_name.as_str(),
+                  allowednames)
+        }
+        Ok(())
+    }
+
+    /// Check that an element with `child_meta` is allowed as content
+    /// value number `i` of an element with `meta`.
+    pub(crate) fn verify_child_element(
+        &self, meta: &ElementMeta, i: usize, child_meta: &ElementMeta
+    ) -> Result<()> {
+        let allowed = &meta.child_elements;
+        let is_allowed = allowed.contains(&child_meta.tag_name)
+            || (meta.allows_child_text && self.is_custom_element(child_meta));
+        if ! is_allowed {
+            let mut allowednames: Vec<&str> = allowed.iter().map(
+                |k| k.as_str()).collect();
+            allowednames.sort();
+            bail!("content value #{i}: element {:?} not allowed as \
+                   a child of element {:?}, only: {:?}{}",
+                  child_meta.tag_name.as_str(),
+                  meta.tag_name.as_str(),
+                  allowednames,
+                  if meta.allows_child_text {
+                      " as well as text"
+                  } else {
+                      " (no text)"
+                  })
+        }
+        Ok(())
+    }
+
+    /// Check that the text `s` is allowed as content value number `i`
+    /// of an element with `meta`.
+    pub(crate) fn verify_child_text(
+        &self, meta: &ElementMeta, i: usize, s: &str
+    ) -> Result<()> {
+        if (! meta.allows_child_text) && (! all_whitespace(s)) {
+            let allowed = &meta.child_elements;
+            let mut allowednames: Vec<&str> = allowed.iter().map(
+                |k| k.as_str()).collect();
+            allowednames.sort();
+            bail!("content value #{i}: text is not allowed as \
+                   a child of element {:?}, only: {:?}",
+                  meta.tag_name.as_str(),
+                  allowednames)
+        }
+        Ok(())
+    }
+
     pub fn clear(&mut self) {
         self.atts.exclusive_clear();
         self.nodes.exclusive_clear();
         self.ids.borrow_mut().clear();
+        self.canonical_attribute_order.set(false);
+        self.attribute_quoting.set(AttributeQuoting::default());
+        self.verify.set(self.metadb.is_some());
         // Maybe in the future want to let regions be reusable
         // forever. So, don't `+= 1`!
         self.regionid.generation =
@@ -216,6 +440,40 @@ impl HtmlAllocator {
         )
     }
 
+    /// If `true`, attributes are serialized sorted by name (stable,
+    /// i.e. duplicate names keep their relative order) instead of in
+    /// insertion order, making the output independent of the order
+    /// of construction (e.g. from `HashMap`s). Off by default, and
+    /// reset by `clear`, i.e. for every use from a pool.
+    pub fn set_canonical_attribute_order(&self, on: bool) {
+        self.canonical_attribute_order.set(on);
+    }
+    pub fn canonical_attribute_order(&self) -> bool {
+        self.canonical_attribute_order.get()
+    }
+
+    /// How to quote attribute values when serializing, see
+    /// `AttributeQuoting`. `Double` by default, and reset by `clear`.
+    pub fn set_attribute_quoting(&self, quoting: AttributeQuoting) {
+        self.attribute_quoting.set(quoting);
+    }
+    pub fn attribute_quoting(&self) -> AttributeQuoting {
+        self.attribute_quoting.get()
+    }
+
+    /// Switch the verification of the HTML structure during element
+    /// allocation on or off, e.g. on for admin or preview pages but
+    /// off for hot public paths. When switching it on for an
+    /// allocator created without a metadb, the standard one is used.
+    /// Initially on if a metadb was given, and reset to that by
+    /// `clear`, i.e. for every use from a pool.
+    pub fn set_verify(&self, on: bool) {
+        self.verify.set(on);
+    }
+    pub fn verify(&self) -> bool {
+        self.verify.get()
+    }
+
     pub fn regionid(&self) -> RegionId {
         self.regionid
     }
@@ -245,7 +503,7 @@ impl HtmlAllocator {
             self.id_to_bare(val);
     }
 
-    pub fn get_node<'a>(&'a self, id: AId<Node>) -> Option<&'a Node> {
+    pub fn get_node(&self, id: AId<Node>) -> Option<&Node> {
         if let Some(v) = self.nodes.get(self.id_to_index(id)) {
             if let Some(n) = v {
                 Some(n)
@@ -260,8 +518,8 @@ impl HtmlAllocator {
     }
 
     // COPY-PASTE of above
-    pub fn get_att<'a>(&'a self, id: AId<(KString, KString)>)
-                   -> Option<&'a (KString, KString)>
+    pub fn get_att(&self, id: AId<(KString, KString)>)
+                   -> Option<&(KString, KString)>
     {
         if let Some(v) = self.atts.get(self.id_to_index(id)) {
             if let Some(n) = v {
@@ -297,7 +555,8 @@ impl HtmlAllocator {
         AVec::new_with_capacity(self, capacity)
     }
 
-    /// But also see element method for more comfort.
+    /// But also see element method for more comfort. `attr` and
+    /// `body` are not consumed, see `ASlice`.
     pub fn new_element(
         &self,
         meta: &'static ElementMeta,
@@ -308,105 +567,41 @@ impl HtmlAllocator {
     ) -> Result<AId<Node>> {
 
         // verify
-        if let Some(global_meta) = self.metadb {
-            {
-                let allowed = &meta.attributes;
-                for (i, att) in attr.iter_att(self).enumerate() {
-                    if global_meta.global_attribute_names.contains(&att.0) {
-                        // OK; XX verify attribute value, too, but
-                        // don't have the data yet.
-                    } else if let Some(_a) = allowed.get(&att.0) {
-                        // OK; XX: todo: verify attribute value, too
-                    } else {
-                        let mut allowednamesset =
-                            allowed.keys().map(|k| k.clone()).collect::<HashSet<KString>>();
-                        allowednamesset.extend(global_meta.global_attribute_names.iter()
-                                               .map(|k| k.clone()));
-                        let mut allowednames: Vec<&str> =
-                            allowednamesset.iter().map(
-                                |v| v.as_str()).collect();
-                        allowednames.sort();
-                        bail!("invalid attribute #{i} {:?} for element {:?} \
-                               (valid: {:?})\n{:?}",
-                              att.0.as_str(),
-                              meta.tag_name.as_str(),
-                              allowednames,
-                              Backtrace::new())
-                    }
-                }
+        let is_permissive = self.is_permissive_element(meta);
+        if self.verify.get() && ! is_permissive {
+            let global_meta: &'static MetaDb = self.metadb.unwrap_or(&*METADB);
+            let with_backtrace = |e: anyhow::Error| {
+                anyhow!("{e}\n{:?}", Backtrace::new())
+            };
+            for (i, att) in attr.iter_att(self).enumerate() {
+                self.verify_attribute(global_meta, meta, i, &att.0)
+                    .map_err(with_backtrace)?;
             }
-            {
-                let allowed = &meta.child_elements;
-                for (i, node) in body.iter_node(self).enumerate() {
-                    let verify_child_element_meta =
-                        |child_meta: &ElementMeta| -> Result<()>
-                    {
-                        if ! allowed.contains(&child_meta.tag_name) {
-                            let mut allowednames: Vec<&str> = allowed.iter().map(
-                                |k| k.as_str()).collect();
-                            allowednames.sort();
-                            bail!("content value #{i}: element {:?} not allowed as \
-                                   a child of element {:?}, only: {:?}{}\n{:?}",
-                                  child_meta.tag_name.as_str(),
-                                  meta.tag_name.as_str(),
-                                  allowednames,
-                                  if meta.allows_child_text {
-                                      " as well as text"
-                                  } else {
-                                      " (no text)"
-                                  },
-                                  Backtrace::new())
-                        }
-                        Ok(())
-                    };
-                    match &*node {
-                        Node::Element(elt) => {
-                            verify_child_element_meta(elt.meta)?
-                        }
-                        Node::String(s) => {
-                            if (! meta.allows_child_text) &&
-                                (! all_whitespace(s.as_str()))
-                            {
-                                let mut allowednames: Vec<&str> = allowed.iter().map(
-                                    |k| k.as_str()).collect();
-                                allowednames.sort();
-                                bail!("content value #{i}: text is not allowed as \
-                                       a child of element {:?}, only: {:?}\n{:?}",
-                                      meta.tag_name.as_str(),
-                                      allowednames,
-                                      Backtrace::new())
-                            }
-                        }
-                        Node::Preserialized(ser) => {
-                            verify_child_element_meta(ser.meta)?
-                        }
-                        Node::None => {},
-                    }
-                }
+            for (i, node) in body.iter_node(self).enumerate() {
+                self.verify_child_node(meta, i, node).map_err(with_backtrace)?;
             }
         }
 
+        let body = if meta.allows_child_text || is_permissive {
+            body
+        } else {
+            self.drop_whitespace_text(body)?
+        };
+
         let mut attr = attr;
         if AHTML_TRACE.load(std::sync::atomic::Ordering::Relaxed) {
-            let mut seen_title = false;
-            let mut vec = self.new_vec_with_capacity(attr.len + 1)?;
-            for id in attr.iter_aid(&self) {
-                let r = self.get_att(id).expect("exists because it's in attr");
-                if r.0 == "title" {
-                    seen_title = true;
-                }
-                vec.push(id)?;
-            }
             let bt_str = PartialBacktrace::new().part_to_string(1, "src/rouille_runner.rs");
-            if seen_title {
+            if (Element { meta, attr, body }).has_attribute("title", self) {
                 warn!("element {:?} already has 'title' attribute, not adding tracing at:\n\
                        {bt_str}",
                       &*meta.tag_name);
             } else {
+                let mut vec = self.new_vec_with_capacity(attr.len + 1)?;
+                vec.extend_from_slice(&attr, self)?;
                 vec.push(self.attribute("title", format!("Generated at:\n\
                                                           {bt_str}"))?)?;
+                attr = vec.as_slice();
             }
-            attr = vec.to_aslice(self)?;
         }
 
         // (Note: now can get .len() even though that can update even
@@ -423,6 +618,42 @@ impl HtmlAllocator {
         Ok(AId::new(self.regionid, id_ as u32))
     }
 
+    /// Check `node` as child number `i` of a `meta` element; the
+    /// children of fragments are checked in its place.
+    fn verify_child_node(&self, meta: &ElementMeta, i: usize, node: &Node) -> Result<()> {
+        match node {
+            Node::Element(elt) => self.verify_child_element(meta, i, elt.meta),
+            Node::String(s) => self.verify_child_text(meta, i, s),
+            Node::Preserialized(ser) => self.verify_child_element(meta, i, ser.meta),
+            Node::Fragment(body) => {
+                for child in body.iter_node(self) {
+                    self.verify_child_node(meta, i, child)?;
+                }
+                Ok(())
+            }
+            Node::None => Ok(()),
+        }
+    }
+
+    /// `body` without its whitespace-only text nodes, for elements
+    /// that don't allow text: those are accepted there (as they
+    /// commonly come from source formatting or markdown soft breaks)
+    /// but carry no meaning, thus not worth keeping in the DOM.
+    fn drop_whitespace_text(&self, body: ASlice<Node>) -> Result<ASlice<Node>> {
+        let is_whitespace_text = |node: &Node| matches!(node, Node::String(s)
+                                                        if all_whitespace(s));
+        if ! body.iter_node(self).any(is_whitespace_text) {
+            return Ok(body)
+        }
+        let mut vec = self.new_vec_with_capacity(body.len)?;
+        for (id, node) in body.iter_aid(self).zip(body.iter_node(self)) {
+            if ! is_whitespace_text(node) {
+                vec.push(id)?;
+            }
+        }
+        vec.to_aslice(self)
+    }
+
     // XX naming needs work (new_element, element, (add_element), allocate_element).
     pub fn allocate_element(&self, elt: Element) -> Result<AId<Node>> {
         self.new_element(elt.meta, elt.attr, elt.body)
@@ -466,6 +697,23 @@ impl HtmlAllocator {
         self.new_attribute((KString::myfrom(key), KString::myfrom(val)))
     }
 
+    /// An attribute list from `pairs`, to be built once and then
+    /// given to any number of elements in this allocator (see
+    /// `ASlice`), e.g. the same `class` for every cell of a table
+    /// column.
+    pub fn attrs<K, V>(
+        &self,
+        pairs: impl IntoIterator<Item = (K, V)>
+    ) -> Result<ASlice<(KString, KString)>>
+        where KString: MyFrom<K>, KString: MyFrom<V>
+    {
+        let mut vec = self.new_vec();
+        for (key, val) in pairs {
+            vec.push(self.attribute(key, val)?)?;
+        }
+        Ok(vec.as_slice())
+    }
+
     pub fn preserialized(
         &self,
         val: impl IntoArc<SerHtmlFrag>
@@ -540,6 +788,18 @@ impl HtmlAllocator {
         self.new_string(KString::myfrom(s))
     }
 
+    /// A text node from any string type that can be stored without
+    /// copying: `&'static str` (kept as a reference, no allocation),
+    /// `String`, `KString` and `Cow<'static, str>`. For other `&str`,
+    /// use `str` or `text`.
+    pub fn text_nocopy(
+        &self,
+        s: impl ToTextNode
+    ) -> Result<AId<Node>>
+    {
+        self.new_string(s.into_text_kstring())
+    }
+
     // XX remove now that there's text()?
     pub fn string(
         &self,
@@ -570,15 +830,23 @@ impl HtmlAllocator {
         self.new_string(s)
     }
 
-    // /// Create a transparent pseudo element with the given body; that
-    // /// body is flattened into the element's body where it is placed.
-    // pub fn flat(
-    //     &self,
-    //     body: impl ToASlice<Node>
-    // ) -> Result<AId<Node>>
-    // {
-    //     body.to_aslice(self)
-    // }
+    /// Create a transparent pseudo element with the given body: it
+    /// is serialized as just the body, and checked as if the body
+    /// was placed directly in the element where the fragment is
+    /// placed. For returning multiple nodes as one `AId` without a
+    /// wrapper element.
+    pub fn fragment(
+        &self,
+        body: impl ToASlice<Node>
+    ) -> Result<AId<Node>>
+    {
+        let body = body.to_aslice(self)?;
+        // copy-paste
+        let id_ = self.nodes.len();
+        self.nodes.push_within_capacity_(Some(Node::Fragment(body)))
+            .map_err(|_e| self.out_of_memory_error("nodes", self.nodes.capacity()))?;
+        Ok(AId::new(self.regionid, id_ as u32))
+    }
 
     /// Create an element from normal slices or arrays, for nice to use
     /// syntax.
@@ -634,7 +902,7 @@ impl<T: AllocatorType> AId<T> {
 // derive is broken when using PhantomData, so do it manually:
 impl<T> Clone for AId<T> {
     fn clone(&self) -> Self {
-        Self { t: PhantomData, regionid: self.regionid, id: self.id }
+        *self
     }
 }
 impl<T> Copy for AId<T> {}
@@ -687,6 +955,10 @@ impl<'a, T: AllocatorType> AVec<'a, T> {
         self.len
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
     pub fn push(&mut self, itemid: AId<T>) -> Result<()> {
         if self.len == self.cap {
             // let oldalloclen = self.allocator.ids.borrow().len();//debug
@@ -731,6 +1003,17 @@ impl<'a, T: AllocatorType> AVec<'a, T> {
         }
     }
 
+    /// Remove the last item and return it (the storage is not
+    /// freed).
+    pub fn pop(&mut self) -> Option<AId<T>> {
+        if self.len > 0 {
+            self.len -= 1;
+            self.allocator.get_id(self.start + self.len)
+        } else {
+            None
+        }
+    }
+
     pub fn reverse(&mut self) {
         let ids = &mut *self.allocator.ids.borrow_mut();
         for i in 0..self.len / 2 {
@@ -753,7 +1036,10 @@ impl<'a, T: AllocatorType> AVec<'a, T> {
 
 // about storage *inside* an allocator, thus no allocator field. XX
 // could this be improved?
-/// A slice of stored `AId<T>`s inside a `HtmlAllocator`.
+/// A slice of stored `AId<T>`s inside a `HtmlAllocator`. The stored
+/// ids are never modified, thus a slice (it's `Copy`) can be used
+/// any number of times, e.g. the same attributes or children for
+/// multiple elements, as long as it's in the same allocator.
 #[derive(Debug)]
 pub struct ASlice<T> {
     t: PhantomData<fn() -> T>,
@@ -765,7 +1051,7 @@ pub struct ASlice<T> {
 // again, [derive(Clone)] can't handle it for Clone of T, so do it ourselves:
 impl<T> Clone for ASlice<T> {
     fn clone(&self) -> Self {
-        Self { t: self.t, regionid: self.regionid, len: self.len, start: self.start }
+        *self
     }
 }
 impl<T> Copy for ASlice<T> {}
@@ -875,6 +1161,8 @@ impl<'a, T: AllocatorType> IntoIterator for &AVec<'a, T> {
 impl<'a, T: AllocatorType> ASlice<T> {
     pub fn len(&self) -> u32 { self.len }
 
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
     pub fn iter_node(&self, allocator: &'a HtmlAllocator) -> ASliceNodeIterator<'a, T> {
         allocator.assert_regionid(self.regionid);
         ASliceNodeIterator {
@@ -904,6 +1192,12 @@ impl<'a, T: AllocatorType> ASlice<T> {
         }
     }
 
+    /// All ids in the slice, as a std `Vec` (for reordering, random
+    /// access etc.).
+    pub fn to_vec_aid(&self, allocator: &'a HtmlAllocator) -> Vec<AId<T>> {
+        self.iter_aid(allocator).collect()
+    }
+
     pub fn try_filter_map<F: Fn(AId<T>) -> Result<Option<AId<T>>>>(
         &self,
         f: F,
@@ -1018,11 +1312,12 @@ fn unwrap_node(
     match node {
         Node::Element(e) =>
             if (! strict) || e.attr.len == 0 {
-                Some(e.body.clone())
+                Some(e.body)
             } else {
                 None
             },
         Node::String(_) => None,
+        Node::Fragment(_) => None,
         Node::Preserialized(p) =>
             if p.meta == meta {
                 warn!("can't unwrap_element of preserialized node");
@@ -1047,7 +1342,7 @@ impl<'a> ASlice<Node> {
             let node = allocator.get_node(nodeid).expect(
                 "exists because checked when entered into slice" // was it?
             );
-            unwrap_node(&*node, meta, strict)
+            unwrap_node(node, meta, strict)
         } else {
             None
         }
@@ -1060,8 +1355,7 @@ impl<'a> ASlice<Node> {
                           meta: &ElementMeta,
                           strict: bool,
                           allocator: &'a HtmlAllocator) -> ASlice<Node> {
-        self.unwrap_element_opt(meta, strict, allocator).unwrap_or_else(
-            || self.clone())
+        self.unwrap_element_opt(meta, strict, allocator).unwrap_or(*self)
     }
 
     /// Unwrap even if there are multiple elements, unwrap all of
@@ -1077,7 +1371,7 @@ impl<'a> ASlice<Node> {
                     let node = allocator.get_node(id).expect(
                         // as long as region id is correct? todo: details again?
                         "nodes from slices should always be found?");
-                    if let Some(subslice) = unwrap_node(&*node, meta, strict) {
+                    if let Some(subslice) = unwrap_node(node, meta, strict) {
                         for id in subslice.iter_aid(allocator) {
                             v.push(id)?;
                         }
@@ -1102,6 +1396,16 @@ pub struct SerHtmlFrag {
 }
 
 impl SerHtmlFrag {
+    /// A fragment from HTML source that is not checked in any way
+    /// (e.g. author-written HTML to be output verbatim). `meta` is
+    /// what the fragment counts as when checking the element it is
+    /// put into.
+    pub fn new_unchecked(meta: &'static ElementMeta, html: &str) -> SerHtmlFrag {
+        SerHtmlFrag {
+            meta,
+            kstring: KString::from_ref(html)
+        }
+    }
     #[inline(always)]
     pub fn meta(&self) -> &'static ElementMeta {
         self.meta
@@ -1119,6 +1423,9 @@ pub enum Node {
     Element(Element),
     String(KString),
     Preserialized(Arc<SerHtmlFrag>),
+    /// Its body is used in place of the node, see
+    /// `HtmlAllocator::fragment`.
+    Fragment(ASlice<Node>),
     None,
 }
 
@@ -1128,6 +1435,17 @@ impl Node {
             Node::Element(e) => Some(e),
             Node::String(_) => None,
             Node::Preserialized(_) => None,
+            Node::Fragment(_) => None,
+            Node::None => None,
+        }
+    }
+    /// The child nodes of elements and fragments.
+    pub fn children(&self) -> Option<&ASlice<Node>> {
+        match self {
+            Node::Element(e) => Some(&e.body),
+            Node::Fragment(body) => Some(body),
+            Node::String(_) => None,
+            Node::Preserialized(_) => None,
             Node::None => None,
         }
     }
@@ -1138,6 +1456,8 @@ impl Node {
                 bail!("not a Node::String, but Node::Preserialized"),
             Node::Preserialized(_) =>
                 bail!("not an Node::Element, but Node::Preserialized"),
+            Node::Fragment(_) =>
+                bail!("not an Node::Element, but Node::Fragment"),
             Node::None => 
                 bail!("not an Node::Element, but Node::None"),
         }
@@ -1161,15 +1481,25 @@ impl Element {
     pub fn attr(&self) -> &ASlice<(KString, KString)> { &self.attr }
     pub fn body(&self) -> &ASlice<Node> { &self.body }
 
-    pub fn try_filter_map_body<'a, T: AllocatorType>(
+    /// The value of the first attribute named `name`, if any.
+    pub fn get_attribute<'a>(&self, name: &str, allocator: &'a HtmlAllocator)
+                             -> Option<&'a KString> {
+        self.attr.iter_att(allocator).find_map(|(k, v)| (k == name).then_some(v))
+    }
+
+    pub fn has_attribute(&self, name: &str, allocator: &HtmlAllocator) -> bool {
+        self.get_attribute(name, allocator).is_some()
+    }
+
+    pub fn try_filter_map_body<T: AllocatorType>(
         &self,
         f: impl Fn(AId<Node>) -> Result<Option<AId<Node>>>,
-        allocator: &'a HtmlAllocator
+        allocator: &HtmlAllocator
     ) -> Result<Element> {
         let body2 = self.body.try_filter_map(f, None, allocator)?;
         Ok(Element {
             meta: self.meta,
-            attr: self.attr.clone(),
+            attr: self.attr,
             body: body2.as_slice()
         })
     }
@@ -1191,6 +1521,74 @@ mod tests {
             .expect("system has at least 32 bits");
     }
 
+    #[test]
+    fn t_pool_prewarm() {
+        let pool = HtmlAllocatorPool::new(1000, true, Arc::new("t_pool_prewarm"));
+        assert_eq!(pool.idle_count(), 0);
+        pool.prewarm(3);
+        assert_eq!(pool.idle_count(), 3);
+        pool.prewarm(2);
+        assert_eq!(pool.idle_count(), 3);
+        {
+            let _a = pool.get();
+            let _b = pool.get();
+            assert_eq!(pool.idle_count(), 1);
+        }
+        assert_eq!(pool.idle_count(), 3);
+    }
+
+    #[test]
+    fn t_to_vec_aid() -> Result<()> {
+        use crate::{Print, TryCollectBody};
+        let html = HtmlAllocator::new(1000, Arc::new("t_to_vec_aid"));
+        let items = [html.li([], "a")?, html.li([], "b")?, html.li([], "c")?].to_aslice(&html)?;
+        let mut ids = items.to_vec_aid(&html);
+        assert_eq!(ids.len(), 3);
+        ids.reverse();
+        let reversed = ids.into_iter().map(Ok).try_collect_body(&html)?;
+        assert_eq!(html.ul([], reversed)?.to_html_fragment_string(&html)?,
+                   "<ul><li>c</li><li>b</li><li>a</li></ul>");
+        Ok(())
+    }
+
+    #[test]
+    fn t_get_attribute() -> Result<()> {
+        use crate::att;
+        let html = HtmlAllocator::new(1000, Arc::new("t_get_attribute"));
+        let a = html.a([att("href", "/x"), att("title", ""), att("href", "/y")], "x")?;
+        let elt = html.get_node(a).unwrap().try_element()?;
+        assert_eq!(elt.get_attribute("href", &html).map(|v| v.as_str()), Some("/x"));
+        assert_eq!(elt.get_attribute("title", &html).map(|v| v.as_str()), Some(""));
+        assert!(elt.has_attribute("title", &html));
+        assert!(! elt.has_attribute("Href", &html));
+        assert_eq!(elt.get_attribute("id", &html), None);
+        Ok(())
+    }
+
+    #[test]
+    fn t_set_verify() -> Result<()> {
+        let mut html = HtmlAllocator::new(1000, Arc::new("t_set_verify"));
+        let invalid = |html: &HtmlAllocator| -> Result<AId<Node>> {
+            html.ul([], [html.p([], [])?])
+        };
+        assert!(html.verify());
+        assert!(invalid(&html).is_err());
+        html.set_verify(false);
+        assert!(invalid(&html).is_ok());
+        html.set_verify(true);
+        assert!(invalid(&html).is_err());
+        html.set_verify(false);
+        html.clear();
+        assert!(html.verify());
+
+        // Uses the standard metadb if created without one
+        let html = HtmlAllocator::new_with_metadb(1000, None, Arc::new("t_set_verify"));
+        assert!(invalid(&html).is_ok());
+        html.set_verify(true);
+        assert!(invalid(&html).is_err());
+        Ok(())
+    }
+
     #[test]
     fn t_siz() {
         assert_eq!(size_of::<RegionId>(), 4);
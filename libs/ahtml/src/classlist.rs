@@ -0,0 +1,140 @@
+//! The value of a `class` attribute, built from conditional parts.
+
+use std::fmt::{Display, Formatter};
+
+use kstring::KString;
+
+use crate::myfrom::MyFrom;
+
+/// A set of class names, kept in the order they were first added,
+/// without duplicates. Usable as attribute value, e.g.
+/// `att("class", ClassList::new().add("btn").add_if(active, "active"))`.
+/// Names given to the methods can be multiple space-separated names.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClassList(Vec<KString>);
+
+impl ClassList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `classes` that are not present yet. (Named after the DOM's
+    /// `classList.add`, not meant as `std::ops::Add`.)
+    #[allow(clippy::should_implement_trait)]
+    pub fn add(mut self, classes: &str) -> Self {
+        for class in classes.split_ascii_whitespace() {
+            if ! self.contains(class) {
+                self.0.push(KString::from_ref(class));
+            }
+        }
+        self
+    }
+
+    /// `add(classes)` if `condition` is true.
+    pub fn add_if(self, condition: bool, classes: &str) -> Self {
+        if condition {
+            self.add(classes)
+        } else {
+            self
+        }
+    }
+
+    pub fn remove(mut self, classes: &str) -> Self {
+        for class in classes.split_ascii_whitespace() {
+            self.0.retain(|c| c != class);
+        }
+        self
+    }
+
+    /// Remove each of `classes` that is present, add those that
+    /// aren't.
+    pub fn toggle(mut self, classes: &str) -> Self {
+        for class in classes.split_ascii_whitespace() {
+            self = if self.contains(class) {
+                self.remove(class)
+            } else {
+                self.add(class)
+            };
+        }
+        self
+    }
+
+    pub fn contains(&self, class: &str) -> bool {
+        self.0.iter().any(|c| c == class)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// None if empty, for `opt_att` (to omit the attribute instead of
+    /// giving `class=""`).
+    pub fn into_option(self) -> Option<Self> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self)
+        }
+    }
+
+    /// The attribute value (names separated by spaces).
+    pub fn to_kstring(&self) -> KString {
+        if let [class] = &self.0[..] {
+            class.clone()
+        } else {
+            KString::from_string(self.to_string())
+        }
+    }
+}
+
+impl Display for ClassList {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, class) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            f.write_str(class)?;
+        }
+        Ok(())
+    }
+}
+
+impl MyFrom<ClassList> for KString {
+    fn myfrom(classes: ClassList) -> Self {
+        classes.to_kstring()
+    }
+}
+
+impl MyFrom<&ClassList> for KString {
+    fn myfrom(classes: &ClassList) -> Self {
+        classes.to_kstring()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_classlist() {
+        let classes = |active: bool, disabled: bool| {
+            ClassList::new().add("btn").add_if(active, "active")
+                .add_if(disabled, "disabled btn").to_string()
+        };
+        assert_eq!(classes(false, false), "btn");
+        assert_eq!(classes(true, false), "btn active");
+        assert_eq!(classes(true, true), "btn active disabled");
+        assert_eq!(classes(false, true), "btn disabled");
+
+        let c = ClassList::new().add("a  b a").add("b c");
+        assert_eq!(c.to_kstring(), "a b c");
+        assert_eq!(c.clone().toggle("b d").to_kstring(), "a c d");
+        assert_eq!(c.clone().remove("a c").to_kstring(), "b");
+        assert!(c.contains("c") && ! c.contains("a b"));
+        assert!(ClassList::new().add(" ").is_empty());
+        assert_eq!(crate::opt_att("class", ClassList::new().add_if(false, "x").into_option()),
+                   None);
+        assert_eq!(crate::att("class", &c), Some(("class".into(), "a b c".into())));
+    }
+}
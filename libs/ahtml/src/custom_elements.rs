@@ -0,0 +1,155 @@
+//! Elements not in the static HTML5 meta database, e.g. web
+//! components like `<my-widget>`.
+
+use std::collections::{HashMap, HashSet};
+
+use ahtml_html::meta::ElementMeta;
+use anyhow::{Result, bail};
+use kstring::KString;
+
+use crate::METADB;
+
+/// Names of custom elements must contain a hyphen (and the standard
+/// HTML elements never do).
+pub fn is_custom_element_name(name: &str) -> bool {
+    name.contains('-')
+}
+
+/// Metadata for an element that accepts any attributes, text, and
+/// child elements.
+pub fn permissive_element_meta(name: &str) -> ElementMeta {
+    ElementMeta {
+        tag_name: KString::from_ref(name),
+        has_global_attributes: true,
+        has_closing_tag: true,
+        attributes: HashMap::new(),
+        allows_child_text: true,
+        child_elements: HashSet::new(),
+    }
+}
+
+/// A set of element metas registered at runtime, used in addition to
+/// the static `MetaDb` by `HtmlAllocator`s that were given it via
+/// `with_custom_elements`. Custom elements are allowed as children
+/// wherever text is allowed (they are phrasing content). Elements
+/// registered via `register` are verified according to their meta,
+/// those registered via `register_permissive` are accepted with any
+/// attributes and children. Only registered elements are known, so
+/// that the set (and the leaked metas) can't grow while serving.
+#[derive(Default)]
+pub struct CustomElements {
+    elementmeta: HashMap<KString, &'static ElementMeta>,
+    /// The names registered via `register_permissive`
+    permissive: HashSet<KString>,
+}
+
+impl CustomElements {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `meta`; its storage is leaked (registration is meant
+    /// to happen once at startup). Gives an error if the name is
+    /// already registered or is a standard HTML element.
+    pub fn register(&mut self, meta: ElementMeta) -> Result<&'static ElementMeta> {
+        let name = meta.tag_name.clone();
+        if METADB.elementmeta.contains_key(&name) {
+            bail!("can't register custom element {:?}: is a standard HTML element",
+                  name.as_str())
+        }
+        if self.elementmeta.contains_key(&name) {
+            bail!("custom element {:?} is already registered", name.as_str())
+        }
+        let meta: &'static ElementMeta = Box::leak(Box::new(meta));
+        self.elementmeta.insert(name, meta);
+        Ok(meta)
+    }
+
+    /// Register the custom element `name` (which must contain a
+    /// hyphen) with permissive rules: any attributes and children.
+    pub fn register_permissive(&mut self, name: &str) -> Result<&'static ElementMeta> {
+        if ! is_custom_element_name(name) {
+            bail!("custom element name {name:?} does not contain a hyphen")
+        }
+        let meta = self.register(permissive_element_meta(name))?;
+        self.permissive.insert(KString::from_ref(name));
+        Ok(meta)
+    }
+
+    /// Look up the meta for `name`.
+    pub fn get(&self, name: &str) -> Option<&'static ElementMeta> {
+        self.elementmeta.get(name).copied()
+    }
+
+    /// Whether `meta` is handled by this set.
+    pub fn contains(&self, meta: &ElementMeta) -> bool {
+        self.elementmeta.contains_key(meta.tag_name.as_str())
+    }
+
+    /// Whether `meta` is accepted without verification of its
+    /// attributes and children.
+    pub fn is_permissive(&self, meta: &ElementMeta) -> bool {
+        self.permissive.contains(meta.tag_name.as_str())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{HtmlAllocator, Print, att};
+
+    use super::*;
+
+    fn custom_elements() -> Result<&'static CustomElements> {
+        let mut c = CustomElements::new();
+        let mut meta = permissive_element_meta("x-card");
+        meta.allows_child_text = false;
+        meta.child_elements.insert(KString::from_static("p"));
+        c.register(meta)?;
+        assert!(c.register(permissive_element_meta("x-card")).is_err());
+        assert!(c.register(permissive_element_meta("div")).is_err());
+        c.register_permissive("my-widget")?;
+        assert!(c.register_permissive("widget").is_err());
+        Ok(Box::leak(Box::new(c)))
+    }
+
+    #[test]
+    fn t_custom_elements() -> Result<()> {
+        let c = custom_elements()?;
+        let html = HtmlAllocator::new(1000, Arc::new(""))
+            .with_custom_elements(c);
+
+        // Registered one, verified according to its meta
+        let card = html.elementmeta("x-card").expect("registered");
+        let e = html.div(
+            [],
+            [html.element(card, [], [html.p([], [html.str("hi")?])?])?])?;
+        assert_eq!(e.to_html_fragment_string(&html)?,
+                   "<div><x-card><p>hi</p></x-card></div>");
+        assert!(html.element(card, [], [html.str("text")?]).is_err());
+
+        // Permissive one
+        let widget = html.elementmeta("my-widget").expect("registered");
+        let e = html.p(
+            [],
+            [html.element(widget,
+                          [att("data-src", "/x"), att("size", "3")],
+                          [html.str("w")?, html.b([], [html.str("b")?])?])?])?;
+        assert_eq!(e.to_html_fragment_string(&html)?,
+                   r#"<p><my-widget data-src="/x" size="3">w<b>b</b></my-widget></p>"#);
+
+        // Not registered, hyphenated or not
+        assert!(html.elementmeta("widget").is_none());
+        assert!(html.elementmeta("other-widget").is_none());
+        // Still rejected where text isn't allowed
+        assert!(html.ul([], [html.element(widget, [], [])?]).is_err());
+
+        // Allocators without the custom elements reject them
+        let html2 = HtmlAllocator::new(1000, Arc::new(""));
+        assert!(html2.elementmeta("my-widget").is_none());
+        assert!(html2.div([], [html2.element(widget, [], [])?]).is_err());
+        Ok(())
+    }
+}
@@ -71,15 +71,15 @@ impl<'a, T: AllocatorType> ASlice<T> {
 }
 
 impl Element {
-    pub fn try_flat_map_body<'a, T: AllocatorType>(
+    pub fn try_flat_map_body<T: AllocatorType>(
         &self,
         f: impl Fn(AId<Node>) -> Result<Flat<Node>>,
-        allocator: &'a HtmlAllocator
+        allocator: &HtmlAllocator
     ) -> Result<Element> {
         let body2 = self.body.try_flat_map(f, None, allocator)?;
         Ok(Element {
             meta: self.meta,
-            attr: self.attr.clone(),
+            attr: self.attr,
             body: body2.as_slice()
         })
     }
@@ -7,14 +7,27 @@ pub mod allocator;
 pub mod flat;
 pub mod more_vec;
 pub mod stillvec;
+pub mod custom_elements;
+pub mod walker;
+pub mod classlist;
+pub mod reader;
+mod parse;
+mod validate;
 
 use std::{cell::RefMut,
-          io::Write};
+          io::Write,
+          ops::Deref};
 use allocator::Context;
 pub use allocator::{HtmlAllocator, HtmlAllocatorPool, AId, Node, ASlice, Element,
-                    AllocatorType, SerHtmlFrag, ToASlice, AVec};
+                    AllocatorType, SerHtmlFrag, ToASlice, ToTextNode, AVec,
+                    AttributeQuoting};
+pub use custom_elements::CustomElements;
+pub use walker::NodeWalker;
+pub use classlist::ClassList;
+pub use reader::HtmlReader;
+pub use parse::HtmlToken;
 use kstring::KString;
-use anyhow::{Result, bail};
+use anyhow::{Result, bail, anyhow};
 use lazy_static::lazy_static;
 use ahtml_html::meta::{MetaDb, ElementMeta, read_meta_db};
 
@@ -54,6 +67,30 @@ pub trait Print {
         })
     }
 
+    /// Print serialized HTML indented for reading, with block-level
+    /// elements on separate lines, indented by `indent` spaces per
+    /// nesting level. Inline elements (like `span`, `a`, `em`) and
+    /// the text around them are kept on one line, and the contents
+    /// of `pre`, `textarea`, `script` and `style` as well as
+    /// preserialized HTML are printed unchanged, so that the
+    /// rendering is not affected (except for whitespace between
+    /// blocks). The default is the normal (unindented) output.
+    fn print_html_fragment_pretty(
+        &self, out: &mut impl Write, allocator: &HtmlAllocator, _indent: usize
+    ) -> Result<()> {
+        self.print_html_fragment(out, allocator)
+    }
+
+    fn to_pretty_html_string(&self, allocator: &HtmlAllocator, indent: usize)
+                             -> Result<String> {
+        let mut s = Vec::new();
+        self.print_html_fragment_pretty(&mut s, allocator, indent)?;
+        Ok(unsafe {
+            // Safe for the same reasons as in to_html_fragment_string
+            String::from_utf8_unchecked(s)
+        })
+    }
+
     fn to_plain_string(&self, allocator: &HtmlAllocator) -> Result<String> {
         let mut s = String::new();
         self.print_plain(&mut s, allocator)?;
@@ -72,6 +109,13 @@ impl Print for AId<Node> {
         node.print_html_fragment(out, allocator)
     }
 
+    fn print_html_fragment_pretty(
+        &self, out: &mut impl Write, allocator: &HtmlAllocator, indent: usize
+    ) -> Result<()> {
+        let node = allocator.get_node(*self).expect("id should resolve: {self:?}");
+        node.print_html_fragment_pretty(out, allocator, indent)
+    }
+
     fn print_plain(&self, out: &mut String, allocator: &HtmlAllocator)
                    -> Result<()> {
         let node = allocator.get_node(*self).expect("id should resolve: {self:?}");
@@ -81,8 +125,45 @@ impl Print for AId<Node> {
 
 
 
+/// The env vars `read_meta_db` consults.
+const METADB_ENV_VARS: &[&str] = &[
+    "HTML_READ_META_DB_FROM_JSON_DIR",
+    "WRITE_STATIC_META_DB_RS_PATH",
+    "HTML_META_DEBUG",
+];
+
+fn metadb_load_error(e: anyhow::Error) -> String {
+    format!("could not load the HTML meta database (env vars consulted: {}): {e:#}",
+            METADB_ENV_VARS.join(", "))
+}
+
 lazy_static!{
-    pub static ref METADB: MetaDb = read_meta_db().unwrap();
+    static ref METADB_RESULT: Result<MetaDb, String> =
+        read_meta_db().map_err(metadb_load_error);
+}
+
+/// Get the HTML meta database, loading it on first use. Call this
+/// early (e.g. in `main`) to have a misconfiguration reported as an
+/// error, instead of as a panic on the first use of `METADB`.
+pub fn try_metadb() -> Result<&'static MetaDb> {
+    METADB_RESULT.as_ref().map_err(|e| anyhow!("{e}"))
+}
+
+/// Access to the HTML meta database via `Deref`; panics with the
+/// load error if it can't be loaded (see `try_metadb`).
+pub struct MetaDbRef(());
+
+pub static METADB: MetaDbRef = MetaDbRef(());
+
+impl Deref for MetaDbRef {
+    type Target = MetaDb;
+
+    fn deref(&self) -> &MetaDb {
+        match &*METADB_RESULT {
+            Ok(metadb) => metadb,
+            Err(e) => panic!("{e}"),
+        }
+    }
 }
 
 impl HtmlAllocatorPool {
@@ -130,6 +211,19 @@ pub fn opt_att<T, U>(key: T, val: Option<U>) -> Option<(KString, KString)>
     val.map(|val| (ks(key), ks(val)))
 }
 
+/// Attributes from an iterator of key/value pairs (e.g. a `HashMap`
+/// or a `Vec` of string pairs), for use as the attributes argument of
+/// element methods. (`ToASlice` can't be implemented for iterators
+/// directly, as that would conflict with the impl for arrays.)
+pub struct AttIter<I>(pub I);
+
+pub fn att_iter<I, T, U>(pairs: I) -> AttIter<impl Iterator<Item = (KString, KString)>>
+where I: IntoIterator<Item = (T, U)>,
+      KString: MyFrom<T> + MyFrom<U>
+{
+    AttIter(pairs.into_iter().map(|(key, val)| (ks(key), ks(val))))
+}
+
 
 impl<T> ToASlice<T> for ASlice<T> {
     fn to_aslice(self, _allocator: &HtmlAllocator) -> Result<ASlice<T>> {
@@ -155,6 +249,14 @@ impl ToASlice<Node> for AId<Node> {
     }
 }
 
+/// A static string as the sole body of an element, e.g. `html.p([],
+/// "Hello")`, without copying it.
+impl ToASlice<Node> for &'static str {
+    fn to_aslice(self, html: &HtmlAllocator) -> Result<ASlice<Node>> {
+        html.staticstr(self)?.to_aslice(html)
+    }
+}
+
 // Take ownership of an array (best syntax, and allows to avoid the
 // need for swap), version for attributes:
 
@@ -181,23 +283,39 @@ impl ToASlice<Node> for AId<Node> {
 // }
 
 // Same for values returned by `att` and `opt_att`:
-impl<'a, const N: usize> ToASlice<(KString, KString)> for [Option<(KString, KString)>; N] {
+impl<const N: usize> ToASlice<(KString, KString)> for [Option<(KString, KString)>; N] {
     fn to_aslice(
         self,
         allocator: &HtmlAllocator
     ) -> Result<ASlice<(KString, KString)>>
     {
         let mut vec = allocator.new_vec();
-        for opt_val in self {
-            if let Some(val) = opt_val {
-                let id_ = allocator.new_attribute(val)?;
-                vec.push(id_)?;
-            }
+        for val in self.into_iter().flatten() {
+            let id_ = allocator.new_attribute(val)?;
+            vec.push(id_)?;
         }
         Ok(vec.as_slice())
     }
 }
 
+impl<I: Iterator<Item = (KString, KString)>> ToASlice<(KString, KString)> for AttIter<I> {
+    fn to_aslice(self, allocator: &HtmlAllocator) -> Result<ASlice<(KString, KString)>> {
+        let mut vec = allocator.new_vec();
+        for val in self.0 {
+            vec.push(allocator.new_attribute(val)?)?;
+        }
+        Ok(vec.as_slice())
+    }
+}
+
+/// Attributes built at runtime. (Only this one `Vec` impl exists, so
+/// that `vec![]` is not ambiguous.)
+impl ToASlice<(KString, KString)> for Vec<(KString, KString)> {
+    fn to_aslice(self, allocator: &HtmlAllocator) -> Result<ASlice<(KString, KString)>> {
+        AttIter(self.into_iter()).to_aslice(allocator)
+    }
+}
+
 impl<const N: usize> ToASlice<Node> for [AId<Node>; N] {
     fn to_aslice(self, allocator: &HtmlAllocator) -> Result<ASlice<Node>> {
         // Instantiated for every length, need to keep this short! --
@@ -215,30 +333,50 @@ impl<const N: usize> ToASlice<Node> for [AId<Node>; N] {
 
 
 
+/// Write `bytes` to `out` with the characters that are special in
+/// HTML text and (quoted) attribute values escaped. Unlike
+/// `HtmlAllocator::html_escape`, this uses no intermediate buffer,
+/// thus can be used at any nesting depth. `bytes` must represent
+/// proper UTF-8, e.g. string.as_bytes().
+pub fn write_escaped(out: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+    write_escaped_except(out, bytes, None)
+}
+
+/// Like `write_escaped` but leaving the byte `keep` (a quote
+/// character) alone.
+fn write_escaped_except(
+    out: &mut impl Write, bytes: &[u8], keep: Option<u8>
+) -> std::io::Result<()> {
+    // Write the runs of bytes that don't need escaping in one go
+    let mut start = 0;
+    for (i, b) in bytes.iter().enumerate() {
+        if Some(*b) == keep {
+            continue
+        }
+        let escaped: &[u8] = match b {
+            b'&' => b"&amp;",
+            b'<' => b"&lt;",
+            b'>' => b"&gt;",
+            b'"' => b"&quot;",
+            b'\'' => b"&#39;",
+            _ => continue
+        };
+        out.write_all(&bytes[start..i])?;
+        out.write_all(escaped)?;
+        start = i + 1;
+    }
+    out.write_all(&bytes[start..])
+}
+
 impl HtmlAllocator {
     /// `bytes` must represent proper UTF-8,
     /// e.g. string.as_bytes(). The resulting reference must be
     /// dropped before calling html_escape again, or there will be a
-    /// panic.
-    pub fn html_escape(&self, bytes: &[u8]) -> RefMut<Vec<u8>> {
+    /// panic. When writing to a `Write`, use `write_escaped` instead.
+    pub fn html_escape(&self, bytes: &[u8]) -> RefMut<'_, Vec<u8>> {
         let mut bufref = self.html_escape_tmp.borrow_mut();
-        let append = |buf: &mut Vec<u8>, bstr: &[u8]| {
-            // XX wanted to use copy_from_slice. But how to reserve
-            // space for it efficiently?
-            buf.extend(bstr.iter());
-        };
-        let buf = &mut *bufref;
-        buf.clear();
-        for b in bytes {
-            match b {
-                b'&' => append(buf, b"&amp;"),
-                b'<' => append(buf, b"&lt;"),
-                b'>' => append(buf, b"&gt;"),
-                b'"' => append(buf, b"&quot;"),
-                b'\'' => append(buf, b"&#39;"),
-                _=> buf.push(*b)
-            }
-        }
+        bufref.clear();
+        write_escaped(&mut *bufref, bytes).expect("writing to a Vec never fails");
         bufref
     }
 
@@ -249,7 +387,7 @@ impl HtmlAllocator {
             // too?)
             "invalid generation/allocator_id leads to panic, hence this should \
              always resolve");
-        match &*noderef {
+        match noderef {
             Node::Element(_) => (),
             Node::String(_) => {
                 // eprintln!("toplevel print_html: Warning: printing of a \
@@ -258,6 +396,7 @@ impl HtmlAllocator {
             Node::Preserialized(_) =>
                 eprintln!("toplevel print_html: Warning: printing of a \
                            Node::Preserialized"),
+            Node::Fragment(_) => {},
             Node::None => {},
         }
         noderef.print_html_fragment(out, self)
@@ -271,13 +410,21 @@ impl HtmlAllocator {
         self.print_html_fragment(id_, out)
     }
 
-    pub fn to_html_string(&self, id: AId<Node>, want_doctype: bool) -> String {
-        let mut v = Vec::new();
+    /// Like `to_html_string`, but into `buf`, which is cleared
+    /// first. For re-using the buffer (and its capacity) across
+    /// renders, e.g. from thread-local storage.
+    pub fn print_into(&self, id: AId<Node>, buf: &mut Vec<u8>, want_doctype: bool) {
+        buf.clear();
         if want_doctype {
-            self.print_html_document(id, &mut v)
+            self.print_html_document(id, buf)
         } else {
-            self.print_html_fragment(id, &mut v)
+            self.print_html_fragment(id, buf)
         }.expect("no I/O errors can happen");
+    }
+
+    pub fn to_html_string(&self, id: AId<Node>, want_doctype: bool) -> String {
+        let mut v = Vec::new();
+        self.print_into(id, &mut v, want_doctype);
 
         // Safe because v was filled from bytes derived from
         // String/str values and byte string literals (typed in via
@@ -294,8 +441,7 @@ impl HtmlAllocator {
                 // too?)
                 "invalid generation/allocator_id leads to panic, hence this should \
                  always resolve");
-            let n = &*noderef;
-            match n {
+            match noderef {
                 Node::Element(e) => e.meta,
                 _ => bail!("can only preserialize element nodes")
             }
@@ -316,16 +462,14 @@ impl HtmlAllocator {
             // too?)
             "invalid generation/allocator_id leads to panic, hence this should \
              always resolve");
-        match &*noderef {
+        match noderef {
             Node::Element(_) => (),
             Node::String(_) => {
                 // eprintln!("toplevel print_plain: Warning: printing of a \
                 //            Node::String")
             }
-            Node::Preserialized(_) =>
-            // XX eh, that won't work anyway, error later on?
-                eprintln!("toplevel print_plain: Warning: printing of a \
-                           Node::Preserialized"),
+            Node::Preserialized(_) => {},
+            Node::Fragment(_) => {},
             Node::None => {},
         }
         noderef.print_plain(out, self)
@@ -350,24 +494,137 @@ impl HtmlAllocator {
             // too?)
             "invalid generation/allocator_id leads to panic, hence this should \
              always resolve");
-        match &*noderef {
-            Node::Element(_) => {
+        match noderef {
+            Node::Element(_) | Node::Fragment(_) | Node::Preserialized(_) => {
                 let mut v = String::new();
                 self.print_plain(id, &mut v)?;
                 self.string(v)
             }
             Node::String(_) => Ok(id),
-            // OK to give an error right away? *Would* error out
-            // anyway on print_plain ('though'), right?
-            Node::Preserialized(_) =>
-                bail!("can't currently strip markup from preserialized HTML"),
             Node::None => Ok(id), // XX is this OK or do we promise to return a string node?
         }
     }
+
+    /// The plain text content of `id` (like `to_plain_string`), for
+    /// summaries like meta descriptions: whitespace is collapsed,
+    /// block elements (like paragraphs) are separated by a space,
+    /// and the text is cut off after `max_chars` characters (not
+    /// bytes), in which case "…" is appended. The tree is only
+    /// walked as far as needed.
+    pub fn first_text(&self, id: AId<Node>, max_chars: usize) -> Result<String> {
+        let mut summary = Summary {
+            out: String::new(),
+            num_chars: 0,
+            max_chars,
+            pending_space: false,
+        };
+        if self.summarize(id, &mut summary)? {
+            summary.out.push('…');
+        }
+        Ok(summary.out)
+    }
+
+    /// Returns true if `summary` is full (and more text would
+    /// follow).
+    fn summarize(&self, id: AId<Node>, summary: &mut Summary) -> Result<bool> {
+        match self.get_node(id).expect("invalid id leads to panic in get_node") {
+            Node::Element(e) => {
+                let is_block = SUMMARY_BLOCK_ELEMENTS.contains(&e.meta.tag_name.as_str());
+                if is_block && summary.push_str(" ") {
+                    return Ok(true)
+                }
+                for child in e.body.iter_aid(self) {
+                    if self.summarize(child, summary)? {
+                        return Ok(true)
+                    }
+                }
+                Ok(is_block && summary.push_str(" "))
+            }
+            Node::String(s) => Ok(summary.push_str(s)),
+            Node::Fragment(body) => {
+                for child in body.iter_aid(self) {
+                    if self.summarize(child, summary)? {
+                        return Ok(true)
+                    }
+                }
+                Ok(false)
+            }
+            Node::Preserialized(ser) => self.summarize(self.parse_fragment(ser)?, summary),
+            Node::None => Ok(false),
+        }
+    }
+
+    /// Wrap an existing slice in a `<div>` without attributes. The
+    /// slice's id range is used directly as the element body, i.e. no
+    /// `AVec` is built (useful for the parts from `split_when`,
+    /// `split_at` or `first_and_rest`).
+    pub fn div_of_slice(&self, slice: ASlice<Node>) -> Result<AId<Node>> {
+        self.new_element(&DIV_META, self.empty_slice(), slice)
+    }
+
+    /// The `items` with `separator` between each of them (not before
+    /// the first or after the last), e.g. for "a, b, c" from a
+    /// list. The same `separator` node is referenced multiple times.
+    pub fn join_nodes(
+        &self,
+        items: impl IntoIterator<Item = AId<Node>>,
+        separator: AId<Node>,
+    ) -> Result<ASlice<Node>> {
+        let mut v = self.new_vec();
+        for (i, item) in items.into_iter().enumerate() {
+            if i > 0 {
+                v.push(separator)?;
+            }
+            v.push(item)?;
+        }
+        Ok(v.as_slice())
+    }
 }
 
 include!("../includes/ahtml_elements_include.rs");
 
+/// Elements whose text `first_text` separates from the surrounding
+/// text.
+const SUMMARY_BLOCK_ELEMENTS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "br", "dd", "div", "dt",
+    "figcaption", "footer", "h1", "h2", "h3", "h4", "h5", "h6", "header",
+    "li", "main", "nav", "p", "pre", "section", "td", "th", "tr",
+];
+
+/// Accumulator for `HtmlAllocator::first_text`.
+struct Summary {
+    out: String,
+    num_chars: usize,
+    max_chars: usize,
+    /// Whitespace was seen, to be output as a single space before
+    /// the next non-whitespace character.
+    pending_space: bool,
+}
+
+impl Summary {
+    /// Append `s` with whitespace collapsed (and none at the start or
+    /// end), returns true if there was more than fits.
+    fn push_str(&mut self, s: &str) -> bool {
+        for c in s.chars() {
+            if c.is_whitespace() {
+                self.pending_space = ! self.out.is_empty();
+                continue
+            }
+            let space = usize::from(self.pending_space);
+            if self.num_chars + space + 1 > self.max_chars {
+                return true
+            }
+            if self.pending_space {
+                self.out.push(' ');
+                self.pending_space = false;
+            }
+            self.out.push(c);
+            self.num_chars += space + 1;
+        }
+        false
+    }
+}
+
 
 impl<T: AllocatorType> Print for ASlice<T> {
     fn print_html_fragment(&self, out: &mut impl Write, allocator: &HtmlAllocator)
@@ -378,6 +635,14 @@ impl<T: AllocatorType> Print for ASlice<T> {
         Ok(())
     }
 
+    fn print_html_fragment_pretty(
+        &self, out: &mut impl Write, allocator: &HtmlAllocator, indent: usize
+    ) -> Result<()> {
+        let mut nodes = Vec::new();
+        push_flattened(&mut nodes, self, allocator);
+        print_pretty_nodes(&nodes, out, allocator, indent, 0)
+    }
+
     fn print_plain(&self, out: &mut String, allocator: &HtmlAllocator)
                    -> Result<()> {
         for node in self.iter_node(allocator) {
@@ -388,13 +653,34 @@ impl<T: AllocatorType> Print for ASlice<T> {
 }
 
 
+/// Whether `value` can be an unquoted attribute value (being
+/// conservative, e.g. the spec allows `'` in some positions).
+fn attribute_value_can_be_unquoted(value: &[u8]) -> bool {
+    ! value.is_empty() && ! value.iter().any(
+        |b| b.is_ascii_whitespace() || b"\"'=<>`".contains(b))
+}
+
 impl Print for (KString, KString) {
     fn print_html_fragment(&self, out: &mut impl Write, allocator: &HtmlAllocator)
              -> Result<()> {
         out.write_all(self.0.as_bytes())?; // XX no escape ever needed?
-        out.write_all(b"=\"")?;
-        out.write_all(&allocator.html_escape(self.1.as_bytes()))?;
-        out.write_all(b"\"")?;
+        let value = self.1.as_bytes();
+        match allocator.attribute_quoting() {
+            AttributeQuoting::Minimal if attribute_value_can_be_unquoted(value) => {
+                out.write_all(b"=")?;
+                write_escaped(out, value)?;
+            }
+            AttributeQuoting::Minimal if value.contains(&b'"') && ! value.contains(&b'\'') => {
+                out.write_all(b"='")?;
+                write_escaped_except(out, value, Some(b'"'))?;
+                out.write_all(b"'")?;
+            }
+            _ => {
+                out.write_all(b"=\"")?;
+                write_escaped(out, value)?;
+                out.write_all(b"\"")?;
+            }
+        }
         Ok(())
     }
 
@@ -407,50 +693,89 @@ impl Print for (KString, KString) {
 impl Print for Node {
     fn print_html_fragment(&self, out: &mut impl Write, allocator: &HtmlAllocator) -> Result<()>
     {
-        Ok(match self {
-            Node::Element(e) => e.print_html_fragment(out, allocator)?,
-            Node::String(s) => out.write_all(&allocator.html_escape(s.as_bytes()))?,
+        match self {
+            Node::Element(e) => e.print_html_fragment(out, allocator),
+            Node::String(s) => Ok(write_escaped(out, s.as_bytes())?),
             Node::Preserialized(ser) =>
-                out.write_all(ser.as_str().as_bytes())?,
-            Node::None => (),
-        })
+                Ok(out.write_all(ser.as_str().as_bytes())?),
+            Node::Fragment(body) => body.print_html_fragment(out, allocator),
+            Node::None => Ok(()),
+        }
+    }
+    fn print_html_fragment_pretty(
+        &self, out: &mut impl Write, allocator: &HtmlAllocator, indent: usize
+    ) -> Result<()> {
+        match self {
+            Node::Fragment(body) => body.print_html_fragment_pretty(out, allocator, indent),
+            Node::None => Ok(()),
+            _ => print_pretty_nodes(&[self], out, allocator, indent, 0),
+        }
     }
     fn print_plain(&self, out: &mut String, allocator: &HtmlAllocator) -> Result<()>
     {
         match self {
             Node::Element(e) => e.print_plain(out, allocator),
-            Node::String(s) => Ok(out.push_str(s.as_str())),
-            Node::Preserialized(_) =>
-                // would require re-parsing
-                bail!("print_plain: cannot (currently) print pre-serialized HTML \
-                       as plain text"),
+            Node::String(s) => {
+                out.push_str(s.as_str());
+                Ok(())
+            }
+            Node::Preserialized(ser) => parse::print_plain_serialized(ser.as_str(), out),
+            Node::Fragment(body) => body.print_plain(out, allocator),
             Node::None => Ok(()),
         }
     }
 }
 
 
-impl Print for Element {
-    fn print_html_fragment(&self, out: &mut impl Write, allocator: &HtmlAllocator)
-             -> Result<()>
-    {
+impl Element {
+    fn print_start_tag(&self, out: &mut impl Write, allocator: &HtmlAllocator)
+                       -> Result<()> {
         let meta = self.meta;
         // meta.has_global_attributes XX ? only for verification?
         out.write_all(b"<")?;
         out.write_all(meta.tag_name.as_bytes())?;
-        for att in self.attr.iter_att(allocator) {
-            out.write_all(b" ")?;
-            att.print_html_fragment(out, allocator)?;
+        if allocator.canonical_attribute_order() {
+            let mut atts: Vec<&(KString, KString)> =
+                self.attr.iter_att(allocator).collect();
+            atts.sort_by(|a, b| a.0.cmp(&b.0));
+            for att in atts {
+                out.write_all(b" ")?;
+                att.print_html_fragment(out, allocator)?;
+            }
+        } else {
+            for att in self.attr.iter_att(allocator) {
+                out.write_all(b" ")?;
+                att.print_html_fragment(out, allocator)?;
+            }
         }
         out.write_all(b">")?;
-        self.body.print_html_fragment(out, allocator)?;
-        if meta.has_closing_tag {
+        Ok(())
+    }
+
+    fn print_end_tag(&self, out: &mut impl Write) -> Result<()> {
+        if self.meta.has_closing_tag {
             out.write_all(b"</")?;
-            out.write_all(meta.tag_name.as_bytes())?;
+            out.write_all(self.meta.tag_name.as_bytes())?;
             out.write_all(b">")?;
         }
         Ok(())
     }
+}
+
+impl Print for Element {
+    fn print_html_fragment(&self, out: &mut impl Write, allocator: &HtmlAllocator)
+             -> Result<()>
+    {
+        self.print_start_tag(out, allocator)?;
+        self.body.print_html_fragment(out, allocator)?;
+        self.print_end_tag(out)
+    }
+
+    fn print_html_fragment_pretty(
+        &self, out: &mut impl Write, allocator: &HtmlAllocator, indent: usize
+    ) -> Result<()> {
+        print_pretty_element(self, out, allocator, indent, 0)
+    }
 
     fn print_plain(&self, out: &mut String, allocator: &HtmlAllocator) -> Result<()> {
         self.body.print_plain(out, allocator)
@@ -458,6 +783,112 @@ impl Print for Element {
 }
 
 
+/// Elements that `print_html_fragment_pretty` keeps on the line with
+/// the surrounding text.
+const PRETTY_INLINE_ELEMENTS: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "br", "button", "cite", "code", "data",
+    "del", "dfn", "em", "i", "img", "input", "ins", "kbd", "label", "mark",
+    "q", "s", "samp", "select", "small", "span", "strong", "sub", "sup",
+    "textarea", "time", "u", "var", "wbr",
+];
+
+/// Elements whose contents `print_html_fragment_pretty` doesn't
+/// touch, as whitespace in them is significant.
+const PRETTY_VERBATIM_ELEMENTS: &[&str] = &["pre", "textarea", "script", "style"];
+
+fn is_pretty_block(node: &Node) -> bool {
+    let meta = match node {
+        Node::Element(e) => e.meta,
+        Node::Preserialized(ser) => ser.meta,
+        _ => return false
+    };
+    ! PRETTY_INLINE_ELEMENTS.contains(&meta.tag_name.as_str())
+}
+
+/// The nodes of `body` with fragments replaced by their contents.
+fn push_flattened<'a, T: AllocatorType>(
+    nodes: &mut Vec<&'a Node>, body: &ASlice<T>, allocator: &'a HtmlAllocator
+) {
+    for node in body.iter_node(allocator) {
+        match node {
+            Node::Fragment(body) => push_flattened(nodes, body, allocator),
+            Node::None => (),
+            _ => nodes.push(node),
+        }
+    }
+}
+
+fn write_indentation(out: &mut impl Write, indent: usize, level: usize) -> Result<()> {
+    for _ in 0..indent * level {
+        out.write_all(b" ")?;
+    }
+    Ok(())
+}
+
+/// Block-level nodes on their own lines, the runs of other nodes
+/// between them each on one line (dropped if only whitespace).
+fn print_pretty_nodes(
+    nodes: &[&Node], out: &mut impl Write, allocator: &HtmlAllocator, indent: usize,
+    level: usize
+) -> Result<()> {
+    let mut i = 0;
+    while i < nodes.len() {
+        let node = nodes[i];
+        if is_pretty_block(node) {
+            match node {
+                Node::Element(e) => print_pretty_element(e, out, allocator, indent, level)?,
+                _ => {
+                    write_indentation(out, indent, level)?;
+                    node.print_html_fragment(out, allocator)?;
+                    out.write_all(b"\n")?;
+                }
+            }
+            i += 1;
+        } else {
+            let end = nodes[i..].iter().position(|n| is_pretty_block(n))
+                .map_or(nodes.len(), |j| i + j);
+            let run = &nodes[i..end];
+            let is_whitespace = run.iter().all(
+                |n| matches!(n, Node::String(s) if s.trim().is_empty()));
+            if ! is_whitespace {
+                write_indentation(out, indent, level)?;
+                for node in run {
+                    node.print_html_fragment(out, allocator)?;
+                }
+                out.write_all(b"\n")?;
+            }
+            i = end;
+        }
+    }
+    Ok(())
+}
+
+/// A block-level (or top-level) element: on one line if it doesn't
+/// contain block-level elements, otherwise with the contents on
+/// separate lines between the tags.
+fn print_pretty_element(
+    element: &Element, out: &mut impl Write, allocator: &HtmlAllocator, indent: usize,
+    level: usize
+) -> Result<()> {
+    let mut nodes = Vec::new();
+    push_flattened(&mut nodes, &element.body, allocator);
+    write_indentation(out, indent, level)?;
+    if PRETTY_VERBATIM_ELEMENTS.contains(&element.meta.tag_name.as_str())
+        || ! nodes.iter().any(|n| is_pretty_block(n))
+    {
+        element.print_html_fragment(out, allocator)?;
+    } else {
+        element.print_start_tag(out, allocator)?;
+        out.write_all(b"\n")?;
+        print_pretty_nodes(&nodes, out, allocator, indent, level + 1)?;
+        write_indentation(out, indent, level)?;
+        element.print_end_tag(out)?;
+    }
+    out.write_all(b"\n")?;
+    Ok(())
+}
+
+
 pub trait TryCollectBody {
     fn try_collect_body(&mut self, html: &HtmlAllocator) -> Result<ASlice<Node>>;
 }
@@ -473,6 +904,333 @@ impl<I: Iterator<Item = Result<AId<Node>>>> TryCollectBody for I {
 }
 
 
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ahtml_html::meta::read_meta_db_with_json_dir;
+
+    use super::*;
+
+    #[test]
+    fn t_canonical_attribute_order() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let a = html.a([att("href", "/x"), att("class", "c"), att("id", "i")],
+                       [html.str("x")?])?;
+        let b = html.a([att("id", "i"), att("href", "/x"), att("class", "c")],
+                       [html.str("x")?])?;
+        assert_eq!(a.to_html_fragment_string(&html)?,
+                   r#"<a href="/x" class="c" id="i">x</a>"#);
+        assert_ne!(a.to_html_fragment_string(&html)?,
+                   b.to_html_fragment_string(&html)?);
+        html.set_canonical_attribute_order(true);
+        assert_eq!(a.to_html_fragment_string(&html)?,
+                   r#"<a class="c" href="/x" id="i">x</a>"#);
+        assert_eq!(a.to_html_fragment_string(&html)?,
+                   b.to_html_fragment_string(&html)?);
+        Ok(())
+    }
+
+    #[test]
+    fn t_reused_attrs() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let num = html.attrs([("class", "num"), ("headers", "amount")])?;
+        let row = html.tr([], [html.td(num, "1")?,
+                               html.td(num, "22")?,
+                               html.td(num, "333")?])?;
+        assert_eq!(row.to_html_fragment_string(&html)?,
+                   "<tr><td class=\"num\" headers=\"amount\">1</td>\
+                    <td class=\"num\" headers=\"amount\">22</td>\
+                    <td class=\"num\" headers=\"amount\">333</td></tr>");
+        assert_eq!(num.iter_att(&html).count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn t_metadb_bad_json_dir() {
+        let r = read_meta_db_with_json_dir(Some("/nonexistent/ahtml-meta".as_ref()));
+        let msg = metadb_load_error(r.expect_err("dir does not exist"));
+        assert!(msg.starts_with(
+            "could not load the HTML meta database (env vars consulted: \
+             HTML_READ_META_DB_FROM_JSON_DIR, "));
+        assert!(msg.contains("reading meta db from directory \"/nonexistent/ahtml-meta\""));
+        assert!(msg.contains("No such file or directory"));
+    }
+
+    #[test]
+    fn t_static_text_not_copied() -> Result<()> {
+        static LONG: &str = "a static string too long to be stored inline";
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let text_ptr = |id| match html.get_node(id).expect("valid") {
+            Node::String(s) => s.as_str().as_ptr(),
+            _ => panic!("not a text node"),
+        };
+        // `text_nocopy` and element bodies refer to the static string
+        assert_eq!(text_ptr(html.text_nocopy(LONG)?), LONG.as_ptr());
+        let p = html.p([], LONG)?;
+        let body = *html.get_node(p).expect("valid").as_element().expect("element").body();
+        let (first, _) = body.first_and_rest(&html).expect("non-empty");
+        assert_eq!(text_ptr(first), LONG.as_ptr());
+        assert_eq!(p.to_html_fragment_string(&html)?, format!("<p>{LONG}</p>"));
+        // whereas `str` and `text` copy
+        assert_ne!(text_ptr(html.str(LONG)?), LONG.as_ptr());
+        assert_ne!(text_ptr(html.text(LONG)?), LONG.as_ptr());
+        // Owned strings are moved
+        let s = String::from(LONG);
+        let s_ptr = s.as_ptr();
+        assert_eq!(text_ptr(html.text_nocopy(s)?), s_ptr);
+        Ok(())
+    }
+
+    #[test]
+    fn t_div_of_slice() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let body = [html.p([], [html.str("lead")?])?,
+                    html.h2([], [html.str("Heading")?])?,
+                    html.p([], [html.str("main")?])?].to_aslice(&html)?;
+        let (lead, main) = body.split_at(1).expect("within slice");
+        let ids_before = html.ids.borrow().len();
+        let wrapped = html.div_of_slice(main)?;
+        assert_eq!(html.ids.borrow().len(), ids_before);
+        let mut v = html.new_vec();
+        v.extend_from_slice(&main, &html)?;
+        let naive = html.div([], v.as_slice())?;
+        assert_eq!(wrapped.to_html_fragment_string(&html)?,
+                   naive.to_html_fragment_string(&html)?);
+        assert_eq!(wrapped.to_html_fragment_string(&html)?,
+                   "<div><h2>Heading</h2><p>main</p></div>");
+        assert_eq!(html.div_of_slice(lead)?.to_html_fragment_string(&html)?,
+                   "<div><p>lead</p></div>");
+        Ok(())
+    }
+
+    #[test]
+    fn t_join_nodes() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let items = [html.str("a")?, html.b([], [html.str("b")?])?, html.str("c")?];
+        let sep = html.str(", ")?;
+        // (AId doesn't implement PartialEq)
+        let ids = |slice: ASlice<Node>| format!("{:?}", slice.iter_aid(&html).collect::<Vec<_>>());
+        let joined = html.join_nodes(items, sep)?;
+        assert_eq!(ids(joined), format!("{:?}", [items[0], sep, items[1], sep, items[2]]));
+        assert_eq!(html.p([], joined)?.to_html_fragment_string(&html)?,
+                   "<p>a, <b>b</b>, c</p>");
+        assert_eq!(html.join_nodes([], sep)?.len, 0);
+        assert_eq!(ids(html.join_nodes([items[0]], sep)?), format!("{:?}", [items[0]]));
+        Ok(())
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn t_naivedate() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let date = chrono::NaiveDate::from_ymd_opt(987, 1, 2).unwrap();
+        let t = html.time([att("datetime", date)], [html.text(date)?])?;
+        assert_eq!(t.to_html_fragment_string(&html)?,
+                   r#"<time datetime="0987-01-02">0987-01-02</time>"#);
+        assert_eq!(opt_att("datetime", Some(date)),
+                   Some((KString::from_static("datetime"), KString::from_static("0987-01-02"))));
+        Ok(())
+    }
+
+    #[test]
+    fn t_dynamic_attributes() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let mut attrs = vec![(KString::from_static("id"), KString::from_static("x"))];
+        for i in 1..3 {
+            attrs.push((KString::from_string(format!("data-n{i}")),
+                        KString::from_string(i.to_string())));
+        }
+        assert_eq!(html.div(attrs, [])?.to_html_fragment_string(&html)?,
+                   r#"<div id="x" data-n1="1" data-n2="2"></div>"#);
+        assert_eq!(html.div(vec![], [])?.to_html_fragment_string(&html)?, "<div></div>");
+
+        let map: std::collections::BTreeMap<&str, String> =
+            [("class", "a".into()), ("title", "T".into())].into_iter().collect();
+        assert_eq!(html.span(att_iter(&map), [])?.to_html_fragment_string(&html)?,
+                   r#"<span class="a" title="T"></span>"#);
+        assert_eq!(html.span(att_iter(Vec::<(&str, &str)>::new()), [])?
+                   .to_html_fragment_string(&html)?,
+                   "<span></span>");
+        Ok(())
+    }
+
+    #[test]
+    fn t_write_escaped() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let mut out = Vec::new();
+        write_escaped(&mut out, "a<b & \"c\" 'd'> é".as_bytes())?;
+        assert_eq!(std::str::from_utf8(&out)?, "a&lt;b &amp; &quot;c&quot; &#39;d&#39;&gt; é");
+        assert_eq!(&*html.html_escape(b"<x>"), b"&lt;x&gt;");
+
+        // Serializing doesn't use the shared buffer of `html_escape`,
+        // thus works while a reference to it is held
+        let held = html.html_escape(b"a&b");
+        let div = html.div([att("title", "x<\"y\">")],
+                           [html.p([att("class", "a&b")], [html.str("1 < 2")?])?])?;
+        assert_eq!(div.to_html_fragment_string(&html)?,
+                   r#"<div title="x&lt;&quot;y&quot;&gt;"><p class="a&amp;b">1 &lt; 2</p></div>"#);
+        assert_eq!(&*held, b"a&amp;b");
+        Ok(())
+    }
+
+    #[test]
+    fn t_first_text() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let doc = html.div([], [
+            html.h1([], [html.str("Über uns")?])?,
+            html.p([], [html.str("Wir  sind\n ")?,
+                        html.em([], [html.str("kleines")?])?,
+                        html.str(" Team in Zürich.")?])?,
+            html.p([], [html.str("Mehr später.")?])?])?;
+        assert_eq!(html.first_text(doc, 1000)?,
+                   "Über uns Wir sind kleines Team in Zürich. Mehr später.");
+        // The 50th char is the (two byte) "ä" of "später"
+        assert_eq!(html.first_text(doc, 50)?,
+                   "Über uns Wir sind kleines Team in Zürich. Mehr spä…");
+        // Not cut if it fits exactly
+        assert_eq!(html.first_text(doc, 54)?,
+                   "Über uns Wir sind kleines Team in Zürich. Mehr später.");
+        // No whitespace before the ellipsis
+        assert_eq!(html.first_text(doc, 9)?, "Über uns…");
+        assert_eq!(html.first_text(doc, 0)?, "…");
+        Ok(())
+    }
+
+    #[test]
+    fn t_data_and_aria_attributes() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new("t_data_and_aria_attributes"));
+        let e = html.div([att("data-foo", "1"), att("aria-label", "Menu"),
+                          att("aria-hidden", "true")], [])?;
+        assert_eq!(e.to_html_fragment_string(&html)?,
+                   r#"<div data-foo="1" aria-label="Menu" aria-hidden="true"></div>"#);
+        let err = html.div([att("foo", "1")], []).expect_err("invalid attribute");
+        assert!(err.to_string().contains(r#""data-*""#), "{err}");
+        // Just the prefix is not enough
+        assert!(html.div([att("data-", "1")], []).is_err());
+        assert!(html.div([att("datafoo", "1")], []).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn t_print_into() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let mut buf = Vec::new();
+        html.print_into(html.p([], [html.str("first, longer text")?])?, &mut buf, true);
+        assert_eq!(std::str::from_utf8(&buf)?,
+                   "\u{FEFF}<!DOCTYPE html>\n<p>first, longer text</p>");
+        let capacity = buf.capacity();
+        html.print_into(html.em([], [html.str("2nd")?])?, &mut buf, false);
+        assert_eq!(std::str::from_utf8(&buf)?, "<em>2nd</em>");
+        assert_eq!(buf.capacity(), capacity);
+        Ok(())
+    }
+
+    #[test]
+    fn t_attribute_quoting() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let t = |val: &str| html.div([att("title", val)], [])?.to_html_fragment_string(&html);
+        assert_eq!(t("a b")?, r#"<div title="a b"></div>"#);
+        html.set_attribute_quoting(AttributeQuoting::Minimal);
+        // Needs quotes
+        assert_eq!(t("a b")?, r#"<div title="a b"></div>"#);
+        assert_eq!(t("a>b")?, r#"<div title="a&gt;b"></div>"#);
+        assert_eq!(t("a=b")?, r#"<div title="a=b"></div>"#);
+        assert_eq!(t("")?, r#"<div title=""></div>"#);
+        // Safe to leave unquoted
+        assert_eq!(t("foo-1.2/x")?, r#"<div title=foo-1.2/x></div>"#);
+        assert_eq!(t("a&b")?, r#"<div title=a&amp;b></div>"#);
+        // Embedded quotes
+        assert_eq!(t(r#"say "hi""#)?, r#"<div title='say "hi"'></div>"#);
+        assert_eq!(t("it's")?, r#"<div title="it&#39;s"></div>"#);
+        assert_eq!(t(r#""it's""#)?, r#"<div title="&quot;it&#39;s&quot;"></div>"#);
+        Ok(())
+    }
+
+    #[test]
+    fn t_fragment() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let items = html.fragment([html.li([], [html.str("a")?])?,
+                                   html.li([], [html.str("b & c")?])?])?;
+        let ul = html.ul([], [items, html.li([], [html.str("d")?])?])?;
+        assert_eq!(ul.to_html_fragment_string(&html)?,
+                   "<ul><li>a</li><li>b &amp; c</li><li>d</li></ul>");
+        assert_eq!(html.to_plain_string(ul)?.as_str(), "ab & cd");
+        assert_eq!(html.to_html_string(items, false), "<li>a</li><li>b &amp; c</li>");
+        // Nested fragments
+        let nested = html.fragment([html.fragment([html.str("x")?])?, html.str("y")?])?;
+        assert_eq!(html.to_html_string(nested, false), "xy");
+
+        // Validation looks through the fragment
+        let paras = html.fragment([html.p([], [html.str("a")?])?])?;
+        assert!(html.ul([], [paras]).is_err());
+        let text = html.fragment([html.str("text")?])?;
+        assert!(html.ul([], [text]).is_err());
+        assert!(html.div([], [paras, text]).is_ok());
+
+        // Also when checking afterwards
+        let html = HtmlAllocator::new_with_metadb(1000, None, Arc::new(""));
+        let paras = html.fragment([html.p([], [html.str("a")?])?])?;
+        let ul = html.ul([], [paras])?;
+        assert!(html.validate_tree(ul).is_err());
+        let div = html.div([], [paras])?;
+        assert!(html.validate_tree(div).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn t_drop_whitespace_text() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let ul = html.ul([], [html.str("\n")?,
+                              html.li([], [html.str("a")?])?,
+                              html.str("\n  ")?,
+                              html.li([], [html.str(" ")?])?,
+                              html.str("\n")?])?;
+        let body = html.get_node(ul).unwrap().children().unwrap();
+        assert_eq!(body.len, 2);
+        assert!(body.iter_node(&html).all(|n| matches!(n, Node::Element(_))));
+        assert_eq!(ul.to_html_fragment_string(&html)?, "<ul><li>a</li><li> </li></ul>");
+        // Whitespace in elements allowing text is kept
+        let p = html.p([], [html.str("a")?, html.str(" ")?, html.str("b")?])?;
+        assert_eq!(p.to_html_fragment_string(&html)?, "<p>a b</p>");
+        Ok(())
+    }
+
+    #[test]
+    fn t_pretty() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let div = html.div(
+            [att("class", "c")],
+            [html.p([], [html.str("Some ")?,
+                         html.a([att("href", "/x")], [html.em([], [html.str("linked")?])?])?,
+                         html.str(" text")?])?,
+             html.fragment([html.ul([], [html.li([], [html.str("a")?])?,
+                                          html.li([], [html.p([], [html.str("b")?])?])?])?])?,
+             html.pre([], [html.str("  keep\n    this\n")?])?,
+             html.preserialized(html.preserialize(html.hr([], [])?)?)?])?;
+        assert_eq!(div.to_pretty_html_string(&html, 2)?,
+                   "<div class=\"c\">\n\
+                    \x20 <p>Some <a href=\"/x\"><em>linked</em></a> text</p>\n\
+                    \x20 <ul>\n\
+                    \x20   <li>a</li>\n\
+                    \x20   <li>\n\
+                    \x20     <p>b</p>\n\
+                    \x20   </li>\n\
+                    \x20 </ul>\n\
+                    \x20 <pre>  keep\n    this\n</pre>\n\
+                    \x20 <hr>\n\
+                    </div>\n");
+        // The same, unindented, as normal output without the newlines
+        assert_eq!(div.to_pretty_html_string(&html, 0)?.replace("\n", ""),
+                   div.to_html_fragment_string(&html)?.replace("\n", ""));
+        // Inline elements at the top level stay on one line
+        let body = html.fragment([html.str("a ")?, html.span([], [html.str("b")?])?])?;
+        assert_eq!(body.to_pretty_html_string(&html, 4)?, "a <span>b</span>\n");
+        Ok(())
+    }
+}
+
+
 // fn p_ab(attr: &[(KString, KString)], body: &[Node]) -> Element {
     // Element {
     //     meta: &P_META,
@@ -49,11 +49,13 @@ fn t_usize_range_len() {
 }
 #[test]
 #[should_panic]
+#[allow(clippy::reversed_empty_ranges)]
 fn t_usize_range_len_invalid_start() {
     usize_range_len(&identity(5..4), 2);
 }
 #[test]
 #[should_panic]
+#[allow(clippy::reversed_empty_ranges)]
 fn t_usize_range_len_invalid_start_2() {
     usize_range_len(&identity(5..=4), 2);
 }
@@ -69,6 +71,7 @@ pub trait MoreVec<T> {
 
     /// Like `extend_from_within` but fails if the extension cannot be
     /// done within capacity.
+    #[allow(clippy::result_unit_err)]
     fn extend_from_within_within_capacity<R>(&mut self, src: R) -> Result<(), ()>
     where
         R: RangeBounds<usize>,
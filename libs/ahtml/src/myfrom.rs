@@ -19,7 +19,7 @@ impl MyFrom<&str> for KString {
 
 impl MyFrom<&&str> for KString {
     fn myfrom(s: &&str) -> Self {
-        KString::from_ref(*s)
+        KString::from_ref(s)
     }
 }
 
@@ -63,6 +63,15 @@ impl MyFrom<usize> for KString {
     }
 }
 
+/// ISO 8601 format (`2023-10-23`), as needed e.g. for the `datetime`
+/// attribute.
+#[cfg(feature = "chrono")]
+impl MyFrom<chrono::NaiveDate> for KString {
+    fn myfrom(date: chrono::NaiveDate) -> Self {
+        KString::from_string(date.to_string())
+    }
+}
+
 // impl<'t> MyFrom<HtmlString> for KString {
 //     fn myfrom(s: HtmlString) -> Self {
 //         let s2 = String::from_utf8(*s)?;
@@ -124,7 +133,7 @@ impl<'s> MyFrom<&'s str> for &'s str {
 
 impl<'s> MyFrom<&&'s str> for &'s str {
     fn myfrom(s: &&'s str) -> Self {
-        *s
+        s
     }
 }
 
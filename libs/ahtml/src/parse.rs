@@ -0,0 +1,211 @@
+//! Turning serialized HTML back into nodes, see
+//! `HtmlAllocator::parse_fragment`.
+
+use anyhow::{Result, anyhow, bail};
+use html5gum::{DefaultEmitter, HtmlString, Token, Tokenizer};
+use kstring::KString;
+
+use ahtml_html::meta::ElementMeta;
+
+use crate::{allocator::AVec, HtmlAllocator, AId, Node, SerHtmlFrag};
+
+/// This can't be replaced with `att` or the MyFrom trait, because it
+/// can fail.
+fn kstring(s: HtmlString) -> Result<KString> {
+    Ok(KString::from_string(String::from_utf8(s.0)?))
+}
+
+/// The tokens of `s`, with the tokenizer switching to raw text for
+/// the contents of `script`, `style` etc. like a browser does.
+pub(crate) fn tokens(s: &str) -> impl Iterator<Item = Token> + '_ {
+    let mut emitter = DefaultEmitter::default();
+    emitter.switch_states(true);
+    Tokenizer::new_with_emitter(s, emitter).infallible()
+}
+
+/// A token of HTML source, with the element name looked up and
+/// attributes and text allocated, see `HtmlAllocator::parse_tokens`.
+pub enum HtmlToken<'a> {
+    StartTag {
+        meta: &'static ElementMeta,
+        attr: AVec<'a, (KString, KString)>,
+        /// Written as `<br/>` or for an element without closing
+        /// tag, i.e. there is no body to collect.
+        self_closing: bool,
+    },
+    /// Also given for elements without closing tag (`</br>`).
+    EndTag(&'static ElementMeta),
+    Text(AId<Node>),
+    Comment,
+    Doctype,
+    /// A syntax error reported by the tokenizer, e.g. `eof-in-comment`
+    /// for a comment that isn't closed in `s`.
+    Error(String),
+}
+
+struct Frame<'a> {
+    meta: &'static ElementMeta,
+    attr: AVec<'a, (KString, KString)>,
+    body: AVec<'a, Node>,
+}
+
+impl HtmlAllocator {
+    /// The tokens of the HTML in `s`, for building nodes from HTML
+    /// that is mixed with other content (otherwise use
+    /// `parse_fragment`). Fails for unknown element names.
+    pub fn parse_tokens<'a: 's, 's>(
+        &'a self,
+        s: &'s str,
+    ) -> impl Iterator<Item = Result<HtmlToken<'a>>> + 's {
+        let name = |s: &HtmlString| -> Result<&'static ElementMeta> {
+            let name = std::str::from_utf8(s)?;
+            self.elementmeta(name).ok_or_else(
+                || anyhow!("not an HTML5 tag name: {name:?}"))
+        };
+        tokens(s).map(move |token| Ok(match token {
+            Token::StartTag(starttag) => {
+                let meta = name(&starttag.name)?;
+                let mut attr = self.new_vec();
+                for (k, v) in starttag.attributes {
+                    attr.push(self.attribute(kstring(k)?, kstring(v)?)?)?;
+                }
+                HtmlToken::StartTag {
+                    meta,
+                    attr,
+                    self_closing: starttag.self_closing || ! meta.has_closing_tag,
+                }
+            }
+            Token::EndTag(endtag) => HtmlToken::EndTag(name(&endtag.name)?),
+            Token::String(s) => HtmlToken::Text(self.kstring(kstring(s)?)?),
+            Token::Comment(_) => HtmlToken::Comment,
+            Token::Doctype(_) => HtmlToken::Doctype,
+            Token::Error(e) => HtmlToken::Error(e.to_string()),
+        }))
+    }
+
+    /// Parse the HTML in `ser` back into nodes. If it doesn't consist
+    /// of exactly one element of the kind given by `ser.meta()` (as
+    /// can be the case for `SerHtmlFrag::new_unchecked`), the parsed
+    /// nodes are wrapped in such an element, so that the result is
+    /// always an element with that `meta`. Elements left open at the
+    /// end are closed; comments are dropped.
+    pub fn parse_fragment(&self, ser: &SerHtmlFrag) -> Result<AId<Node>> {
+        let close = |frame: Frame| self.new_element(
+            frame.meta, frame.attr.as_slice(), frame.body.as_slice());
+        let mut open: Vec<Frame> = Vec::new();
+        let mut top = self.new_vec();
+        for token in self.parse_tokens(ser.as_str()) {
+            match token? {
+                HtmlToken::StartTag { meta, attr, self_closing } => {
+                    let frame = Frame { meta, attr, body: self.new_vec() };
+                    if self_closing {
+                        let element = close(frame)?;
+                        open.last_mut().map_or(&mut top, |f| &mut f.body).push(element)?;
+                    } else {
+                        open.push(frame);
+                    }
+                }
+                HtmlToken::EndTag(meta) => {
+                    if meta.has_closing_tag {
+                        let frame = open.pop().ok_or_else(
+                            || anyhow!("closing tag without opening tag: </{}>",
+                                       meta.tag_name))?;
+                        if frame.meta != meta {
+                            bail!("closing tag </{}> does not match opening tag <{}>",
+                                  meta.tag_name, frame.meta.tag_name)
+                        }
+                        let element = close(frame)?;
+                        open.last_mut().map_or(&mut top, |f| &mut f.body).push(element)?;
+                    }
+                }
+                HtmlToken::Text(node) => {
+                    open.last_mut().map_or(&mut top, |f| &mut f.body).push(node)?;
+                }
+                HtmlToken::Comment => (),
+                HtmlToken::Doctype => bail!("unexpected doctype in HTML fragment"),
+                HtmlToken::Error(e) => bail!("HTML5 parsing error: {e} for {:?}", ser.as_str()),
+            }
+        }
+        while let Some(frame) = open.pop() {
+            let element = close(frame)?;
+            open.last_mut().map_or(&mut top, |f| &mut f.body).push(element)?;
+        }
+        let top = top.as_slice();
+        if top.len() == 1 {
+            let id = top.iter_aid(self).next().expect("len is 1");
+            if let Node::Element(e) = self.get_node(id).expect("valid id") {
+                if e.meta == ser.meta {
+                    return Ok(id)
+                }
+            }
+        }
+        self.new_element(ser.meta, self.empty_slice(), top)
+    }
+}
+
+/// Append the text in the HTML `s` to `out`, i.e. `print_plain`
+/// without building nodes first. The contents of `script` and
+/// `style` elements are not text and are skipped.
+pub(crate) fn print_plain_serialized(s: &str, out: &mut String) -> Result<()> {
+    let is_raw = |name: &[u8]| name.eq_ignore_ascii_case(b"script")
+        || name.eq_ignore_ascii_case(b"style");
+    let mut in_raw = false;
+    for token in tokens(s) {
+        match token {
+            Token::StartTag(starttag) =>
+                in_raw = is_raw(&starttag.name) && ! starttag.self_closing,
+            Token::EndTag(endtag) if is_raw(&endtag.name) => in_raw = false,
+            Token::String(s) if ! in_raw => out.push_str(std::str::from_utf8(&s)?),
+            Token::Error(e) => bail!("HTML5 parsing error: {e} for {s:?}"),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{att, DIV_META, P_META, SPAN_META};
+
+    use super::*;
+
+    #[test]
+    fn t_parse_fragment() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new("t_parse_fragment"));
+        let p = html.p([att("class", "a b")],
+                       [html.str("1 < 2 & ")?,
+                        html.em([], [html.str("\"so\"")?])?,
+                        html.br([], [])?,
+                        html.str("x")?])?;
+        let ser = html.preserialize(p)?;
+        let parsed = html.parse_fragment(&ser)?;
+        assert!(html.get_node(parsed).expect("valid").as_element().expect("element").meta
+                == *P_META);
+        assert_eq!(html.to_html_string(parsed, false), ser.as_str());
+
+        // Unchecked fragments get wrapped
+        let ser = SerHtmlFrag::new_unchecked(*DIV_META, "<span>a</span> <!-- c -->b");
+        let parsed = html.parse_fragment(&ser)?;
+        assert_eq!(html.to_html_string(parsed, false), "<div><span>a</span> b</div>");
+        let ser = SerHtmlFrag::new_unchecked(*SPAN_META, "<span>a");
+        assert_eq!(html.to_html_string(html.parse_fragment(&ser)?, false), "<span>a</span>");
+        assert!(html.parse_fragment(&SerHtmlFrag::new_unchecked(*DIV_META, "a</b>")).is_err());
+
+        // Plain text
+        let div = html.div([], [html.str("T: ")?,
+                                html.preserialized(html.preserialize(p)?)?])?;
+        assert_eq!(html.to_plain_string(div)?, "T: 1 < 2 & \"so\"x");
+        assert_eq!(html.to_plain_string(html.to_plain_string_aid(
+            html.preserialized(SerHtmlFrag::new_unchecked(*DIV_META, "<b>x</b>y"))?)?)?,
+                   "xy");
+        assert_eq!(html.to_plain_string(html.to_plain_string_aid(
+            html.preserialized(SerHtmlFrag::new_unchecked(
+                *DIV_META,
+                "a<script>if (1 < 2) {}</script>b<style>p { x: 1 }</style>c"))?)?)?,
+                   "abc");
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+//! Serializing a DOM tree lazily, as the bytes are read, see
+//! `HtmlReader`.
+
+use std::{io::{self, Read}, ops::Deref};
+
+use ahtml_html::meta::ElementMeta;
+
+use crate::{HtmlAllocator, AId, ASlice, Node, Print, BOM, DOCTYPE};
+
+/// Serialize in pieces of about this many bytes.
+const CHUNK_SIZE: usize = 8192;
+
+enum Step {
+    Node(AId<Node>),
+    /// The nodes of the slice from index `pos` on.
+    Children(ASlice<Node>, u32),
+    EndTag(&'static ElementMeta),
+}
+
+/// A `Read` giving the same bytes as
+/// `HtmlAllocator::print_html_document` (if `want_doctype` is true)
+/// or `print_html_fragment`, but produced as they are read, instead
+/// of holding the whole serialization in memory. It owns the
+/// allocator handle (e.g. a `HtmlAllocatorGuard` or `Box`), so that
+/// it can be handed out as a response body; the allocator is
+/// released when the reader is dropped.
+pub struct HtmlReader<A: Deref<Target = HtmlAllocator>> {
+    allocator: A,
+    /// Remaining work, the next step at the end.
+    todo: Vec<Step>,
+    buf: Vec<u8>,
+    /// How much of `buf` has been read.
+    pos: usize,
+}
+
+impl<A: Deref<Target = HtmlAllocator>> HtmlReader<A> {
+    pub fn new(allocator: A, id: AId<Node>, want_doctype: bool) -> Self {
+        let mut buf = Vec::new();
+        if want_doctype {
+            buf.extend_from_slice(BOM.as_bytes());
+            buf.extend_from_slice(DOCTYPE.as_bytes());
+        }
+        HtmlReader { allocator, todo: vec![Step::Node(id)], buf, pos: 0 }
+    }
+
+    /// Serialize the next piece into `buf`, returns false if there
+    /// was nothing left.
+    fn step(&mut self) -> anyhow::Result<bool> {
+        let html = &*self.allocator;
+        let Some(step) = self.todo.pop() else {
+            return Ok(false)
+        };
+        match step {
+            Step::Node(id) => {
+                let node = html.get_node(id).expect("id should resolve");
+                match node {
+                    Node::Element(e) => {
+                        e.print_start_tag(&mut self.buf, html)?;
+                        self.todo.push(Step::EndTag(e.meta));
+                        self.todo.push(Step::Children(e.body, 0));
+                    }
+                    Node::Fragment(body) => self.todo.push(Step::Children(*body, 0)),
+                    _ => node.print_html_fragment(&mut self.buf, html)?,
+                }
+            }
+            Step::Children(slice, pos) => {
+                if pos < slice.len {
+                    self.todo.push(Step::Children(slice, pos + 1));
+                    let id = html.get_id(slice.start + pos).expect(
+                        "slice should always point to allocated storage");
+                    self.todo.push(Step::Node(id));
+                }
+            }
+            Step::EndTag(meta) => {
+                if meta.has_closing_tag {
+                    self.buf.extend_from_slice(b"</");
+                    self.buf.extend_from_slice(meta.tag_name.as_bytes());
+                    self.buf.extend_from_slice(b">");
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<A: Deref<Target = HtmlAllocator>> Read for HtmlReader<A> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buf.len() {
+            self.buf.clear();
+            self.pos = 0;
+            while self.buf.len() < CHUNK_SIZE && self.step().map_err(io::Error::other)? {}
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+
+    use crate::{att, HtmlAllocatorPool, TryCollectBody};
+
+    use super::*;
+
+    #[test]
+    fn t_html_reader() -> Result<()> {
+        let pool = HtmlAllocatorPool::new(100000, true, Arc::new("t_html_reader"));
+        let html = pool.get();
+        let items = (0..2000).map(
+            |i| html.li([att("class", "x")], [html.string(format!("item {i} <&>"))?]))
+            .try_collect_body(&html)?;
+        let page = html.html(
+            [],
+            [html.head([], [html.title([], [html.str("T")?])?])?,
+             html.body([], [html.fragment([html.ul([], items)?,
+                                           html.hr([], [])?])?,
+                            html.preserialized(html.preserialize(
+                                html.p([], [html.str("pre")?])?)?)?])?])?;
+        let expected = html.to_html_string(page, true);
+        assert!(expected.len() > 3 * CHUNK_SIZE);
+        let fragment = html.to_html_string(page, false);
+
+        let mut s = String::new();
+        HtmlReader::new(&*html, page, false).read_to_string(&mut s)?;
+        assert_eq!(s, fragment);
+
+        // In small reads, and owning the allocator
+        let mut reader = HtmlReader::new(html, page, true);
+        let mut bytes = Vec::new();
+        let mut buf = [0; 7];
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break
+            }
+            bytes.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(String::from_utf8(bytes)?, expected);
+        Ok(())
+    }
+}
@@ -23,6 +23,10 @@ impl<T> StillVec<T> {
         unsafe {&*p}.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn capacity(&self) -> usize {
         let p = self.0.get();
         // Safe because StillVec since there is no API for mutating
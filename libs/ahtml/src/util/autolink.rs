@@ -4,7 +4,7 @@ use anyhow::Result;
 use chj_util::nowarn as warn;
 
 fn find_from(s: &str, pos: usize, needle: &str) -> Option<usize> {
-    (&s[pos..]).find(needle).map(|p| p + pos)
+    s[pos..].find(needle).map(|p| p + pos)
 }
 
 // Characters that are OK in http/https URLs *for auto-detection
@@ -46,14 +46,14 @@ pub fn autolink(html: &HtmlAllocator, text: &str) -> Result<ASlice<Node>> {
         warn!("found pos={pos}");
         let pos_rest = pos + 4; // after the 'http'
 
-        let mut backwardsiter = (&text[0..pos]).chars().rev();
+        let mut backwardsiter = text[0..pos].chars().rev();
         if !is_character_in_front(backwardsiter.next()) {
             warn!("nope 0");
             pos_remainder = pos_rest;
             continue;
         }
 
-        let mut restiter = (&text[pos_rest..]).chars();
+        let mut restiter = text[pos_rest..].chars();
         let c0 = restiter.next();
         let c1 = restiter.next();
         let skip_len = match c0 {
@@ -73,16 +73,16 @@ pub fn autolink(html: &HtmlAllocator, text: &str) -> Result<ASlice<Node>> {
             }
         };
         let pos_rest = pos_rest + skip_len;
-        if !(&text[pos_rest..]).starts_with("//") {
+        if !text[pos_rest..].starts_with("//") {
             warn!("nope: no // in {:?}", &text[pos_rest..]);
             pos_remainder = pos_rest;
             continue;
         }
         let pos_rest = pos_rest + 2;
         let (one_before_end, end); // in text
-        'find_end: loop { // loop bc labels on blocks are unstable
+        'find_end: {
             let mut last_i = 0;
-            for (i, c) in (&text[pos_rest..]).char_indices() {
+            for (i, c) in text[pos_rest..].char_indices() {
                 if !is_url_character(c) {
                     one_before_end = pos_rest + last_i;
                     end = pos_rest + i;
@@ -92,7 +92,6 @@ pub fn autolink(html: &HtmlAllocator, text: &str) -> Result<ASlice<Node>> {
             }
             one_before_end = pos_rest + last_i;
             end = text.len();
-            break;
         }
 
         if one_before_end == end {
@@ -113,9 +112,9 @@ pub fn autolink(html: &HtmlAllocator, text: &str) -> Result<ASlice<Node>> {
         let url = &text[pos..real_end];
 
         if pos - pos_done > 0 {
-            nodes.push(html.text(&text[pos_done..pos])?)?;
+            nodes.push(html.str(&text[pos_done..pos])?)?;
         }
-        let link = html.a([att("href", url)], [html.text(url)?])?;
+        let link = html.a([att("href", url)], [html.str(url)?])?;
         nodes.push(link)?;
         warn!("pushed node: {}", html.to_html_string(link, false));
 
@@ -124,7 +123,7 @@ pub fn autolink(html: &HtmlAllocator, text: &str) -> Result<ASlice<Node>> {
     }
 
     if pos_done < text.len() {
-        nodes.push(html.text(&text[pos_done..])?)?;
+        nodes.push(html.str(&text[pos_done..])?)?;
     }
 
     Ok(nodes.as_slice())
@@ -37,7 +37,7 @@ impl SoftPre {
                 if self.autolink {
                     autolink(html, line)?
                 } else {
-                    html.text(line)?.to_aslice(html)?
+                    html.str(line)?.to_aslice(html)?
                 };
 
             if let Some(n) = self.tabs_to_nbsp {
@@ -0,0 +1,212 @@
+//! Validation of a fully assembled DOM against the meta database,
+//! including the contents of preserialized fragments (which
+//! `new_element` only checks at their top level).
+
+use ahtml_html::meta::{MetaDb, ElementMeta};
+use anyhow::{Result, anyhow, bail};
+
+use html5gum::Token;
+
+use crate::{HtmlAllocator, AId, Node, METADB, parse::tokens};
+
+/// The element being checked, and the number of content values seen
+/// in it so far.
+struct Frame {
+    meta: &'static ElementMeta,
+    permissive: bool,
+    num_children: usize,
+}
+
+fn path_string(stack: &[Frame]) -> String {
+    let names: Vec<&str> = stack.iter().map(|f| f.meta.tag_name.as_str()).collect();
+    names.join(" > ")
+}
+
+struct Validator<'a> {
+    html: &'a HtmlAllocator,
+    metadb: &'static MetaDb,
+    stack: Vec<Frame>,
+}
+
+impl<'a> Validator<'a> {
+    /// Check an element with the given meta and attribute names
+    /// against the element on top of the stack, and enter it.
+    fn enter<'n>(
+        &mut self,
+        meta: &'static ElementMeta,
+        attribute_names: impl Iterator<Item = &'n str>
+    ) -> Result<()> {
+        if let Some(parent) = self.stack.last_mut() {
+            let i = parent.num_children;
+            parent.num_children += 1;
+            if ! parent.permissive {
+                self.html.verify_child_element(parent.meta, i, meta)?;
+            }
+        }
+        let permissive = self.html.is_permissive_element(meta);
+        self.stack.push(Frame { meta, permissive, num_children: 0 });
+        if ! permissive {
+            for (i, name) in attribute_names.enumerate() {
+                self.html.verify_attribute(self.metadb, meta, i, name)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.stack.pop();
+    }
+
+    fn text(&mut self, s: &str) -> Result<()> {
+        if let Some(parent) = self.stack.last_mut() {
+            let i = parent.num_children;
+            parent.num_children += 1;
+            if ! parent.permissive {
+                self.html.verify_child_text(parent.meta, i, s)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn node(&mut self, id: AId<Node>) -> Result<()> {
+        let noderef = self.html.get_node(id).expect("id should resolve: {id:?}");
+        match noderef {
+            Node::Element(e) => {
+                self.enter(e.meta,
+                           e.attr.iter_att(self.html).map(|(k, _v)| k.as_str()))?;
+                for child in e.body.iter_aid(self.html) {
+                    self.node(child)?;
+                }
+                self.leave();
+            }
+            Node::String(s) => self.text(s)?,
+            Node::Preserialized(ser) => {
+                let depth = self.stack.len();
+                self.fragment(ser.as_str())?;
+                if self.stack.len() != depth {
+                    bail!("unclosed element {:?} in preserialized fragment",
+                          self.stack.last().expect("deeper than before").meta
+                          .tag_name.as_str())
+                }
+            }
+            Node::Fragment(body) => {
+                for child in body.iter_aid(self.html) {
+                    self.node(child)?;
+                }
+            }
+            Node::None => (),
+        }
+        Ok(())
+    }
+
+    /// Re-parse serialized HTML (using the same tokenizer as
+    /// `HtmlAllocator::parse_fragment`), and check it.
+    fn fragment(&mut self, s: &str) -> Result<()> {
+        let depth = self.stack.len();
+        for token in tokens(s) {
+            match token {
+                Token::StartTag(starttag) => {
+                    let name = std::str::from_utf8(&starttag.name)?;
+                    let meta = self.html.elementmeta(name).ok_or_else(
+                        || anyhow!("unknown element {name:?} in preserialized fragment"))?;
+                    let names = starttag.attributes.keys().map(
+                        |k| std::str::from_utf8(k)).collect::<Result<Vec<_>, _>>()?;
+                    self.enter(meta, names.into_iter())?;
+                    if starttag.self_closing || ! meta.has_closing_tag {
+                        self.leave();
+                    }
+                }
+                Token::EndTag(endtag) => {
+                    let name = std::str::from_utf8(&endtag.name)?;
+                    if self.stack.len() <= depth {
+                        bail!("end tag {name:?} without start tag in preserialized fragment")
+                    }
+                    let open = self.stack.last().expect("checked above").meta.tag_name.as_str();
+                    if name != open {
+                        bail!("end tag {name:?} does not match open element {open:?} \
+                               in preserialized fragment")
+                    }
+                    self.leave();
+                }
+                Token::String(text) => self.text(std::str::from_utf8(&text)?)?,
+                Token::Comment(_) => (),
+                Token::Doctype(_) => bail!("unexpected doctype in preserialized fragment"),
+                Token::Error(e) => bail!("HTML5 parsing error in preserialized fragment: {e}"),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl HtmlAllocator {
+    /// Walk the DOM at `root` and check all elements against the meta
+    /// database, like `new_element` does while building, but also
+    /// inside preserialized fragments, which are re-parsed for this
+    /// purpose. This catches e.g. an `<li>` inside a fragment that
+    /// ended up outside of a list. Checks even if this allocator was
+    /// created without verification. Meant for debugging and tests,
+    /// it's not fast.
+    pub fn validate_tree(&self, root: AId<Node>) -> Result<()> {
+        let mut validator = Validator {
+            html: self,
+            metadb: self.metadb.unwrap_or(&*METADB),
+            stack: Vec::new(),
+        };
+        validator.node(root).map_err(|e| {
+            let path = path_string(&validator.stack);
+            e.context(format!("validate_tree: in {path:?}"))
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{att, AttributeQuoting, Print, SerHtmlFrag, P_META};
+
+    use super::*;
+
+    #[test]
+    fn t_validate_tree() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let unverified = HtmlAllocator::new_with_metadb(1000, None, Arc::new(""));
+
+        let list = html.preserialize(
+            html.ul([att("class", "x")],
+                    [html.li([], [html.str("a < b")?])?,
+                     html.li([], [html.a([att("href", "/?a&b")], [html.str("b")?])?])?])?)?;
+        let page = html.div([], [html.p([], [html.str("hi")?, html.br([], [])?])?,
+                                 html.preserialized(list)?])?;
+        html.validate_tree(page)?;
+
+        // Minimal quoting, and `>` in attribute values
+        let quoting = HtmlAllocator::new(1000, Arc::new(""));
+        quoting.set_attribute_quoting(AttributeQuoting::Minimal);
+        let p = quoting.preserialize(
+            quoting.p([att("class", "x"), att("title", "a > b")], [quoting.str("a")?])?)?;
+        assert_eq!(p.as_str(), "<p class=x title=\"a &gt; b\">a</p>");
+        html.validate_tree(html.div([], [html.preserialized(p)?])?)?;
+        let p = SerHtmlFrag::new_unchecked(*P_META, "<p title='a>b' class=x>a</p>");
+        html.validate_tree(html.div([], [html.preserialized(p)?])?)?;
+
+        // Each part is fine on its own, but the fragment's `li` ends
+        // up in a `div`, only caught by re-parsing the fragment.
+        let item = unverified.preserialize(
+            unverified.div([], [unverified.li([], [unverified.str("a")?])?])?)?;
+        let page = html.div([], [html.p([], [html.str("hi")?])?,
+                                 html.preserialized(item)?])?;
+        assert_eq!(page.to_html_fragment_string(&html)?,
+                   "<div><p>hi</p><div><li>a</li></div></div>");
+        let e = html.validate_tree(page).expect_err("invalid");
+        let msg = format!("{e:#}");
+        assert!(msg.starts_with(r#"validate_tree: in "div > div": content value #0: element "li" not allowed as a child of element "div""#),
+                "{msg}");
+
+        // Unverified allocators are checked, too
+        let bad = unverified.ul([], [unverified.p([], [])?])?;
+        assert!(unverified.validate_tree(bad).is_err());
+        Ok(())
+    }
+}
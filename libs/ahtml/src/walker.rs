@@ -0,0 +1,177 @@
+//! Traversal of a DOM tree without having to pass the allocator
+//! around, see `NodeWalker`.
+
+use std::collections::VecDeque;
+
+use ahtml_html::meta::ElementMeta;
+
+use crate::{HtmlAllocator, AId, Node};
+
+/// Walks the tree below a root node, in depth-first (document) or
+/// breadth-first order. The traversals are iterators yielding `(id,
+/// node, depth)` (with depth 0 for the root), use their `filter`,
+/// `map` etc. methods to select what's of interest. Element bodies
+/// and fragments are descended into; preserialized nodes are not
+/// parsed, i.e. they are leaves.
+#[derive(Clone, Copy)]
+pub struct NodeWalker<'a> {
+    html: &'a HtmlAllocator,
+}
+
+impl<'a> NodeWalker<'a> {
+    pub fn new(html: &'a HtmlAllocator) -> Self {
+        NodeWalker { html }
+    }
+
+    /// Pre-order depth-first traversal, i.e. in the order the nodes
+    /// appear in the serialized HTML.
+    pub fn dfs(&self, root: AId<Node>) -> Dfs<'a> {
+        Dfs { html: self.html, stack: vec![(root, 0)] }
+    }
+
+    /// Breadth-first traversal, i.e. all nodes of one depth before
+    /// the ones of the next.
+    pub fn bfs(&self, root: AId<Node>) -> Bfs<'a> {
+        Bfs { html: self.html, queue: VecDeque::from([(root, 0)]) }
+    }
+}
+
+impl HtmlAllocator {
+    /// The ids of `root` and all nodes below it, in document order
+    /// (like `NodeWalker::dfs`, without the nodes and depths).
+    pub fn iter_descendants(&self, root: AId<Node>) -> impl Iterator<Item = AId<Node>> + '_ {
+        NodeWalker::new(self).dfs(root).map(|(id, _, _)| id)
+    }
+
+    /// The elements of kind `meta` in `root` (including `root`
+    /// itself), in document order.
+    pub fn find_elements(&self, root: AId<Node>, meta: &'static ElementMeta) -> Vec<AId<Node>> {
+        NodeWalker::new(self).dfs(root).filter_map(
+            |(id, node, _)| (node.as_element()?.meta == meta).then_some(id)).collect()
+    }
+}
+
+fn get_node(html: &HtmlAllocator, id: AId<Node>) -> &Node {
+    html.get_node(id).expect("invalid id leads to panic in get_node")
+}
+
+pub struct Dfs<'a> {
+    html: &'a HtmlAllocator,
+    stack: Vec<(AId<Node>, usize)>,
+}
+
+impl<'a> Iterator for Dfs<'a> {
+    type Item = (AId<Node>, &'a Node, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (id, depth) = self.stack.pop()?;
+        let node = get_node(self.html, id);
+        if let Some(children) = node.children() {
+            let len = self.stack.len();
+            self.stack.extend(children.iter_aid(self.html).map(|child| (child, depth + 1)));
+            self.stack[len..].reverse();
+        }
+        Some((id, node, depth))
+    }
+}
+
+pub struct Bfs<'a> {
+    html: &'a HtmlAllocator,
+    queue: VecDeque<(AId<Node>, usize)>,
+}
+
+impl<'a> Iterator for Bfs<'a> {
+    type Item = (AId<Node>, &'a Node, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (id, depth) = self.queue.pop_front()?;
+        let node = get_node(self.html, id);
+        if let Some(children) = node.children() {
+            self.queue.extend(children.iter_aid(self.html).map(|child| (child, depth + 1)));
+        }
+        Some((id, node, depth))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+
+    use crate::{att, H2_META, DIV_META, IMG_META};
+
+    use super::*;
+
+    /// Tag name or text of the node.
+    fn name(node: &Node) -> &str {
+        match node {
+            Node::Element(e) => e.meta.tag_name.as_str(),
+            Node::String(s) => s.as_str(),
+            _ => "?",
+        }
+    }
+
+    #[test]
+    fn t_walk() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let root = html.div([], [
+            html.p([], [html.str("a")?, html.em([], [html.str("b")?])?])?,
+            html.ul([], [html.li([], [html.str("c")?])?])?,
+        ])?;
+        let walker = NodeWalker::new(&html);
+
+        let dfs: Vec<_> = walker.dfs(root).map(|(_, node, depth)| (name(node), depth)).collect();
+        assert_eq!(dfs, [("div", 0), ("p", 1), ("a", 2), ("em", 2), ("b", 3),
+                         ("ul", 1), ("li", 2), ("c", 3)]);
+
+        let bfs: Vec<_> = walker.bfs(root).map(|(_, node, depth)| (name(node), depth)).collect();
+        assert_eq!(bfs, [("div", 0), ("p", 1), ("ul", 1), ("a", 2), ("em", 2), ("li", 2),
+                         ("b", 3), ("c", 3)]);
+
+        // With combinators; the ids can be used as usual
+        let texts: Vec<_> = walker.dfs(root)
+            .filter(|(_, node, depth)| *depth == 3 && node.as_element().is_none())
+            .map(|(id, _, _)| html.to_plain_string(id).map(|s| s.to_string()))
+            .collect::<Result<_>>()?;
+        assert_eq!(texts, ["b", "c"]);
+        Ok(())
+    }
+
+    #[test]
+    fn t_iter_descendants() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let ser = html.preserialize(html.p([], [html.a([att("href", "/c")], [])?])?)?;
+        let root = html.div([], [
+            html.a([att("href", "/a")], [html.str("a")?])?,
+            html.fragment([html.p([], [html.a([att("href", "/b")], [])?])?])?,
+            html.preserialized(ser)?,
+        ])?;
+        // Preserialized HTML is not looked into
+        let hrefs: Vec<_> = html.iter_descendants(root).filter_map(
+            |id| html.get_node(id)?.as_element()?.get_attribute("href", &html))
+            .collect();
+        assert_eq!(hrefs, ["/a", "/b"]);
+        assert_eq!(html.iter_descendants(root).count(), 7);
+        Ok(())
+    }
+
+    #[test]
+    fn t_find_elements() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let root = html.div([], [
+            html.h2([], [html.str("A")?])?,
+            html.div([], [html.p([], [html.str("x")?])?,
+                          html.div([], [html.h2([], [html.str("B")?])?])?,
+                          html.h3([], [html.str("not")?])?])?,
+            html.h2([], [html.str("C")?])?,
+        ])?;
+        let headings = html.find_elements(root, *H2_META).into_iter().map(
+            |id| html.to_plain_string(id).map(|s| s.to_string())).collect::<Result<Vec<_>>>()?;
+        assert_eq!(headings, ["A", "B", "C"]);
+        assert_eq!(html.find_elements(root, *DIV_META).len(), 3);
+        assert!(html.find_elements(root, *IMG_META).is_empty());
+        Ok(())
+    }
+}
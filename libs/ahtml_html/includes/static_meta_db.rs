@@ -15106,6 +15106,7 @@ const STATIC_META_DB: StaticMetaDb = StaticMetaDb {
     allows_child_text: false
 ,
     child_elements: StaticSet(&[
+"img",
 "script",
 "source",
 "template"])
@@ -18171,13 +18172,33 @@ const STATIC_META_DB: StaticMetaDb = StaticMetaDb {
     has_closing_tag: false
 ,
     attributes: StaticMap(&[
+("height", StaticAttribute {
+    description: "Vertical dimension",
+    ty: StaticAttributeType::KString}
+),
 ("media", StaticAttribute {
     description: "Applicable media",
     ty: StaticAttributeType::KString}
 ),
+("sizes", StaticAttribute {
+    description: "Image sizes for different page layouts",
+    ty: StaticAttributeType::KString}
+),
+("src", StaticAttribute {
+    description: "Address of the resource",
+    ty: StaticAttributeType::KString}
+),
+("srcset", StaticAttribute {
+    description: "Images to use in different situations, e.g., high-resolution displays, small monitors, etc.",
+    ty: StaticAttributeType::KString}
+),
 ("type", StaticAttribute {
     description: "Type of embedded resource",
     ty: StaticAttributeType::KString}
+),
+("width", StaticAttribute {
+    description: "Horizontal dimension",
+    ty: StaticAttributeType::KString}
 )])
 ,
     allows_child_text: false
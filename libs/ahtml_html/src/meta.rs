@@ -21,7 +21,7 @@ const GLOBAL_ATTRIBUTE_NAMES: &[&str] = &[
     "class", 
     "contenteditable", 
     // "contextmenu", //  "Deprecated", 
-    // "data-*", XX  https://developer.mozilla.org/en-US/docs/Web/HTML/Global_attributes/data-*
+    // "data-*", see GLOBAL_ATTRIBUTE_PREFIXES
     "dir", 
     "draggable", 
     "enterkeyhint", 
@@ -49,10 +49,17 @@ const GLOBAL_ATTRIBUTE_NAMES: &[&str] = &[
     "translate", 
     "virtualkeyboardpolicy", 
     // The ARIA role attribute and the multiple aria-* states and
-    // properties, used for ensuring accessibility.
+    // properties (see GLOBAL_ATTRIBUTE_PREFIXES), used for ensuring
+    // accessibility.
     "role", 
     ];
 
+/// Attribute names starting with these (and having something after
+/// them) are global attributes, too: `data-*`
+/// (https://developer.mozilla.org/en-US/docs/Web/HTML/Global_attributes/data-*)
+/// and the ARIA states and properties.
+pub const GLOBAL_ATTRIBUTE_PREFIXES: &[&str] = &["data-", "aria-"];
+
 const EVENT_HANDLER_ATTRIBUTE_NAMES: &[&str] = &[
     "onabort", "onautocomplete", "onautocompleteerror", "onblur", "oncancel", "oncanplay", "oncanplaythrough", "onchange", "onclick", "onclose", "oncontextmenu", "oncuechange", "ondblclick", "ondrag", "ondragend", "ondragenter", "ondragleave", "ondragover", "ondragstart", "ondrop", "ondurationchange", "onemptied", "onended", "onerror", "onfocus", "oninput", "oninvalid", "onkeydown", "onkeypress", "onkeyup", "onload", "onloadeddata", "onloadedmetadata", "onloadstart", "onmousedown", "onmouseenter", "onmouseleave", "onmousemove", "onmouseout", "onmouseover", "onmouseup", "onmousewheel", "onpause", "onplay", "onplaying", "onprogress", "onratechange", "onreset", "onresize", "onscroll", "onseeked", "onseeking", "onselect", "onshow", "onsort", "onstalled", "onsubmit", "onsuspend", "ontimeupdate", "ontoggle", "onvolumechange", "onwaiting"
 ];
@@ -329,6 +336,15 @@ pub struct MetaDb {
     pub elementmeta: HashMap<KString, ElementMeta>,
 }
 
+impl MetaDb {
+    /// Whether the attribute `name` is allowed on all elements.
+    pub fn is_global_attribute(&self, name: &str) -> bool {
+        self.global_attribute_names.contains(name)
+            || GLOBAL_ATTRIBUTE_PREFIXES.iter().any(
+                |prefix| name.len() > prefix.len() && name.starts_with(prefix))
+    }
+}
+
 struct StaticMetaDb<'t> {
     pub global_attribute_names: StaticSet<'t, &'t str>,
     pub elementmeta: StaticMap<'t, &'t str, StaticElementMeta<'t>>,
@@ -481,11 +497,22 @@ fn get_env_bool(varname: &str) -> Result<bool> {
 
 include!("../includes/static_meta_db.rs");
 
+/// Read the meta db from the directory given in the
+/// `HTML_READ_META_DB_FROM_JSON_DIR` env var if set, otherwise use the
+/// static one compiled in.
 pub fn read_meta_db() -> Result<MetaDb> {
+    let dir = opt_get_env::<String>("HTML_READ_META_DB_FROM_JSON_DIR")?;
+    read_meta_db_with_json_dir(dir.as_deref().map(Path::new))
+}
+
+/// `read_meta_db` with the JSON directory passed explicitly instead
+/// of via the env var (None for the static meta db).
+pub fn read_meta_db_with_json_dir(dir: Option<&Path>) -> Result<MetaDb> {
     let debug = get_env_bool("HTML_META_DEBUG")?;
-    if let Some(dir) = opt_get_env::<String>("HTML_READ_META_DB_FROM_JSON_DIR")? {
+    if let Some(dir) = dir {
         if debug { eprintln!("reading meta db from json") };
-        let metadb = read_meta_db_from_json(dir.as_ref())?;
+        let metadb = read_meta_db_from_json(dir).with_context(
+            || anyhow!("reading meta db from directory {dir:?}"))?;
         // XX HACK
         if let Some(path) = opt_get_env::<String>("WRITE_STATIC_META_DB_RS_PATH")? {
             if debug { eprintln!("rewriting {path:?} from meta db from json..") };
@@ -16,6 +16,12 @@ thread_local!{
         RefCell::new(DbConnection::mynew(DB.clone()));
 }
 
+/// Make `access_control_transaction` on the current thread use `db`
+/// instead of "accounts.db" from now on (e.g. for tests).
+pub fn set_thread_db(db: Arc<Db>) {
+    DBCONNECTION.with(|b| *b.borrow_mut() = DbConnection::mynew(db));
+}
+
 pub fn access_control_transaction<F, R, E>(
     will_write: bool, f: F
 ) -> Result<R, TransactError<E>>
@@ -8,8 +8,8 @@ use chj_util::{warn_thread, notime};
 
 use crate::{defn_with_statement, get_statement, try_sqlite};
 use super::{transaction::Transaction,
-            types::{User, Group, Count, SessionData, UserId, GroupId, UserOrGroupName},
-            util::{get_unique_by, UniqueError, RequiredUniqueError, required_unique},
+            types::{User, UserInfo, Group, Count, SessionData, UserId, GroupId, UserOrGroupName},
+            util::{get_unique_by, get_all_by, UniqueError, RequiredUniqueError, required_unique},
             sqliteposerror::SQLitePosError};
 
 pub static DO_WARN_THREAD: AtomicBool = AtomicBool::new(false);
@@ -18,6 +18,7 @@ pub static DO_WARN_THREAD: AtomicBool = AtomicBool::new(false);
 pub struct Statements {
     st_select_user_by_id: Option<Result<Statement<'static>, sqlite::Error>>,
     st_select_user_by_username: Option<Result<Statement<'static>, sqlite::Error>>,
+    st_select_users: Option<Result<Statement<'static>, sqlite::Error>>,
     st_select_group_by_groupname: Option<Result<Statement<'static>, sqlite::Error>>,
     st_select_userid_from_username_groupname: Option<Result<Statement<'static>, sqlite::Error>>,
     st_select_groupid_from_userid_groupname: Option<Result<Statement<'static>, sqlite::Error>>,
@@ -70,6 +71,7 @@ impl Drop for ConnectionAndStatements {
         }
         drop!(st_select_user_by_id);
         drop!(st_select_user_by_username);
+        drop!(st_select_users);
         drop!(st_select_group_by_groupname);
         drop!(st_select_userid_from_username_groupname);
         drop!(st_select_groupid_from_userid_groupname);
@@ -97,6 +99,7 @@ impl DbConnection {
                 statements: Statements {
                     st_select_user_by_id: None,
                     st_select_user_by_username: None,
+                    st_select_users: None,
                     st_select_group_by_groupname: None,
                     st_select_userid_from_username_groupname: None,
                     st_select_groupid_from_userid_groupname: None,
@@ -180,6 +183,23 @@ impl<'t> Transaction<'t> {
     }
 }
 
+defn_with_statement!(with_select_users,
+                     st_select_users,
+                     "select id, username, email, name, surname \
+                      from User order by id limit ? offset ?");
+impl<'t> Transaction<'t> {
+    /// Up to `limit` users, skipping the first `offset`, ordered by
+    /// id (e.g. for an admin view), without their password hashes.
+    pub fn all_users(
+        &mut self, limit: i64, offset: i64
+    ) -> Result<Vec<UserInfo>, SQLitePosError>
+    {
+        self.connection_and_statements.with_select_users(|sth| {
+            get_all_by(sth, [limit, offset].as_ref())
+        })
+    }
+}
+
 defn_with_statement!(with_select_group_by_groupname,
                      st_select_group_by_groupname,
                      "select id, groupname \
@@ -427,6 +447,19 @@ impl<'t> Transaction<'t> {
     // provide a `get_sessiondata_by_sessionid`.
 }
 
+impl<'t> Transaction<'t> {
+    /// The user logged in with the session, if any.
+    pub fn get_user_by_sessionid_hash(
+        &mut self, sessionid_hash: &Hash
+    ) -> Result<Option<User>, UniqueError>
+    {
+        match self.get_sessiondata_by_sessionid_hash(sessionid_hash)? {
+            Some(SessionData { user_id: Some(user_id), .. }) => self.get_user_by_id(user_id.0),
+            _ => Ok(None)
+        }
+    }
+}
+
 defn_with_statement!(with_update_sessiondata,
                      st_update_sessiondata,
                      "update \"SessionData\" \
@@ -471,3 +504,49 @@ impl<'t> Transaction<'t> {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use crate::{access_control::transaction::transact, test_util::TempDir};
+
+    use super::*;
+
+    #[test]
+    fn t_all_users() -> Result<()> {
+        let dir = TempDir::new("t_all_users")?;
+        let path = dir.join("accounts.db");
+        sqlite::open(&path)?.execute(include_str!("../../accounts-schema.sql"))?;
+        let mut dbconnection = DbConnection::mynew(
+            Arc::new(Db::new(path.to_str().expect("temp dir is UTF-8"))));
+
+        transact(&mut dbconnection, true, |trans| -> Result<()> {
+            for username in ["alice", "bob", "carol"] {
+                trans.insert_user(&User {
+                    id: None,
+                    username: username.try_into()?,
+                    email: None,
+                    name: username.to_uppercase(),
+                    surname: "".into(),
+                    hashed_pass: format!("secret-{username}"),
+                })?;
+            }
+            Ok(())
+        })?;
+        let mut usernames = |limit, offset| {
+            transact(&mut dbconnection, false, |trans| -> Result<Vec<String>> {
+                Ok(trans.all_users(limit, offset)?.into_iter().map(
+                    |user| user.username.to_string()).collect())
+            })
+        };
+        assert_eq!(usernames(2, 0)?, ["alice", "bob"]);
+        assert_eq!(usernames(2, 2)?, ["carol"]);
+        assert!(usernames(2, 4)?.is_empty());
+
+        let users = transact(&mut dbconnection, false, |trans| trans.all_users(10, 0))?;
+        assert_eq!(users[2].name, "CAROL");
+        assert!(! format!("{users:?}").contains("secret"));
+
+        drop(dbconnection);
+        Ok(())
+    }
+}
@@ -115,7 +115,6 @@ impl TryFrom<&str> for UserOrGroupName {
 pub struct UserId(pub i64);
 newtype_sqlite_copy!(UserId, i64);
 
-#[derive(Debug)]
 pub struct User {
     pub id: Option<UserId>,
     pub username: UserOrGroupName,
@@ -125,6 +124,21 @@ pub struct User {
     pub hashed_pass: String,
 }
 
+// Don't leak the password hash into logs.
+impl Debug for User {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let User { id, username, email, name, surname, hashed_pass: _ } = self;
+        f.debug_struct("User")
+            .field("id", id)
+            .field("username", username)
+            .field("email", email)
+            .field("name", name)
+            .field("surname", surname)
+            .field("hashed_pass", &"<hidden>")
+            .finish()
+    }
+}
+
 impl User {
     pub fn displayname(&self) -> String {
         let mut displayname = format!("{} {}", self.name, self.surname);
@@ -150,6 +164,31 @@ impl FromStatement for User {
         }, sth))
     }
 }
+
+/// A `User` without the password hash, for listings.
+#[derive(Debug)]
+pub struct UserInfo {
+    pub id: UserId,
+    pub username: UserOrGroupName,
+    pub email: Option<String>,
+    pub name: String,
+    pub surname: String,
+}
+
+impl FromStatement for UserInfo {
+    fn from_statement<'s, 'slf>(
+        sth: &'s mut Statement<'slf>
+    ) -> Result<(Self, &'s mut Statement<'slf>), sqlite::Error> {
+        Ok((UserInfo {
+            id: sth.read(0)?,
+            username: sth.read(1)?,
+            email: sth.read(2)?,
+            name: sth.read(3)?,
+            surname: sth.read(4)?,
+        }, sth))
+    }
+}
+
 impl Bindable for &User {
     fn bind(self, st: &mut Statement) -> sqlite::Result<()> {
         // HACK: assume we want to bind it from index 0, and id should
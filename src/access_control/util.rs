@@ -41,6 +41,24 @@ where R: FromStatement,
     }
 }
 
+/// Like `get_unique_by` but for any number of results.
+pub fn get_all_by<'slf, 's, R, A>(
+    sth: &'s mut Statement<'slf>,
+    arguments: A,
+) -> Result<Vec<R>, SQLitePosError>
+where R: FromStatement,
+      A: Bindable + Debug + Copy // references
+{
+    try_sqlite!(sth.reset());
+    try_sqlite!(sth.bind(arguments));
+    let mut results = Vec::new();
+    while let State::Row = try_sqlite!(sth.next()) {
+        let (r, _) = try_sqlite!(R::from_statement(sth));
+        results.push(r);
+    }
+    Ok(results)
+}
+
 
 def_boxed_thiserror!(RequiredUniqueError, pub enum RequiredUniqueErrorKind {
     #[error("retrieving the entry")]
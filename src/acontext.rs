@@ -1,5 +1,5 @@
 use std::{net::{SocketAddr, IpAddr}, io::Write, time::SystemTime,
-          cell::Cell, borrow::Cow, sync::Arc};
+          cell::{Cell, RefCell}, borrow::Cow, sync::Arc};
 
 use anyhow::{Result, anyhow};
 use blake3::Hasher;
@@ -13,7 +13,8 @@ use chj_util::warn;
 use crate::{ppath::PPath,
             http_request_method::HttpRequestMethod,
             rouille_util::{get_cookie, possibly_add_cookie_header, NewCookieValue},
-            language::Language, auri::QueryString, url_encoding::UrlDecodingError};
+            language::Language, auri::{QueryString, has_scheme, resolve_relative_url},
+            url_encoding::UrlDecodingError};
 
 
 pub trait CookieKey {
@@ -99,6 +100,30 @@ pub struct AContext<'r, 's, 'h, L: Language> {
     lang_cookie: Cookie<LangKey>,
     // A `blake3::Hasher` that has already been filled with some secret data.
     sessionid_hasher: &'h Hasher,
+    // Entries for the `Link` response header, see `add_link`.
+    links: RefCell<Vec<String>>,
+    // See `set_base_href`.
+    base_href: RefCell<Option<String>>,
+}
+
+/// Whether `s` can be used unquoted as a parameter value in a `Link`
+/// header (RFC 8288, `token` in RFC 9110).
+fn is_http_token(s: &str) -> bool {
+    ! s.is_empty() && s.chars().all(
+        |c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+}
+
+/// One entry of a `Link` header, like `</a.css>; rel=preload; as=style`.
+pub fn link_header_entry(href: &str, params: &[(&str, &str)]) -> String {
+    let mut s = format!("<{}>", href.replace('>', "%3E"));
+    for (key, value) in params {
+        if is_http_token(value) {
+            s.push_str(&format!("; {key}={value}"));
+        } else {
+            s.push_str(&format!("; {key}={value:?}"));
+        }
+    }
+    s
 }
 
 impl<'r, 's, 'h, L: Language + Default> AContext<'r, 's, 'h, L> {
@@ -108,8 +133,10 @@ impl<'r, 's, 'h, L: Language + Default> AContext<'r, 's, 'h, L> {
         lang_from_path: Arc<dyn Fn(&PPath<KString>) -> Option<L> + Send + Sync>,
     ) -> Result<Self>
     {
-        let path_original = request.url(); // path only
-        let path: PPath<KString> = PPath::from_str(&path_original);
+        // (Not `request.url()`, as that decodes `%2F` to a separator.)
+        let raw_url = request.raw_url();
+        let raw_path = raw_url.split_once('?').map_or(raw_url, |(path, _query)| path);
+        let path: PPath<KString> = PPath::from_percent_encoded(raw_path);
         let path_string = path.to_string();
         // let headers = request.headers();  -- iterator
         let method = HttpRequestMethod::from_str(request.method())?;
@@ -155,6 +182,8 @@ impl<'r, 's, 'h, L: Language + Default> AContext<'r, 's, 'h, L> {
             sessionid_hasher,
             lang,
             lang_cookie,
+            links: RefCell::new(Vec::new()),
+            base_href: RefCell::new(None),
         })
     }
     
@@ -165,6 +194,46 @@ impl<'r, 's, 'h, L: Language + Default> AContext<'r, 's, 'h, L> {
                                    self.lang_cookie.key(),
                                    self.lang_cookie.take_out_value(),
                                    &self.lang_cookie.got);
+        let links = self.links.take();
+        if ! links.is_empty() {
+            let links = links.join(", ");
+            // Merge with a `Link` header the handler set itself
+            if let Some((_, value)) = headers.iter_mut().find(
+                |(k, _)| k.eq_ignore_ascii_case("Link"))
+            {
+                *value = Cow::from(format!("{value}, {links}"));
+            } else {
+                headers.push((Cow::from("Link"), Cow::from(links)));
+            }
+        }
+    }
+
+    /// Add an entry to the `Link` response header (e.g. for preload
+    /// hints, which allow for early hints and work with CDNs), like
+    /// `add_link("/static/main.css", &[("rel", "preload"), ("as",
+    /// "style")])`. All entries are sent in one header by
+    /// `set_headers`.
+    pub fn add_link(&self, href: &str, params: &[(&str, &str)]) {
+        self.links.borrow_mut().push(link_header_entry(href, params));
+    }
+
+    /// Have the layout emit a `<base href>` with this URL, against
+    /// which the browser then resolves all relative URLs in the page
+    /// (e.g. `context.path().as_dir()`, for a document whose relative
+    /// links refer to things below its own URL, whether requested
+    /// with a trailing slash or not). Unlike rewriting the URLs in
+    /// the document (`MarkdownConfig::base_url`) this needs no
+    /// knowledge of where URLs appear, but it also affects the
+    /// relative URLs of the layout, and fragment-only links (`#foo`,
+    /// as used for footnotes and tables of contents) then point to
+    /// the base URL instead of the current page, i.e. load another
+    /// page unless the base is the page's own URL.
+    pub fn set_base_href(&self, href: &str) {
+        *self.base_href.borrow_mut() = Some(href.to_string());
+    }
+
+    pub fn base_href(&self) -> Option<String> {
+        self.base_href.borrow().clone()
     }
 
     /// Like the request part in Apache style Combined Log Format
@@ -212,6 +281,60 @@ impl<'r, 's, 'h, L: Language + Default> AContext<'r, 's, 'h, L> {
         self.request.header("host").unwrap_or(&self.listen_addr)
     }
     pub fn client_addr(&'r self) -> &'r SocketAddr { self.request.remote_addr() }
+
+    /// The value of the `X-Forwarded-*` header `key` if the request
+    /// comes from a reverse proxy on this machine (from a loopback
+    /// address; from elsewhere these headers could be faked).
+    fn forwarded_header(&self, key: &str) -> Option<&str> {
+        if self.request.remote_addr().ip().is_loopback() {
+            self.header(key).and_then(
+                |v| v.split(',').next()).map(str::trim).filter(|v| ! v.is_empty())
+        } else {
+            None
+        }
+    }
+
+    /// Whether the client used TLS (to us, or to a reverse proxy on
+    /// this machine that says so via `X-Forwarded-Proto`).
+    pub fn is_https(&self) -> bool {
+        self.request.is_secure()
+            || self.forwarded_header("x-forwarded-proto").is_some_and(
+                |proto| proto.eq_ignore_ascii_case("https"))
+    }
+
+    /// The host (and port, if given) the client used, from
+    /// `X-Forwarded-Host` (see `is_https`) or the `Host` header.
+    pub fn public_host(&self) -> &str {
+        self.forwarded_header("x-forwarded-host").unwrap_or_else(
+            || self.host_or_listen_addr())
+    }
+
+    /// `url` as an absolute URL with the scheme and host the client
+    /// used (see `is_https` and `public_host`), e.g. for feeds or
+    /// `og:url`. `url` can be a path, absolute or relative to the
+    /// request path; protocol relative (`//example.com/a`); or already
+    /// have a scheme, then it's returned unchanged. It is expected to
+    /// be percent-encoded already.
+    pub fn absolute_url(&self, url: &str) -> String {
+        if has_scheme(url) {
+            return url.to_string()
+        }
+        let scheme = if self.is_https() { "https" } else { "http" };
+        if url.starts_with("//") {
+            return format!("{scheme}:{url}")
+        }
+        let raw_url = self.request.raw_url();
+        let raw_path = raw_url.split_once('?').map_or(raw_url, |(path, _query)| path);
+        let path = if url.starts_with('/') {
+            Cow::Borrowed(url)
+        } else if let Some(path) = resolve_relative_url(raw_path, url) {
+            Cow::Owned(path)
+        } else {
+            // Empty, or only a query or fragment
+            Cow::Owned(format!("{raw_path}{url}"))
+        };
+        format!("{scheme}://{}{path}", self.public_host())
+    }
     pub fn path(&self) -> &PPath<KString> { &self.path }
     pub fn path_str(&self) -> &str { &self.path_string }
     pub fn now(&self) -> &SystemTime { &self.now }
@@ -263,3 +386,67 @@ impl<'r, 's, 'h, L: Language> Drop for AContext<'r, 's, 'h, L> {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{lang_en_de::Lang, test_util::fake_context};
+
+    use super::*;
+
+    #[test]
+    fn t_absolute_url() -> Result<()> {
+        use crate::test_util::fake_context_from;
+        let absolute = |request: crate::test_util::FakeRequest, url: &'static str| {
+            request.with_context(|context: &AContext<Lang>| Ok(context.absolute_url(url)))
+        };
+        let host = [("Host", "example.com")];
+        let plain = || fake_context("GET", "/blog/2023/post.html", &host, &[("page", "2")]);
+        assert_eq!(absolute(plain(), "/feed.xml")?, "http://example.com/feed.xml");
+        assert_eq!(absolute(plain(), "img/a%20b.png")?,
+                   "http://example.com/blog/2023/img/a%20b.png");
+        assert_eq!(absolute(plain(), "../")?, "http://example.com/blog/");
+        assert_eq!(absolute(plain(), "#top")?, "http://example.com/blog/2023/post.html#top");
+        assert_eq!(absolute(plain(), "https://other.example/x")?, "https://other.example/x");
+        assert_eq!(absolute(plain(), "mailto:a@example.com")?, "mailto:a@example.com");
+        assert_eq!(absolute(plain(), "//cdn.example/x.js")?, "http://cdn.example/x.js");
+
+        // TLS
+        let tls = || fake_context_from("192.0.2.1:4000".parse().unwrap(), true,
+                                       "GET", "/a", &host, &[]);
+        assert_eq!(absolute(tls(), "/b")?, "https://example.com/b");
+        assert_eq!(absolute(tls(), "//cdn.example/x.js")?, "https://cdn.example/x.js");
+
+        // A reverse proxy on the same machine terminating TLS
+        let forwarded = [("Host", "localhost:3000"), ("X-Forwarded-Proto", "https"),
+                         ("X-Forwarded-Host", "www.example.com")];
+        assert_eq!(absolute(fake_context("GET", "/a", &forwarded, &[]), "/b")?,
+                   "https://www.example.com/b");
+        // but not from elsewhere
+        let remote = fake_context_from("192.0.2.1:4000".parse().unwrap(), false,
+                                       "GET", "/a", &forwarded, &[]);
+        assert_eq!(absolute(remote, "/b")?, "http://localhost:3000/b");
+        Ok(())
+    }
+
+    #[test]
+    fn t_link_header() -> Result<()> {
+        let headers = fake_context("GET", "/", &[], &[]).with_context(
+            |context: &AContext<Lang>| {
+                context.add_link("/static/main.css", &[("rel", "preload"), ("as", "style")]);
+                context.add_link("/static/font.woff2",
+                                 &[("rel", "preload"), ("as", "font"),
+                                   ("type", "font/woff2"), ("crossorigin", "anonymous")]);
+                let mut headers = Vec::new();
+                context.set_headers(&mut headers);
+                Ok(headers)
+            })?;
+        let links: Vec<_> = headers.iter().filter(|(k, _)| k == "Link").collect();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].1,
+                   "</static/main.css>; rel=preload; as=style, \
+                    </static/font.woff2>; rel=preload; as=font; type=\"font/woff2\"; \
+                    crossorigin=anonymous");
+        Ok(())
+    }
+}
@@ -6,6 +6,7 @@ use std::borrow::Cow;
 use std::mem::swap;
 use std::panic;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::{time::{Duration, SystemTime, Instant}, io::{stderr, BufWriter}};
 use std::io::Write;
 
@@ -20,7 +21,7 @@ use crate::acontext::AContext;
 use crate::aresponse::AResponse;
 use crate::date_format::months_short;
 use crate::easy_fs::open_log_output;
-use crate::http_response_status_codes::HttpResponseStatusCode;
+use crate::handler::HandlerError;
 use crate::language::Language;
 use crate::try_result;
 use crate::webutils::errorpage_from_status;
@@ -116,6 +117,45 @@ fn write_error<L: Language>(
     Ok(())
 }
 
+/// Requests whose handling takes longer than this (in microseconds)
+/// are additionally reported in the error log; `u64::MAX` means
+/// never.
+static SLOW_REQUEST_THRESHOLD_MICROS: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Set the duration above which handling a request is reported in
+/// the error log (as `[slow]` entry), or `None` to disable it (the
+/// default). Can be changed at any time.
+pub fn set_slow_request_threshold(threshold: Option<Duration>) {
+    SLOW_REQUEST_THRESHOLD_MICROS.store(
+        threshold.map(|d| d.as_micros().try_into().unwrap_or(u64::MAX - 1))
+            .unwrap_or(u64::MAX),
+        Ordering::Relaxed);
+}
+
+pub fn slow_request_threshold() -> Option<Duration> {
+    match SLOW_REQUEST_THRESHOLD_MICROS.load(Ordering::Relaxed) {
+        u64::MAX => None,
+        micros => Some(Duration::from_micros(micros))
+    }
+}
+
+/// Write a slow request entry to error.log
+fn write_slow<L: Language>(
+    outp: &mut impl Write,
+    context: &AContext<L>,
+    duration: Duration,
+) -> Result<()> {
+    let now = SystemTime::now();
+    write!(outp, "[")?;
+    write_time(outp, now)?;
+    writeln!(outp, "] [slow] [client {}] {} {:?} took {duration:?}",
+             context.client_ip(),
+             context.method_str(),
+             context.path_str())?;
+    outp.flush()?;
+    Ok(())
+}
+
 /// Panic log to stderr. Panics on errors logging to stderr.
 fn write_panic_stderr<L: Language>(
     context: &AContext<L>,
@@ -191,31 +231,41 @@ where
     let elapsed = start_instant.elapsed();
 
     match result {
-        Ok((logs, result)) => match result {
-            Ok(mut response) => {
-                {
-                    let mut _logs = logs.lock().expect(
-                        "if `write` panics then we are lost anyway");
-                    match write_combined(&mut _logs.access_log, context, elapsed, &mut response)
+        Ok((logs, result)) => {
+            if slow_request_threshold().is_some_and(|threshold| elapsed > threshold) {
+                let mut _logs = logs.lock().expect(
+                    "if `write` panics then we are lost anyway");
+                match write_slow(&mut _logs.error_log, context, elapsed) {
+                    Ok(()) => (),
+                    Err(e) => warn!("could not write to error log: {e:#}")
+                }
+            }
+            match result {
+                Ok(mut response) => {
                     {
-                        Ok(()) => (),
-                        Err(e) => warn!("could not write to access log: {e:#}")
+                        let mut _logs = logs.lock().expect(
+                            "if `write` panics then we are lost anyway");
+                        match write_combined(&mut _logs.access_log, context, elapsed, &mut response)
+                        {
+                            Ok(()) => (),
+                            Err(e) => warn!("could not write to access log: {e:#}")
+                        }
                     }
+                    response
                 }
-                response
-            }
-            Err(err) => {
-                {
-                    let mut _logs = logs.lock().expect(
-                        "if `write` panics then we are lost anyway");
-                    match write_error(&mut _logs.error_log, context, elapsed, err) {
-                        Ok(()) => (),
-                        Err(e) => warn!("could not write to error log: {e:#}")
+                Err(err) => {
+                    let status = HandlerError::status_of(&err);
+                    {
+                        let mut _logs = logs.lock().expect(
+                            "if `write` panics then we are lost anyway");
+                        match write_error(&mut _logs.error_log, context, elapsed, err) {
+                            Ok(()) => (),
+                            Err(e) => warn!("could not write to error log: {e:#}")
+                        }
                     }
+                    // XX btw expects that the requester accepts HTML. Not always OK?
+                    errorpage_from_status(status).into()
                 }
-                // XX btw expects that the requester accepts HTML. Not always OK?
-                errorpage_from_status(HttpResponseStatusCode::InternalServerError500)
-                    .into()
             }
         },
         Err(payload) => {
@@ -226,3 +276,69 @@ where
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use crate::{lang_en_de::Lang, test_util::{fake_context, SharedBuf},
+                http_response_status_codes::HttpResponseStatusCode};
+
+    use super::*;
+
+    #[test]
+    fn t_log_combined_error_status() -> Result<()> {
+        let logs = Arc::new(Mutex::new(Logs {
+            access_log: Box::new(std::io::sink()),
+            error_log: Box::new(std::io::sink()),
+        }));
+        let status_for = |err: anyhow::Error| {
+            fake_context("GET", "/", &[], &[]).with_context(|context: &AContext<Lang>| {
+                Ok(log_combined(context, || (logs.clone(), Err(err))).response.status_code)
+            })
+        };
+        let bad_request = || HandlerError::new(HttpResponseStatusCode::BadRequest400,
+                                               anyhow!("invalid number"));
+        assert_eq!(status_for(bad_request().into())?, 400);
+        // Context added on the way up doesn't hide the status
+        assert_eq!(status_for(Err::<(), _>(bad_request()).context("in form")
+                              .unwrap_err())?, 400);
+        assert_eq!(status_for(anyhow!("other error"))?, 500);
+
+        // Conversion back from anyhow
+        let e = HandlerError::from(anyhow::Error::from(bad_request()));
+        assert_eq!(e.status.code(), 400);
+        assert_eq!(e.to_string(), "400: invalid number");
+        assert_eq!(HandlerError::from(anyhow!("other error")).status.code(), 500);
+        Ok(())
+    }
+
+    #[test]
+    fn t_slow_request_log() -> Result<()> {
+        let access_log = SharedBuf::default();
+        let error_log = SharedBuf::default();
+        let logs = Arc::new(Mutex::new(Logs {
+            access_log: Box::new(access_log.clone()),
+            error_log: Box::new(error_log.clone()),
+        }));
+        let request = || fake_context("GET", "/some/page", &[], &[]).with_context(
+            |context: &AContext<Lang>| {
+                Ok(log_combined(context, || (logs.clone(),
+                                             Ok(rouille::Response::text("hi").into())))
+                   .response.status_code)
+            });
+
+        set_slow_request_threshold(Some(Duration::ZERO));
+        assert_eq!(request()?, 200);
+        let errors = error_log.contents();
+        assert!(errors.contains("] [slow] [client 127.0.0.1] GET \"/some/page\" took "),
+                "{errors}");
+        assert_eq!(errors.lines().count(), 1);
+        assert!(! access_log.contents().contains("[slow]"));
+
+        set_slow_request_threshold(None);
+        assert_eq!(request()?, 200);
+        assert_eq!(error_log.contents().lines().count(), 1);
+        Ok(())
+    }
+}
@@ -1,6 +1,10 @@
-use std::time::Instant;
+use std::{time::Instant, borrow::Cow};
 
-use rouille::Response;
+use anyhow::Result;
+use rouille::{Response, ResponseBody};
+use serde::Serialize;
+
+use crate::http_response_status_codes::HttpResponseStatusCode;
 
 
 pub struct AResponse {
@@ -26,3 +30,79 @@ impl ToAResponse for Response {
         AResponse { response: self, sleep_until }
     }
 }
+
+/// `Cache-Control` value for responses that depend on the session
+/// (e.g. login state) and hence must never be stored by caches.
+pub const CACHE_CONTROL_NO_STORE: &str = "private, no-store";
+
+impl AResponse {
+    /// Whether the status is 2xx or 304, i.e. the response is fine
+    /// for caching.
+    pub fn is_success(&self) -> bool {
+        let code = self.response.status_code;
+        (200..300).contains(&code) || code == 304
+    }
+
+    /// The value of the `Cache-Control` header, if present.
+    pub fn cache_control(&self) -> Option<&str> {
+        self.response.headers.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("Cache-Control"))
+            .map(|(_, v)| v.as_ref())
+    }
+
+    /// Set the `Cache-Control` header, replacing any existing one.
+    pub fn set_cache_control(&mut self, value: &str) {
+        let headers = &mut self.response.headers;
+        headers.retain(|(k, _)| ! k.eq_ignore_ascii_case("Cache-Control"));
+        headers.push((Cow::from("Cache-Control"), Cow::from(value.to_string())));
+    }
+}
+
+/// Serialize `value` via serde_json and return it with the given
+/// status as `application/json`. The JSON counterpart to
+/// `webutils::htmlresponse`.
+pub fn json_response<T: Serialize + ?Sized>(
+    status: HttpResponseStatusCode,
+    value: &T
+) -> Result<AResponse>
+{
+    let s = serde_json::to_string(value)?;
+    Ok(Response {
+        status_code: status.code(),
+        headers: vec![(Cow::from("Content-type"),
+                       Cow::from("application/json"))],
+        data: ResponseBody::from_string(s),
+        upgrade: None,
+    }.into())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Hit {
+        path: &'static str,
+        score: u32,
+    }
+
+    #[test]
+    fn t_json_response() -> Result<()> {
+        let aresp = json_response(HttpResponseStatusCode::Created201,
+                                  &[Hit { path: "/blog/", score: 3 }])?;
+        assert!(aresp.sleep_until.is_none());
+        let resp = aresp.response;
+        assert_eq!(resp.status_code, 201);
+        assert_eq!(resp.headers,
+                   vec![(Cow::from("Content-type"),
+                         Cow::from("application/json"))]);
+        let (mut reader, _) = resp.data.into_reader_and_size();
+        let mut s = String::new();
+        reader.read_to_string(&mut s)?;
+        assert_eq!(s, r#"[{"path":"/blog/","score":3}]"#);
+        Ok(())
+    }
+}
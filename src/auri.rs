@@ -5,6 +5,8 @@
 
 //! Also, I may want relative paths, too?
 
+use std::{borrow::Cow, collections::BTreeMap};
+
 use kstring::KString;
 
 use crate::{ppath::PPath, url_encoding::{url_encode, url_decode, UrlDecodingError}};
@@ -12,9 +14,10 @@ use crate::{ppath::PPath, url_encoding::{url_encode, url_decode, UrlDecodingErro
 
 // ------------------------------------------------------------------
 
-/// Simple representation of query strings (are nested representations
-/// even supported by browsers?). So far just for constructing the
-/// serialized representation.
+/// Simple representation of query strings: the key/value pairs in
+/// order. Repeated keys, the array style `tags[]=a&tags[]=b` and
+/// nested keys (`user[name]=x` or `user.name=x`) are only interpreted
+/// by the accessors (`get_all`, `multi`, `nested`).
 #[derive(Debug)]
 pub struct QueryString(Vec<(KString, KString)>);
 
@@ -109,6 +112,76 @@ impl QueryString {
     pub fn push(&mut self, keyval: (KString, KString)) {
         self.0.push(keyval);
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// The first value for `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.iter().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    /// All values for `key`, given as `key` or `key[]`, in order.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.iter().filter(|(k, _)| array_key(k) == key).map(|(_, v)| v).collect()
+    }
+
+    /// All values by key (with `[]` stripped), keys sorted, values in
+    /// order.
+    pub fn multi(&self) -> BTreeMap<&str, Vec<&str>> {
+        let mut m: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for (k, v) in self.iter() {
+            m.entry(array_key(k)).or_default().push(v);
+        }
+        m
+    }
+
+    /// The parameters nested under `key`, with the rest of their key
+    /// as key: for `key` "user", `user[name]=x&user.mail=y` gives
+    /// `name=x&mail=y` and `user[address][city]=z` gives
+    /// `address[city]=z`.
+    pub fn nested(&self, key: &str) -> QueryString {
+        QueryString(self.0.iter().filter_map(|(k, v)| {
+            let (first, rest) = split_key(k);
+            if first == key && ! rest.is_empty() {
+                Some((KString::from_ref(&rest), v.clone()))
+            } else {
+                None
+            }
+        }).collect())
+    }
+}
+
+/// Split a key into the first part and the rest as a key of its own:
+/// `a[b][c]` into `a` and `b[c]`, `a.b.c` into `a` and `b.c`. The
+/// rest is empty for simple keys and arrays (`a[]`).
+pub fn split_key(key: &str) -> (&str, Cow<'_, str>) {
+    match key.find(['[', '.']) {
+        None | Some(0) => (key, Cow::Borrowed("")),
+        Some(i) if key.as_bytes()[i] == b'.' => (&key[..i], Cow::Borrowed(&key[i + 1..])),
+        Some(i) => {
+            let after = &key[i + 1..];
+            match after.find(']') {
+                Some(j) => {
+                    let (name, rest) = (&after[..j], &after[j + 1..]);
+                    if rest.is_empty() {
+                        // (Also for arrays, where `name` is empty)
+                        (&key[..i], Cow::Borrowed(name))
+                    } else {
+                        // `b][c]` -> `b[c]`
+                        (&key[..i], Cow::Owned(format!("{name}{rest}")))
+                    }
+                }
+                None => (key, Cow::Borrowed(""))
+            }
+        }
+    }
+}
+
+/// `key` without an `[]` suffix.
+fn array_key(key: &str) -> &str {
+    key.strip_suffix("[]").unwrap_or(key)
 }
 
 #[cfg(test)]
@@ -132,6 +205,30 @@ mod tests1 {
         // ^ XX is it ok to decode keys, but doing it after & and = splitting?
         // XXX test unicode
     }
+
+    #[test]
+    fn t_querystring_multi() -> Result<(), UrlDecodingError> {
+        let q = QueryString::from_str("a=1&a=2&b[]=x&b%5B%5D=y&c=3")?;
+        assert_eq!(q.get("a"), Some("1"));
+        assert_eq!(q.get("c"), Some("3"));
+        assert_eq!(q.get("b"), None);
+        assert_eq!(q.get_all("a"), ["1", "2"]);
+        assert_eq!(q.get_all("b"), ["x", "y"]);
+        assert_eq!(q.get_all("d"), [] as [&str; 0]);
+        assert_eq!(q.multi(), BTreeMap::from([("a", vec!["1", "2"]),
+                                              ("b", vec!["x", "y"]),
+                                              ("c", vec!["3"])]));
+
+        let q = QueryString::from_str(
+            "user[name]=x&user.mail=y&user[tags][]=t1&user[tags][]=t2&user[a][city]=z&u=1")?;
+        let user = q.nested("user");
+        assert_eq!(user.iter().collect::<Vec<_>>(),
+                   [("name", "x"), ("mail", "y"), ("tags[]", "t1"), ("tags[]", "t2"),
+                    ("a[city]", "z")]);
+        assert_eq!(user.get_all("tags"), ["t1", "t2"]);
+        assert_eq!(user.nested("a").get("city"), Some("z"));
+        Ok(())
+    }
 }
 
 
@@ -171,11 +268,79 @@ impl From<AUriLocal> for String {
     }
 }
 
+// ------------------------------------------------------------------
+
+/// Whether `url` starts with a scheme like `https:` or `mailto:`.
+pub(crate) fn has_scheme(url: &str) -> bool {
+    match url.split_once(':') {
+        Some((scheme, _)) => {
+            let mut cs = scheme.chars();
+            cs.next().is_some_and(|c| c.is_ascii_alphabetic())
+                && cs.all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        }
+        None => false
+    }
+}
+
+/// Resolve a relative `url` (like `img/a.png` or `../b.html`) against
+/// `base` (a path like `/blog/2023/10/23/post.html`; as in browsers,
+/// only the part up to the last `/` is used), giving an absolute
+/// path. Returns `None` if `url` is not relative: has a scheme, is
+/// protocol relative or absolute, or is empty or only a query or
+/// fragment (those refer to the current document).
+pub fn resolve_relative_url(base: &str, url: &str) -> Option<String> {
+    if url.is_empty() || url.starts_with(['/', '#', '?']) || has_scheme(url) {
+        return None
+    }
+    let (urlpath, suffix) = url.find(['?', '#']).map_or((url, ""), |i| url.split_at(i));
+    let basedir = base.rfind('/').map_or("", |i| &base[..i]);
+    let mut segments: Vec<&str> = basedir.split('/').filter(|s| ! s.is_empty()).collect();
+    let mut ends_with_slash = true;
+    for segment in urlpath.split('/') {
+        ends_with_slash = true;
+        match segment {
+            "" | "." => (),
+            ".." => { segments.pop(); }
+            _ => {
+                segments.push(segment);
+                ends_with_slash = false;
+            }
+        }
+    }
+    let mut s = String::new();
+    for segment in segments {
+        s.push('/');
+        s.push_str(segment);
+    }
+    if ends_with_slash || s.is_empty() {
+        s.push('/');
+    }
+    s.push_str(suffix);
+    Some(s)
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn t_resolve_relative_url() {
+        let base = "/blog/2023/10/23/post.html";
+        let r = |url| resolve_relative_url(base, url);
+        assert_eq!(r("img/a.png").as_deref(), Some("/blog/2023/10/23/img/a.png"));
+        assert_eq!(r("./a.html#x").as_deref(), Some("/blog/2023/10/23/a.html#x"));
+        assert_eq!(r("../../b?q=../c").as_deref(), Some("/blog/2023/b?q=../c"));
+        assert_eq!(r("../").as_deref(), Some("/blog/2023/10/"));
+        assert_eq!(r("../../../../../..").as_deref(), Some("/"));
+        assert_eq!(resolve_relative_url("/blog/", "a").as_deref(), Some("/blog/a"));
+        assert_eq!(resolve_relative_url("", "a").as_deref(), Some("/a"));
+        for url in ["/a", "//example.com/a", "https://example.com/",
+                    "mailto:a@example.com", "#top", "?page=2", ""] {
+            assert_eq!(r(url), None);
+        }
+    }
+
     #[test]
     fn t_1() {
         let uri = AUriLocal::new(
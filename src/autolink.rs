@@ -0,0 +1,234 @@
+//! Turning bare URLs and email addresses in text into links (like
+//! GitHub's "extended autolinks"), see `MarkdownConfig::autolink`.
+
+use std::ops::Range;
+
+use anyhow::Result;
+
+use ahtml::{HtmlAllocator, ASlice, Node, A_META, CODE_META, PRE_META, att};
+
+use crate::webutils::email_url;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutolinkKind {
+    /// Starting with `http://`, `https://` or `www.`
+    Url,
+    Email,
+}
+
+/// A bare URL or email address found in a text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Autolink {
+    pub range: Range<usize>,
+    pub kind: AutolinkKind,
+}
+
+impl Autolink {
+    /// The link target, given the text `s` that `range` refers to.
+    pub fn href(&self, s: &str) -> String {
+        let text = &s[self.range.clone()];
+        match self.kind {
+            AutolinkKind::Url =>
+                if starts_with_ignore_case(text, "www.") {
+                    format!("http://{text}")
+                } else {
+                    text.into()
+                },
+            AutolinkKind::Email => email_url(text),
+        }
+    }
+}
+
+const URL_PREFIXES: &[&str] = &["https://", "http://", "www."];
+
+fn starts_with_ignore_case(s: &str, prefix: &str) -> bool {
+    s.as_bytes().get(..prefix.len()).is_some_and(
+        |start| start.eq_ignore_ascii_case(prefix.as_bytes()))
+}
+
+/// Whether a link may start after the character `prev`.
+fn is_boundary(prev: Option<char>) -> bool {
+    prev.is_none_or(|c| c.is_whitespace() || "(*_~\"'[“‘«„‚»".contains(c))
+}
+
+/// Drop trailing punctuation that more likely belongs to the
+/// sentence than to the URL; closing parentheses and brackets only
+/// if unbalanced.
+fn trim_url_end(url: &str) -> &str {
+    let mut url = url;
+    while let Some(c) = url.chars().next_back() {
+        let strip = match c {
+            '?' | '!' | '.' | ',' | ':' | ';' | '*' | '_' | '~'
+                | '\'' | '"' | '”' | '’' | '»' | '“' | '‘' | '«' => true,
+            ')' => url.matches(')').count() > url.matches('(').count(),
+            ']' => url.matches(']').count() > url.matches('[').count(),
+            _ => false
+        };
+        if ! strip {
+            break
+        }
+        url = &url[..url.len() - c.len_utf8()];
+    }
+    url
+}
+
+/// The length of the URL starting at the beginning of `s`, if any.
+fn url_len(s: &str) -> Option<usize> {
+    let prefix = URL_PREFIXES.iter().find(|p| starts_with_ignore_case(s, p))?;
+    let end = s.find(|c: char| c.is_whitespace() || c == '<').unwrap_or(s.len());
+    let url = trim_url_end(&s[..end]);
+    let host = url.get(prefix.len()..)?.split(['/', '?', '#']).next().unwrap_or("");
+    let valid_host = ! host.is_empty()
+        && ! host.starts_with(['.', '-', ':'])
+        && host.chars().all(|c| c.is_alphanumeric() || "-_.:@".contains(c));
+    valid_host.then_some(url.len())
+}
+
+fn is_email_local(c: char) -> bool {
+    c.is_ascii_alphanumeric() || ".+-_".contains(c)
+}
+
+fn is_email_domain(c: char) -> bool {
+    c.is_ascii_alphanumeric() || ".-_".contains(c)
+}
+
+/// Email addresses in `s[range]`.
+fn find_emails(s: &str, range: Range<usize>, out: &mut Vec<Autolink>) {
+    let mut pos = range.start;
+    while let Some(i) = s[pos..range.end].find('@') {
+        let at = pos + i;
+        let start = s[pos..at].char_indices().rev().find(|(_, c)| ! is_email_local(*c))
+            .map_or(pos, |(j, c)| pos + j + c.len_utf8());
+        let domain_len = s[at + 1..range.end].find(|c| ! is_email_domain(c))
+            .unwrap_or(range.end - at - 1);
+        let domain = s[at + 1..at + 1 + domain_len].trim_end_matches('.');
+        let valid = start < at
+            && ! s[start..].starts_with('.')
+            && is_boundary(s[..start].chars().next_back())
+            && domain.contains('.')
+            && ! domain.starts_with(['.', '-', '_'])
+            && domain.ends_with(|c: char| c.is_ascii_alphanumeric());
+        if valid {
+            let end = at + 1 + domain.len();
+            out.push(Autolink { range: start..end, kind: AutolinkKind::Email });
+            pos = end;
+        } else {
+            pos = at + 1;
+        }
+    }
+}
+
+/// The bare URLs and email addresses in `s`, in order.
+pub fn find_autolinks(s: &str) -> Vec<Autolink> {
+    let mut links = Vec::new();
+    let mut gap_start = 0;
+    let mut prev = None;
+    let mut pos = 0;
+    while let Some(c) = s[pos..].chars().next() {
+        if is_boundary(prev) {
+            if let Some(len) = url_len(&s[pos..]) {
+                find_emails(s, gap_start..pos, &mut links);
+                links.push(Autolink { range: pos..pos + len, kind: AutolinkKind::Url });
+                pos += len;
+                gap_start = pos;
+                prev = s[..pos].chars().next_back();
+                continue
+            }
+        }
+        prev = Some(c);
+        pos += c.len_utf8();
+    }
+    find_emails(s, gap_start..s.len(), &mut links);
+    links
+}
+
+/// `body` with bare URLs and email addresses in text nodes wrapped in
+/// `a` elements, except within `a`, `code` and `pre` elements. Nodes
+/// without changes are re-used.
+pub fn autolink_body(html: &HtmlAllocator, body: ASlice<Node>) -> Result<ASlice<Node>> {
+    Ok(autolink_body_changed(html, body)?.unwrap_or(body))
+}
+
+/// None if there's nothing to link in `body`.
+fn autolink_body_changed(
+    html: &HtmlAllocator,
+    body: ASlice<Node>
+) -> Result<Option<ASlice<Node>>> {
+    let mut out = html.new_vec();
+    let mut changed = false;
+    for id in body.iter_aid(html) {
+        let node = html.get_node(id).expect("slice holds valid ids");
+        match node {
+            Node::String(s) => {
+                let links = find_autolinks(s);
+                if links.is_empty() {
+                    out.push(id)?;
+                } else {
+                    changed = true;
+                    let mut pos = 0;
+                    for link in links {
+                        if pos < link.range.start {
+                            out.push(html.str(&s[pos..link.range.start])?)?;
+                        }
+                        out.push(html.a([att("href", link.href(s))],
+                                        [html.str(&s[link.range.clone()])?])?)?;
+                        pos = link.range.end;
+                    }
+                    if pos < s.len() {
+                        out.push(html.str(&s[pos..])?)?;
+                    }
+                }
+            }
+            Node::Element(e) if ! [*A_META, *CODE_META, *PRE_META].contains(&e.meta) => {
+                if let Some(new_body) = autolink_body_changed(html, e.body)? {
+                    changed = true;
+                    out.push(html.new_element(e.meta, e.attr, new_body)?)?;
+                } else {
+                    out.push(id)?;
+                }
+            }
+            Node::Fragment(fragment_body) => {
+                if let Some(new_body) = autolink_body_changed(html, *fragment_body)? {
+                    changed = true;
+                    out.push(html.fragment(new_body)?)?;
+                } else {
+                    out.push(id)?;
+                }
+            }
+            _ => out.push(id)?,
+        }
+    }
+    Ok(changed.then(|| out.as_slice()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn links(s: &str) -> Vec<(&str, String)> {
+        find_autolinks(s).into_iter().map(
+            |link| (&s[link.range.clone()], link.href(s))).collect()
+    }
+
+    #[test]
+    fn t_find_autolinks() {
+        assert_eq!(links("See https://example.com/a_(b)?x=1. Or (www.example.com), \
+                          mail me@example.com."),
+                   [("https://example.com/a_(b)?x=1", "https://example.com/a_(b)?x=1".into()),
+                    ("www.example.com", "http://www.example.com".into()),
+                    ("me@example.com", "mailto:me@example.com".into())]);
+        assert_eq!(links("write to “me@example.com” now"),
+                   [("me@example.com", "mailto:me@example.com".into())]);
+        assert_eq!(links("„me@example.com“ „https://example.com/ä“"),
+                   [("me@example.com", "mailto:me@example.com".into()),
+                    ("https://example.com/ä", "https://example.com/ä".into())]);
+        // Only ASCII in the local part, and not starting mid-word
+        assert_eq!(links("müller@example.com, ümläut@example.com"), []);
+        assert_eq!(links("https://user@example.com/x"),
+                   [("https://user@example.com/x", "https://user@example.com/x".into())]);
+        for s in ["http://", "https:// x", "xhttps://example.com", "www.", "a@b",
+                  "@example.com", "me@.example.com", "me@example.com-", "nothing"] {
+            assert_eq!(links(s), [], "{s:?}");
+        }
+    }
+}
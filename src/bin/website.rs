@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use blake3::Hasher;
 use chj_util::warn;
@@ -7,14 +8,14 @@ use anyhow::{Result, bail};
 
 use ahtml::allocator::AHTML_TRACE;
 use ahtml::flat::Flat;
-use ahtml::{HtmlAllocatorPool, HtmlAllocator, Node, att};
+use ahtml::{HtmlAllocatorPool, HtmlAllocator, Node, att, try_metadb};
 
 use website::access_control::db::access_control_transaction;
 use website::access_control::statements_and_methods::DO_WARN_THREAD;
 use website::access_control::transaction::TransactError;
 use website::access_control::types::GroupId;
 use website::alist::AList;
-use website::apachelog::Logs;
+use website::apachelog::{Logs, set_slow_request_threshold};
 use website::acontext::AContext;
 use website::blog::Blog;
 use website::hostrouter::{HostRouter, HostsRouter};
@@ -24,17 +25,18 @@ use website::io_util::my_read_to_string;
 use website::lang_en_de::Lang;
 use website::path::base_and_suffix;
 use website::ppath::PPath;
-use website::rouille_runner::{RouilleRunner, Tlskeys};
-use website::style::footnotes::{WikipediaStyle, BlogStyle};
+use website::rouille_runner::{RouilleRunner, RouilleRunnerConfig, Tlskeys};
+use website::style::footnotes::{WikipediaStyle, BlogStyle, FootnotesConfig};
 use website::handler::{ExactFnHandler, RedirectHandler};
 use website::handler::FileHandler;
 use lazy_static::lazy_static;
 use website::markdown::StylingInterface;
 use website::nav::{Nav, NavEntry, SubEntries};
 use website::router::MultiRouter;
-use website::util::{log_basedir, getenv_or, getenv, xgetenv, getenv_bool};
+use website::util::{log_basedir, getenv_or, getenv, xgetenv, getenv_bool, getenv_usize};
 use website::webparts::{markdownpage_handler, blog_handler,
-                        login_handler, Restricted, unlisted_markdowndir_handler,
+                        login_handler, account_handler, Restricted,
+                        unlisted_markdowndir_handler,
                         language_handler, mixed_dir_handler};
 use website::website_layout::WebsiteLayout;
 use website::handler::Handler;
@@ -164,6 +166,9 @@ fn get_group_id(group_name: &str) -> Result<GroupId, TransactError<anyhow::Error
 fn main() -> Result<()> {
     DO_WARN_THREAD.store(true, std::sync::atomic::Ordering::SeqCst);
 
+    // Load now to report misconfiguration before serving anything.
+    try_metadb()?;
+
     let sessionid_hasher = {
         let sessionid_hasher_secret = xgetenv("SESSIONID_HASHER_SECRET")?;
         let mut h = Hasher::new();
@@ -198,10 +203,14 @@ fn main() -> Result<()> {
         }).transpose()?;
     
     let footnotestyle = {
+        let config = FootnotesConfig {
+            heading: true,
+            ..Default::default()
+        };
         let s : Arc<dyn StylingInterface> =
             match getenv_or("STYLE", Some("blog"))?.as_str() {
-                "blog" => Arc::new(BlogStyle {}),
-                "wikipedia" => Arc::new(WikipediaStyle {}),
+                "blog" => Arc::new(BlogStyle { config }),
+                "wikipedia" => Arc::new(WikipediaStyle { config }),
                 _ => bail!("no match for STYLE env var value"),
             };
         move || s.clone()
@@ -213,6 +222,9 @@ fn main() -> Result<()> {
             site_name: site_owner,
             copyright_owner: site_owner,
             nav: &NAV,
+            // main.css is loaded as a stylesheet already, and doesn't
+            // refer to fonts or images
+            preloads: &[],
             header_contents: Box::new({
                 let in_datadir = in_datadir.clone();
                 move |html: &HtmlAllocator| -> Result<Flat<Node>> {
@@ -234,6 +246,7 @@ fn main() -> Result<()> {
         let mut router : MultiRouter<Arc<dyn Handler<Lang>>> = MultiRouter::new();
         router
             .add("/login", login_handler(style()))
+            .add("/account", account_handler(style()))
             .add("/bench", Arc::new(ExactFnHandler::new(website_benchmark::benchmark)))
             .add("/", language_handler())
         // --------------------------------------------
@@ -349,10 +362,26 @@ fn main() -> Result<()> {
         Ok(Arc::new(hostsrouter))
     };
 
-    let rouille_runner = RouilleRunner::new(
+    // Avoid the latency of allocator construction on the first
+    // requests. XX config
+    ALLOCPOOL.prewarm(8);
+
+    set_slow_request_threshold(
+        getenv_usize("SLOW_REQUEST_MS")?.map(|ms| Duration::from_millis(ms as u64)));
+
+    let worker_threads = getenv_usize("WORKER_THREADS")?;
+    if worker_threads == Some(0) {
+        bail!("WORKER_THREADS must be at least 1, or unset for the default")
+    }
+    let rouille_runner = RouilleRunner::new_with_config(
         &ALLOCPOOL,
         sessionid_hasher,
-        Arc::new(lang_from_path));
+        Arc::new(lang_from_path),
+        RouilleRunnerConfig {
+            worker_threads,
+            http_threads: getenv_usize("HTTP_THREADS")?,
+            max_concurrent_requests: getenv_usize("MAX_CONCURRENT_REQUESTS")?,
+        });
 
     let http_thread = {
         let addr = std::env::var("LISTEN_HTTP").unwrap_or("127.0.0.1:3000".into());
@@ -1,30 +1,33 @@
-use std::{sync::Arc,
+use std::{sync::{Arc, Mutex},
+          collections::BTreeMap,
           path::{Path, PathBuf},
           time::{Duration, SystemTime},
           fs::read_dir,
           thread,
+          process::Command,
           panic::catch_unwind};
 use anyhow::{Result, anyhow, bail, Context};
 use chrono::NaiveDate;
 use kstring::KString;
 
-use ahtml::{SerHtmlFrag, HtmlAllocator, HtmlAllocatorPool, AVec, Node, att, myfrom::MyFrom};
+use ahtml::{SerHtmlFrag, HtmlAllocator, HtmlAllocatorPool, AVec, ASlice, Node, att, myfrom::MyFrom};
 use ahtml::{H2_META, P_META};
-use chj_util::{nodt as dt, time, notime};
+use chj_util::{nodt as dt, time, notime, warn};
 
 use crate::{router::UniqueRouter,
             util::first_and_rest,
-            markdown::{MarkdownFile, StylingInterface},
+            markdown::{MarkdownFile, MarkdownConfig, StylingInterface},
             conslist::{List, cons},
             path::{extension_eq, base, IntoBoxPath},
             miniarcswap::MiniArcSwap,
             cmpfilemeta::{CmpFileMeta, GetCmpFileMeta},
             easyfiletype::EasyFileType,
             loop_try,
-            trie::Trie,
+            trie::{Trie, TrieIterReportStyle},
             try_option,
             try_result,
-            option_util::TryMap};
+            option_util::TryMap,
+            str_util::str_truncate_words};
 
 #[derive(Debug, Clone)]
 pub struct Breadcrumb {
@@ -47,6 +50,9 @@ impl Breadcrumb {
 #[derive(Debug, Clone)]
 pub struct BlogPost {
     pub cmpfilemeta: CmpFileMeta,
+    /// Derived from `cmpfilemeta` and `modified()`, i.e. changes
+    /// when the source changes (without quotes).
+    pub etag: KString,
     pub publish_date: NaiveDate, // parsed from file path
     pub title_plain: KString,
     pub title_html: Arc<SerHtmlFrag>,
@@ -54,16 +60,29 @@ pub struct BlogPost {
     pub toc: Arc<SerHtmlFrag>,
     /// The part before the first header, or the first paragraph (teaser)
     pub lead: Option<Arc<SerHtmlFrag>>,
+    /// `lead` as plain text, with whitespace collapsed
+    pub lead_plain: Option<KString>,
     /// The part after the lead
     pub main: Arc<SerHtmlFrag>,
+    /// The text of the first paragraph, shortened, for `<meta
+    /// name="description">`; empty if there is no paragraph
+    pub description: KString,
     pub num_footnotes: usize,
     pub footnotes: Arc<SerHtmlFrag>,
     pub breadcrumb: Breadcrumb,
+    /// Additional paths (relative to the blog root) that redirect to
+    /// this post, from the `permalink` and `aliases` front matter
+    /// fields, e.g. to keep URLs from a previous blog system working.
+    pub aliases: Vec<Vec<KString>>,
+    /// See `BlogConfig::git_timestamps`
+    pub git_commit_time: Option<SystemTime>,
 }
 impl BlogPost {
-    // XX todo: use time from Git, not mtime!
+    /// The time of the last Git commit of the source file if
+    /// `BlogConfig::git_timestamps` is enabled and it was found, the
+    /// file's mtime otherwise.
     pub fn modified(&self) -> SystemTime {
-        self.cmpfilemeta.modified_time
+        self.git_commit_time.unwrap_or(self.cmpfilemeta.modified_time)
     }
 }
 
@@ -71,19 +90,25 @@ impl BlogPost {
 pub struct BlogPostIndex {
     // None for top level index
     pub breadcrumb: Option<Breadcrumb>,
+    /// Of the directory, at the time it was scanned
+    pub dir_meta: CmpFileMeta,
 }
 
 #[derive(Debug)]
 pub enum BlogNode {
     BlogPost(BlogPost),
-    BlogPostIndex(BlogPostIndex)
+    BlogPostIndex(BlogPostIndex),
+    /// An alias of a post, redirecting (301) to the post's path
+    /// (segments relative to the blog root).
+    Redirect(Vec<KString>),
 }
 
 impl BlogNode {
     fn blogpost(&self) -> Option<&BlogPost> {
         match self {
             BlogNode::BlogPost(p) => Some(p),
-            BlogNode::BlogPostIndex(_) => None
+            BlogNode::BlogPostIndex(_) => None,
+            BlogNode::Redirect(_) => None,
         }
     }
 }
@@ -92,6 +117,78 @@ impl BlogNode {
 #[derive(Debug)]
 pub struct BlogCache {
     pub router: UniqueRouter<BlogNode>,
+    /// The commit at HEAD of the repository holding the blog, if
+    /// `BlogConfig::git_timestamps` is enabled.
+    git_head: Option<String>,
+    /// All posts with their path, newest first.
+    posts_sorted: Vec<(Vec<KString>, BlogPost)>,
+    /// Slugs used by more than one post, with their paths.
+    duplicate_slugs: Vec<(KString, Vec<String>)>,
+    /// Aliases that clash with other paths, with the path of their
+    /// post.
+    ignored_aliases: Vec<(Vec<KString>, Vec<KString>)>,
+}
+
+/// What to do when posts on different dates have the same slug (file
+/// name), e.g. `2023/10/23/post.html` and `2024/01/01/post.html`.
+/// Such posts have different URLs, but that may not be intended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateSlugPolicy {
+    #[default]
+    Allow,
+    /// Print a warning (once, not on every rescan).
+    Warn,
+    /// Fail loading the blog (the previous state stays in use when
+    /// rescanning).
+    Error,
+}
+
+/// What to do with posts without content (e.g. an empty or
+/// whitespace-only `.md` file, possibly with front matter).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmptyPostPolicy {
+    /// Publish it, with "(missing title)" as its title.
+    #[default]
+    Publish,
+    /// Leave it out (its URL gives a 404), e.g. for drafts that were
+    /// just created.
+    Skip,
+    /// Fail loading the blog (the previous state stays in use when
+    /// rescanning).
+    Error,
+}
+
+/// What to show below each entry in the blog index, besides date
+/// and title.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlogIndexTeaser {
+    #[default]
+    None,
+    /// The post's `lead`, as HTML.
+    Lead,
+    /// The plain text of the post's `lead`, cut off after the given
+    /// number of characters (with an ellipsis appended then).
+    PlainText(usize),
+}
+
+/// Options for `Blog::open_with_config`.
+#[derive(Debug, Clone, Default)]
+pub struct BlogConfig {
+    pub duplicate_slugs: DuplicateSlugPolicy,
+    pub index_teaser: BlogIndexTeaser,
+    /// Scheme and host prepended to the `rel="canonical"` URL of
+    /// posts, e.g. "https://example.com". If not given, the
+    /// canonical URL is just the path.
+    pub site_base: Option<String>,
+    pub empty_posts: EmptyPostPolicy,
+    /// Use the time of the last Git commit of a post's file as its
+    /// modification time (e.g. for `Last-Modified` and feeds),
+    /// instead of the file's mtime, which is just the checkout time
+    /// when deploying from a fresh clone. Runs `git log` once per
+    /// file, and again when the file changes or, as checked on full
+    /// scans, Git's HEAD; falls back to the mtime for files that
+    /// aren't committed or if Git fails.
+    pub git_timestamps: bool,
 }
 
 pub enum ParsedDatePart {
@@ -205,17 +302,178 @@ fn breadcrumb<'f>(
     })
 }
 
+// The directory listing of `fsdirpath` with the metadata of the
+// items.
+fn read_dir_items(fsdirpath: &Path) -> Result<Vec<(String, PathBuf, CmpFileMeta)>> {
+    read_dir(fsdirpath).with_context(
+        || anyhow!("read_dir on {:?}", fsdirpath))?
+    .map(
+        |direntry| -> Result<_> {
+            let direntry = direntry?;
+            // Make sure the file name is UTF-8 to prevent
+            // problems with trying to send URLs containing other
+            // byte sequences to the browser.
+            let filename = direntry.file_name().into_string().ok().ok_or_else(
+                || anyhow!("Blog under {fsdirpath:?}: item can't be \
+                            converted to string: {:?}",
+                           direntry.file_name().to_string_lossy()))?;
+            let mut fspath: PathBuf = fsdirpath.into();
+            fspath.push(&filename);
+            let x = fspath.symlink_metadata()?.cmpfilemeta()?;
+            Ok((filename,
+                fspath,
+                x))
+        }).collect()
+}
+
+// The items of `fsdirpath` as known from the previous scan in
+// `oldleaf`, for when the directory itself is unchanged: the posts
+// with their old metadata (i.e. they are reused without looking at
+// the files), the subdirectories with fresh metadata, since entries
+// changing further down don't change the mtime of this directory.
+fn old_dir_items(
+    oldleaf: &Trie<BlogNode>, fsdirpath: &Path
+) -> Result<Vec<(String, PathBuf, CmpFileMeta)>> {
+    oldleaf.children().filter_map(
+        |(segment, oldleaf2)| -> Option<Result<_>> {
+            let endpoint = oldleaf2.endpoint()?;
+            if let BlogNode::Redirect(_) = endpoint {
+                // re-added from the posts after scanning
+                return None
+            }
+            Some(try_result!{
+                let (filename, cmpfilemeta) = match endpoint {
+                    BlogNode::BlogPost(blogpost) =>
+                        (format!("{}.md", base(segment).expect("posts have .html suffix")),
+                         Some(blogpost.cmpfilemeta.clone())),
+                    BlogNode::BlogPostIndex(_) =>
+                        (segment.to_string(), None),
+                    BlogNode::Redirect(_) => unreachable!(),
+                };
+                let fspath = fsdirpath.join(&filename);
+                let cmpfilemeta = match cmpfilemeta {
+                    Some(m) => m,
+                    None => fspath.symlink_metadata().with_context(
+                        || anyhow!("blog subdirectory {fspath:?} vanished"))?
+                        .cmpfilemeta()?,
+                };
+                Ok((filename, fspath, cmpfilemeta))
+            })
+        }).collect()
+}
+
+// Path segments of an alias of a post, given as a path relative to
+// the blog root (a leading slash is allowed).
+fn alias_segments(alias: &str) -> Result<Vec<KString>> {
+    let segments: Vec<KString> = alias.trim_start_matches('/').split('/')
+        .map(KString::from_ref).collect();
+    if segments.iter().any(|s| s.is_empty() || s == "." || s == "..") {
+        bail!("invalid alias path {alias:?}")
+    }
+    Ok(segments)
+}
+
+/// Maximal length of `BlogPost::description`, in characters.
+const DESCRIPTION_MAX_CHARS: usize = 160;
+
+// The plain text of the first paragraph in `body`, with whitespace
+// collapsed and cut at a word boundary to at most
+// `DESCRIPTION_MAX_CHARS` characters (plus ellipsis), or the empty
+// string if there is no paragraph.
+fn description(html: &HtmlAllocator, body: ASlice<Node>) -> Result<KString> {
+    for id in body.iter_aid(html) {
+        if let Some(p) = html.find_elements(id, *P_META).first() {
+            let text = html.first_text(*p, usize::MAX)?;
+            return Ok(KString::from_ref(
+                str_truncate_words(text.trim(), DESCRIPTION_MAX_CHARS).as_ref()))
+        }
+    }
+    Ok(KString::from_static(""))
+}
+
+// The time of the last commit touching the file at `path`, None if
+// it isn't committed.
+fn git_commit_time(path: &Path) -> Result<Option<SystemTime>> {
+    let dir = path.parent().ok_or_else(|| anyhow!("path has no parent: {path:?}"))?;
+    let file = path.file_name().ok_or_else(|| anyhow!("path has no file name: {path:?}"))?;
+    let output = Command::new("git")
+        .arg("-C").arg(dir)
+        .args(["log", "-1", "--format=%ct", "--"])
+        .arg(file)
+        .output()
+        .with_context(|| anyhow!("running git log for {path:?}"))?;
+    if ! output.status.success() {
+        bail!("git log for {path:?} gave {}: {}",
+              output.status, String::from_utf8_lossy(&output.stderr).trim())
+    }
+    let out = std::str::from_utf8(&output.stdout)?.trim();
+    if out.is_empty() {
+        return Ok(None)
+    }
+    let secs: u64 = out.parse().with_context(
+        || anyhow!("unexpected git log output for {path:?}: {out:?}"))?;
+    Ok(Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)))
+}
+
+// The commit at HEAD of the repository holding `dir`, None if it
+// has no commits yet.
+fn git_head(dir: &Path) -> Result<Option<String>> {
+    let output = Command::new("git")
+        .arg("-C").arg(dir)
+        .args(["rev-parse", "--verify", "--quiet", "HEAD"])
+        .output()
+        .with_context(|| anyhow!("running git rev-parse in {dir:?}"))?;
+    match output.status.code() {
+        Some(0) => Ok(Some(std::str::from_utf8(&output.stdout)?.trim().into())),
+        Some(1) => Ok(None),
+        _ => bail!("git rev-parse in {dir:?} gave {}: {}",
+                   output.status, String::from_utf8_lossy(&output.stderr).trim())
+    }
+}
+
+// `git_commit_time` if enabled in `config`, warning on errors.
+fn post_git_commit_time(path: &Path, config: &BlogConfig) -> Option<SystemTime> {
+    if config.git_timestamps {
+        git_commit_time(path).unwrap_or_else(|e| {
+            warn!("blog: using mtime instead: {e:#}");
+            None
+        })
+    } else {
+        None
+    }
+}
+
+// The ETag of a post, changing with the file and with the commit
+// time, if any.
+fn post_etag(cmpfilemeta: &CmpFileMeta, git_commit_time: Option<SystemTime>) -> KString {
+    KString::from(match git_commit_time {
+        Some(time) => cmpfilemeta.etag_value_with_time(time),
+        None => cmpfilemeta.etag_value(),
+    })
+}
+
 // Walk the file system, copying over entries from oldleaf if
-// available and matching (unchanged `CmpFilemeta`)
+// available and matching (unchanged `CmpFilemeta`; if
+// `git_head_changed`, with their Git commit time looked up again). Unless
+// `full_scan` is true, directories that are unchanged since the
+// previous scan (`dir_meta` of the directory matches) are not read
+// again, only their subdirectories are checked; note that this
+// misses changes to files that are edited in place (which doesn't
+// change the directory), those are only picked up by the next full
+// scan.
 fn populate<'f, 'c>(
     leaf: &mut Trie<BlogNode>,
     oldleaf: Option<&Trie<BlogNode>>,
     coming_context: &'c [ContextFrame], // part of CONTEXT
     parsed_context: &'f List<ParsedContextFrame<'f>>,
     fsdirpath: &Path,
+    dir_meta: &CmpFileMeta,
     fsbasepath: &Path,
+    full_scan: bool,
+    git_head_changed: bool,
     html: &HtmlAllocator,
     style: &dyn StylingInterface,
+    config: &BlogConfig,
 ) -> Result<()> {
     dt!("populate", fsdirpath);
 
@@ -229,33 +487,27 @@ fn populate<'f, 'c>(
             };
         let endp = leaf.endpoint_mut().expect("always allowed due to `true`");
         *endp = Some(BlogNode::BlogPostIndex(BlogPostIndex {
-            breadcrumb
+            breadcrumb,
+            dir_meta: dir_meta.clone(),
         }));
     }
 
+    let old_dir_unchanged = try_option!{
+        if full_scan { return None }
+        let oldleaf = oldleaf?;
+        match oldleaf.endpoint()? {
+            BlogNode::BlogPostIndex(index) if index.dir_meta == *dir_meta => Some(oldleaf),
+            _ => None
+        }
+    };
     let items =
-        read_dir(fsdirpath).with_context(
-            || anyhow!("read_dir on {:?}", fsdirpath))?
-        .into_iter().map(
-            |direntry| -> Result<_> {
-                let direntry = direntry?;
-                // Make sure the file name is UTF-8 to prevent
-                // problems with trying to send URLs containing other
-                // byte sequences to the browser.
-                let filename = direntry.file_name().into_string().ok().ok_or_else(
-                    || anyhow!("Blog under {fsdirpath:?}: item can't be \
-                                converted to string: {:?}",
-                               direntry.file_name().to_string_lossy()))?;
-                let mut fspath: PathBuf = fsdirpath.into();
-                fspath.push(&filename);
-                let x = fspath.symlink_metadata()?.cmpfilemeta()?;
-                Ok((filename,
-                    fspath,
-                    x))
-            });
-
-    for item in items {
-        let (filename, fspath, cmpfilemeta) = item?;
+        if let Some(oldleaf) = old_dir_unchanged {
+            old_dir_items(oldleaf, fsdirpath)?
+        } else {
+            read_dir_items(fsdirpath)?
+        };
+
+    for (filename, fspath, cmpfilemeta) in items {
         let fspath_lossy = KString::myfrom(fspath.to_string_lossy());
         match try_result!{
             macro_rules! leafs_for_recursion {
@@ -290,9 +542,13 @@ fn populate<'f, 'c>(
                             rest_context,
                             &ctx,
                             &fspath,
+                            &cmpfilemeta,
                             fsbasepath,
+                            full_scan,
+                            git_head_changed,
                             html,
-                            style)?;
+                            style,
+                            config)?;
                     } else {
                         bail!("invalid blog subdirectory at {fspath:?}: \
                                expected {desc} as the filename part");
@@ -323,10 +579,19 @@ fn populate<'f, 'c>(
 
                         let blogpost =
                             if let Some(blogpost) = reuse_blogpost {
-                                (*blogpost).clone()
+                                let mut blogpost = (*blogpost).clone();
                                 // ^ ~cheap since it contains just Arc's
                                 // and some small fields (CmpFileMeta is
                                 // about 5 words).
+                                if git_head_changed {
+                                    // May have been committed without
+                                    // changing
+                                    blogpost.git_commit_time =
+                                        post_git_commit_time(&fspath, config);
+                                    blogpost.etag = post_etag(&cmpfilemeta,
+                                                              blogpost.git_commit_time);
+                                }
+                                blogpost
                             } else {
                                 time!{
                                     fspath.to_string_lossy();
@@ -348,20 +613,29 @@ fn populate<'f, 'c>(
                                                  path yyyy/mm/dd"),
                                         };
 
-                                    let mf = MarkdownFile::new(fspath);
+                                    let git_commit_time =
+                                        post_git_commit_time(&fspath, config);
+                                    let mf = MarkdownFile::new_with_config(
+                                        fspath,
+                                        MarkdownConfig {
+                                            footnote_markers: style.footnote_markers(),
+                                            footnote_links: style.footnote_links(),
+                                            front_matter: true,
+                                            image_dimensions: true,
+                                            ..Default::default()
+                                        });
                                     let pmd = mf.process_to_html(html)?;
-                                    let fixed_body = pmd.fixed_html(html)?;
-                                    let (lead, main) = {
-                                        let bodynode = html.get_node(fixed_body).expect(
-                                            "guaranteed");
-                                        let elt = bodynode.as_element().ok_or_else(
-                                            || anyhow!("guaranteed to be an element, no?"))?;
-                                        if elt.attr().len() != 0 {
-                                            bail!("guaranteed to not have atts, no?")
+                                    if pmd.is_empty() {
+                                        match config.empty_posts {
+                                            EmptyPostPolicy::Publish => (),
+                                            EmptyPostPolicy::Skip => return Ok(()),
+                                            EmptyPostPolicy::Error => bail!("empty blog post"),
                                         }
-                                        let bodyslice = elt.body().clone();
-                                        drop(bodynode);
-                                        let div = |slice| html.div([], slice);
+                                    }
+                                    let bodyslice = pmd.fixed_body(html)?;
+                                    let description = description(html, bodyslice)?;
+                                    let (lead, main) = {
+                                        let div = |slice| html.div_of_slice(slice);
                                         let no_lead = || -> Result<_> {
                                             Ok((None, div(bodyslice)?))
                                         };
@@ -410,8 +684,15 @@ fn populate<'f, 'c>(
                                     let toc = pmd.meta().toc_html_fragment(html)?;
                                     let (num_footnotes, footnotes) =
                                         pmd.meta().footnotes_html_fragment(html, style)?;
+                                    let front_matter = pmd.meta().front_matter();
+                                    let aliases =
+                                        front_matter.get_all("permalink").into_iter()
+                                        .chain(front_matter.get_all("aliases"))
+                                        .map(|alias| alias_segments(alias))
+                                        .collect::<Result<_>>()?;
 
                                     BlogPost {
+                                        etag: post_etag(&cmpfilemeta, git_commit_time),
                                         cmpfilemeta,
                                         publish_date,
                                         title_plain:
@@ -420,17 +701,24 @@ fn populate<'f, 'c>(
                                         Arc::new(html.preserialize(title)?),
                                         toc:
                                         Arc::new(html.preserialize(toc)?),
+                                        lead_plain:
+                                        lead.try_map(|id| -> Result<_> {
+                                            Ok(KString::from(html.first_text(id, usize::MAX)?))
+                                        })?,
                                         lead:
                                         lead.try_map(|id| -> Result<_> {
                                             Ok(Arc::new(html.preserialize(id)?))
                                         })?,
                                         main:
                                         Arc::new(html.preserialize(main)?),
+                                        description,
                                         num_footnotes,
                                         footnotes:
                                         Arc::new(html.preserialize(footnotes)?),
                                         breadcrumb:
                                         breadcrumb(html, parsed_context)?,
+                                        aliases,
+                                        git_commit_time,
                                     }
                                 }
                             };
@@ -463,28 +751,146 @@ impl BlogCache {
     fn new() -> BlogCache {
         BlogCache {
             router: UniqueRouter::new(true),
+            git_head: None,
+            posts_sorted: Vec::new(),
+            duplicate_slugs: Vec::new(),
+            ignored_aliases: Vec::new(),
+        }
+    }
+
+    /// Slugs (file names) that are used by more than one post, with
+    /// the paths of those posts, see `DuplicateSlugPolicy`.
+    pub fn duplicate_slugs(&self) -> &[(KString, Vec<String>)] {
+        &self.duplicate_slugs
+    }
+
+    fn find_duplicate_slugs(&mut self) {
+        let mut by_slug: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for (path, _) in &self.posts_sorted {
+            if let Some(slug) = path.last() {
+                by_slug.entry(slug.as_str()).or_default().push(path.join("/"));
+            }
+        }
+        self.duplicate_slugs = by_slug.into_iter().filter_map(|(slug, mut paths)| {
+            if paths.len() > 1 {
+                paths.sort();
+                Some((KString::from_ref(slug), paths))
+            } else {
+                None
+            }
+        }).collect();
+    }
+
+    /// All posts (no index nodes) with their path segments, newest
+    /// first (by publish date, then by path, descending).
+    pub fn posts_sorted(&self) -> Vec<(Vec<&str>, &BlogPost)> {
+        self.posts_sorted.iter().map(
+            |(path, post)| (path.iter().map(|s| s.as_str()).collect(), post))
+            .collect()
+    }
+
+    fn sort_posts(&mut self) {
+        let mut posts: Vec<(Vec<KString>, BlogPost)> =
+            self.router.trie().iter(false, TrieIterReportStyle::BeforeRecursing)
+            .filter_map(|(path, trie)| {
+                let post = trie.endpoint()?.blogpost()?;
+                Some((path.into_iter().map(KString::from_ref).collect(),
+                      post.clone()))
+            }).collect();
+        posts.sort_by(|(path_a, a), (path_b, b)| {
+            (b.publish_date, path_b).cmp(&(a.publish_date, path_a))
+        });
+        self.posts_sorted = posts;
+    }
+
+    /// Add `BlogNode::Redirect` entries for the aliases of all posts
+    /// (call after `sort_posts`). Aliases clashing with existing
+    /// paths are ignored, with a warning unless already ignored in
+    /// `oldcache`.
+    fn insert_aliases(&mut self, oldcache: Option<&BlogCache>) {
+        let BlogCache { router, posts_sorted, ignored_aliases, .. } = self;
+        for (path, post) in posts_sorted.iter() {
+            for alias in &post.aliases {
+                let result = router.trie_mut().get_endpoint_mut(alias).and_then(
+                    |endpoint| {
+                        if endpoint.is_some() {
+                            bail!("path is already in use")
+                        }
+                        *endpoint = Some(BlogNode::Redirect(path.clone()));
+                        Ok(())
+                    });
+                if let Err(e) = result {
+                    let ignored = (alias.clone(), path.clone());
+                    if oldcache.is_none_or(|c| ! c.ignored_aliases.contains(&ignored)) {
+                        warn!("blog: ignoring alias {:?} of post {:?}: {e}",
+                              alias.join("/"), path.join("/"));
+                    }
+                    ignored_aliases.push(ignored);
+                }
+            }
         }
     }
     
     /// Needs an HtmlAllocator but only temporarily, BlogCache does not contain
-    /// AId:s but only preserialized HTML.
+    /// AId:s but only preserialized HTML. See `populate` for
+    /// `full_scan`.
     fn from_dir(
         basepath: &Path,
-        oldtrie: Option<&Trie<BlogNode>>, // for the same basepath, please
+        oldcache: Option<&BlogCache>, // for the same basepath, please
+        full_scan: bool,
         html: &HtmlAllocator,
-        style: &dyn StylingInterface
+        style: &dyn StylingInterface,
+        config: &BlogConfig,
     ) -> Result<BlogCache> {
         notime!{
             "BlogCache::from_dir";
             let mut blogcache = BlogCache::new();
+            let dir_meta = basepath.metadata().with_context(
+                || anyhow!("blog directory {basepath:?}"))?.cmpfilemeta()?;
+            blogcache.git_head =
+                if ! config.git_timestamps {
+                    None
+                } else if full_scan || oldcache.is_none() {
+                    git_head(basepath).unwrap_or_else(|e| {
+                        warn!("blog: {e:#}");
+                        None
+                    })
+                } else {
+                    oldcache.and_then(|c| c.git_head.clone())
+                };
+            let git_head_changed = oldcache.is_some_and(|c| c.git_head != blogcache.git_head);
             populate(blogcache.router.trie_mut(),
-                     oldtrie,
+                     oldcache.map(|c| c.router.trie()),
                      CONTEXT,
                      &List::Null,
                      basepath,
+                     &dir_meta,
                      basepath,
+                     full_scan,
+                     git_head_changed,
                      html,
-                     style)?;
+                     style,
+                     config)?;
+            blogcache.sort_posts();
+            blogcache.insert_aliases(oldcache);
+            blogcache.find_duplicate_slugs();
+            for (slug, paths) in &blogcache.duplicate_slugs {
+                match config.duplicate_slugs {
+                    DuplicateSlugPolicy::Allow => (),
+                    DuplicateSlugPolicy::Warn => {
+                        let is_new = oldcache.is_none_or(|c| {
+                            ! c.duplicate_slugs.iter().any(|(s, p)| s == slug && p == paths)
+                        });
+                        if is_new {
+                            warn!("blog {basepath:?}: duplicate slug {:?} in {paths:?}",
+                                  slug.as_str());
+                        }
+                    }
+                    DuplicateSlugPolicy::Error =>
+                        bail!("blog {basepath:?}: duplicate slug {:?} in {paths:?}",
+                              slug.as_str()),
+                }
+            }
             Ok(blogcache)
         }
     }
@@ -494,51 +900,66 @@ pub struct Blog {
     basepath: Box<Path>,
     blogcache: MiniArcSwap<BlogCache>,
     style: Arc<dyn StylingInterface>,
+    config: BlogConfig,
     allocpool: &'static HtmlAllocatorPool,
+    /// Held while rebuilding the cache, so that a rebuild always
+    /// starts from the result of the previous one.
+    rebuild_lock: Mutex<()>,
     // ^ go Arc instead of 'static? -- XX not even needed, just have
     // updater_thread have it, handlers will get it anyway
     // updater_thread: JoinHandle<()>,
 }
 
+/// Every how many scans of the blog directory (which happen every
+/// 400 ms) to do a full scan, which also picks up files edited in
+/// place, see `populate`.
+const FULL_SCAN_EVERY: u32 = 25;
+
 impl Blog {
     pub fn open<P: IntoBoxPath>(
         basepath: P,
         allocpool: &'static HtmlAllocatorPool,
         style: Arc<dyn StylingInterface>
     ) -> Result<Arc<Blog>>
+    {
+        Self::open_with_config(basepath, allocpool, style, BlogConfig::default())
+    }
+
+    pub fn open_with_config<P: IntoBoxPath>(
+        basepath: P,
+        allocpool: &'static HtmlAllocatorPool,
+        style: Arc<dyn StylingInterface>,
+        config: BlogConfig,
+    ) -> Result<Arc<Blog>>
     {
         let basepath = basepath.into_box_path();
         let blogcache = {
             let allocguard = allocpool.get();
             Arc::new(BlogCache::from_dir(&basepath,
                                          None,
+                                         true,
                                          &*allocguard,
-                                         &*style)?)
+                                         &*style,
+                                         &config)?)
         };
         let blog = Arc::new(Blog {
             basepath: basepath.into_box_path(),
             blogcache: MiniArcSwap::new(blogcache),
             allocpool,
+            rebuild_lock: Mutex::new(()),
             style,
+            config,
         });
         let _updater_thread =
             thread::Builder::new().name("blog_updater".into()).spawn({
                 let blog = Arc::clone(&blog);
                 move || -> ! {
+                    let mut num_scans: u32 = 0;
                     loop_try! {
                         thread::sleep(Duration::from_millis(400));
-                        match catch_unwind(|| -> Result<()> {
-                            let oldblogcache = blog.blogcache.get();
-                            let allocguard = blog.allocpool.get();
-                            let newblogcache = BlogCache::from_dir(
-                                &blog.basepath,
-                                Some(oldblogcache.router.trie()),
-                                &*allocguard,
-                                &*blog.style)?;
-                            // ah, and need a way to know if new? actually
-                            // doesn't matter, just publish it:
-                            blog.blogcache.set(Arc::new(newblogcache));
-                            Ok(())
+                        num_scans = num_scans.wrapping_add(1);
+                        match catch_unwind(|| {
+                            blog.rebuild(num_scans.is_multiple_of(FULL_SCAN_EVERY))
                         }) {
                             Ok(Ok(())) => Ok(()),
                             Ok(Err(e)) => Err(e),
@@ -552,8 +973,298 @@ impl Blog {
         Ok(blog)
     }
 
+    fn rebuild(&self, full_scan: bool) -> Result<()> {
+        // (Poisoning, from a panic in an earlier rebuild, is
+        // harmless as the lock guards no data)
+        let _guard = self.rebuild_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let oldblogcache = self.blogcache.get();
+        let allocguard = self.allocpool.get();
+        let newblogcache = BlogCache::from_dir(
+            &self.basepath,
+            Some(&oldblogcache),
+            full_scan,
+            &*allocguard,
+            &*self.style,
+            &self.config)?;
+        // ah, and need a way to know if new? actually
+        // doesn't matter, just publish it:
+        self.blogcache.set(Arc::new(newblogcache));
+        Ok(())
+    }
+
+    /// Rescan the blog directory (fully, see `populate`) right away
+    /// instead of waiting for the updater thread, e.g. from a deploy
+    /// hook. When this returns, `blogcache` gives the new state.
+    pub fn rebuild_now(&self) -> Result<()> {
+        self.rebuild(true)
+    }
+
     pub fn blogcache(&self) -> Arc<BlogCache> {
         self.blogcache.get()
     }
+
+    pub fn config(&self) -> &BlogConfig {
+        &self.config
+    }
+
+    /// The footnotes style given to `open`.
+    pub fn style(&self) -> &dyn StylingInterface {
+        &*self.style
+    }
+
+    /// The pool given to `open`, e.g. for building pages that are
+    /// sent via `htmlresponse_streaming`.
+    pub fn allocpool(&self) -> &'static HtmlAllocatorPool {
+        self.allocpool
+    }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use crate::{style::footnotes::BlogStyle, ppath::PPath, test_util::TempDir};
+
+    use super::*;
+
+    #[test]
+    fn t_posts_sorted() -> Result<()> {
+        let dir = TempDir::new("t_posts_sorted")?;
+        for (dir2, file, title) in [("2023/10/23", "b.md", "B"),
+                                    ("2023/10/23", "a.md", "A"),
+                                    ("2022/12/01", "old.md", "Old"),
+                                    ("2023/11/02", "new.md", "New")] {
+            let d = dir.join(dir2);
+            std::fs::create_dir_all(&d)?;
+            std::fs::write(d.join(file), format!("# {title}\n\nText.\n"))?;
+        }
+        let html = HtmlAllocator::new(100000, Arc::new(""));
+        let blogcache = BlogCache::from_dir(&dir, None, true, &html, &BlogStyle::default(),
+                                            &BlogConfig::default())?;
+
+        let posts: Vec<(String, &str)> = blogcache.posts_sorted().into_iter().map(
+            |(path, post)| (path.join("/"), post.title_plain.as_str())).collect();
+        assert_eq!(posts,
+                   [("2023/11/02/new.html".into(), "New"),
+                    ("2023/10/23/b.html".into(), "B"),
+                    ("2023/10/23/a.html".into(), "A"),
+                    ("2022/12/01/old.html".into(), "Old")]);
+        // The index nodes are in the trie, but not in the list
+        assert!(matches!(blogcache.router.get_trie(&PPath::<KString>::from_str("2023"))
+                         .and_then(|trie| trie.endpoint()),
+                         Some(BlogNode::BlogPostIndex(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn t_skip_unchanged_dirs() -> Result<()> {
+        let dir = TempDir::new("t_skip_unchanged_dirs")?;
+        for (dir2, file, title) in [("2023/10/23", "a.md", "A"),
+                                    ("2023/11/02", "b.md", "B")] {
+            let d = dir.join(dir2);
+            std::fs::create_dir_all(&d)?;
+            std::fs::write(d.join(file), format!("# {title}\n\nText.\n"))?;
+        }
+        let html = HtmlAllocator::new(100000, Arc::new(""));
+        let from_dir = |oldcache, full_scan| BlogCache::from_dir(
+            &dir, oldcache, full_scan, &html, &BlogStyle::default(), &BlogConfig::default());
+        let titles = |blogcache: &BlogCache| -> Vec<String> {
+            blogcache.posts_sorted().into_iter().map(
+                |(_, post)| post.title_plain.to_string()).collect()
+        };
+
+        let c1 = from_dir(None, true)?;
+        assert_eq!(titles(&c1), ["B", "A"]);
+
+        // Editing in place doesn't change the directory, thus isn't
+        // seen,
+        std::fs::write(dir.join("2023/10/23/a.md"), "# A2\n\nText.\n")?;
+        let c2 = from_dir(Some(&c1), false)?;
+        assert_eq!(titles(&c2), ["B", "A"]);
+        // but a new file further down in another directory is
+        std::fs::write(dir.join("2023/11/02/c.md"), "# C\n\nText.\n")?;
+        let c3 = from_dir(Some(&c2), false)?;
+        assert_eq!(titles(&c3), ["C", "B", "A"]);
+        // and the in-place edit is picked up with a full scan.
+        let c4 = from_dir(Some(&c3), true)?;
+        assert_eq!(titles(&c4), ["C", "B", "A2"]);
+
+        // Removing a post
+        std::fs::remove_file(dir.join("2023/11/02/b.md"))?;
+        let c5 = from_dir(Some(&c4), false)?;
+        assert_eq!(titles(&c5), ["C", "A2"]);
+        Ok(())
+    }
+
+    #[test]
+    fn t_rebuild_now() -> Result<()> {
+        let dir = TempDir::new("t_rebuild_now")?;
+        std::fs::create_dir_all(dir.join("2023/10/23"))?;
+        std::fs::write(dir.join("2023/10/23/a.md"), "# A\n\nText.\n")?;
+        let allocpool: &'static HtmlAllocatorPool = Box::leak(Box::new(
+            HtmlAllocatorPool::new(100000, true, Arc::new("t_rebuild_now"))));
+        let blog = Blog::open(dir.to_path_buf(), allocpool, Arc::new(BlogStyle::default()))?;
+        let titles = || -> Vec<String> {
+            blog.blogcache().posts_sorted().into_iter().map(
+                |(_, post)| post.title_plain.to_string()).collect()
+        };
+        assert_eq!(titles(), ["A"]);
+
+        // Also picks up edits in place, which the updater only sees
+        // with its full scans
+        std::fs::write(dir.join("2023/10/23/a.md"), "# A2\n\nText.\n")?;
+        std::fs::write(dir.join("2023/10/23/b.md"), "# B\n\nText.\n")?;
+        blog.rebuild_now()?;
+        assert_eq!(titles(), ["B", "A2"]);
+        Ok(())
+    }
+
+    #[test]
+    fn t_duplicate_slugs() -> Result<()> {
+        let dir = TempDir::new("t_duplicate_slugs")?;
+        for (dir2, file) in [("2023/10/23", "post.md"),
+                             ("2024/01/02", "post.md"),
+                             ("2024/01/02", "other.md")] {
+            let d = dir.join(dir2);
+            std::fs::create_dir_all(&d)?;
+            std::fs::write(d.join(file), "# Title\n\nText.\n")?;
+        }
+        let html = HtmlAllocator::new(100000, Arc::new(""));
+        let from_dir = |duplicate_slugs| BlogCache::from_dir(
+            &dir, None, true, &html, &BlogStyle::default(),
+            &BlogConfig { duplicate_slugs, ..Default::default() });
+
+        let blogcache = from_dir(DuplicateSlugPolicy::Warn)?;
+        assert_eq!(blogcache.duplicate_slugs(),
+                   [(KString::from_static("post.html"),
+                     vec!["2023/10/23/post.html".to_string(),
+                          "2024/01/02/post.html".to_string()])]);
+        assert_eq!(blogcache.posts_sorted().len(), 3);
+
+        let err = from_dir(DuplicateSlugPolicy::Error).expect_err("fails");
+        assert!(err.to_string().contains(
+            "duplicate slug \"post.html\" in [\"2023/10/23/post.html\", \
+             \"2024/01/02/post.html\"]"),
+                "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn t_empty_posts() -> Result<()> {
+        let dir = TempDir::new("t_empty_posts")?;
+        let d = dir.join("2023/10/23");
+        std::fs::create_dir_all(&d)?;
+        std::fs::write(d.join("post.md"), "# Title\n\nText.\n")?;
+        std::fs::write(d.join("draft.md"), " \n\n")?;
+        let html = HtmlAllocator::new(100000, Arc::new(""));
+        let from_dir = |empty_posts| BlogCache::from_dir(
+            &dir, None, true, &html, &BlogStyle::default(),
+            &BlogConfig { empty_posts, ..Default::default() });
+        let posts = |blogcache: &BlogCache| -> Vec<(String, String)> {
+            blogcache.posts_sorted().into_iter().map(
+                |(path, post)| (path.join("/"), post.title_plain.to_string())).collect()
+        };
+        let draft = PPath::<KString>::from_str("2023/10/23/draft.html");
+
+        let blogcache = from_dir(EmptyPostPolicy::Publish)?;
+        assert_eq!(posts(&blogcache),
+                   [("2023/10/23/post.html".into(), "Title".into()),
+                    ("2023/10/23/draft.html".into(), "(missing title)".into())]);
+
+        let blogcache = from_dir(EmptyPostPolicy::Skip)?;
+        assert_eq!(posts(&blogcache), [("2023/10/23/post.html".into(), "Title".into())]);
+        assert!(blogcache.router.get_trie(&draft).and_then(|trie| trie.endpoint()).is_none());
+
+        let err = from_dir(EmptyPostPolicy::Error).expect_err("fails");
+        assert!(err.to_string().contains("empty blog post"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn t_description() -> Result<()> {
+        let dir = TempDir::new("t_description")?;
+        let d = dir.join("2023/10/23");
+        std::fs::create_dir_all(&d)?;
+        std::fs::write(d.join("quote.md"),
+                       "# Quote\n\n> quoted *text*\n> here\n\nSecond.\n")?;
+        std::fs::write(d.join("long.md"),
+                       format!("# Long\n\n{}\n", "Lörem ipsum ".repeat(20)))?;
+        std::fs::write(d.join("none.md"), "# None\n\n## Section\n")?;
+        let html = HtmlAllocator::new(100000, Arc::new(""));
+        let blogcache = BlogCache::from_dir(&dir, None, true, &html, &BlogStyle::default(),
+                                            &BlogConfig::default())?;
+        let description = |file: &str| blogcache.posts_sorted().into_iter().find(
+            |(path, _)| path.last().is_some_and(|f| *f == file))
+            .expect("post exists").1.description.to_string();
+        assert_eq!(description("quote.html"), "quoted text here");
+        assert_eq!(description("none.html"), "");
+        let long = description("long.html");
+        assert_eq!(long, format!("{}…", "Lörem ipsum ".repeat(13).trim_end()));
+        assert!(long.chars().count() <= DESCRIPTION_MAX_CHARS + 1);
+        Ok(())
+    }
+
+    #[test]
+    fn t_git_timestamps() -> Result<()> {
+        if ! Command::new("git").arg("--version").output().is_ok_and(|o| o.status.success()) {
+            eprintln!("t_git_timestamps: skipped, git is not available");
+            return Ok(())
+        }
+        let dir = TempDir::new("t_git_timestamps")?;
+        // The repository contains the blog directory
+        let blogdir = dir.join("blog");
+        let d = blogdir.join("2023/10/23");
+        std::fs::create_dir_all(&d)?;
+        std::fs::write(d.join("committed.md"), "# Committed\n")?;
+        std::fs::write(d.join("untracked.md"), "# Untracked\n")?;
+        let git = |args: &[&str]| -> Result<()> {
+            let status = Command::new("git").arg("-C").arg(dir.path())
+                .args(["-c", "user.name=T", "-c", "user.email=t@example.com",
+                       "-c", "commit.gpgsign=false"])
+                .args(args)
+                .env("GIT_COMMITTER_DATE", "@1600000000 +0000")
+                .status()?;
+            if ! status.success() { bail!("git {args:?} failed") }
+            Ok(())
+        };
+        git(&["init", "-q"])?;
+        git(&["add", "blog/2023/10/23/committed.md"])?;
+        git(&["commit", "-q", "-m", "post"])?;
+        let html = HtmlAllocator::new(100000, Arc::new(""));
+        let from_dir = |oldcache, git_timestamps| BlogCache::from_dir(
+            &blogdir, oldcache, true, &html, &BlogStyle::default(),
+            &BlogConfig { git_timestamps, ..Default::default() });
+        let modified_in = |blogcache: &BlogCache| -> Vec<(String, SystemTime)> {
+            let mut modified: Vec<_> = blogcache.posts_sorted().into_iter().map(|(path, post)| {
+                // The ETag changes together with Last-Modified, and
+                // with the file
+                assert_eq!(post.etag, post_etag(&post.cmpfilemeta, post.git_commit_time));
+                assert!(post.etag.starts_with(&post.cmpfilemeta.etag_value()));
+                (path.join("/"), post.modified())
+            }).collect();
+            modified.sort();
+            modified
+        };
+        let modified = |git_timestamps| -> Result<Vec<(String, SystemTime)>> {
+            Ok(modified_in(&from_dir(None, git_timestamps)?))
+        };
+        let mtime = |file: &str| -> Result<SystemTime> {
+            Ok(d.join(file).metadata()?.modified()?)
+        };
+        let commit_time = SystemTime::UNIX_EPOCH + Duration::from_secs(1600000000);
+        let cache = from_dir(None, true)?;
+        assert_eq!(modified_in(&cache),
+                   [("2023/10/23/committed.html".into(), commit_time),
+                    ("2023/10/23/untracked.html".into(), mtime("untracked.md")?)]);
+        assert_eq!(modified(false)?,
+                   [("2023/10/23/committed.html".into(), mtime("committed.md")?),
+                    ("2023/10/23/untracked.html".into(), mtime("untracked.md")?)]);
+
+        // Committing doesn't change the file, but HEAD
+        git(&["add", "blog/2023/10/23/untracked.md"])?;
+        git(&["commit", "-q", "-m", "another post"])?;
+        assert_eq!(modified_in(&from_dir(Some(&cache), true)?),
+                   [("2023/10/23/committed.html".into(), commit_time),
+                    ("2023/10/23/untracked.html".into(), commit_time)]);
+        Ok(())
+    }
+}
@@ -1,7 +1,7 @@
 //! File metadata that can be used as key to verify (with good chance,
 //! given good faith actors) if a file has changed on disk.
 
-use std::{time::SystemTime, fs::Metadata, os::unix::prelude::MetadataExt};
+use std::{time::{SystemTime, UNIX_EPOCH}, fs::Metadata, os::unix::prelude::MetadataExt};
 
 use anyhow::Result;
 
@@ -16,6 +16,25 @@ pub struct CmpFileMeta {
     pub len: u64, // size
 }
 
+fn nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos())
+}
+
+impl CmpFileMeta {
+    /// A value for an `ETag` (without the quotes) that changes when
+    /// the file changes, in the style of Apache (inode, size, mtime).
+    pub fn etag_value(&self) -> String {
+        format!("{:x}-{:x}-{:x}", self.ino, self.len, nanos(self.modified_time))
+    }
+
+    /// `etag_value`, with `time` added (e.g. when that is what's
+    /// reported as `Last-Modified`, and can change while the file
+    /// doesn't).
+    pub fn etag_value_with_time(&self, time: SystemTime) -> String {
+        format!("{}-{:x}", self.etag_value(), nanos(time))
+    }
+}
+
 pub trait GetCmpFileMeta {
     fn cmpfilemeta(&self) -> Result<CmpFileMeta>;
 }
@@ -0,0 +1,215 @@
+//! Serve CSS assembled from several sources as one file, at a URL
+//! containing a hash of the contents so that it can be cached forever
+//! (a change gives a new URL), see `CssBundleHandler`.
+
+use std::{fmt::Debug, path::PathBuf, sync::Arc, time::SystemTime};
+
+use anyhow::{Result, Context, anyhow, bail};
+use kstring::KString;
+
+use ahtml::HtmlAllocator;
+use chj_util::warn;
+
+use crate::{acontext::AContext,
+            aresponse::AResponse,
+            file_source::MemoryFileSource,
+            handler::{Handler, FileHandler},
+            http_request_method::HttpRequestMethodSimple,
+            language::Language,
+            miniarcswap::MiniArcSwap,
+            ppath::PPath};
+
+/// For URLs that never change their contents.
+pub const CACHE_CONTROL_IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// Number of hex digits of the hash used in the URL.
+const HASH_LEN: usize = 16;
+
+/// A part of a `CssBundleHandler` bundle.
+#[derive(Debug, Clone)]
+pub enum CssSource {
+    File(PathBuf),
+    /// E.g. generated CSS.
+    Text(KString),
+}
+
+#[derive(Debug)]
+struct CssBundle {
+    /// Serving the bundle as its only file, named `file_name(hash)`
+    files: FileHandler,
+    /// Hex, `HASH_LEN` digits
+    hash: String,
+    /// The modification times and sizes of the `CssSource::File`
+    /// sources
+    stamps: Vec<(SystemTime, u64)>,
+}
+
+fn file_stamps(sources: &[CssSource]) -> Result<Vec<(SystemTime, u64)>> {
+    sources.iter().filter_map(|source| match source {
+        CssSource::File(path) => Some(
+            path.metadata().and_then(|m| Ok((m.modified()?, m.len()))).with_context(
+                || anyhow!("CSS source {path:?}"))),
+        CssSource::Text(_) => None,
+    }).collect()
+}
+
+impl CssBundle {
+    fn build(sources: &[CssSource]) -> Result<CssBundle> {
+        // Get the times first, so that changes while reading are
+        // seen next time
+        let stamps = file_stamps(sources)?;
+        let mut data = Vec::new();
+        for source in sources {
+            match source {
+                CssSource::File(path) => {
+                    let mut css = std::fs::read(path).with_context(
+                        || anyhow!("reading CSS source {path:?}"))?;
+                    data.append(&mut css);
+                }
+                CssSource::Text(css) => data.extend_from_slice(css.as_bytes()),
+            }
+            if ! data.ends_with(b"\n") {
+                data.push(b'\n');
+            }
+        }
+        let hash = blake3::hash(&data).to_hex()[..HASH_LEN].to_string();
+        let mut source = MemoryFileSource::new();
+        source.insert(file_name(&hash), data, "text/css; charset=utf-8", SystemTime::now());
+        let files = FileHandler::with_source(source)
+            .with_cache_control(CACHE_CONTROL_IMMUTABLE);
+        Ok(CssBundle { files, hash, stamps })
+    }
+}
+
+fn file_name(hash: &str) -> String {
+    format!("style.{hash}.css")
+}
+
+/// Serves the concatenation of `sources` at `{mount_path}style.{hash}.css`,
+/// with `CACHE_CONTROL_IMMUTABLE`; `url()` gives that URL, for the
+/// layout to link to. Requests for other hashes (older versions) get
+/// a 404. The bundle is built when creating the handler, and in
+/// `dev_mode` rebuilt whenever a source file was modified (checked
+/// on each request and `url()` call).
+pub struct CssBundleHandler {
+    mount_path: KString,
+    sources: Vec<CssSource>,
+    dev_mode: bool,
+    bundle: MiniArcSwap<CssBundle>,
+}
+
+impl Debug for CssBundleHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CssBundleHandler")
+            .field("mount_path", &self.mount_path)
+            .field("sources", &self.sources)
+            .field("dev_mode", &self.dev_mode)
+            .finish()
+    }
+}
+
+impl CssBundleHandler {
+    /// `mount_path`: the URL path the handler is mounted at, e.g.
+    /// "/assets/".
+    pub fn new(
+        mount_path: &str,
+        sources: Vec<CssSource>,
+        dev_mode: bool,
+    ) -> Result<CssBundleHandler> {
+        if ! (mount_path.starts_with('/') && mount_path.ends_with('/')) {
+            bail!("mount_path must start and end with '/', got {mount_path:?}")
+        }
+        let bundle = CssBundle::build(&sources)?;
+        Ok(CssBundleHandler {
+            mount_path: KString::from_ref(mount_path),
+            sources,
+            dev_mode,
+            bundle: MiniArcSwap::new(Arc::new(bundle)),
+        })
+    }
+
+    /// The current bundle, rebuilt first if in `dev_mode` and a
+    /// source changed. On errors rebuilding, warns and keeps the
+    /// previous bundle.
+    fn bundle(&self) -> Arc<CssBundle> {
+        let bundle = self.bundle.get();
+        if self.dev_mode {
+            let changed = file_stamps(&self.sources).map_or(true, |s| s != bundle.stamps);
+            if changed {
+                match CssBundle::build(&self.sources) {
+                    Ok(new) => {
+                        let new = Arc::new(new);
+                        self.bundle.set(new.clone());
+                        return new
+                    }
+                    Err(e) => warn!("CssBundleHandler: keeping previous bundle: {e:#}"),
+                }
+            }
+        }
+        bundle
+    }
+
+    /// The URL path of the current bundle.
+    pub fn url(&self) -> String {
+        format!("{}{}", self.mount_path, file_name(&self.bundle().hash))
+    }
+}
+
+impl<L: Language> Handler<L> for CssBundleHandler {
+    fn call<'a>(
+        &self,
+        context: &AContext<L>,
+        method: HttpRequestMethodSimple,
+        pathrest: &PPath<KString>,
+        html: &HtmlAllocator)
+        -> Result<Option<AResponse>>
+    {
+        self.bundle().files.call(context, method, pathrest, html)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{lang_en_de::Lang, test_util::{fake_context, TempDir}};
+
+    use super::*;
+
+    #[test]
+    fn t_css_bundle_handler() -> Result<()> {
+        let dir = TempDir::new("t_css_bundle_handler")?;
+        let a = dir.join("a.css");
+        std::fs::write(&a, "body { margin: 0 }")?;
+        let bundle = Arc::new(CssBundleHandler::new(
+            "/assets/",
+            vec![CssSource::File(a.clone()),
+                 CssSource::Text(KString::from_static("p { color: red }\n"))],
+            true)?);
+        let handler: Arc<dyn Handler<Lang>> = bundle.clone();
+        let get = |url: &str, headers: &[(&str, &str)]| {
+            let pathrest = url.strip_prefix("/assets/").expect("mount path");
+            fake_context("GET", url, headers, &[]).call_with_pathrest(&handler, pathrest)
+        };
+
+        let content = "body { margin: 0 }\np { color: red }\n";
+        let url = bundle.url();
+        assert_eq!(url, format!("/assets/style.{}.css",
+                                &blake3::hash(content.as_bytes()).to_hex()[..HASH_LEN]));
+        let r = get(&url, &[])?.expect("handled");
+        assert_eq!(r.status_code, 200);
+        assert_eq!(r.body, content);
+        assert_eq!(r.header("Cache-Control"), Some(CACHE_CONTROL_IMMUTABLE));
+        let etag = r.header("ETag").expect("etag").to_string();
+        let r = get(&url, &[("If-None-Match", &etag)])?.expect("handled");
+        assert_eq!(r.status_code, 304);
+
+        // Changing a source gives a new URL, the old one is gone
+        std::fs::write(&a, "body { margin: 1em }\n")?;
+        let new_url = bundle.url();
+        assert_ne!(new_url, url);
+        assert_eq!(get(&new_url, &[])?.expect("handled").body,
+                   "body { margin: 1em }\np { color: red }\n");
+        assert!(get(&url, &[])?.is_none());
+        Ok(())
+    }
+}
@@ -2,7 +2,7 @@
 
 use std::time::SystemTime;
 
-use chrono::{TimeZone, DateTime, Timelike, Datelike};
+use chrono::{TimeZone, DateTime, Timelike, Datelike, NaiveDate};
 use chrono_tz::Tz;
 
 use crate::lang_en_de::Lang;
@@ -16,6 +16,15 @@ pub const fn months_short(lang: Lang) -> &'static [&'static str; 12] {
     }
 }
 
+pub const fn months_long(lang: Lang) -> &'static [&'static str; 12] {
+    match lang {
+        Lang::En => &["January", "February", "March", "April", "May", "June",
+                      "July", "August", "September", "October", "November", "December"],
+        Lang::De => &["Januar", "Februar", "März", "April", "Mai", "Juni",
+                      "Juli", "August", "September", "Oktober", "November", "Dezember"],
+    }
+}
+
 pub const fn wdays_short(lang: Lang) -> &'static [&'static str; 7] {
     match lang {
         Lang::En => &["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"],
@@ -61,4 +70,14 @@ pub fn date_format_httplike(t: SystemTime, zone: Tz, lang: Lang) -> String {
     }
 }
 
-
+/// A calendar date as it is written in running text, e.g. "October
+/// 23, 2023" or "23. Oktober 2023".
+pub fn date_format_naivedate(date: NaiveDate, lang: Lang) -> String {
+    let day = date.day();
+    let month = months_long(lang)[date.month0() as usize];
+    let year = date.year();
+    match lang {
+        Lang::En => format!("{month} {day}, {year}"),
+        Lang::De => format!("{day}. {month} {year}"),
+    }
+}
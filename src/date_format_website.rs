@@ -7,10 +7,11 @@
 
 use std::time::SystemTime;
 
+use chrono::NaiveDate;
 use chrono_tz::Europe::Zurich;
 
 use crate::{language::Language,
-            date_format::date_format_httplike,
+            date_format::{date_format_httplike, date_format_naivedate},
             lang_en_de::Lang};
 
 pub fn date_format_httplike_switzerland<L: Language>(t: SystemTime, lang: L) -> String {
@@ -18,6 +19,10 @@ pub fn date_format_httplike_switzerland<L: Language>(t: SystemTime, lang: L) ->
     date_format_httplike(t, Zurich, Lang::verbose_from(langname))
 }
 
+pub fn date_format_naivedate_website<L: Language>(date: NaiveDate, lang: L) -> String {
+    date_format_naivedate(date, Lang::verbose_from(lang.as_str()))
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
@@ -60,4 +65,13 @@ mod tests {
                    "So, 31. Mär 2024 03:00:00 CEST");
     }
 
+    #[test]
+    fn t_naivedate() {
+        let d = NaiveDate::from_ymd_opt(2023, 10, 23).unwrap();
+        assert_eq!(date_format_naivedate_website(d, Lang::En), "October 23, 2023");
+        assert_eq!(date_format_naivedate_website(d, Lang::De), "23. Oktober 2023");
+        let d = NaiveDate::from_ymd_opt(987, 3, 1).unwrap();
+        assert_eq!(date_format_naivedate_website(d, Lang::En), "March 1, 987");
+        assert_eq!(date_format_naivedate_website(d, Lang::De), "1. März 987");
+    }
 }
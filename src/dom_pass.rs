@@ -0,0 +1,201 @@
+//! Post-processing of converted documents by independent passes over
+//! the DOM, see `DomPass` and `ProcessedMarkdown::apply_dom_passes`.
+
+use anyhow::Result;
+use kstring::KString;
+
+use ahtml::{AId, ASlice, HtmlAllocator, Node, Element, A_META, IMG_META, IFRAME_META};
+
+use crate::auri::has_scheme;
+
+/// A transformation of a document tree. Passes are applied in
+/// order, each one getting the root returned by the previous one.
+pub trait DomPass: Send + Sync {
+    /// `root` with the changes applied; `root` itself if there's
+    /// nothing to change.
+    fn apply(&self, root: AId<Node>, html: &HtmlAllocator) -> Result<AId<Node>>;
+}
+
+/// Apply `passes` to `root`, in order.
+pub fn apply_dom_passes(
+    passes: &[Box<dyn DomPass>],
+    root: AId<Node>,
+    html: &HtmlAllocator,
+) -> Result<AId<Node>> {
+    passes.iter().try_fold(root, |root, pass| pass.apply(root, html))
+}
+
+/// For `map_attributes`: the new attributes for an element, if any.
+pub type AttributesFn<'f> =
+    dyn Fn(&Element) -> Result<Option<ASlice<(KString, KString)>>> + 'f;
+
+/// `root` with the attributes of all elements (including `root`
+/// itself) replaced by what `f` returns for them, where it returns
+/// `Some`. Unchanged subtrees are re-used. A building block for
+/// passes.
+pub fn map_attributes(
+    root: AId<Node>,
+    html: &HtmlAllocator,
+    f: &AttributesFn,
+) -> Result<AId<Node>> {
+    Ok(map_attributes_changed(root, html, f)?.unwrap_or(root))
+}
+
+/// None if nothing changed below and including `id`.
+fn map_attributes_changed(
+    id: AId<Node>,
+    html: &HtmlAllocator,
+    f: &AttributesFn,
+) -> Result<Option<AId<Node>>> {
+    let node = html.get_node(id).expect("valid id");
+    let map_body = |body: &ASlice<Node>| -> Result<Option<ASlice<Node>>> {
+        let mut out = html.new_vec();
+        let mut changed = false;
+        for child in body.iter_aid(html) {
+            if let Some(new_child) = map_attributes_changed(child, html, f)? {
+                changed = true;
+                out.push(new_child)?;
+            } else {
+                out.push(child)?;
+            }
+        }
+        Ok(changed.then(|| out.as_slice()))
+    };
+    match node {
+        Node::Element(e) => {
+            let attr = f(e)?;
+            let body = map_body(&e.body)?;
+            if attr.is_none() && body.is_none() {
+                return Ok(None)
+            }
+            Ok(Some(html.new_element(e.meta,
+                                     attr.unwrap_or(e.attr),
+                                     body.unwrap_or(e.body))?))
+        }
+        Node::Fragment(body) =>
+            map_body(body)?.map(|body| html.fragment(body)).transpose(),
+        _ => Ok(None),
+    }
+}
+
+/// The attributes of `element` plus `key` with `val`, or None if it
+/// already has a `key` attribute.
+pub fn add_missing_attribute(
+    element: &Element,
+    key: &str,
+    val: &KString,
+    html: &HtmlAllocator,
+) -> Result<Option<ASlice<(KString, KString)>>> {
+    if element.has_attribute(key, html) {
+        return Ok(None)
+    }
+    let mut attr = html.new_vec();
+    attr.extend_from_slice(&element.attr, html)?;
+    attr.push(html.attribute(key, val)?)?;
+    Ok(Some(attr.as_slice()))
+}
+
+/// Adds `loading="lazy"` to `img` and `iframe` elements that don't
+/// have a `loading` attribute.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LazyLoading;
+
+impl DomPass for LazyLoading {
+    fn apply(&self, root: AId<Node>, html: &HtmlAllocator) -> Result<AId<Node>> {
+        let lazy = KString::from_static("lazy");
+        map_attributes(root, html, &|e| {
+            if e.meta == *IMG_META || e.meta == *IFRAME_META {
+                add_missing_attribute(e, "loading", &lazy, html)
+            } else {
+                Ok(None)
+            }
+        })
+    }
+}
+
+/// Adds a `rel` attribute to links to other sites (URLs with a
+/// `http` or `https` scheme, or protocol-relative ones), unless
+/// they already have one.
+#[derive(Debug, Clone)]
+pub struct ExternalLinkRel {
+    pub rel: KString,
+}
+
+impl Default for ExternalLinkRel {
+    fn default() -> Self {
+        ExternalLinkRel { rel: KString::from_static("noopener noreferrer") }
+    }
+}
+
+fn is_external_url(url: &str) -> bool {
+    if has_scheme(url) {
+        let scheme = url.split_once(':').expect("has scheme").0;
+        scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https")
+    } else {
+        url.starts_with("//")
+    }
+}
+
+impl DomPass for ExternalLinkRel {
+    fn apply(&self, root: AId<Node>, html: &HtmlAllocator) -> Result<AId<Node>> {
+        map_attributes(root, html, &|e| {
+            let is_external = e.meta == *A_META
+                && e.get_attribute("href", html).is_some_and(|href| is_external_url(href));
+            if is_external {
+                add_missing_attribute(e, "rel", &self.rel, html)
+            } else {
+                Ok(None)
+            }
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use ahtml::Print;
+
+    use crate::{markdown::{MarkdownFile, MarkdownConfig, MarkdownWrapper},
+                style::footnotes::BlogStyle};
+
+    use super::*;
+
+    #[test]
+    fn t_dom_passes() -> Result<()> {
+        let html = HtmlAllocator::new(10000, std::sync::Arc::new(""));
+        let passes: Vec<Box<dyn DomPass>> = vec![Box::new(LazyLoading),
+                                                 Box::new(ExternalLinkRel::default())];
+        let md = "![a](a.png) [b](https://b.example/) [c](/c) \
+                  <a href=\"//d.example/\" rel=\"me\">d</a>\n";
+        let convert = |wrapper| -> Result<String> {
+            let config = MarkdownConfig { wrapper, ..Default::default() };
+            let mut pmd = MarkdownFile::process_str_to_html(md, &config, &html)?;
+            pmd.apply_dom_passes(&passes, &html)?;
+            pmd.body().to_html_fragment_string(&html)
+        };
+        let expected = "<p><img src=\"a.png\" alt=\"a\" loading=\"lazy\"> \
+                        <a href=\"https://b.example/\" rel=\"noopener noreferrer\">b</a> \
+                        <a href=\"/c\">c</a> <a href=\"//d.example/\" rel=\"me\">d</a></p>";
+        assert_eq!(convert(MarkdownWrapper::default())?, expected);
+        assert_eq!(convert(MarkdownWrapper::None)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn t_dom_passes_footnotes() -> Result<()> {
+        let html = HtmlAllocator::new(10000, std::sync::Arc::new(""));
+        let passes: Vec<Box<dyn DomPass>> = vec![Box::new(LazyLoading),
+                                                 Box::new(ExternalLinkRel::default())];
+        let mut pmd = MarkdownFile::process_str_to_html(
+            "Text[^n].\n\n[^n]: See [b](https://b.example/) ![a](a.png)\n",
+            &MarkdownConfig::default(),
+            &html)?;
+        pmd.apply_dom_passes(&passes, &html)?;
+        let (_, footnotes) = pmd.meta().footnotes_html_fragment(&html, &BlogStyle::default())?;
+        let footnotes = footnotes.to_html_fragment_string(&html)?;
+        assert!(footnotes.contains(
+            "See <a href=\"https://b.example/\" rel=\"noopener noreferrer\">b</a> \
+             <img src=\"a.png\" alt=\"a\" loading=\"lazy\">"), "{footnotes}");
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+//! Where `FileHandler` gets the files from: the local file system, or
+//! memory (for tests, or assets bundled into the binary).
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Cursor};
+use std::os::linux::fs::MetadataExt;
+use std::path::{PathBuf, Path};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use anyhow::{Result, Context, anyhow};
+use kstring::KString;
+use rouille::extension_to_mime;
+
+use chj_util::warn;
+
+use crate::webutils::mime_with_charset;
+
+/// An opened file.
+pub struct SourceFile {
+    pub mimetype: Cow<'static, str>,
+    pub mtime: SystemTime,
+    pub size: usize,
+    pub reader: Box<dyn Read + Send>,
+}
+
+pub trait FileSource: Debug + Send + Sync {
+    /// `path` is relative and canonicalized (no "." or ".." segments,
+    /// no empty segments). Returns None if there's no file at `path`.
+    fn open(&self, path: &str) -> Result<Option<SourceFile>>;
+}
+
+/// The mime type for `path` based on its extension.
+pub fn mime_from_extension(path: &Path) -> Cow<'static, str> {
+    mime_with_charset(
+        if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+            // Not known to rouille
+            match extension.to_ascii_lowercase().as_str() {
+                "webp" => "image/webp",
+                "avif" => "image/avif",
+                _ => extension_to_mime(extension)
+            }
+        } else {
+            "text/plain" // XX ?
+        })
+}
+
+/// Files below a base directory in the local file system.
+#[derive(Debug)]
+pub struct FsFileSource {
+    basepath: PathBuf,
+}
+
+impl FsFileSource {
+    pub fn new(basepath: impl Into<PathBuf>) -> FsFileSource {
+        FsFileSource {
+            basepath: basepath.into()
+        }
+    }
+}
+
+impl FileSource for FsFileSource {
+    fn open(&self, path: &str) -> Result<Option<SourceFile>> {
+        let full_path: PathBuf = self.basepath.join(path);
+        // XX would we need better than extension based mime type
+        // matching?
+
+        // XX instead do File::open first and then get metadata from
+        // the fh: *does* this work (portably?) for directories, too?
+        let metadata =
+            match full_path.metadata() {
+                Ok(m) => m,
+                Err(e) =>
+                    match e.kind() {
+                        ErrorKind::NotFound => return Ok(None),
+                        _ => return Err(e).with_context(
+                            || anyhow!("can't open file for reading: {:?}",
+                                       full_path))
+                    }
+            };
+
+        if metadata.is_dir() {
+            warn!("is_dir, not handling dirs yet");
+            Ok(None)
+        } else if metadata.is_symlink() {
+            warn!("is_symlink, not handling symlinks yet");
+            Ok(None)
+        } else if metadata.is_file() {
+            match File::open(&full_path) {
+                Err(e) =>
+                    match e.kind() {
+                        ErrorKind::NotFound => Ok(None),
+                        _ => Err(e).with_context(
+                            || anyhow!("can't open file for reading: {:?}",
+                                       full_path))?
+                    },
+                Ok(fh) => Ok(Some(SourceFile {
+                    mimetype: mime_from_extension(&full_path),
+                    mtime: metadata.modified()?,
+                    // XX dangerous re panics?
+                    size: metadata.st_size() as usize,
+                    reader: Box::new(fh),
+                }))
+            }
+        } else {
+            warn!("neither file nor symlink nor dir: device file or fifo or socket?");
+            Ok(None)
+        }
+    }
+}
+
+/// Files held in memory, keyed by their relative path.
+#[derive(Debug, Default)]
+pub struct MemoryFileSource {
+    files: HashMap<PathBuf, (Arc<[u8]>, KString, SystemTime)>,
+}
+
+impl MemoryFileSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add or replace the file at `path` (relative, like
+    /// "css/main.css"), with the given contents, mime type (used
+    /// as is for the `Content-type` header) and modification time.
+    pub fn insert(
+        &mut self,
+        path: impl Into<PathBuf>,
+        data: impl Into<Arc<[u8]>>,
+        mimetype: &str,
+        mtime: SystemTime,
+    ) -> &mut Self {
+        self.files.insert(path.into(), (data.into(), KString::from_ref(mimetype), mtime));
+        self
+    }
+}
+
+impl FileSource for MemoryFileSource {
+    fn open(&self, path: &str) -> Result<Option<SourceFile>> {
+        Ok(self.files.get(Path::new(path)).map(
+            |(data, mimetype, mtime)| SourceFile {
+                mimetype: Cow::Owned(mimetype.to_string()),
+                mtime: *mtime,
+                size: data.len(),
+                reader: Box::new(Cursor::new(data.clone())),
+            }))
+    }
+}
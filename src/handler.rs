@@ -1,60 +1,30 @@
 //! Lower level astraction for request handlers. You usually want to
 //! use the higher level ones in `webparts.rs`.
 
-use std::fs::File;
-use std::io::ErrorKind;
 use std::marker::PhantomData;
-use std::os::linux::fs::MetadataExt;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
 use std::{fmt::Debug, any::type_name, path::PathBuf, borrow::Cow};
 
-use anyhow::{Result, Context, anyhow, bail};
-use httpdate::{fmt_http_date, parse_http_date};
+use anyhow::{Result, anyhow, bail};
+use httpdate::fmt_http_date;
 use kstring::KString;
-use rouille::{Response, extension_to_mime, ResponseBody};
+use rouille::{Response, ResponseBody};
 
 use ahtml::HtmlAllocator;
-use chj_util::warn;
 
 use crate::acontext::AContext;
 use crate::aresponse::AResponse;
+use crate::file_source::{FileSource, FsFileSource, SourceFile};
 use crate::http_request_method::HttpRequestMethodSimple;
 use crate::http_response_status_codes::HttpResponseStatusCode;
 use crate::language::Language;
 use crate::myasstr::MyAsStr;
 use crate::ppath::PPath;
 use crate::or_return_none;
-
-// Can't just check `mtime > modsince` since that's ~always true
-// because mtime has a nsec value, where modsince has 0
-// there. Sigh. fmt_http_date should not accept SystemTime please, and
-// SystemTime should have a second accessor or something; huh. And
-// can't get at contents at all directly, so can't write tv_sec()
-// accessor either.  `modsince.checked_sub(mtime)` doesn't work
-// either, takes a Duration as argument. duration_since it is, but
-// then have to do it both ways if wanting to know how far off it
-// is. Sick.
-// fn file_mtime_indicates_file_has_changed(mtime: SystemTime, modsince: SystemTime) -> bool {
-// }
-
-// But then, if just wanting to know if the file is *newer* than snapshot time:
- fn file_is_newer_than_snapshot_time(mtime: SystemTime, modsince: SystemTime) -> bool {
-     match mtime.duration_since(modsince) {
-         Err(_e) => {
-             // file is older than snapshot time; client is cheating,
-             // or file has been restored to an older version; in any
-             // case, it is not newer, so say no
-             false
-         }
-         Ok(secsnewer) => {
-             // Make sure it is at least a second newer, due to the
-             // rounding issue. Otherwise it would report a fake newer.
-             secsnewer >= Duration::from_secs(1)
-         }
-     }
-}
-
-
+use crate::webutils::{check_preconditions, errorpage_from_status,
+                      Precondition, accept_lists};
 
 
 // fn cow<'t1, T: Clone>(
@@ -91,6 +61,9 @@ where S: MyAsStr + 's
                 },
             // Oh, don't forget this one (multiple slashes to one):
             "" => (),
+            // Segments are decoded, `%2F` must not lead to path
+            // traversal
+            _ if segment.contains(['/', '\0']) => return None,
             _ => out.push(segment)
         }
     }
@@ -125,6 +98,231 @@ mod tests {
 
         assert_eq!(canonicalize_path(&["foo", "", ".", "", "", "a", ".", ""]),
                    Some(vec!["foo", "a"]));
+
+        // Decoded segments with slashes (from `%2F`) are refused
+        assert_eq!(canonicalize_path(&["a", "..%2F.."]), Some(vec!["a", "..%2F.."]));
+        assert_eq!(canonicalize_path(&["a", "../.."]), None);
+        assert_eq!(canonicalize_path(&["a/b"]), None);
+    }
+
+    #[test]
+    fn t_file_handler_percent_encoded() -> Result<()> {
+        use crate::{lang_en_de::Lang, test_util::{fake_context, TempDir}};
+
+        let dir = TempDir::new("t_file_handler_percent_encoded")?;
+        std::fs::create_dir_all(dir.join("x"))?;
+        std::fs::write(dir.join("café.html"), "<p>café</p>")?;
+        std::fs::write(dir.join("x").join("y"), "y")?;
+        let handler = FileHandler::new(dir.path());
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let get = |path| fake_context("GET", path, &[], &[]).with_context(
+            |context: &AContext<Lang>| {
+                Ok(handler.call(context, HttpRequestMethodSimple::GET, context.path(), &html)?
+                   .map(|r| r.response.status_code))
+            });
+        assert_eq!(get("/caf%C3%A9.html")?, Some(200));
+        assert_eq!(get("/x/y")?, Some(200));
+        // `%2F` is not a path separator
+        assert_eq!(get("/x%2Fy")?, None);
+        assert_eq!(get("/x/..%2F..%2Fetc%2Fpasswd")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn t_file_handler_memory_source() -> Result<()> {
+        use std::time::SystemTime;
+        use crate::{lang_en_de::Lang, test_util::fake_context,
+                    file_source::MemoryFileSource};
+
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut source = MemoryFileSource::new();
+        source.insert("css/main.css", &b"p { }"[..], "text/css; charset=utf-8", mtime);
+        let handler: Arc<dyn Handler<Lang>> = Arc::new(FileHandler::with_source(source));
+        let get = |path: &str, headers: &[(&str, &str)]| {
+            fake_context("GET", path, headers, &[]).call_with_pathrest(&handler, path)
+        };
+
+        let r = get("css/main.css", &[])?.expect("handled");
+        assert_eq!(r.status_code, 200);
+        assert_eq!(r.body, "p { }");
+        assert_eq!(r.header("Content-type"), Some("text/css; charset=utf-8"));
+        assert_eq!(r.header("Last-Modified"), Some("Tue, 14 Nov 2023 22:13:20 GMT"));
+        let etag = r.header("ETag").expect("has ETag").to_string();
+
+        let r = get("css/main.css", &[("If-None-Match", &etag)])?.expect("handled");
+        assert_eq!(r.status_code, 304);
+        assert_eq!(r.body, "");
+        let r = get("css/main.css", &[("If-Modified-Since", &fmt_http_date(SystemTime::now()))])?
+            .expect("handled");
+        assert_eq!(r.status_code, 304);
+        let r = get("css/main.css", &[("If-Match", "\"other\"")])?.expect("handled");
+        assert_eq!(r.status_code, 412);
+
+        assert!(get("css/other.css", &[])?.is_none());
+        assert!(get("css/./../css/main.css", &[])?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn t_file_handler_image_alternates() -> Result<()> {
+        use crate::{lang_en_de::Lang, test_util::fake_context,
+                    file_source::MemoryFileSource};
+
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut source = MemoryFileSource::new();
+        source.insert("a.jpg", &b"jpg a"[..], "image/jpeg", mtime)
+            .insert("a.avif", &b"avif a"[..], "image/avif", mtime)
+            .insert("a.webp", &b"webp a"[..], "image/webp", mtime)
+            .insert("b.png", &b"png b"[..], "image/png", mtime)
+            .insert("b.webp", &b"webp b"[..], "image/webp", mtime)
+            .insert("c.jpg", &b"jpg c"[..], "image/jpeg", mtime)
+            .insert("d.avif", &b"avif d"[..], "image/avif", mtime);
+        let handler: Arc<dyn Handler<Lang>> = Arc::new(
+            FileHandler::with_source(source).with_image_alternates());
+        let browser = "image/avif,image/webp,*/*";
+        let get = |path: &str, accept: &str| -> Result<(String, String, String)> {
+            let r = fake_context("GET", path, &[("Accept", accept)], &[])
+                .call_with_pathrest(&handler, path)?.expect("handled");
+            assert_eq!(r.header("Vary"), Some("Accept"));
+            Ok((r.body.clone(),
+                r.header("Content-type").expect("type").into(),
+                r.header("ETag").expect("etag").into()))
+        };
+
+        // AVIF preferred
+        let (body, mime, avif_etag) = get("a.jpg", browser)?;
+        assert_eq!((body.as_str(), mime.as_str()), ("avif a", "image/avif"));
+        // WebP if that's all the client takes, or all there is
+        let (body, mime, webp_etag) = get("a.jpg", "image/webp,*/*")?;
+        assert_eq!((body.as_str(), mime.as_str()), ("webp a", "image/webp"));
+        assert_ne!(avif_etag, webp_etag);
+        assert_eq!(get("b.png", browser)?.0, "webp b");
+        // The original if there's no alternate or the client doesn't
+        // list the types explicitly
+        let (body, mime, _) = get("c.jpg", browser)?;
+        assert_eq!((body.as_str(), mime.as_str()), ("jpg c", "image/jpeg"));
+        assert_eq!(get("a.jpg", "*/*")?.0, "jpg a");
+        assert_eq!(get("a.jpg", "image/avif;q=0,image/*")?.0, "jpg a");
+        // No alternates without the original
+        assert!(fake_context("GET", "d.jpg", &[("Accept", browser)], &[])
+                .call_with_pathrest(&handler, "d.jpg")?.is_none());
+
+        let r = fake_context("GET", "a.jpg", &[("Accept", browser),
+                                              ("If-None-Match", &avif_etag)], &[])
+            .call_with_pathrest(&handler, "a.jpg")?.expect("handled");
+        assert_eq!(r.status_code, 304);
+        Ok(())
+    }
+
+    #[test]
+    fn t_file_handler_charset() -> Result<()> {
+        use crate::{lang_en_de::Lang, test_util::{fake_context, TempDir}};
+
+        let dir = TempDir::new("t_file_handler_charset")?;
+        for file in ["a.html", "a.css", "a.js", "a.png", "a.webp", "a.avif"] {
+            std::fs::write(dir.join(file), "a")?;
+        }
+        let handler: Arc<dyn Handler<Lang>> = Arc::new(FileHandler::new(dir.path()));
+        let content_type = |path: &str| -> Result<Option<String>> {
+            let r = fake_context("GET", path, &[], &[])
+                .call_with_pathrest(&handler, path)?.expect("handled");
+            Ok(r.header("Content-type").map(String::from))
+        };
+        assert_eq!(content_type("a.html")?.as_deref(), Some("text/html; charset=utf-8"));
+        assert_eq!(content_type("a.css")?.as_deref(), Some("text/css; charset=utf-8"));
+        assert_eq!(content_type("a.js")?.as_deref(),
+                   Some("application/javascript; charset=utf-8"));
+        assert_eq!(content_type("a.png")?.as_deref(), Some("image/png"));
+        assert_eq!(content_type("a.webp")?.as_deref(), Some("image/webp"));
+        assert_eq!(content_type("a.avif")?.as_deref(), Some("image/avif"));
+        Ok(())
+    }
+
+    #[test]
+    fn t_hsts_handler() -> Result<()> {
+        use crate::{lang_en_de::Lang, test_util::fake_context};
+
+        let inner: Arc<dyn Handler<Lang>> = Arc::new(ExactFnHandler::new(
+            |_context, _method, _html| Ok(Response::text("hi").into())));
+        let handler: Arc<dyn Handler<Lang>> = Arc::new(HstsHandler::new(
+            inner, 31536000, true, vec!["127.0.0.1".parse()?], None));
+
+        // Plain HTTP is redirected, keeping the raw path and query
+        let r = fake_context("GET", "/a%20b", &[("Host", "example.com")], &[("x", "1")])
+            .call(&handler)?.expect("handled");
+        assert_eq!(r.status_code, 301);
+        assert_eq!(r.location(), Some("https://example.com/a%20b?x=1"));
+        assert_eq!(r.header("Strict-Transport-Security"), None);
+
+        let r = fake_context("POST", "/a", &[("Host", "example.com")], &[])
+            .call(&handler)?.expect("handled");
+        assert_eq!(r.status_code, 308);
+
+        // HTTPS via the trusted proxy gets the header
+        let r = fake_context("GET", "/a", &[("Host", "example.com"),
+                                            ("X-Forwarded-Proto", "https")], &[])
+            .call(&handler)?.expect("handled");
+        assert_eq!(r.status_code, 200);
+        assert_eq!(r.body, "hi");
+        assert_eq!(r.header("Strict-Transport-Security"),
+                   Some("max-age=31536000; includeSubDomains"));
+
+        // X-Forwarded-Proto from others is ignored
+        let inner: Arc<dyn Handler<Lang>> = Arc::new(ExactFnHandler::new(
+            |_context, _method, _html| Ok(Response::text("hi").into())));
+        let handler: Arc<dyn Handler<Lang>> = Arc::new(HstsHandler::new(
+            inner, 60, false, vec!["10.0.0.1".parse()?], None));
+        let r = fake_context("GET", "/a", &[("Host", "example.com"),
+                                            ("X-Forwarded-Proto", "https")], &[])
+            .call(&handler)?.expect("handled");
+        assert_eq!(r.status_code, 301);
+
+        // The port of plain HTTP is dropped; without Host header, no
+        // redirect to the listen address
+        let redirect = |handler: &Arc<dyn Handler<Lang>>, headers: &[(&str, &str)]|
+                        -> Result<(u16, Option<String>)> {
+            let r = fake_context("GET", "/a", headers, &[]).call(handler)?.expect("handled");
+            Ok((r.status_code, r.location().map(String::from)))
+        };
+        assert_eq!(redirect(&handler, &[("Host", "example.com:8080")])?,
+                   (301, Some("https://example.com/a".into())));
+        assert_eq!(redirect(&handler, &[("Host", "[::1]:8080")])?,
+                   (301, Some("https://[::1]/a".into())));
+        assert_eq!(redirect(&handler, &[])?.0, 400);
+
+        // The canonical host is used regardless of the request
+        let inner: Arc<dyn Handler<Lang>> = Arc::new(ExactFnHandler::new(
+            |_context, _method, _html| Ok(Response::text("hi").into())));
+        let handler: Arc<dyn Handler<Lang>> = Arc::new(HstsHandler::new(
+            inner, 60, false, vec![], Some("www.example.com")));
+        assert_eq!(redirect(&handler, &[("Host", "example.com:8080")])?,
+                   (301, Some("https://www.example.com/a".into())));
+        assert_eq!(redirect(&handler, &[])?,
+                   (301, Some("https://www.example.com/a".into())));
+        Ok(())
+    }
+
+    #[test]
+    fn t_mount_handler() -> Result<()> {
+        use crate::{lang_en_de::Lang, test_util::fake_context};
+
+        let inner: Arc<dyn Handler<Lang>> = Arc::new(FnHandler::new(
+            |_context: &AContext<Lang>, _method, pathrest: &PPath<KString>,
+             _html: &HtmlAllocator| {
+                Ok(Some(Response::text(pathrest.to_string()).into()))
+            }));
+        let handler: Arc<dyn Handler<Lang>> = Arc::new(MountHandler::new("/blog/", inner));
+        let get = |path| -> Result<Option<String>> {
+            Ok(fake_context("GET", path, &[], &[]).call_with_pathrest(&handler, path)?
+               .map(|r| r.body))
+        };
+        // The inner handler gets the relative path after the prefix
+        assert_eq!(get("/blog/2023/x.html")?.as_deref(), Some("2023/x.html"));
+        assert_eq!(get("/blog/2023/")?.as_deref(), Some("2023/"));
+        assert_eq!(get("/blog")?.as_deref(), Some("."));
+        assert_eq!(get("/blogs/x.html")?, None);
+        assert_eq!(get("/x/blog/x.html")?, None);
+        Ok(())
     }
 }
 
@@ -149,21 +347,127 @@ pub trait Handler<L: Language>: Debug + Send + Sync {
 }
 
 
+/// An error that should be answered with a particular status instead
+/// of 500, e.g. 400 for bad input. Handlers still return
+/// `anyhow::Error`, which can carry this (e.g. via
+/// `bail!(HandlerError::new(...))`); the status is found again via
+/// `HandlerError::status_of`, even after adding context.
+#[derive(Debug)]
+pub struct HandlerError {
+    pub status: HttpResponseStatusCode,
+    pub source: anyhow::Error,
+}
+
+impl HandlerError {
+    pub fn new(status: HttpResponseStatusCode, source: impl Into<anyhow::Error>) -> Self {
+        HandlerError { status, source: source.into() }
+    }
+
+    /// The status to respond with for `err`: the one from a
+    /// `HandlerError` in it, otherwise 500.
+    pub fn status_of(err: &anyhow::Error) -> HttpResponseStatusCode {
+        err.downcast_ref::<HandlerError>().map_or(
+            HttpResponseStatusCode::InternalServerError500,
+            |e| e.status)
+    }
+}
+
+impl From<anyhow::Error> for HandlerError {
+    /// Keeps the status if `err` carries a `HandlerError`, otherwise
+    /// 500.
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast::<HandlerError>() {
+            Ok(e) => e,
+            Err(err) => HandlerError::new(HttpResponseStatusCode::InternalServerError500, err)
+        }
+    }
+}
+
+impl std::fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {:#}", self.status.code(), self.source)
+    }
+}
+
+impl std::error::Error for HandlerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+
 // ------------------------------------------------------------------
-/// Serve files from the local file system
+/// Serve files from the local file system, or another `FileSource`
 #[derive(Debug)]
 pub struct FileHandler {
-    /// Path to base directory in local file system from which to
-    /// serve the files. No ".." or "." are allowed in the surplus of
-    /// the request path.
-    basepath: PathBuf,
+    /// Where to get the files from. No ".." or "." are allowed in
+    /// the surplus of the request path.
+    source: Arc<dyn FileSource>,
+    /// See `with_image_alternates`.
+    image_alternates: bool,
+    /// See `with_cache_control`.
+    cache_control: Option<&'static str>,
     // no cache for now
 }
+
+/// The formats that `FileHandler::with_image_alternates` looks for,
+/// in order of preference: (extension, mime type).
+const IMAGE_ALTERNATES: &[(&str, &str)] = &[("avif", "image/avif"), ("webp", "image/webp")];
+
 impl FileHandler {
+    /// Serve files from the base directory `basepath` in the local
+    /// file system.
     pub fn new(basepath: impl Into<PathBuf>) -> FileHandler {
+        FileHandler::with_source(FsFileSource::new(basepath))
+    }
+
+    pub fn with_source(source: impl FileSource + 'static) -> FileHandler {
         FileHandler {
-            basepath: basepath.into()
+            source: Arc::new(source),
+            image_alternates: false,
+            cache_control: None,
+        }
+    }
+
+    /// For requests for `.jpg`, `.jpeg` or `.png` files, serve a
+    /// sibling with the same name but extension `.avif` or `.webp`
+    /// instead if there is one and the client's `Accept` header lists
+    /// its type (AVIF preferred). Those responses carry `Vary:
+    /// Accept`. Alternates are only served if the original exists.
+    pub fn with_image_alternates(mut self) -> FileHandler {
+        self.image_alternates = true;
+        self
+    }
+
+    /// Send `cache_control` as the `Cache-Control` header, instead
+    /// of a max-age (and `Expires`) derived from the file's age.
+    pub fn with_cache_control(mut self, cache_control: &'static str) -> FileHandler {
+        self.cache_control = Some(cache_control);
+        self
+    }
+
+    /// `path` without the extension, if it is an image that may have
+    /// alternates.
+    fn image_alternates_base(path: &str) -> Option<&str> {
+        let (base, ext) = path.rsplit_once('.')?;
+        ["jpg", "jpeg", "png"].iter().any(|e| ext.eq_ignore_ascii_case(e)).then_some(base)
+    }
+
+    /// Open the alternate (`base` plus one of the `IMAGE_ALTERNATES`
+    /// extensions) to serve for `accept`, if any, with its extension.
+    fn open_image_alternate(
+        &self,
+        base: &str,
+        accept: Option<&str>
+    ) -> Result<Option<(SourceFile, &'static str)>> {
+        for (ext, mime) in IMAGE_ALTERNATES {
+            if accept_lists(accept, mime) {
+                if let Some(file) = self.source.open(&format!("{base}.{ext}"))? {
+                    return Ok(Some((file, ext)))
+                }
+            }
         }
+        Ok(None)
     }
 }
 
@@ -185,129 +489,95 @@ impl<L: Language + Default> Handler<L> for FileHandler {
                 // Todo: directory indices, but as a separate handler
         }
         let canonpathstr: String = canonpath.join("/");
-        let full_path: PathBuf = self.basepath.join(&canonpathstr);
-        // XX would we need better than extension based mime type
-        // matching?
-
-        // XX instead do File::open first and then get metadata from
-        // the fh: *does* this work (portably?) for directories, too?
-        let metadata =
-            match full_path.metadata() {
-                Ok(m) => m,
-                Err(e) =>
-                    match e.kind() {
-                        ErrorKind::NotFound => return Ok(None),
-                        _ => return Err(e).with_context(
-                            || anyhow!("can't open file for reading: {:?}",
-                                       full_path))
-                    }
+        let image_base =
+            if self.image_alternates {
+                Self::image_alternates_base(&canonpathstr)
+            } else {
+                None
+            };
+        // The original has to exist, the alternates are only
+        // replacements for it
+        let original = or_return_none!(self.source.open(&canonpathstr)?);
+        let alternate =
+            if let Some(base) = image_base {
+                self.open_image_alternate(base, context.header("Accept"))?
+            } else {
+                None
+            };
+        let (SourceFile { mimetype, mtime, size, reader }, alternate_ext) =
+            match alternate {
+                Some((file, ext)) => (file, Some(ext)),
+                None => (original, None)
             };
 
-        if metadata.is_dir() {
-            warn!("is_dir, not handling dirs yet");
-            Ok(None)
-        } else if metadata.is_symlink() {
-            warn!("is_symlink, not handling symlinks yet");
-            Ok(None)
-        } else if metadata.is_file() {
-            let mimetype = 
-                if let Some(extension_os) = full_path.extension() {
-                    let extension = extension_os.to_str().expect("came from String above");
-                    extension_to_mime(extension)
-                } else {
-                    "text/plain" // XX ?
-                };
-            match File::open(&full_path) {
-                Err(e) =>
-                    match e.kind() {
-                        ErrorKind::NotFound => Ok(None),
-                        _ => Err(e).with_context(
-                            || anyhow!("can't open file for reading: {:?}",
-                                       full_path))?
-                    },
-                Ok(fh) => {
-                    let mtime: SystemTime = metadata.modified()?;
-                    let age: Duration = mtime.elapsed()?;
-                    let age_seconds = age.as_secs() as u128;
-                    let age_allowed = age_seconds + age_seconds / 10;
-                    let age_allowed_duration: Duration = Duration::new(age_allowed as u64, 0);
-                    let expires = mtime.checked_add(age_allowed_duration).ok_or_else(
-                        || anyhow!("time overflow??"))?;
-                    let mtime_seconds = mtime.duration_since(UNIX_EPOCH)?.as_secs();
-                    let etag_quoted = format!("{:?}", mtime_seconds.to_string());
-
-                    let headers = vec![
-                        cow!("Content-type", mimetype),
-                        cow!("Last-Modified", fmt_http_date(mtime)),
-
-                        // The Content-Length header is dropped again! No point adding it.
-                        // cow!("Content-Length", metadata.st_size().to_string()),
-
-                        // https://developer.mozilla.org/en-US/docs/Web/HTTP/Caching
-                        // HTTP caching - HTTP MDN.html
-                        cow!("Cache-Control",
-                             format!("max-age={}", age_allowed)),
-                        // And also add Expires, even though it hasn't
-                        // changed anything for Firefox issue either.
-                        cow!("Expires", fmt_http_date(expires)),
-
-                        // https://webmasters.stackexchange.com/questions/63119/why-doesnt-firefox-cache-my-javascript-file
-                        // iis - Why doesn't FireFox cache my JavaScript file - Webmasters Stack Exchange.html
-                        cow!("ETag", etag_quoted.clone()),
-                    ];
-                    let send_file = |headers| {
-                        Ok(Some(Response {
-                            status_code:
-                            HttpResponseStatusCode::OK200.code(),
-                            headers,
-                            data: ResponseBody::from_reader_and_size(
-                                fh,
-                                // XX dangerous re panics?
-                                metadata.st_size() as usize),
-                            upgrade: None, // XX
-                        }.into()))
-                    };
-                    let send_notmodified = |headers| {
-                        Ok(Some(Response {
-                            status_code:
-                            HttpResponseStatusCode::NotModified304.code(),
-                            // Still send these headers? --
-                            // Yes, let the client know that
-                            // the file might even be *older*
-                            // than what it saw?
-                            headers,
-                            data: ResponseBody::empty(),
-                            upgrade: None, // XX
-                        }.into()))
-                    };
-                    if let Some(modsince_str) = context.header("If-Modified-Since")
-                    {
-                        let modsince = parse_http_date(modsince_str).with_context(
-                            || anyhow!("parsing If-Modified-Since {:?}",
-                                       modsince_str))?;
-                        if file_is_newer_than_snapshot_time(mtime, modsince) {
-                            warn!("If-Modified-Since: {}; sending it", modsince_str);
-                            send_file(headers)
-                        } else {
-                            warn!("If-Modified-Since: {}; NotModified304", modsince_str);
-                            send_notmodified(headers)
-                        }
-                    } else if let Some(nonematch_str) = context.header("If-None-Match") {
-                        warn!("got If-None-Match {nonematch_str:?}, \
-                               compared to calculated {etag_quoted:?}");
-                        if nonematch_str == etag_quoted {
-                            send_notmodified(headers)
-                        } else {
-                            send_file(headers)
-                        }
-                    } else {
-                        send_file(headers)
-                    }
-                }
-            }
+        let mtime_seconds = mtime.duration_since(UNIX_EPOCH)?.as_secs();
+        // Different for the alternates, in case they have the same
+        // mtime
+        let etag = match alternate_ext {
+            Some(ext) => format!("{mtime_seconds}-{ext}"),
+            None => mtime_seconds.to_string()
+        };
+        let etag_quoted = format!("{etag:?}");
+
+        let mut headers = vec![
+            cow!("Content-type", mimetype),
+            cow!("Last-Modified", fmt_http_date(mtime)),
+
+            // The Content-Length header is dropped again! No point adding it.
+            // cow!("Content-Length", metadata.st_size().to_string()),
+        ];
+        if let Some(cache_control) = self.cache_control {
+            headers.push(cow!("Cache-Control", cache_control));
         } else {
-            warn!("neither file nor symlink nor dir: device file or fifo or socket?");
-            Ok(None)
+            let age: Duration = mtime.elapsed()?;
+            let age_seconds = age.as_secs() as u128;
+            let age_allowed = age_seconds + age_seconds / 10;
+            let age_allowed_duration: Duration = Duration::new(age_allowed as u64, 0);
+            let expires = mtime.checked_add(age_allowed_duration).ok_or_else(
+                || anyhow!("time overflow??"))?;
+            // https://developer.mozilla.org/en-US/docs/Web/HTTP/Caching
+            // HTTP caching - HTTP MDN.html
+            headers.push(cow!("Cache-Control",
+                              format!("max-age={}", age_allowed)));
+            // And also add Expires, even though it hasn't
+            // changed anything for Firefox issue either.
+            headers.push(cow!("Expires", fmt_http_date(expires)));
+        }
+        // https://webmasters.stackexchange.com/questions/63119/why-doesnt-firefox-cache-my-javascript-file
+        // iis - Why doesn't FireFox cache my JavaScript file - Webmasters Stack Exchange.html
+        headers.push(cow!("ETag", etag_quoted.clone()));
+        if image_base.is_some() {
+            headers.push(cow!("Vary", "Accept"));
+        }
+        let send_file = |headers| {
+            Ok(Some(Response {
+                status_code:
+                HttpResponseStatusCode::OK200.code(),
+                headers,
+                data: ResponseBody::from_reader_and_size(reader, size),
+                upgrade: None, // XX
+            }.into()))
+        };
+        let send_notmodified = |headers| {
+            Ok(Some(Response {
+                status_code:
+                HttpResponseStatusCode::NotModified304.code(),
+                // Still send these headers? --
+                // Yes, let the client know that
+                // the file might even be *older*
+                // than what it saw?
+                headers,
+                data: ResponseBody::empty(),
+                upgrade: None, // XX
+            }.into()))
+        };
+        match check_preconditions(context, method, Some(&etag_quoted),
+                                  Some(mtime)) {
+            Precondition::Satisfied => send_file(headers),
+            Precondition::NotModified => send_notmodified(headers),
+            Precondition::Failed => Ok(Some(
+                errorpage_from_status(
+                    HttpResponseStatusCode::PreconditionFailed412).into())),
         }
     }
 }
@@ -496,3 +766,157 @@ where L: Language  + Send + Sync,
         Ok(Some(responder(target).into()))
     }
 }
+
+
+/// Enforce HTTPS for the wrapped handler: plain HTTP requests are
+/// redirected to the same URL via https (301, or 308 for POST so
+/// that the method is kept), responses to HTTPS requests get a
+/// `Strict-Transport-Security` header. When running behind a reverse
+/// proxy terminating TLS, list its address in `trusted_proxies`; its
+/// `X-Forwarded-Proto` header is then believed (from other clients
+/// it is ignored). The redirect goes to `canonical_host` if given,
+/// otherwise to the host from the `Host` header (without the port,
+/// which is the one for plain HTTP); requests without `Host` header
+/// get a 400 then.
+pub struct HstsHandler<L: Language> {
+    handler: Arc<dyn Handler<L>>,
+    /// In seconds.
+    max_age: u64,
+    include_subdomains: bool,
+    trusted_proxies: Vec<IpAddr>,
+    canonical_host: Option<KString>,
+}
+
+impl<L: Language> HstsHandler<L> {
+    pub fn new(
+        handler: Arc<dyn Handler<L>>,
+        max_age: u64,
+        include_subdomains: bool,
+        trusted_proxies: Vec<IpAddr>,
+        canonical_host: Option<&str>,
+    ) -> Self {
+        HstsHandler {
+            handler, max_age, include_subdomains, trusted_proxies,
+            canonical_host: canonical_host.map(KString::from_ref),
+        }
+    }
+
+    /// Whether the request reached us (or the trusted proxy) via TLS.
+    pub fn is_https(&self, context: &AContext<L>) -> bool {
+        context.is_secure()
+            || (self.trusted_proxies.contains(&context.client_ip())
+                && context.header("x-forwarded-proto").is_some_and(
+                    |proto| proto.trim().eq_ignore_ascii_case("https")))
+    }
+
+    fn header_value(&self) -> String {
+        if self.include_subdomains {
+            format!("max-age={}; includeSubDomains", self.max_age)
+        } else {
+            format!("max-age={}", self.max_age)
+        }
+    }
+}
+
+/// `host` (as in the `Host` header) without the port, if any.
+fn host_without_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        // IPv6 address
+        host.find(']').map_or(host, |i| &host[..=i])
+    } else {
+        host.rsplit_once(':').map_or(host, |(host, _port)| host)
+    }
+}
+
+impl<L: Language> Debug for HstsHandler<L> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("HstsHandler({:?}, {})",
+                                 self.handler, self.header_value()))
+    }
+}
+
+impl<L: Language> Handler<L> for HstsHandler<L> {
+    fn call<'a>(
+        &self,
+        context: &AContext<L>,
+        method: HttpRequestMethodSimple,
+        pathrest: &PPath<KString>,
+        html: &HtmlAllocator
+    ) -> Result<Option<AResponse>> {
+        if ! self.is_https(context) {
+            let host = match (&self.canonical_host, context.host()) {
+                (Some(host), _) => host.as_str(),
+                (None, Some(host)) => host_without_port(host),
+                (None, None) => return Ok(Some(errorpage_from_status(
+                    HttpResponseStatusCode::BadRequest400).into())),
+            };
+            // raw_url is the path and query as sent, still encoded
+            let target = format!("https://{host}{}", context.request().raw_url());
+            let response = if method.is_post() {
+                Response::redirect_308(target)
+            } else {
+                Response::redirect_301(target)
+            };
+            return Ok(Some(response.into()))
+        }
+        let mut r = self.handler.call(context, method, pathrest, html)?;
+        if let Some(aresponse) = &mut r {
+            let headers = &mut aresponse.response.headers;
+            if ! headers.iter().any(
+                |(k, _)| k.eq_ignore_ascii_case("Strict-Transport-Security"))
+            {
+                headers.push((Cow::from("Strict-Transport-Security"),
+                              Cow::from(self.header_value())));
+            }
+        }
+        Ok(r)
+    }
+}
+
+
+/// Mount a handler under a path prefix: if `pathrest` starts with the
+/// segments of `prefix`, they are stripped and the rest is passed to
+/// the wrapped handler, otherwise the request is declined. This
+/// allows nesting handlers (like a blog) at arbitrary places.
+pub struct MountHandler<L: Language> {
+    prefix: Vec<KString>,
+    handler: Arc<dyn Handler<L>>,
+}
+
+impl<L: Language> MountHandler<L> {
+    /// `prefix` is a path like `/blog` (leading and trailing slashes
+    /// don't matter).
+    pub fn new(prefix: &str, handler: Arc<dyn Handler<L>>) -> Self {
+        MountHandler {
+            prefix: PPath::<KString>::from_str(prefix).segments().to_vec(),
+            handler,
+        }
+    }
+}
+
+impl<L: Language> Debug for MountHandler<L> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("MountHandler({:?}, {:?})",
+                                 self.prefix.join("/"), self.handler))
+    }
+}
+
+impl<L: Language> Handler<L> for MountHandler<L> {
+    fn call<'a>(
+        &self,
+        context: &AContext<L>,
+        method: HttpRequestMethodSimple,
+        pathrest: &PPath<KString>,
+        html: &HtmlAllocator
+    ) -> Result<Option<AResponse>> {
+        let segments = pathrest.segments();
+        if ! segments.starts_with(&self.prefix) {
+            return Ok(None)
+        }
+        let rest = PPath::new(false,
+                              pathrest.ends_with_slash(),
+                              segments[self.prefix.len()..].to_vec());
+        self.handler.call(context, method, &rest, html)
+    }
+}
+
@@ -8,7 +8,7 @@ use kstring::KString;
 use ahtml::HtmlAllocator;
 use chj_util::warn;
 
-use crate::{router::MultiRouter,
+use crate::{router::{MultiRouter, ANY_METHOD},
             handler::Handler,
             apachelog::Logs,
             acontext::AContext,
@@ -59,6 +59,18 @@ impl<L: Language> HostRouter<L> {
         (self.logs.clone(),
          Ok(errorpage_from_status(HttpResponseStatusCode::NotFound404).into()))
     }
+
+    /// All mounted paths as `(method, path)`, sorted, for
+    /// diagnostics; see `MultiRouter::routes`. The fallback, if any,
+    /// is listed as path `*`.
+    pub fn routes(&self) -> Vec<(String, String)> {
+        let mut routes = self.router.as_ref().map(|r| r.routes()).unwrap_or_default();
+        if self.fallback.is_some() {
+            routes.push((ANY_METHOD.into(), "*".into()));
+            routes.sort();
+        }
+        routes
+    }
 }
 
 /// Routes for all hosts (domains)
@@ -68,7 +80,7 @@ pub struct HostsRouter<L: Language> {
     /// Fallback when either no `Host` header was sent, or it was not
     /// found in `routers`.
     pub fallback: Option<Arc<HostRouter<L>>>,
-    /// Logs when there is no fallback handler.
+    /// Logs when there is no fallback handler (421 responses).
     pub logs: Arc<Mutex<Logs>>,
 }
 
@@ -83,6 +95,46 @@ impl<L: Language> HostsRouter<L> {
         }
     }
 
+    /// Dispatch to the `HostRouter` for the `Host` header of the
+    /// request, or to the fallback if there's no such router. If
+    /// there's no fallback either, responds with 421 Misdirected
+    /// Request, to distinguish unknown hosts from the 404 of known
+    /// hosts without a matching route.
+    pub fn handle_request(
+        &self,
+        context: &AContext<L>,
+        method: HttpRequestMethodSimple,
+        allocator: &HtmlAllocator
+    ) -> (Arc<Mutex<Logs>>, anyhow::Result<AResponse>)
+    {
+        if let Some(host) = context.host() {
+            let lchost = host.to_lowercase();
+            if let Some(hostrouter) = self.routers.get(&KString::from_string(lchost)) {
+                return hostrouter.handle_request(context, method, allocator)
+            }
+        }
+        if let Some(fallback) = &self.fallback {
+            return fallback.handle_request(context, method, allocator)
+        }
+        (self.logs.clone(),
+         Ok(errorpage_from_status(HttpResponseStatusCode::MisdirectedRequest421).into()))
+    }
+
+    /// The logs that `handle_request` would use for the request, for
+    /// responses made without calling it.
+    pub fn logs_for(&self, context: &AContext<L>) -> Arc<Mutex<Logs>> {
+        if let Some(host) = context.host() {
+            let lchost = host.to_lowercase();
+            if let Some(hostrouter) = self.routers.get(&KString::from_string(lchost)) {
+                return hostrouter.logs.clone()
+            }
+        }
+        if let Some(fallback) = &self.fallback {
+            return fallback.logs.clone()
+        }
+        self.logs.clone()
+    }
+
     pub fn add(&mut self,
                hostname: &str,
                hostrouter: Arc<HostRouter<L>>
@@ -97,3 +149,85 @@ impl<L: Language> HostsRouter<L> {
         self
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::{lang_en_de::Lang, handler::ExactFnHandler, test_util::fake_context};
+
+    use super::*;
+
+    fn logs() -> Arc<Mutex<Logs>> {
+        Arc::new(Mutex::new(Logs {
+            access_log: Box::new(std::io::sink()),
+            error_log: Box::new(std::io::sink()),
+        }))
+    }
+
+    fn status(hostsrouter: &HostsRouter<Lang>, host: &str, path: &str) -> Result<u16> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        fake_context("GET", path, &[("Host", host)], &[]).with_context(
+            |context: &AContext<Lang>| {
+                let (_logs, r) = hostsrouter.handle_request(
+                    context, HttpRequestMethodSimple::GET, &html);
+                Ok(r?.response.status_code)
+            })
+    }
+
+    #[test]
+    fn t_unknown_host() -> Result<()> {
+        let mut router = MultiRouter::new();
+        let handler: Arc<dyn Handler<Lang>> = Arc::new(ExactFnHandler::new(
+            |_context: &AContext<Lang>, _method, _html: &HtmlAllocator| {
+                Ok(rouille::Response::text("hi").into())
+            }));
+        router.add("/hi", handler);
+        let hostrouter = Arc::new(HostRouter {
+            router: Some(Arc::new(router)),
+            fallback: None,
+            logs: logs(),
+        });
+        let mut hostsrouter = HostsRouter::new(None, logs());
+        hostsrouter.add("Example.com", hostrouter.clone());
+
+        assert_eq!(status(&hostsrouter, "example.com", "/hi")?, 200);
+        assert_eq!(status(&hostsrouter, "EXAMPLE.com", "/hi")?, 200);
+        // Known host, no route
+        assert_eq!(status(&hostsrouter, "example.com", "/nope")?, 404);
+        // Unknown host, no fallback
+        assert_eq!(status(&hostsrouter, "example.org", "/hi")?, 421);
+
+        let hostsrouter = HostsRouter::new(Some(hostrouter), logs());
+        assert_eq!(status(&hostsrouter, "example.org", "/hi")?, 200);
+        assert_eq!(status(&hostsrouter, "example.org", "/nope")?, 404);
+        Ok(())
+    }
+
+    #[test]
+    fn t_routes() {
+        let handler = || -> Arc<dyn Handler<Lang>> {
+            Arc::new(ExactFnHandler::new(
+                |_context: &AContext<Lang>, _method, _html: &HtmlAllocator| {
+                    Ok(rouille::Response::text("hi").into())
+                }))
+        };
+        let mut router = MultiRouter::new();
+        router
+            .add("/static", handler())
+            .add("/", handler())
+            .add("/blog/feed.xml", handler())
+            .add("/blog", handler())
+            .add("/static", handler());
+        let hostrouter = HostRouter {
+            router: Some(Arc::new(router)),
+            fallback: Some(handler()),
+            logs: logs(),
+        };
+        let routes: Vec<_> = hostrouter.routes().into_iter().map(
+            |(method, path)| format!("{method} {path}")).collect();
+        assert_eq!(routes, ["* *", "* /", "* /blog", "* /blog/feed.xml",
+                            "* /static", "* /static"]);
+    }
+}
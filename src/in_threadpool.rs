@@ -1,12 +1,26 @@
 use std::panic;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::channel;
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use scoped_thread_pool::Pool;
 
+/// The message of a panic, from the payload given by `catch_unwind`.
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "(non-string panic payload)"
+    }
+}
+
 /// Execute function inside thread pool and return its result. Why is
-/// this not part of the `threadpool` crate?
+/// this not part of the `threadpool` crate? A panic in `f` is caught
+/// and returned as an error (with `panic = "abort"`, as in the dev
+/// profile, the process is aborted instead).
 pub fn in_threadpool<F, R>(threadpool: Arc<Pool>, f: F) -> Result<R>
 where F: FnOnce() -> R + Send,
       R: Send
@@ -21,6 +35,50 @@ where F: FnOnce() -> R + Send,
             tx.send(result).expect("channel is there and working");
         });
         let msg = rx.recv()?;
-        Ok(msg.expect("XXX size business"))
+        msg.map_err(|payload| anyhow!("panic in worker thread: {}",
+                                      panic_message(&*payload)))
     })
 }
+
+/// Limits how many jobs may be running in or waiting for a thread
+/// pool, so that callers can reject work (e.g. with a 503 response)
+/// instead of queueing it up without bound.
+#[derive(Debug)]
+pub struct ConcurrencyLimit {
+    max: usize,
+    current: AtomicUsize,
+}
+
+/// Holds a slot of a `ConcurrencyLimit` until dropped.
+#[derive(Debug)]
+pub struct ConcurrencyLimitGuard<'l> {
+    limit: &'l ConcurrencyLimit,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(max: usize) -> Self {
+        ConcurrencyLimit { max, current: AtomicUsize::new(0) }
+    }
+
+    pub fn max(&self) -> usize { self.max }
+
+    /// The number of slots currently taken.
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::SeqCst)
+    }
+
+    /// Take a slot, or return None if all `max` slots are taken.
+    pub fn try_acquire(&self) -> Option<ConcurrencyLimitGuard<'_>> {
+        self.current.fetch_update(
+            Ordering::SeqCst, Ordering::SeqCst,
+            |n| if n < self.max { Some(n + 1) } else { None })
+            .ok()?;
+        Some(ConcurrencyLimitGuard { limit: self })
+    }
+}
+
+impl Drop for ConcurrencyLimitGuard<'_> {
+    fn drop(&mut self) {
+        self.limit.current.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -1,4 +1,4 @@
-use std::{path::Path, fs::read_to_string};
+use std::{path::Path, fs::{read, read_to_string}};
 
 use anyhow::{Result, anyhow, Context};
 
@@ -7,3 +7,8 @@ pub fn my_read_to_string<P: AsRef<Path>>(path: P) -> Result<String> {
     read_to_string(&path).with_context(
         || anyhow!("opening path for reading: {:?}", path.as_ref()))
 }
+
+pub fn my_read<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    read(&path).with_context(
+        || anyhow!("opening path for reading: {:?}", path.as_ref()))
+}
@@ -9,6 +9,7 @@ use strum::IntoEnumIterator; // ::iter()
 use chj_util::warn;
 
 use crate::language::Language;
+use crate::smart_punctuation::SmartQuotes;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy,
          strum_macros::EnumVariantNames, // ::VARIANTS
@@ -40,6 +41,13 @@ impl Language for Lang {
     fn strs() -> &'static [&'static str] {
         &Self::VARIANTS
     }
+
+    fn quotes(self) -> SmartQuotes {
+        match self {
+            Lang::En => SmartQuotes::ENGLISH,
+            Lang::De => SmartQuotes::GERMAN,
+        }
+    }
 }
 
 impl Default for Lang {
@@ -1,4 +1,5 @@
 use crate::str_util::str_take;
+use crate::smart_punctuation::SmartQuotes;
 
 /// A human language representation for i18n.
 pub trait Language: Clone + Copy + PartialEq + Eq + Default + Send + Sync {
@@ -23,5 +24,10 @@ pub trait Language: Clone + Copy + PartialEq + Eq + Default + Send + Sync {
 
     // XX generate from members?
     fn strs() -> &'static [&'static str];
+
+    /// The quotation marks for typographic quotes in this language.
+    fn quotes(self) -> SmartQuotes {
+        SmartQuotes::ENGLISH
+    }
 }
 
@@ -1,4 +1,6 @@
 pub mod handler;
+pub mod file_source;
+pub mod css_bundle;
 pub mod website_layout;
 pub mod easy_fs;
 pub mod time_util;
@@ -22,6 +24,7 @@ pub mod alist;
 pub mod language;
 pub mod rouille_util;
 pub mod str_util;
+pub mod smart_punctuation;
 pub mod lang_en_de;
 pub mod date_format;
 pub mod date_format_website;
@@ -39,6 +42,9 @@ pub mod myfrom;
 pub mod webparts;
 pub mod http_response_status_codes;
 pub mod markdown;
+pub mod markdown_plain;
+pub mod autolink;
+pub mod dom_pass;
 pub mod trie;
 pub mod router;
 pub mod util;
@@ -49,5 +55,8 @@ pub mod miniarcswap;
 pub mod easyfiletype;
 pub mod cmpfilemeta;
 pub mod blog;
+pub mod seo;
 pub mod ppath;
 pub mod website_benchmark;
+#[cfg(test)]
+pub mod test_util;
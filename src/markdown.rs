@@ -1,41 +1,37 @@
 //! Convert markdown to HTML.
 
-use std::{path::PathBuf, fmt::{Display, Debug}, collections::HashMap, panic::RefUnwindSafe};
+use std::{path::{Path, PathBuf}, fmt::{Display, Debug}, collections::{HashMap, BTreeMap},
+          panic::RefUnwindSafe,
+          cell::{Cell, RefCell}};
 use anyhow::{Result, anyhow, bail};
-use backtrace::Backtrace;
-use html5gum::{Token, HtmlString};
 use kstring::KString;
-use pulldown_cmark::{Parser, Options, Event, Tag, HeadingLevel, LinkType};
+use pulldown_cmark::{Parser, Options, Event, Tag, HeadingLevel, LinkType, BrokenLink, CowStr,
+                     CodeBlockKind, Alignment};
 
 use ahtml::{AId, HtmlAllocator, Node, AVec, P_META,
                      H1_META, H2_META, H3_META, H4_META, H5_META, H6_META,
-                     DIV_META, OL_META, UL_META, LI_META, PRE_META,
+                     DIV_META, IMG_META, OL_META, UL_META, LI_META,
                      BLOCKQUOTE_META, TABLE_META, TH_META, TR_META,
-                     TD_META, EM_META, STRONG_META, S_META, METADB, ASlice, Print,
-                     TITLE_META, Element,
-            att, flat::Flat};
+                     TD_META, EM_META, STRONG_META, S_META, ASlice, Print,
+                     TITLE_META, SPAN_META, Element, SerHtmlFrag, HtmlToken,
+            att, opt_att, flat::Flat};
 use ahtml_html::meta::ElementMeta;
 
-use chj_util::{nowarn_todo as warn_todo,
-               nowarn as warn,
+use chj_util::{nowarn as warn,
                nodt as dt};
 
 use crate::{webutils::email_url,
             util::{infinite_sequence, autovivify_last, enum_name},
             try_option,
-            io_util::my_read_to_string,
-            myfrom::kstring_myfrom2};
-
-fn error_not_an_html5_tag_name(name: &str) -> anyhow::Error {
-    anyhow!("not an HTML5 tag name: {name:?}\n{:?}",
-            Backtrace::new())
-}
-
-/// This can't be replaced with `att` or the MyFrom trait, because it
-/// can fail.
-fn kstring(s: HtmlString) -> Result<KString> {
-    Ok(KString::from_string(String::from_utf8(s.0)?))
-}
+            io_util::my_read,
+            myfrom::kstring_myfrom2,
+            imageinfo::imageinfo,
+            smart_punctuation::{SmartPunctuation, SmartQuotes},
+            url_encoding::url_decode_lossy,
+            auri::resolve_relative_url,
+            markdown_plain::markdown_to_plain,
+            autolink::autolink_body,
+            dom_pass::{DomPass, apply_dom_passes}};
 
 
 // ------------------------------------------------------------------
@@ -46,6 +42,33 @@ pub trait StylingInterface: Send + Sync + RefUnwindSafe {
         &'c self,
         html: &HtmlAllocator,
     ) -> Result<Box<dyn StylingContextInterface<'c> + 'c>>;
+
+    /// The markers the style uses in the footnotes section; the
+    /// markdown has to be processed with the same
+    /// `MarkdownConfig::footnote_markers` for the references in the
+    /// text to match.
+    fn footnote_markers(&self) -> FootnoteMarkers {
+        FootnoteMarkers::Numbers
+    }
+
+    /// The attributes the style uses on the links in the footnotes
+    /// section; pass the same in `MarkdownConfig::footnote_links` for
+    /// the references in the text.
+    fn footnote_links(&self) -> FootnoteLinks {
+        FootnoteLinks::default()
+    }
+
+    /// Called when rendering a page, with the (language independent,
+    /// cached) result of `format_footnotes`, to e.g. add a heading
+    /// in the language `lang` of the request.
+    fn footnotes_section(
+        &self,
+        footnotes: AId<Node>,
+        _lang: &str,
+        _html: &HtmlAllocator,
+    ) -> Result<AId<Node>> {
+        Ok(footnotes)
+    }
 }
 
 pub trait StylingContextInterface<'c> {
@@ -64,6 +87,351 @@ pub trait StylingContextInterface<'c> {
     ) -> Result<AId<Node>>;
 }
 
+// ------------------------------------------------------------------
+// Processing options
+
+/// What to do with soft breaks (single newlines within a paragraph
+/// in the markdown source).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SoftBreakPolicy {
+    /// Keep the newline (renders as a space in HTML, but is kept in
+    /// plain text).
+    #[default]
+    AsNewline,
+    /// Replace with a space.
+    AsSpace,
+    /// Drop it entirely (e.g. for CJK text, where line breaks in the
+    /// source should not produce a space).
+    Elide,
+}
+
+/// How footnote references directly following each other (like
+/// `[^a][^b]`) are rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AdjacentFootnoteReferences {
+    /// Separate `<sup>` elements with a `<sup>,</sup>` in between.
+    #[default]
+    CommaSeparated,
+    /// A single `<sup>` element holding all the links, separated by
+    /// commas.
+    Merged,
+    /// Separate `<sup>` elements, without separator.
+    Unseparated,
+}
+
+/// What the converted document is wrapped in.
+#[derive(Debug, Clone, Copy)]
+pub enum MarkdownWrapper {
+    /// An element of the given kind, without attributes (by default
+    /// `div`; `article` or `section` are other useful choices).
+    Element(&'static ElementMeta),
+    /// No wrapper, the document is only available as a slice (see
+    /// `ProcessedMarkdown::body`).
+    None,
+}
+
+impl Default for MarkdownWrapper {
+    fn default() -> Self {
+        MarkdownWrapper::Element(*DIV_META)
+    }
+}
+
+/// What to do when a document contains more than one `<title>`
+/// element.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MultipleTitlesPolicy {
+    /// Fail the conversion.
+    #[default]
+    Error,
+    /// Use the first one, ignore the others.
+    UseFirst,
+    /// Use the last one, i.e. later ones override earlier ones.
+    UseLast,
+}
+
+/// How `ProcessedMarkdown::fixed_html` adapts the heading levels of
+/// a document for serving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeadingShift {
+    /// Make the top heading level the given one. If it's below H1
+    /// (the layout shows the title as H1) and the document has a
+    /// single H1 (its title), that one is dropped instead.
+    TopLevel(HeadingLevel),
+    /// Leave the headings as they are.
+    None,
+}
+
+impl Default for HeadingShift {
+    fn default() -> Self {
+        HeadingShift::TopLevel(HeadingLevel::H2)
+    }
+}
+
+/// How footnotes are marked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FootnoteMarkers {
+    /// 1, 2, 3, ...
+    #[default]
+    Numbers,
+    /// *, †, ‡, §, ‖, ¶, then doubled (**, ††, ...) and so on.
+    Symbols,
+}
+
+const FOOTNOTE_SYMBOLS: &[char] = &['*', '†', '‡', '§', '‖', '¶'];
+
+impl FootnoteMarkers {
+    /// The marker for footnote number `n` (counting from 1).
+    pub fn marker(self, n: u32) -> String {
+        match self {
+            FootnoteMarkers::Numbers => n.to_string(),
+            FootnoteMarkers::Symbols => {
+                let i = n.saturating_sub(1) as usize;
+                let c = FOOTNOTE_SYMBOLS[i % FOOTNOTE_SYMBOLS.len()];
+                std::iter::repeat_n(c, i / FOOTNOTE_SYMBOLS.len() + 1).collect()
+            }
+        }
+    }
+}
+
+/// Extra attributes on footnote references and definitions, for
+/// accessibility. The default adds none.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FootnoteLinks {
+    /// If true, add the DPUB-ARIA roles: `doc-noteref` on reference
+    /// links (plus `aria-describedby` pointing to the definition),
+    /// `doc-footnote` on definitions, `doc-backlink` on the links
+    /// back to the references.
+    pub aria: bool,
+    /// The `rel` attribute for reference and back links.
+    pub rel: Option<KString>,
+}
+
+impl FootnoteLinks {
+    /// `role` attribute with the given DPUB-ARIA `role`, if `aria` is
+    /// enabled.
+    pub fn role(&self, role: &'static str) -> Option<(KString, KString)> {
+        opt_att("role", self.aria.then_some(role))
+    }
+
+    pub fn rel(&self) -> Option<(KString, KString)> {
+        opt_att("rel", self.rel.as_ref())
+    }
+
+    /// `aria-describedby` attribute for a link to `reference`, if
+    /// `aria` is enabled.
+    pub fn describedby(&self, reference: Footnoteref) -> Option<(KString, KString)> {
+        opt_att("aria-describedby", self.aria.then(|| reference.to_kstring(false)))
+    }
+}
+
+/// What `MarkdownFile::process_to_html` does with files that are not
+/// valid UTF-8 (a leading UTF-8 byte order mark is always dropped).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NonUtf8Policy {
+    /// Fail, reporting the position of the first invalid byte.
+    #[default]
+    Error,
+    /// Replace invalid byte sequences with U+FFFD (�), with a warning.
+    Lossy,
+    /// Read the file as Latin-1 (ISO 8859-1) instead, with a warning.
+    Latin1,
+}
+
+impl NonUtf8Policy {
+    /// Decode `bytes` as UTF-8, or as per the policy if that fails;
+    /// in the latter case also returns a warning. `path` is for the
+    /// messages.
+    pub fn decode(self, bytes: Vec<u8>, path: &Path) -> Result<(String, Option<String>)> {
+        match String::from_utf8(bytes) {
+            Ok(s) => Ok((s, None)),
+            Err(e) => {
+                let pos = e.utf8_error().valid_up_to();
+                let msg = |how| format!("{path:?} is not valid UTF-8 (invalid byte at \
+                                         position {pos}), {how}");
+                match self {
+                    NonUtf8Policy::Error =>
+                        bail!("{path:?} is not valid UTF-8 (invalid byte at position {pos})"),
+                    NonUtf8Policy::Lossy =>
+                        Ok((String::from_utf8_lossy(e.as_bytes()).into_owned(),
+                            Some(msg("replaced invalid bytes")))),
+                    NonUtf8Policy::Latin1 =>
+                        Ok((e.as_bytes().iter().map(|b| char::from(*b)).collect(),
+                            Some(msg("read as Latin-1")))),
+                }
+            }
+        }
+    }
+}
+
+/// What to do with HTML in markdown documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RawHtmlPolicy {
+    /// Parse it into the DOM; unknown elements, unbalanced tags and
+    /// HTML parse errors make the conversion fail. `<title>` sets the
+    /// document title.
+    #[default]
+    Validate,
+    /// Output it unchanged and unchecked.
+    PassThroughVerbatim,
+    /// Drop it.
+    Strip,
+    /// Show the HTML source as text.
+    Escape,
+}
+
+/// Options for the markdown to HTML conversion.
+#[derive(Debug, Clone, Default)]
+pub struct MarkdownConfig {
+    pub soft_break: SoftBreakPolicy,
+    pub adjacent_footnote_references: AdjacentFootnoteReferences,
+    pub wrapper: MarkdownWrapper,
+    /// If given, relative link and image URLs are resolved against
+    /// this path (e.g. the URL path of the page the document is
+    /// served at), see `auri::resolve_relative_url`.
+    pub base_url: Option<KString>,
+    /// If true, an image that is the only content of a paragraph and
+    /// has a title (or else an alt text) is rendered as a `figure`
+    /// instead, with that text as the `figcaption`, and with an `id`
+    /// for linking from the list of figures (see
+    /// `MarkdownMeta::figures`).
+    pub figures: bool,
+    pub heading_shift: HeadingShift,
+    pub multiple_titles: MultipleTitlesPolicy,
+    /// The markers for footnote references in the text, see
+    /// `StylingInterface::footnote_markers`.
+    pub footnote_markers: FootnoteMarkers,
+    /// The attributes for footnote reference links in the text, see
+    /// `StylingInterface::footnote_links`.
+    pub footnote_links: FootnoteLinks,
+    /// If true, any conversion warning (see
+    /// `ProcessedMarkdown::warnings`) makes the conversion fail
+    /// instead, e.g. for checking content in CI.
+    pub strict: bool,
+    /// If true, a block between `---` lines at the very start of the
+    /// document is parsed as `FrontMatter` instead of being part of
+    /// the text.
+    pub front_matter: bool,
+    /// If true, images that are local files get `width` and `height`
+    /// attributes with their intrinsic size, so that the page layout
+    /// doesn't shift while they load. Relative URLs are looked up in
+    /// the directory of the markdown file (only known when using
+    /// `MarkdownFile::process_to_html`), absolute URL paths in
+    /// `asset_root`. Images that can't be read get no dimensions and
+    /// cause a warning.
+    pub image_dimensions: bool,
+    /// The directory that image URL paths starting with `/` are
+    /// looked up in, see `image_dimensions`.
+    pub asset_root: Option<PathBuf>,
+    /// The quotation marks that straight quotes in the text are
+    /// replaced with (e.g. from `Language::quotes`).
+    pub quotes: SmartQuotes,
+    /// How to read markdown files that are not valid UTF-8.
+    pub non_utf8: NonUtf8Policy,
+    /// What to do with HTML tags and blocks in the markdown (by
+    /// default, validate them).
+    pub raw_html: RawHtmlPolicy,
+    /// The deepest heading level (as written in the document) listed
+    /// in the table of contents, e.g. `Some(HeadingLevel::H3)` for
+    /// only H2 and H3 sections below the title. None lists all.
+    pub max_toc_depth: Option<HeadingLevel>,
+    /// If true, bare URLs (starting with `http://`, `https://` or
+    /// `www.`) and email addresses in the text are turned into links
+    /// (not within links or code), see `autolink::autolink_body`.
+    pub autolink: bool,
+}
+
+/// Metadata from the start of a markdown document, like
+///
+/// ```text
+/// ---
+/// permalink: /2019/old-name.html
+/// aliases:
+///   - /old/a.html
+///   - "/old/b.html"
+/// ---
+/// ```
+///
+/// This is a small subset of YAML: `key: value` lines, with values
+/// optionally quoted, and lists of values either in the block style
+/// shown or in the flow style `[a, b]`. Empty lines and lines
+/// starting with `#` are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrontMatter(Vec<(KString, Vec<KString>)>);
+
+fn front_matter_value(s: &str) -> KString {
+    let s = s.trim();
+    let unquoted = s.strip_prefix('"').and_then(|s| s.strip_suffix('"'))
+        .or_else(|| s.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')));
+    KString::from_ref(unquoted.unwrap_or(s))
+}
+
+impl FrontMatter {
+    /// Parse the lines between the `---` lines.
+    pub fn parse(s: &str) -> Result<FrontMatter> {
+        let mut entries: Vec<(KString, Vec<KString>)> = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue
+            }
+            if let Some(item) = trimmed.strip_prefix("- ") {
+                let (_, values) = entries.last_mut().ok_or_else(
+                    || anyhow!("front matter line {}: list item without key", i + 1))?;
+                values.push(front_matter_value(item));
+            } else if let Some((key, value)) = trimmed.split_once(':') {
+                let value = value.trim();
+                let values =
+                    if value.is_empty() {
+                        Vec::new()
+                    } else if let Some(list) = value.strip_prefix('[')
+                        .and_then(|v| v.strip_suffix(']'))
+                    {
+                        list.split(',').map(str::trim).filter(|v| ! v.is_empty())
+                            .map(front_matter_value).collect()
+                    } else {
+                        vec![front_matter_value(value)]
+                    };
+                entries.push((KString::from_ref(key.trim()), values));
+            } else {
+                bail!("front matter line {}: expecting `key: value`, got {line:?}", i + 1)
+            }
+        }
+        Ok(FrontMatter(entries))
+    }
+
+    /// The first value for `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_all(key).first().map(|v| v.as_str())
+    }
+
+    /// All values for `key` (all entries with that key, in order).
+    pub fn get_all(&self, key: &str) -> Vec<&KString> {
+        self.0.iter().filter(|(k, _)| k == key).flat_map(|(_, v)| v).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Split a document into the front matter (the text between the
+/// `---` lines, if the document starts with such a line) and the
+/// rest.
+pub fn split_front_matter(s: &str) -> (Option<&str>, &str) {
+    let after_start = match s.strip_prefix("---\n").or_else(|| s.strip_prefix("---\r\n")) {
+        Some(rest) => rest,
+        None => return (None, s),
+    };
+    let mut pos = 0;
+    for line in after_start.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            return (Some(&after_start[..pos]), &after_start[pos + line.len()..])
+        }
+        pos += line.len();
+    }
+    (None, s)
+}
+
 // ------------------------------------------------------------------
 
 
@@ -113,7 +481,78 @@ fn level_from_elementmeta(meta: &'static ElementMeta) -> Option<i32> {
     else { None }
 }
 
-fn text_to_anchor(s: &str, res: &mut String) {
+/// If `body` (of a paragraph) consists of just an image with a
+/// title or alt text, returns the image and that text.
+fn standalone_image_caption(
+    body: ASlice<Node>, html: &HtmlAllocator
+) -> Option<(AId<Node>, KString)> {
+    if body.len() != 1 {
+        return None
+    }
+    let (img, _) = body.first_and_rest(html)?;
+    let node = html.get_node(img)?;
+    let elt = node.as_element()?;
+    if elt.meta() != *IMG_META {
+        return None
+    }
+    let attribute = |name: &str| elt.get_attribute(name, html).filter(
+        |v| ! v.is_empty()).cloned();
+    Some((img, attribute("title").or_else(|| attribute("alt"))?))
+}
+
+/// The language of a fenced code block from its info string (the
+/// text after the opening fence), i.e. the first word (up to a space
+/// or comma, as in "rust,ignore"). None if empty or containing
+/// characters that don't belong into a class name.
+fn code_block_language(info: &str) -> Option<&str> {
+    let lang = info.trim().split([' ', '\t', ',']).next()?;
+    let valid = ! lang.is_empty()
+        && lang.chars().all(|c| c.is_ascii_alphanumeric() || "-_+#".contains(c));
+    valid.then_some(lang)
+}
+
+/// `url` resolved against `config.base_url` if given and applicable.
+fn resolved_url(config: &MarkdownConfig, url: CowStr) -> KString {
+    match config.base_url.as_ref().and_then(|base| resolve_relative_url(base, &url)) {
+        Some(s) => KString::from_string(s),
+        None => kstring_myfrom2(url)
+    }
+}
+
+/// The file for an image `url` (as written in the document), for
+/// `MarkdownConfig::image_dimensions`. None for URLs with a scheme or
+/// host, and for paths that can't be resolved since `document_dir`
+/// or `asset_root` is missing.
+fn image_file_path(
+    config: &MarkdownConfig, document_dir: Option<&Path>, url: &str
+) -> Option<PathBuf> {
+    let path = url.split(['?', '#']).next().expect("split gives at least one item");
+    let has_scheme = path.find(':').is_some_and(|i| ! path[..i].contains('/'));
+    if has_scheme || path.starts_with("//") || path.is_empty() {
+        return None
+    }
+    let path = url_decode_lossy(path);
+    if let Some(path) = path.strip_prefix('/') {
+        Some(config.asset_root.as_ref()?.join(path))
+    } else {
+        Some(document_dir?.join(&*path))
+    }
+}
+
+/// Convert heading text to a string usable as URL fragment / `id`
+/// attribute value: ASCII alphanumeric characters are kept
+/// (lowercased), each whitespace character becomes `-`, and every
+/// other character (including non-ASCII ones) becomes `_`. Note that
+/// consecutive whitespace is *not* collapsed (changing that would
+/// break existing links).
+pub fn text_to_anchor(s: &str) -> String {
+    let mut res = String::new();
+    push_text_to_anchor(s, &mut res);
+    res
+}
+
+/// Like `text_to_anchor` but appends to an existing buffer.
+fn push_text_to_anchor(s: &str, res: &mut String) {
     let mut last_was_space = false;
     for c in s.chars() {
         if c.is_ascii_alphanumeric() {
@@ -131,7 +570,8 @@ fn text_to_anchor(s: &str, res: &mut String) {
 }
 
 pub struct MarkdownFile {
-    path: PathBuf
+    path: PathBuf,
+    config: MarkdownConfig,
 }
 
 pub struct MarkdownHeader {
@@ -140,6 +580,23 @@ pub struct MarkdownHeader {
     anchor_name: KString,
 }
 
+/// A figure (see `MarkdownConfig::figures`), for the list of figures.
+pub struct MarkdownFigure {
+    caption: KString,
+    /// The `id` of the `figure` element
+    anchor_name: KString,
+}
+
+impl MarkdownFigure {
+    pub fn caption(&self) -> &KString {
+        &self.caption
+    }
+
+    pub fn anchor_name(&self) -> &KString {
+        &self.anchor_name
+    }
+}
+
 pub struct MarkdownHeading {
     /// Original level as per .md document, used for building up (won't
     /// correspond to the HTML any more after fixing that up).
@@ -165,11 +622,13 @@ impl MarkdownHeading {
     }
 
     fn to_toc_html_fragment(
-        &self, html: &HtmlAllocator
+        &self, html: &HtmlAllocator, max_depth: Option<HeadingLevel>
     ) -> Result<AId<Node>> {
         let mut body = html.new_vec();
         for subheading in &self.subheadings {
-            body.push(subheading.to_toc_html_fragment(html)?)?;
+            if subheading.is_within(max_depth) {
+                body.push(subheading.to_toc_html_fragment(html, max_depth)?)?;
+            }
         }
         html.dl(
             [],
@@ -198,6 +657,10 @@ impl MarkdownHeading {
             ])
     }
 
+    fn is_within(&self, max_depth: Option<HeadingLevel>) -> bool {
+        max_depth.is_none_or(|max| self.level <= max)
+    }
+
     // Again duplication with method in MarkdownMeta. Stupid. todo clean up?
     fn top_heading_level(&self) -> Option<HeadingLevel> {
         if self.header.is_some() {
@@ -239,6 +702,15 @@ struct FootnoteDefinition {
     backreferences: Vec<Backref>,
 }
 
+/// A link reference definition like `[foo]: /url "title"`, as
+/// written in the document (i.e. the url is not resolved against
+/// `MarkdownConfig::base_url`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkReferenceDefinition {
+    pub url: KString,
+    pub title: Option<KString>,
+}
+
 pub struct MarkdownMeta {
     /// contents of <title> tag only (deriving from headers happens
     /// outside)
@@ -246,6 +718,16 @@ pub struct MarkdownMeta {
     headings: Vec<MarkdownHeading>,
     /// footnote label to definition
     footnotes: HashMap<KString, FootnoteDefinition>,
+    /// link reference label (as written in the definition) to
+    /// definition
+    link_references: BTreeMap<KString, LinkReferenceDefinition>,
+    /// empty unless `MarkdownConfig::front_matter` is set
+    front_matter: FrontMatter,
+    /// from `MarkdownConfig::max_toc_depth`
+    max_toc_depth: Option<HeadingLevel>,
+    /// in document order, empty unless `MarkdownConfig::figures` is
+    /// set
+    figures: Vec<MarkdownFigure>,
 }
 impl MarkdownMeta {
     fn new() -> MarkdownMeta {
@@ -253,9 +735,41 @@ impl MarkdownMeta {
             title: None,
             headings: Vec::new(),
             footnotes: HashMap::new(),
+            link_references: BTreeMap::new(),
+            front_matter: FrontMatter::default(),
+            max_toc_depth: None,
+            figures: Vec::new(),
         }
     }
 
+    pub fn front_matter(&self) -> &FrontMatter {
+        &self.front_matter
+    }
+
+    /// All link reference definitions in the document, whether used
+    /// or not, sorted by label.
+    pub fn link_references(&self) -> &BTreeMap<KString, LinkReferenceDefinition> {
+        &self.link_references
+    }
+
+    /// The figures in the document, in order.
+    pub fn figures(&self) -> &[MarkdownFigure] {
+        &self.figures
+    }
+
+    /// The list of figures, as an `ol` of links to them. (Check
+    /// `figures()` for emptiness to decide whether to show it.)
+    pub fn figures_html_fragment(&self, html: &HtmlAllocator) -> Result<AId<Node>> {
+        let mut body = html.new_vec();
+        for figure in &self.figures {
+            body.push(html.li(
+                [],
+                [html.a([att("href", format!("#{}", figure.anchor_name))],
+                        [html.kstring(figure.caption.clone())?])?])?)?;
+        }
+        html.ol([att("class", "list_of_figures")], body)
+    }
+
     fn push_heading(&mut self, h: MarkdownHeading) {
         match h.level {
             HeadingLevel::H1 => self.headings.push(h),
@@ -280,7 +794,9 @@ impl MarkdownMeta {
         let headings = self.title_and_remaining_headings().1;
         let mut body = html.new_vec();
         for subheading in headings {
-            body.push(subheading.to_toc_html_fragment(html)?)?;
+            if subheading.is_within(self.max_toc_depth) {
+                body.push(subheading.to_toc_html_fragment(html, self.max_toc_depth)?)?;
+            }
         }
         // Using `div` here instead of `dl` is wrong in that multiple
         // toplevel entries will be separate now. But what would the
@@ -387,17 +903,95 @@ pub struct ProcessedMarkdown {
     /// Conversion to html of the text, with the original heading
     /// levels translated to identical HTML levels (may need fixing up
     /// before serving).
-    html: AId<Node>,
+    body: ASlice<Node>,
+    /// `body` wrapped as configured, unless configured without
+    /// wrapper.
+    html: Option<AId<Node>>,
+    wrapper: MarkdownWrapper,
+    heading_shift: HeadingShift,
     /// Metadata extracted also during the conversion.
     meta: MarkdownMeta,
+    /// Problems in the document that still allowed the conversion,
+    /// like unresolved reference links, in document order.
+    warnings: Vec<String>,
 }
 
 impl ProcessedMarkdown {
-    pub fn html(&self) -> AId<Node> { self.html }
+    /// The document in its wrapper element, `None` if configured
+    /// with `MarkdownWrapper::None`.
+    pub fn html(&self) -> Option<AId<Node>> { self.html }
+    /// The contents of the document, without wrapper.
+    pub fn body(&self) -> ASlice<Node> { self.body }
     pub fn meta(&self) -> &MarkdownMeta { &self.meta }
+    pub fn warnings(&self) -> &[String] { &self.warnings }
+    /// Whether the document has no content (e.g. the file is empty
+    /// or contains only whitespace or front matter).
+    pub fn is_empty(&self) -> bool { self.body.len() == 0 }
+
+    /// Run `passes` in order over the converted document (with its
+    /// wrapper element, if any), and over each footnote definition.
+    /// The other metadata (like the headings for the TOC) is not
+    /// affected.
+    pub fn apply_dom_passes(
+        &mut self, passes: &[Box<dyn DomPass>], html: &HtmlAllocator
+    ) -> Result<()> {
+        if passes.is_empty() {
+            return Ok(())
+        }
+        // A slice as a single tree for the passes, and back
+        let apply_to_slice = |slice: ASlice<Node>| -> Result<ASlice<Node>> {
+            let root = apply_dom_passes(passes, html.fragment(slice)?, html)?;
+            Ok(match html.get_node(root).expect("valid id") {
+                Node::Fragment(body) => *body,
+                _ => {
+                    let mut body = html.new_vec();
+                    body.push(root)?;
+                    body.as_slice()
+                }
+            })
+        };
+        if let Some(root) = self.html {
+            let root = apply_dom_passes(passes, root, html)?;
+            self.body = *html.get_node(root).expect("valid id").try_element()?.body();
+            self.html = Some(root);
+        } else {
+            self.body = apply_to_slice(self.body)?;
+        }
+        for definition in self.meta.footnotes.values_mut() {
+            if let Some(text) = definition.text {
+                definition.text = Some(apply_to_slice(text)?);
+            }
+        }
+        Ok(())
+    }
 
+    /// `fixed_body` in the configured wrapper element. Gives an error
+    /// if configured with `MarkdownWrapper::None`.
     pub fn fixed_html(&self, html: &HtmlAllocator) -> Result<AId<Node>> {
+        let (meta, unfixed) = match (self.wrapper, self.html) {
+            (MarkdownWrapper::Element(meta), Some(id)) => (meta, id),
+            _ => bail!("fixed_html: markdown was processed without wrapper \
+                        element, use fixed_body instead")
+        };
+        match self.fix_body(html)? {
+            Some(body) => html.new_element(meta, html.empty_slice(), body),
+            None => Ok(unfixed)
+        }
+    }
+
+    /// The contents of the document with the headings fixed up for
+    /// serving (below a page title taken from the document).
+    pub fn fixed_body(&self, html: &HtmlAllocator) -> Result<ASlice<Node>> {
+        Ok(self.fix_body(html)?.unwrap_or(self.body))
+    }
+
+    /// `None` if no fixing is needed.
+    fn fix_body(&self, html: &HtmlAllocator) -> Result<Option<ASlice<Node>>> {
         // Which is the top level we *want*?
+        let top_level_want = match self.heading_shift {
+            HeadingShift::TopLevel(level) => headinglevel_num(level),
+            HeadingShift::None => return Ok(None),
+        };
         let (opt_title, _heading, do_drop_h1) =
             self.meta.title_and_remaining_headings();
         dt!(&format!("fixed_html {:?}",
@@ -408,15 +1002,16 @@ impl ProcessedMarkdown {
         // anything (because H1 existed and was the only H1 header,
         // after dropping it the next level can only be H2 or less and
         // we leave it at what remains), or, shift them if necessary
-        // so that the top level becomes H2. Unless it couldn't
-        // extract a title, in which case we leave the document
-        // untouched.
+        // so that the top level becomes `top_level_want` (H2 by
+        // default). Unless it couldn't extract a title, in which case
+        // we leave the document untouched. Dropping only makes sense
+        // if the page shows the title as H1 instead.
         if opt_title.is_none() {
             warn!("no title could be derived");
-            return Ok(self.html)
+            return Ok(None)
         }
         let fixup: Box<dyn Fn(_) -> _> =
-            if do_drop_h1 {
+            if do_drop_h1 && top_level_want > 1 {
                 warn!("do_drop_h1");
                 Box::new(|id: AId<Node>| -> Result<Option<AId<Node>>> {
                     let node = html.get_node(id).expect("correct HtmlAllocator");
@@ -432,11 +1027,10 @@ impl ProcessedMarkdown {
                 })
             } else {
                 if let Some(top_level_have) = self.meta.top_heading_level() {
-                    let top_level_want = 2; // HeadingLevel::H2;
                     let diff = top_level_want - headinglevel_num(top_level_have);
                     warn!("diff = {diff}");
                     if diff == 0 {
-                        return Ok(self.html)
+                        return Ok(None)
                     }
                     Box::new(move |id: AId<Node>| -> Result<Option<AId<Node>>> {
                         let node = html.get_node(id).expect("correct HtmlAllocator");
@@ -462,27 +1056,50 @@ impl ProcessedMarkdown {
                     })
                 } else {
                     warn!("no headings, thus noop");
-                    return Ok(self.html)
+                    return Ok(None)
                 }
             };
 
-        let node2 = {
-            let elt = {
-                let node = html.get_node(self.html).expect(
-                    "ProcessedMarkdown to be used with the same HtmlAllocator it was created with");
-                // Bummer, Element is quite large (5 words?), but we have
-                // to free up the borrow from get_node because
-                // try_filter_map_body needs a writable one.
-                (*node.try_element()?).clone()
-            };
-            elt.try_filter_map_body::<Node>(fixup, html)?
-        };
-        Ok(html.allocate_element(node2)?)
+        Ok(Some(self.body.try_filter_map(fixup, None, html)?.as_slice()))
     }
 }
 
 // Internals for impl MarkdownFile:
 
+/// The pulldown-cmark extensions used for all conversions.
+pub(crate) fn markdown_options() -> Options {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_FOOTNOTES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+    // (Not using ENABLE_SMART_PUNCTUATION since that only does
+    // English quotes, see `SmartPunctuation`.)
+    options.insert(Options::ENABLE_HEADING_ATTRIBUTES);
+    options
+}
+
+/// The 1-based line and column (in characters) of byte offset `pos`
+/// in `s`.
+fn line_and_column(s: &str, pos: usize) -> (usize, usize) {
+    let before = &s[..pos];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (before.matches('\n').count() + 1,
+     before[line_start..].chars().count() + 1)
+}
+
+/// Strip a BOM and (if enabled in `config`) the front matter off the
+/// markdown source.
+fn split_source<'s>(s: &'s str, config: &MarkdownConfig) -> Result<(FrontMatter, &'s str)> {
+    let s = s.strip_prefix('\u{feff}').unwrap_or(s);
+    if config.front_matter {
+        let (front_matter, s) = split_front_matter(s);
+        Ok((front_matter.map(FrontMatter::parse).transpose()?.unwrap_or_default(), s))
+    } else {
+        Ok((FrontMatter::default(), s))
+    }
+}
+
 #[derive(Debug)]
 enum ContextTag<'t> {
     Markdown(Tag<'t>),
@@ -537,31 +1154,117 @@ struct ContextFrame<'a, 't> {
 
 impl MarkdownFile {
     pub fn new(path: PathBuf) -> MarkdownFile {
-        MarkdownFile { path } 
+        Self::new_with_config(path, MarkdownConfig::default())
+    }
+    pub fn new_with_config(path: PathBuf, config: MarkdownConfig) -> MarkdownFile {
+        MarkdownFile { path, config }
     }
     pub fn path(&self) -> &PathBuf {
         &self.path
     }
-    
+    pub fn config(&self) -> &MarkdownConfig {
+        &self.config
+    }
+
     /// Convert to HTML, and capture metainformation to allow for
     /// creation of TOC and footnotes section.
     pub fn process_to_html(
         &self, html: &HtmlAllocator
     ) -> Result<ProcessedMarkdown>
     {
-        let mut options = Options::empty();
-        options.insert(Options::ENABLE_TABLES);
-        options.insert(Options::ENABLE_FOOTNOTES);
-        options.insert(Options::ENABLE_STRIKETHROUGH);
-        options.insert(Options::ENABLE_TASKLISTS);
-        options.insert(Options::ENABLE_SMART_PUNCTUATION);// XX config
-        options.insert(Options::ENABLE_HEADING_ATTRIBUTES);
+        // XX impose a size limit on the markdown file here?
+        let (s, warning) = self.config.non_utf8.decode(my_read(&self.path)?, &self.path)?;
+        Self::process_str_in_dir(&s, self.path.parent(), warning.into_iter().collect(),
+                                 &self.config, html)
+    }
+
+    /// Like `process_to_html` but taking the markdown source from a
+    /// string.
+    pub fn process_str_to_html(
+        s: &str, config: &MarkdownConfig, html: &HtmlAllocator
+    ) -> Result<ProcessedMarkdown>
+    {
+        Self::process_str_in_dir(s, None, Vec::new(), config, html)
+    }
+
+    /// Convert to plain text, see `markdown_plain`. Front matter is
+    /// dropped.
+    pub fn process_to_plain(&self) -> Result<String> {
+        let (s, warning) = self.config.non_utf8.decode(my_read(&self.path)?, &self.path)?;
+        if let Some(warning) = warning {
+            // (No warnings list to put it into here)
+            chj_util::warn!("{warning}");
+        }
+        Self::process_str_to_plain(&s, &self.config)
+    }
+
+    /// Like `process_to_plain` but taking the markdown source from a
+    /// string.
+    pub fn process_str_to_plain(s: &str, config: &MarkdownConfig) -> Result<String> {
+        let (_front_matter, s) = split_source(s, config)?;
+        Ok(markdown_to_plain(s, config))
+    }
+
+    /// `document_dir`: the directory the markdown file is in, if
+    /// any. `warnings`: from reading the file. Errors while
+    /// converting a construct are prefixed with its location in `s`.
+    fn process_str_in_dir(
+        s: &str,
+        document_dir: Option<&Path>,
+        warnings: Vec<String>,
+        config: &MarkdownConfig,
+        html: &HtmlAllocator
+    ) -> Result<ProcessedMarkdown>
+    {
+        let position = Cell::new(None);
+        Self::process_str_in_dir_tracking(s, document_dir, warnings, config, html, &position)
+            .map_err(|e| match position.get() {
+                Some(pos) => {
+                    let (line, column) = line_and_column(s, pos);
+                    e.context(format!("line {line}, column {column}"))
+                }
+                None => e
+            })
+    }
+
+    /// `position`: set to the byte offset into `s` of the markdown
+    /// event currently being converted, if any.
+    fn process_str_in_dir_tracking(
+        source: &str,
+        document_dir: Option<&Path>,
+        warnings: Vec<String>,
+        config: &MarkdownConfig,
+        html: &HtmlAllocator,
+        position: &Cell<Option<usize>>,
+    ) -> Result<ProcessedMarkdown>
+    {
+        let (front_matter, s) = split_source(source, config)?;
+        // `s` is a suffix of `source`
+        let offset = source.len() - s.len();
+        let options = markdown_options();
+
+        let warnings: RefCell<Vec<String>> = RefCell::new(warnings);
+        let warning = |msg: String| {
+            warn!("{msg}");
+            warnings.borrow_mut().push(msg);
+        };
+        // Called for `[foo][bar]` and `[foo][]` without a definition
+        // for the reference (and for `[foo]`, which we leave alone as
+        // that is commonly just text in brackets).
+        let mut broken_link = |link: BrokenLink| {
+            match link.link_type {
+                LinkType::Shortcut => (),
+                _ => warning(format!("unresolved reference link {:?} at {:?}",
+                                     &*link.reference,
+                                     &s[link.span])),
+            }
+            None
+        };
 
         // `Parser` is NOT supporting streaming. For reasons of
         // shining in (superficial) performance bencharks?
-        // XX impose a size limit on the markdown file here?
-        let s = my_read_to_string(&self.path)?;
-        let mut parser = Parser::new_ext(&s, options);
+        let mut parser = Parser::new_with_broken_link_callback(
+            s, options, Some(&mut broken_link)).into_offset_iter();
 
         // Context
         let mut _context: Vec<ContextFrame> = Vec::new();
@@ -643,18 +1346,54 @@ impl MarkdownFile {
         }
 
         let mut markdownmeta =  MarkdownMeta::new();
+        markdownmeta.front_matter = front_matter;
+        markdownmeta.max_toc_depth = config.max_toc_depth;
         // let mut current_heading = None;
         let mut anchor_name = String::new();
         let mut tmp = String::new();
         // Anchor names to number of uses, acting as id
         let mut anchor_names: HashMap<KString, u32> = HashMap::new();
+        // Make `anchor_name` unique among those of the headings and
+        // figures so far, by appending a number if necessary
+        // (XX should actually do a check like this on the whole
+        // generated page (uh, preserialized parts!))
+        let mut allocate_anchor_name = |anchor_name: &mut String| -> KString {
+            for _ in 0..10 {
+                if let Some(counter) = anchor_names.get_mut(&**anchor_name) {
+                    *counter += 1;
+                    anchor_name.push_str(&format!("-{}", *counter));
+                } else {
+                    let anchor_name_kstr = KString::from_ref(anchor_name);
+                    anchor_names.insert(anchor_name_kstr.clone(), 1);
+                    return anchor_name_kstr
+                }
+            }
+            warning(format!(
+                "more than 10 *levels* of conflicts trying to find \
+                 unallocated name for {anchor_name:?}; leaving it \
+                 conflicting"));
+            KString::from_ref(anchor_name)
+        };
         
         let mut next_footnote_number = infinite_sequence(1, 1);
         let mut next_footnote_backreference = infinite_sequence(1, 1);
-
-        while let Some(item) = parser.next() {
+        let mut next_figure_number = infinite_sequence(1, 1);
+        // The column alignments of the current table, the index of
+        // the next cell in the current row, and whether in the header
+        // row
+        let mut table_alignments: Vec<Alignment> = Vec::new();
+        let mut table_column = 0;
+        let mut in_table_head = false;
+        let mut smart = SmartPunctuation::new(config.quotes);
+
+        while let Some((item, range)) = parser.next() {
+            position.set(Some(offset + range.start));
             match item {
-                Event::Start(x) =>
+                Event::Start(x) => {
+                    if ! matches!(x, Tag::Emphasis | Tag::Strong | Tag::Strikethrough
+                                  | Tag::Link(..) | Tag::Image(..)) {
+                        smart.reset();
+                    }
                     match x {
                         Tag::Paragraph =>
                             mdopen!(Tag::Paragraph),
@@ -670,12 +1409,19 @@ impl MarkdownFile {
                             mdopen!(Tag::Item),
                         Tag::FootnoteDefinition(label) =>
                             mdopen!(Tag::FootnoteDefinition(label)),
-                        Tag::Table(alignments) =>
-                            mdopen!(Tag::Table(alignments)),
-                        Tag::TableHead =>
-                            mdopen!(Tag::TableHead),
-                        Tag::TableRow => 
-                            mdopen!(Tag::TableRow),
+                        Tag::Table(alignments) => {
+                            table_alignments = alignments.clone();
+                            mdopen!(Tag::Table(alignments))
+                        }
+                        Tag::TableHead => {
+                            table_column = 0;
+                            in_table_head = true;
+                            mdopen!(Tag::TableHead)
+                        }
+                        Tag::TableRow => {
+                            table_column = 0;
+                            mdopen!(Tag::TableRow)
+                        }
                         Tag::TableCell =>
                             mdopen!(Tag::TableCell),
                         Tag::Emphasis => 
@@ -688,11 +1434,30 @@ impl MarkdownFile {
                             mdopen!(Tag::Link(linktype, url, title)),
                         Tag::Image(linktype, url, title) =>
                             mdopen!(Tag::Image(linktype, url, title)),
-                    },
+                    }
+                }
                 Event::End(x) =>
                     match x {
-                        Tag::Paragraph =>
-                            mdclose!(Tag::Paragraph, *P_META)?,
+                        Tag::Paragraph => {
+                            let caption = if config.figures {
+                                standalone_image_caption(current_frame!().body.as_slice(),
+                                                         html)
+                            } else {
+                                None
+                            };
+                            if let Some((img, caption)) = caption {
+                                let (_atts, _body, outerframe) = mdpop!(Tag::Paragraph);
+                                let anchor_name = allocate_anchor_name(
+                                    &mut format!("figure-{}", next_figure_number()));
+                                outerframe.body.push(html.figure(
+                                    [att("id", &anchor_name)],
+                                    [img,
+                                     html.figcaption([], [html.kstring(caption.clone())?])?])?)?;
+                                markdownmeta.figures.push(MarkdownFigure { caption, anchor_name });
+                            } else {
+                                mdclose!(Tag::Paragraph, *P_META)?
+                            }
+                        }
                         Tag::Heading(level, fragmentid, classes) => {
                             {
                                 // Store generated HTML for this
@@ -706,28 +1471,8 @@ impl MarkdownFile {
                                     node.print_plain(&mut tmp, html)?;
                                 }
                                 anchor_name.clear();
-                                text_to_anchor(&tmp, &mut anchor_name);
-
-                                // Append number if necessary to avoid conflicts
-                                // (XX should actually do a check like this on the whole
-                                // generated page (uh, preserialized parts!))
-                                let anchor_name_kstr;
-                                'search: loop { // loop bc labels on blocks are unstable
-                                    for _ in 0..10 {
-                                        if let Some(counter) = anchor_names.get_mut(&*anchor_name) {
-                                            *counter += 1;
-                                            anchor_name.push_str(&format!("-{}", *counter));
-                                        } else {
-                                            anchor_name_kstr = KString::from(&anchor_name); 
-                                            anchor_names.insert(anchor_name_kstr.clone(), 1);
-                                            break 'search;
-                                        }
-                                    }
-                                    warn!("more than 10 *levels* of conflicts trying to find \
-                                           unallocated name; leaving it conflicting");
-                                    anchor_name_kstr = KString::from(&anchor_name);
-                                    break;
-                                }
+                                push_text_to_anchor(&tmp, &mut anchor_name);
+                                let anchor_name_kstr = allocate_anchor_name(&mut anchor_name);
 
                                 frame.atts.push(
                                     // XX Should offer an `attribute`
@@ -754,18 +1499,35 @@ impl MarkdownFile {
                         }
                         Tag::BlockQuote =>
                             mdclose!(Tag::BlockQuote, *BLOCKQUOTE_META)?,
-                        Tag::CodeBlock(kind) => 
-                        // XX kind -> class="language-xxx", and do highlighting
-                            mdclose!(Tag::CodeBlock(kind), *PRE_META)?,
-                            
-                        Tag::List(firstitemnum) =>
+                        Tag::CodeBlock(kind) => {
+                            // The `code` element gets the language as
+                            // `class`, for client-side highlighters
+                            let class = match &kind {
+                                CodeBlockKind::Fenced(info) =>
+                                    code_block_language(info).map(
+                                        |lang| format!("language-{lang}")),
+                                CodeBlockKind::Indented => None,
+                            };
+                            let (atts, body, outerframe) = mdpop!(Tag::CodeBlock(kind));
+                            outerframe.body.push(html.pre(
+                                atts.as_slice(),
+                                [html.code([opt_att("class", class)], body)?])?)?;
+                        }
+                        Tag::List(firstitemnum) => {
+                            if let Some(start) = firstitemnum {
+                                if start != 1 {
+                                    current_frame!().atts.push(
+                                        html.attribute("start", start.to_string())?)?;
+                                }
+                            }
                             mdclose!(
                                 Tag::List(firstitemnum),
                                 if firstitemnum.is_some() {
                                     *OL_META
                                 } else {
                                     *UL_META
-                                })?,
+                                })?
+                        }
                         Tag::Item =>
                             mdclose!(Tag::Item, *LI_META)?,
                         Tag::FootnoteDefinition(label) => {
@@ -793,16 +1555,42 @@ impl MarkdownFile {
                                     });
                             }
                         }
-                        Tag::Table(alignments) =>
-                            mdclose!(Tag::Table(alignments),
-                                     // XX todo: handle alignments
-                                     *TABLE_META)?,
-                        Tag::TableHead => 
-                            mdclose!(Tag::TableHead, *TH_META)?,
+                        Tag::Table(alignments) => {
+                            // The first row is the header (`thead`),
+                            // the others go into a `tbody`
+                            let (atts, body, outerframe) = mdpop!(Tag::Table(alignments));
+                            let mut content = html.new_vec();
+                            if let Some((head, rows)) = body.as_slice().first_and_rest(html) {
+                                content.push(head)?;
+                                if ! rows.is_empty() {
+                                    content.push(html.tbody([], rows)?)?;
+                                }
+                            }
+                            outerframe.body.push(html.new_element(
+                                *TABLE_META, atts.as_slice(), content.as_slice())?)?;
+                        }
+                        Tag::TableHead => {
+                            in_table_head = false;
+                            let (atts, body, outerframe) = mdpop!(Tag::TableHead);
+                            outerframe.body.push(
+                                html.thead([], [html.tr(atts.as_slice(), body)?])?)?;
+                        }
                         Tag::TableRow => 
                             mdclose!(Tag::TableRow, *TR_META)?,
-                        Tag::TableCell => 
-                            mdclose!(Tag::TableCell, *TD_META)?,
+                        Tag::TableCell => {
+                            let align = match table_alignments.get(table_column) {
+                                Some(Alignment::Left) => Some("text-align:left"),
+                                Some(Alignment::Center) => Some("text-align:center"),
+                                Some(Alignment::Right) => Some("text-align:right"),
+                                Some(Alignment::None) | None => None,
+                            };
+                            table_column += 1;
+                            if let Some(align) = align {
+                                current_frame!().atts.push(html.attribute("style", align)?)?;
+                            }
+                            mdclose!(Tag::TableCell,
+                                     if in_table_head { *TH_META } else { *TD_META })?
+                        }
                         Tag::Emphasis => 
                             mdclose!(Tag::Emphasis, *EM_META)?,
                         Tag::Strong => 
@@ -816,42 +1604,27 @@ impl MarkdownFile {
                                     Tag::Link(linktype, url.clone(), title));
 
                             let elt = match linktype {
-                                // Inline link like `[foo](bar)`
-                                LinkType::Inline => {
-                                    atts.push(
-                                        html.attribute("href", kstring_myfrom2(url))?)?;
-                                    html.a(atts, body)
-                                }
-                                // Reference link like `[foo][bar]`
-                                LinkType::Reference => {
-                                    warn_todo!("LinkType::Reference: \
-                                                url, presumably?");
-                                    atts.push(
-                                        html.attribute("href", kstring_myfrom2(url))?)?;
-                                    html.a(atts, body)
-                                },
-                                // Reference without destination in
-                                // the document, but resolved by the
-                                // broken_link_callback
-                                LinkType::ReferenceUnknown => todo!(),
-                                // Collapsed link like `[foo][]`
-                                LinkType::Collapsed => todo!(),
-                                // Collapsed link without destination
-                                // in the document, but resolved by
-                                // the broken_link_callback
-                                LinkType::CollapsedUnknown => todo!(),
-                                // Shortcut link like `[foo]`
+                                // Inline link like `[foo](bar)`, and
+                                // reference links like `[foo][bar]`,
+                                // collapsed `[foo][]` and shortcut
+                                // `[foo]`, for which the parser has
+                                // already looked up the url
+                                LinkType::Inline |
+                                LinkType::Reference |
+                                LinkType::Collapsed |
                                 LinkType::Shortcut => {
-                                    warn_todo!("LinkType::Shortcut: need to build \
-                                                index and look up");
                                     atts.push(
-                                        html.attribute("href", kstring_myfrom2(url))?)?;
+                                        html.attribute("href", resolved_url(config, url))?)?;
                                     html.a(atts, body)
-                                },
-                                // Shortcut without destination in the
-                                // document, but resolved by the
-                                // broken_link_callback
-                                LinkType::ShortcutUnknown => todo!(),
+                                }
+                                // References without destination in
+                                // the document, only given if resolved
+                                // by the broken_link_callback, which
+                                // we don't do
+                                LinkType::ReferenceUnknown |
+                                LinkType::CollapsedUnknown |
+                                LinkType::ShortcutUnknown =>
+                                    bail!("unresolved reference link to {:?}", &*url),
                                 // Autolink like `<http://foo.bar/baz>`
                                 LinkType::Autolink =>
                                     html.a([att("href", kstring_myfrom2(url))],
@@ -869,31 +1642,67 @@ impl MarkdownFile {
                             let (mut atts, body, outerframe) =
                                 mdpop!(
                                     // XX uh, need to clone just to verify. better?
-                                    Tag::Link(linktype, url.clone(), title));
+                                    Tag::Image(linktype, url.clone(), title.clone()));
                             let elt = match linktype {
-                                LinkType::Inline => {
+                                // (See Tag::Link)
+                                LinkType::Inline |
+                                LinkType::Reference |
+                                LinkType::Collapsed |
+                                LinkType::Shortcut => {
+                                    // `img` can't have content, the
+                                    // description becomes the alt text
+                                    tmp.clear();
+                                    for node in body.as_slice().iter_node(html) {
+                                        node.print_plain(&mut tmp, html)?;
+                                    }
+                                    let file_path =
+                                        if config.image_dimensions {
+                                            image_file_path(config, document_dir, &url)
+                                        } else {
+                                            None
+                                        };
                                     atts.push(
-                                        html.attribute("src", kstring_myfrom2(url))?)?;
-                                    html.img(atts, body)
+                                        html.attribute("src", resolved_url(config, url))?)?;
+                                    atts.push(html.attribute("alt", tmp.as_str())?)?;
+                                    if ! title.is_empty() {
+                                        atts.push(html.attribute("title", kstring_myfrom2(title))?)?;
+                                    }
+                                    if let Some(file_path) = file_path {
+                                        match imageinfo(&file_path.to_string_lossy()) {
+                                            Ok(size) => {
+                                                atts.push(html.attribute("width", size.width)?)?;
+                                                atts.push(html.attribute("height", size.height)?)?;
+                                            }
+                                            Err(e) => warning(format!(
+                                                "can't get the size of image {file_path:?}: {e}"))
+                                        }
+                                    }
+                                    html.img(atts, [])
                                 }
-                                LinkType::Reference => todo!(),
-                                LinkType::ReferenceUnknown => todo!(),
-                                LinkType::Collapsed => todo!(),
-                                LinkType::CollapsedUnknown => todo!(),
-                                LinkType::Shortcut => todo!(),
-                                LinkType::ShortcutUnknown => todo!(),
-                                LinkType::Autolink => todo!(),
-                                LinkType::Email => todo!(),
+                                LinkType::ReferenceUnknown |
+                                LinkType::CollapsedUnknown |
+                                LinkType::ShortcutUnknown =>
+                                    bail!("unresolved reference image to {:?}", &*url),
+                                // Autolinks are never images
+                                LinkType::Autolink |
+                                LinkType::Email =>
+                                    bail!("unexpected link type {linktype:?} for image {:?}",
+                                          &*url),
                             };
                             outerframe.body.push(elt?)?;
                         }
                     },
                 Event::Text(s) => {
                     let frame = current_frame!();
-                    frame.body.push(html.str(&s)?)?;
+                    if let ContextTag::Markdown(Tag::CodeBlock(_)) = frame.tag {
+                        frame.body.push(html.str(&s)?)?;
+                    } else {
+                        frame.body.push(html.str(&smart.convert(&s))?)?;
+                    }
                 }
                 Event::Code(s) => {
                     warn!("Event::Code({:?})", &*s);
+                    smart.skip(&s);
                     let frame = current_frame!();
                     let elt = html.code(
                         [],
@@ -909,21 +1718,33 @@ impl MarkdownFile {
                     // them, move them outside, is too painful for me
                     // right now, so I go.
                     dt!(&format!("Event::Html({s:?})"));
-                    for token in html5gum::Tokenizer::new(&*s).infallible() {
-                        match token {
-                            Token::StartTag(starttag) => {
-                                let name: &str = std::str::from_utf8(
-                                    &**starttag.name)?;
-                                let meta = METADB.elementmeta.get(name).ok_or_else(
-                                    || error_not_an_html5_tag_name(name))?;
+                    match config.raw_html {
+                        RawHtmlPolicy::Validate => (),
+                        RawHtmlPolicy::PassThroughVerbatim => {
+                            // Block level HTML is directly in a
+                            // container, inline HTML in a paragraph etc.
+                            let is_block = context.len() == 1 || matches!(
+                                current_frame!().tag,
+                                ContextTag::Markdown(Tag::BlockQuote | Tag::Item
+                                                     | Tag::FootnoteDefinition(_)));
+                            let meta = if is_block { *DIV_META } else { *SPAN_META };
+                            current_frame!().body.push(
+                                html.preserialized(SerHtmlFrag::new_unchecked(meta, &s))?)?;
+                            continue
+                        }
+                        RawHtmlPolicy::Strip => continue,
+                        RawHtmlPolicy::Escape => {
+                            current_frame!().body.push(html.str(&s)?)?;
+                            continue
+                        }
+                    }
+                    for token in html.parse_tokens(&s) {
+                        match token? {
+                            HtmlToken::StartTag { meta, attr, self_closing } => {
                                 let mut newframe = new_contextframe!(
                                     ContextTag::Html(meta));
-                                for (k, v) in starttag.attributes {
-                                    newframe.atts.push(
-                                        html.attribute(
-                                            kstring(k)?, kstring(v)?)?)?;
-                                }
-                                if starttag.self_closing || ! meta.has_closing_tag {
+                                newframe.atts = attr;
+                                if self_closing {
                                     let cf = current_frame!();
                                     // XX give context to errors,
                                     // e.g. invalid attribute because,
@@ -934,22 +1755,22 @@ impl MarkdownFile {
                                     context.push(newframe);
                                 }
                             }
-                            Token::EndTag(endtag) => {
-                                let name: &str = std::str::from_utf8(
-                                    &**endtag.name)?;
-                                let meta = METADB.elementmeta.get(name).ok_or_else(
-                                    || error_not_an_html5_tag_name(name))?;
+                            HtmlToken::EndTag(meta) => {
                                 if meta.has_closing_tag {
                                     let (atts, body, outerframe) =
-                                        // XX error context. if only I had
-                                        // location info? sigh?
                                         pop!(ContextTag::Html(meta));
                                     // Special HTML tag treatments
                                     if meta == *TITLE_META {
-                                        if markdownmeta.title.is_some() {
-                                            bail!("multiple <title> elements")
+                                        let keep_old = markdownmeta.title.is_some() &&
+                                            match config.multiple_titles {
+                                                MultipleTitlesPolicy::Error =>
+                                                    bail!("multiple <title> elements"),
+                                                MultipleTitlesPolicy::UseFirst => true,
+                                                MultipleTitlesPolicy::UseLast => false,
+                                            };
+                                        if ! keep_old {
+                                            markdownmeta.title = Some(body.as_slice());
                                         }
-                                        markdownmeta.title = Some(body.as_slice());
                                         // XX dropping atts OK?
                                     } else {
                                         outerframe.body.push(
@@ -961,18 +1782,17 @@ impl MarkdownFile {
                                     // NOOP, we haven't made a frame for it.
                                 }
                             }
-                            Token::String(s) => {
-                                let frame = current_frame!();
-                                frame.body.push(html.kstring(kstring(s)?)?)?;
+                            HtmlToken::Text(node) => {
+                                current_frame!().body.push(node)?;
                             }
-                            Token::Comment(_s) => {
+                            HtmlToken::Comment => {
                                 // This happens only when <!-- and -->
                                 // appear in the same markdown event,
                                 // i.e. in the same paragraph.  todo:
                                 // do something with _s?
                             },
-                            Token::Doctype(_) => todo!(),
-                            Token::Error(e) =>
+                            HtmlToken::Doctype => todo!(),
+                            HtmlToken::Error(e) =>
                                 if s.starts_with("<!--") {
                                     // XX how to check `e` ? Should verify it's "eof-in-comment"
                                     // let newframe = new_contextframe!(
@@ -982,7 +1802,7 @@ impl MarkdownFile {
                                     // No, slurp up markdown
                                     // events right here until -->
                                     // appears.
-                                    while let Some(item) = parser.next() {
+                                    while let Some((item, _)) = parser.next() {
                                         match item {
                                             Event::Html(s) =>
                                                 if s.starts_with("-->") {
@@ -1029,29 +1849,68 @@ impl MarkdownFile {
                         };
 
                     let frame = current_frame!();
-                    if let Some(i) = frame.last_footnote_reference {
-                        if i == frame.body.len() {
-                            // Separate the new reference from the
-                            // last reference; todo?: ideally the 3
-                            // `sup` would be merged.
+                    let follows_reference =
+                        frame.last_footnote_reference == Some(frame.body.len());
+                    match config.adjacent_footnote_references {
+                        AdjacentFootnoteReferences::CommaSeparated |
+                        AdjacentFootnoteReferences::Unseparated => {
+                            if follows_reference && config.adjacent_footnote_references
+                                == AdjacentFootnoteReferences::CommaSeparated
+                            {
+                                // Separate the new reference from the
+                                // last reference
+                                frame.body.push(
+                                    html.sup(
+                                        [],
+                                        [html.str(",")?])?)?;
+                            }
                             frame.body.push(
                                 html.sup(
-                                    [],
-                                    [html.str(",")?])?)?;
+                                    [att("id", backref.to_kstring(false)),],
+                                    [html.a(
+                                        [att("href", reference.to_kstring(true)),
+                                         config.footnote_links.role("doc-noteref"),
+                                         config.footnote_links.describedby(reference),
+                                         config.footnote_links.rel()],
+                                        [html.string(
+                                            config.footnote_markers.marker(reference.0))?])?])?)?;
+                        }
+                        AdjacentFootnoteReferences::Merged => {
+                            // The id goes on the `a` here, since
+                            // there's only one `sup`.
+                            let a = html.a(
+                                [att("id", backref.to_kstring(false)),
+                                 att("href", reference.to_kstring(true)),
+                                 config.footnote_links.role("doc-noteref"),
+                                 config.footnote_links.describedby(reference),
+                                 config.footnote_links.rel()],
+                                [html.string(
+                                    config.footnote_markers.marker(reference.0))?])?;
+                            let mut body = html.new_vec();
+                            if follows_reference {
+                                let last = frame.body.pop().expect(
+                                    "last_footnote_reference is at end of body");
+                                let lastbody = *html.get_node(last).expect("valid id")
+                                    .try_element()?.body();
+                                body.extend_from_slice(&lastbody, html)?;
+                                body.push(html.str(",")?)?;
+                            }
+                            body.push(a)?;
+                            frame.body.push(html.sup([], body)?)?;
                         }
                     }
-                    frame.body.push(
-                        html.sup(
-                            [att("id", backref.to_kstring(false)),],
-                            [html.a(
-                                [att("href", reference.to_kstring(true))],
-                                [html.string(reference.0.to_string())?])?])?)?;
                     frame.last_footnote_reference = Some(frame.body.len());
                 }
                 Event::SoftBreak => {
                     // a single \n in the input
                     let frame = current_frame!();
-                    frame.body.push(html.str("\n")?)?;
+                    match config.soft_break {
+                        SoftBreakPolicy::AsNewline =>
+                            frame.body.push(html.staticstr("\n")?)?,
+                        SoftBreakPolicy::AsSpace =>
+                            frame.body.push(html.staticstr(" ")?)?,
+                        SoftBreakPolicy::Elide => (),
+                    }
                 }
                 Event::HardBreak => {
                     // "  \n" in the input
@@ -1079,7 +1938,8 @@ impl MarkdownFile {
                 }
             }
         }
-        
+        position.set(None);
+
         match context.len() {
             0 => bail!("top-level context was dropped -- should be impossible?"),
             1 => (),
@@ -1091,9 +1951,600 @@ impl MarkdownFile {
                        .join(", "))
         }
         let baseframe = context.pop().unwrap();
+
+        markdownmeta.link_references = parser.reference_definitions().iter().map(
+            |(label, def)| (
+                KString::from_ref(label),
+                LinkReferenceDefinition {
+                    url: kstring_myfrom2(def.dest.clone()),
+                    title: def.title.clone().map(kstring_myfrom2),
+                })).collect();
+
+        let mut undefined_footnotes: Vec<&KString> = markdownmeta.footnotes.iter()
+            .filter(|(_, fnd)| fnd.text.is_none())
+            .map(|(label, _)| label)
+            .collect();
+        undefined_footnotes.sort();
+        for label in undefined_footnotes {
+            warning(format!("footnote {:?} is referenced but not defined",
+                            label.as_str()));
+        }
+        let warnings = warnings.into_inner();
+        if config.strict && ! warnings.is_empty() {
+            bail!("strict mode: {} warning(s) converting markdown: {}",
+                  warnings.len(),
+                  warnings.join("; "))
+        }
+        let mut body = baseframe.body.as_slice();
+        if config.autolink {
+            body = autolink_body(html, body)?;
+            for footnote in markdownmeta.footnotes.values_mut() {
+                if let Some(text) = &mut footnote.text {
+                    *text = autolink_body(html, *text)?;
+                }
+            }
+        }
         Ok(ProcessedMarkdown {
-            html: frame_to_element(baseframe, *DIV_META)?,
-            meta: markdownmeta
+            body,
+            html: match config.wrapper {
+                MarkdownWrapper::Element(meta) =>
+                    Some(html.new_element(meta, baseframe.atts.as_slice(), body)?),
+                MarkdownWrapper::None => None,
+            },
+            wrapper: config.wrapper,
+            heading_shift: config.heading_shift,
+            meta: markdownmeta,
+            warnings,
         })
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn t_text_to_anchor() {
+        assert_eq!(text_to_anchor(""), "");
+        assert_eq!(text_to_anchor("Hello World"), "hello-world");
+        assert_eq!(text_to_anchor("Rust 2021: What's new?"),
+                   "rust-2021_-what_s-new_");
+        assert_eq!(text_to_anchor("a-b_c"), "a_b_c");
+        // Consecutive and non-space whitespace are not collapsed
+        assert_eq!(text_to_anchor("a  b"), "a--b");
+        assert_eq!(text_to_anchor(" a\tb\n"), "-a-b-");
+        // Non-ASCII characters become one `_` each, not per byte
+        assert_eq!(text_to_anchor("Grüße"), "gr__e");
+        assert_eq!(text_to_anchor("日本"), "__");
+        assert_eq!(text_to_anchor("\u{a0}x"), "-x");
+    }
+
+    fn html_and_plain(config: &MarkdownConfig, s: &str) -> Result<(String, String)> {
+        let html = HtmlAllocator::new(1000, std::sync::Arc::new(""));
+        let pmd = MarkdownFile::process_str_to_html(s, config, &html)?;
+        Ok((pmd.html().expect("wrapper").to_html_fragment_string(&html)?,
+            pmd.html().expect("wrapper").to_plain_string(&html)?))
+    }
+
+    #[test]
+    fn t_table_alignment() -> Result<()> {
+        let t = |md| Ok::<_, anyhow::Error>(
+            html_and_plain(&MarkdownConfig::default(), md)?.0);
+        assert_eq!(t("| a | b | c | d |\n|:--|:-:|--:|---|\n| 1 | 2 | 3 | 4 |\n")?,
+                   "<div><table><thead><tr>\
+                    <th style=\"text-align:left\">a</th>\
+                    <th style=\"text-align:center\">b</th>\
+                    <th style=\"text-align:right\">c</th><th>d</th></tr></thead>\
+                    <tbody><tr><td style=\"text-align:left\">1</td>\
+                    <td style=\"text-align:center\">2</td>\
+                    <td style=\"text-align:right\">3</td><td>4</td></tr></tbody>\
+                    </table></div>");
+        // Without body rows
+        assert_eq!(t("| a |\n|--:|\n")?,
+                   "<div><table><thead><tr><th style=\"text-align:right\">a</th></tr>\
+                    </thead></table></div>");
+        Ok(())
+    }
+
+    #[test]
+    fn t_soft_break_policy() -> Result<()> {
+        let md = "Hello\nworld";
+        let t = |soft_break| html_and_plain(
+            &MarkdownConfig { soft_break, ..Default::default() }, md);
+        assert_eq!(t(SoftBreakPolicy::AsNewline)?,
+                   ("<div><p>Hello\nworld</p></div>".into(), "Hello\nworld".into()));
+        assert_eq!(t(SoftBreakPolicy::AsSpace)?,
+                   ("<div><p>Hello world</p></div>".into(), "Hello world".into()));
+        assert_eq!(t(SoftBreakPolicy::Elide)?,
+                   ("<div><p>Helloworld</p></div>".into(), "Helloworld".into()));
+        assert_eq!(MarkdownConfig::default().soft_break, SoftBreakPolicy::AsNewline);
+        Ok(())
+    }
+
+    #[test]
+    fn t_adjacent_footnote_references() -> Result<()> {
+        let md = "Text[^a][^b].\n\n[^a]: A\n\n[^b]: B\n";
+        let t = |adjacent_footnote_references| -> Result<String> {
+            let html = HtmlAllocator::new(1000, std::sync::Arc::new(""));
+            let pmd = MarkdownFile::process_str_to_html(
+                md,
+                &MarkdownConfig { adjacent_footnote_references, ..Default::default() },
+                &html)?;
+            pmd.html().expect("wrapper").to_html_fragment_string(&html)
+        };
+        assert_eq!(t(AdjacentFootnoteReferences::CommaSeparated)?,
+                   "<div><p>Text\
+                    <sup id=\"footnoteref-1\"><a href=\"#footnote-1\">1</a></sup>\
+                    <sup>,</sup>\
+                    <sup id=\"footnoteref-2\"><a href=\"#footnote-2\">2</a></sup>\
+                    .</p></div>");
+        assert_eq!(t(AdjacentFootnoteReferences::Unseparated)?,
+                   "<div><p>Text\
+                    <sup id=\"footnoteref-1\"><a href=\"#footnote-1\">1</a></sup>\
+                    <sup id=\"footnoteref-2\"><a href=\"#footnote-2\">2</a></sup>\
+                    .</p></div>");
+        assert_eq!(t(AdjacentFootnoteReferences::Merged)?,
+                   "<div><p>Text<sup>\
+                    <a id=\"footnoteref-1\" href=\"#footnote-1\">1</a>,\
+                    <a id=\"footnoteref-2\" href=\"#footnote-2\">2</a>\
+                    </sup>.</p></div>");
+        Ok(())
+    }
+
+    #[test]
+    fn t_strict() -> Result<()> {
+        let html = HtmlAllocator::new(10000, std::sync::Arc::new(""));
+        let md = "See [the docs][docs] and [x][], [sic] and \
+                  [the spec][spec].\n\n[spec]: https://example.com/spec\n";
+        let pmd = MarkdownFile::process_str_to_html(
+            md, &MarkdownConfig::default(), &html)?;
+        assert_eq!(pmd.warnings(),
+                   &["unresolved reference link \"docs\" at \"[the docs][docs]\"",
+                     "unresolved reference link \"x\" at \"[x]\""]);
+        assert_eq!(pmd.html().expect("wrapper").to_html_fragment_string(&html)?,
+                   "<div><p>See [the docs][docs] and [x][], [sic] and \
+                    <a href=\"https://example.com/spec\">the spec</a>.</p></div>");
+
+        let strict = MarkdownConfig { strict: true, ..Default::default() };
+        let e = MarkdownFile::process_str_to_html(md, &strict, &html)
+            .map(|_| ()).expect_err("fails in strict mode");
+        assert_eq!(e.to_string(),
+                   "strict mode: 2 warning(s) converting markdown: \
+                    unresolved reference link \"docs\" at \"[the docs][docs]\"; \
+                    unresolved reference link \"x\" at \"[x]\"");
+
+        let e = MarkdownFile::process_str_to_html("Text[^a].\n", &strict, &html)
+            .map(|_| ()).expect_err("fails in strict mode");
+        assert_eq!(e.to_string(),
+                   "strict mode: 1 warning(s) converting markdown: \
+                    footnote \"a\" is referenced but not defined");
+
+        assert!(MarkdownFile::process_str_to_html("[a](/b) [c]\n", &strict, &html)?
+                .warnings().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn t_multiple_titles() -> Result<()> {
+        let html = HtmlAllocator::new(10000, std::sync::Arc::new(""));
+        let md = "<title>First</title>\n\nText.\n\n<title>Second</title>\n";
+        let title = |multiple_titles| -> Result<String> {
+            let config = MarkdownConfig { multiple_titles, ..Default::default() };
+            let pmd = MarkdownFile::process_str_to_html(md, &config, &html)?;
+            Ok(pmd.meta().title_string(&html, "(none)")?.to_string())
+        };
+        assert_eq!(format!("{:#}", title(MultipleTitlesPolicy::Error).expect_err("fails")),
+                   "line 5, column 1: multiple <title> elements");
+        assert_eq!(title(MultipleTitlesPolicy::UseFirst)?, "First");
+        assert_eq!(title(MultipleTitlesPolicy::UseLast)?, "Second");
+        Ok(())
+    }
+
+    #[test]
+    fn t_heading_shift() -> Result<()> {
+        let html = HtmlAllocator::new(10000, std::sync::Arc::new(""));
+        let fixed = |md: &str, heading_shift| -> Result<String> {
+            let config = MarkdownConfig { heading_shift, ..Default::default() };
+            let pmd = MarkdownFile::process_str_to_html(md, &config, &html)?;
+            pmd.fixed_html(&html)?.to_html_fragment_string(&html)
+        };
+        let h2 = HeadingShift::default();
+        let h1 = HeadingShift::TopLevel(HeadingLevel::H1);
+        let sections = "<title>T</title>\n\n# A\n\n## B\n";
+        // Default: the top level becomes H2
+        assert_eq!(fixed(sections, h2)?,
+                   "<div>\n<h2 id=\"a\">A</h2><h3 id=\"b\">B</h3></div>");
+        // Target H1: no shift
+        assert_eq!(fixed(sections, h1)?,
+                   "<div>\n<h1 id=\"a\">A</h1><h2 id=\"b\">B</h2></div>");
+        assert_eq!(fixed("<title>T</title>\n\n## B\n", h1)?,
+                   "<div>\n<h1 id=\"b\">B</h1></div>");
+        // A single H1 is the title, dropped for target H2, kept for H1
+        let titled = "# Title\n\nText.\n\n## B\n";
+        assert_eq!(fixed(titled, h2)?,
+                   "<div><p>Text.</p><h2 id=\"b\">B</h2></div>");
+        assert_eq!(fixed(titled, h1)?,
+                   "<div><h1 id=\"title\">Title</h1><p>Text.</p><h2 id=\"b\">B</h2></div>");
+        assert_eq!(fixed(titled, HeadingShift::None)?,
+                   "<div><h1 id=\"title\">Title</h1><p>Text.</p><h2 id=\"b\">B</h2></div>");
+        Ok(())
+    }
+
+    #[test]
+    fn t_max_toc_depth() -> Result<()> {
+        let html = HtmlAllocator::new(10000, std::sync::Arc::new(""));
+        let toc = |max_toc_depth| -> Result<String> {
+            let config = MarkdownConfig { max_toc_depth, ..Default::default() };
+            let pmd = MarkdownFile::process_str_to_html(
+                "# T\n\n## A\n\n### A1\n\n#### A1x\n\n## B\n", &config, &html)?;
+            pmd.meta().toc_html_fragment(&html)?.to_html_fragment_string(&html)
+        };
+        let entry = |anchor: &str, text: &str, sub: &str| format!(
+            "<dl><dt><a href=\"#{anchor}\">{text}</a></dt><dd>{sub}</dd></dl>");
+        let wrapper = |entries: String| format!("<div class=\"toc_wrapper\">{entries}</div>");
+        assert_eq!(toc(Some(HeadingLevel::H3))?,
+                   wrapper(entry("a", "A", &entry("a1", "A1", "")) + &entry("b", "B", "")));
+        assert_eq!(toc(None)?,
+                   wrapper(entry("a", "A", &entry("a1", "A1", &entry("a1x", "A1x", "")))
+                           + &entry("b", "B", "")));
+        assert_eq!(toc(Some(HeadingLevel::H2))?,
+                   wrapper(entry("a", "A", "") + &entry("b", "B", "")));
+        Ok(())
+    }
+
+    #[test]
+    fn t_autolink() -> Result<()> {
+        let html = HtmlAllocator::new(10000, std::sync::Arc::new(""));
+        let convert = |md: &str, autolink| -> Result<String> {
+            let config = MarkdownConfig { autolink, ..Default::default() };
+            MarkdownFile::process_str_to_html(md, &config, &html)?.html()
+                .expect("wrapped").to_html_fragment_string(&html)
+        };
+        let md = "See https://example.com/a, *or* write to me@example.com.\n\n\
+                  `https://example.com/code` [https://example.com/x](/y)\n\n\
+                  ```\nwww.example.com\n```\n";
+        assert_eq!(convert(md, true)?,
+                   "<div><p>See <a href=\"https://example.com/a\">https://example.com/a</a>, \
+                    <em>or</em> write to \
+                    <a href=\"mailto:me@example.com\">me@example.com</a>.</p>\
+                    <p><code>https://example.com/code</code> \
+                    <a href=\"/y\">https://example.com/x</a></p>\
+                    <pre><code>www.example.com\n</code></pre></div>");
+        assert!(! convert(md, false)?.contains("<a href=\"https://example.com/a\""));
+        Ok(())
+    }
+
+    #[test]
+    fn t_wrapper() -> Result<()> {
+        use ahtml::ARTICLE_META;
+        let html = HtmlAllocator::new(10000, std::sync::Arc::new(""));
+        let md = "# Title\n\nLead.\n\n## Section\n\nText.\n";
+        let article = MarkdownConfig {
+            wrapper: MarkdownWrapper::Element(*ARTICLE_META),
+            ..Default::default()
+        };
+        let pmd = MarkdownFile::process_str_to_html(md, &article, &html)?;
+        assert_eq!(pmd.html().expect("wrapper").to_html_fragment_string(&html)?,
+                   "<article><h1 id=\"title\">Title</h1><p>Lead.</p>\
+                    <h2 id=\"section\">Section</h2><p>Text.</p></article>");
+        // The title is dropped by the fixing up, the wrapper kept
+        assert_eq!(pmd.fixed_html(&html)?.to_html_fragment_string(&html)?,
+                   "<article><p>Lead.</p>\
+                    <h2 id=\"section\">Section</h2><p>Text.</p></article>");
+
+        let bare = MarkdownConfig { wrapper: MarkdownWrapper::None, ..Default::default() };
+        let pmd = MarkdownFile::process_str_to_html(md, &bare, &html)?;
+        assert!(pmd.html().is_none());
+        assert!(pmd.fixed_html(&html).is_err());
+        let body = pmd.fixed_body(&html)?;
+        assert_eq!(body.len(), 3);
+        assert_eq!(html.div_of_slice(body)?.to_html_fragment_string(&html)?,
+                   "<div><p>Lead.</p><h2 id=\"section\">Section</h2><p>Text.</p></div>");
+        Ok(())
+    }
+
+    #[test]
+    fn t_base_url() -> Result<()> {
+        let config = MarkdownConfig {
+            base_url: Some(KString::from_static("/blog/2023/10/23/")),
+            ..Default::default()
+        };
+        let t = |md| Ok::<_, anyhow::Error>(html_and_plain(&config, md)?.0);
+        assert_eq!(t("[x](img/a.png)\n")?,
+                   "<div><p><a href=\"/blog/2023/10/23/img/a.png\">x</a></p></div>");
+        assert_eq!(t("![a](../a.png)\n")?,
+                   "<div><p><img src=\"/blog/2023/10/a.png\" alt=\"a\"></p></div>");
+        assert_eq!(t("[a](/a) [b](https://b.example/c) [c](#c)\n")?,
+                   "<div><p><a href=\"/a\">a</a> <a href=\"https://b.example/c\">b</a> \
+                    <a href=\"#c\">c</a></p></div>");
+        // Without base, relative URLs stay relative
+        assert_eq!(html_and_plain(&MarkdownConfig::default(), "[x](img/a.png)\n")?.0,
+                   "<div><p><a href=\"img/a.png\">x</a></p></div>");
+        Ok(())
+    }
+
+    #[test]
+    fn t_figures() -> Result<()> {
+        let config = MarkdownConfig { figures: true, ..Default::default() };
+        let t = |md| Ok::<_, anyhow::Error>(html_and_plain(&config, md)?.0);
+        assert_eq!(t("![A cat](cat.jpg \"Our cat\")\n")?,
+                   "<div><figure id=\"figure-1\">\
+                    <img src=\"cat.jpg\" alt=\"A cat\" title=\"Our cat\">\
+                    <figcaption>Our cat</figcaption></figure></div>");
+        assert_eq!(t("![A cat](cat.jpg)\n")?,
+                   "<div><figure id=\"figure-1\"><img src=\"cat.jpg\" alt=\"A cat\">\
+                    <figcaption>A cat</figcaption></figure></div>");
+        // Inline images, or ones without any text, stay as they are
+        assert_eq!(t("See ![A cat](cat.jpg \"Our cat\").\n")?,
+                   "<div><p>See <img src=\"cat.jpg\" alt=\"A cat\" title=\"Our cat\">.</p></div>");
+        assert_eq!(t("![](cat.jpg)\n")?,
+                   "<div><p><img src=\"cat.jpg\" alt=\"\"></p></div>");
+        // Ids don't collide with those of headings
+        assert_eq!(t("# Figure 1\n\n![A cat](cat.jpg)\n\n# Figure 2\n")?,
+                   "<div><h1 id=\"figure-1\">Figure 1</h1>\
+                    <figure id=\"figure-1-2\"><img src=\"cat.jpg\" alt=\"A cat\">\
+                    <figcaption>A cat</figcaption></figure>\
+                    <h1 id=\"figure-2\">Figure 2</h1></div>");
+        // Off by default
+        assert_eq!(html_and_plain(&MarkdownConfig::default(), "![A cat](cat.jpg)\n")?.0,
+                   "<div><p><img src=\"cat.jpg\" alt=\"A cat\"></p></div>");
+        Ok(())
+    }
+
+    #[test]
+    fn t_list_of_figures() -> Result<()> {
+        let html = HtmlAllocator::new(10000, std::sync::Arc::new(""));
+        let config = MarkdownConfig { figures: true, ..Default::default() };
+        let pmd = MarkdownFile::process_str_to_html(
+            "![A cat](cat.jpg \"Our cat\")\n\nSee ![inline](x.png).\n\n![A dog](dog.jpg)\n",
+            &config, &html)?;
+        let figures: Vec<_> = pmd.meta().figures().iter().map(
+            |f| (f.caption().as_str(), f.anchor_name().as_str())).collect();
+        assert_eq!(figures, [("Our cat", "figure-1"), ("A dog", "figure-2")]);
+        assert!(pmd.html().expect("wrapped").to_html_fragment_string(&html)?.contains(
+            "<figure id=\"figure-2\"><img src=\"dog.jpg\""));
+        assert_eq!(pmd.meta().figures_html_fragment(&html)?.to_html_fragment_string(&html)?,
+                   "<ol class=\"list_of_figures\">\
+                    <li><a href=\"#figure-1\">Our cat</a></li>\
+                    <li><a href=\"#figure-2\">A dog</a></li></ol>");
+        Ok(())
+    }
+
+    #[test]
+    fn t_ordered_list_start() -> Result<()> {
+        let t = |md| Ok::<_, anyhow::Error>(
+            html_and_plain(&MarkdownConfig::default(), md)?.0);
+        assert_eq!(t("5. five\n6. six\n")?,
+                   "<div><ol start=\"5\"><li>five</li><li>six</li></ol></div>");
+        assert_eq!(t("1. one\n")?,
+                   "<div><ol><li>one</li></ol></div>");
+        assert_eq!(t("0. zero\n")?,
+                   "<div><ol start=\"0\"><li>zero</li></ol></div>");
+        Ok(())
+    }
+
+    #[test]
+    fn t_link_references() -> Result<()> {
+        let html = HtmlAllocator::new(1000, std::sync::Arc::new(""));
+        let pmd = MarkdownFile::process_str_to_html(
+            "See [the docs][Docs] and [x](/x).\n\n\
+             [Docs]: https://docs.example/ \"The Docs\"\n\
+             [unused]: /unused\n",
+            &MarkdownConfig::default(),
+            &html)?;
+        let refs: Vec<_> = pmd.meta().link_references().iter()
+            .map(|(label, def)| (label.as_str(), def.url.as_str(),
+                                 def.title.as_ref().map(|t| t.as_str())))
+            .collect();
+        assert_eq!(refs, [("Docs", "https://docs.example/", Some("The Docs")),
+                          ("unused", "/unused", None)]);
+
+        // All the reference forms resolve, for links and images
+        let t = |md| Ok::<_, anyhow::Error>(
+            html_and_plain(&MarkdownConfig::default(), md)?.0);
+        assert_eq!(t("[a][r], [r][], [r], ![b][i], ![i][], ![i]\n\n\
+                      [r]: /r\n[i]: /i.png \"I\"\n")?,
+                   "<div><p><a href=\"/r\">a</a>, <a href=\"/r\">r</a>, \
+                    <a href=\"/r\">r</a>, <img src=\"/i.png\" alt=\"b\" title=\"I\">, \
+                    <img src=\"/i.png\" alt=\"i\" title=\"I\">, \
+                    <img src=\"/i.png\" alt=\"i\" title=\"I\"></p></div>");
+        // Undefined references stay text
+        assert_eq!(t("[a][nope] and [nope]\n")?,
+                   "<div><p>[a][nope] and [nope]</p></div>");
+        Ok(())
+    }
+
+    #[test]
+    fn t_front_matter() -> Result<()> {
+        let md = "---\n\
+                  permalink: /2019/old.html\n\
+                  aliases:\n  - /a.html\n  - \"/b c.html\"\n\
+                  tags: [x, 'y']\n\
+                  ---\n\
+                  # Title\n";
+        let config = MarkdownConfig { front_matter: true, ..Default::default() };
+        let html = HtmlAllocator::new(1000, std::sync::Arc::new(""));
+        let pmd = MarkdownFile::process_str_to_html(md, &config, &html)?;
+        let fm = pmd.meta().front_matter();
+        assert_eq!(fm.get("permalink"), Some("/2019/old.html"));
+        assert_eq!(fm.get_all("aliases"), ["/a.html", "/b c.html"]);
+        assert_eq!(fm.get_all("tags"), ["x", "y"]);
+        assert_eq!(fm.get("none"), None);
+        assert_eq!(pmd.html().unwrap().to_html_fragment_string(&html)?,
+                   "<div><h1 id=\"title\">Title</h1></div>");
+
+        // Off by default, a leading `---` is markdown then
+        let pmd = MarkdownFile::process_str_to_html(md, &MarkdownConfig::default(), &html)?;
+        assert!(pmd.meta().front_matter().is_empty());
+
+        assert_eq!(split_front_matter("---\na: b\n"), (None, "---\na: b\n"));
+        assert!(FrontMatter::parse("a: b\nc\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn t_image_dimensions() -> Result<()> {
+        let dir = TempDir::new("t_image_dimensions")?;
+        std::fs::create_dir_all(dir.join("assets"))?;
+        // The start of a PNG file with a 3x2 image header
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x03\0\0\0\x02\x08\x02\0\0\0";
+        std::fs::write(dir.join("a b.png"), png)?;
+        std::fs::write(dir.join("assets/c.png"), png)?;
+        std::fs::write(dir.join("doc.md"),
+                       "![A](a%20b.png) ![C](/assets/c.png?v=1) ![M](missing.png) \
+                        ![R](https://example.com/r.png)\n")?;
+        let html = HtmlAllocator::new(1000, std::sync::Arc::new(""));
+        let config = MarkdownConfig {
+            image_dimensions: true,
+            asset_root: Some(dir.to_path_buf()),
+            ..Default::default()
+        };
+        let pmd = MarkdownFile::new_with_config(dir.join("doc.md"), config.clone())
+            .process_to_html(&html)?;
+        assert_eq!(pmd.html().unwrap().to_html_fragment_string(&html)?,
+                   "<div><p>\
+                    <img src=\"a%20b.png\" alt=\"A\" width=\"3\" height=\"2\"> \
+                    <img src=\"/assets/c.png?v=1\" alt=\"C\" width=\"3\" height=\"2\"> \
+                    <img src=\"missing.png\" alt=\"M\"> \
+                    <img src=\"https://example.com/r.png\" alt=\"R\"></p></div>");
+        assert_eq!(pmd.warnings().len(), 1);
+        assert!(pmd.warnings()[0].contains("missing.png"), "{:?}", pmd.warnings());
+
+        Ok(())
+    }
+
+    #[test]
+    fn t_file_encoding() -> Result<()> {
+        let dir = TempDir::new("t_file_encoding")?;
+        std::fs::write(dir.join("bom.md"), "\u{feff}---\ntitle: x\n---\nÜber\n")?;
+        std::fs::write(dir.join("latin1.md"), b"\xdcber\n")?;
+        let html = HtmlAllocator::new(1000, std::sync::Arc::new(""));
+        let convert = |file: &str, non_utf8| -> Result<(String, Vec<String>)> {
+            let config = MarkdownConfig {
+                front_matter: true,
+                non_utf8,
+                ..Default::default()
+            };
+            let pmd = MarkdownFile::new_with_config(dir.join(file), config)
+                .process_to_html(&html)?;
+            Ok((pmd.html().unwrap().to_html_fragment_string(&html)?,
+                pmd.warnings().to_vec()))
+        };
+
+        let (s, warnings) = convert("bom.md", NonUtf8Policy::Error)?;
+        assert_eq!(s, "<div><p>Über</p></div>");
+        assert!(warnings.is_empty());
+
+        let e = convert("latin1.md", NonUtf8Policy::Error).unwrap_err();
+        assert!(e.to_string().contains("is not valid UTF-8 (invalid byte at position 0)"),
+                "{e}");
+        let (s, warnings) = convert("latin1.md", NonUtf8Policy::Latin1)?;
+        assert_eq!(s, "<div><p>Über</p></div>");
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].ends_with("read as Latin-1"), "{warnings:?}");
+        let (s, _) = convert("latin1.md", NonUtf8Policy::Lossy)?;
+        assert_eq!(s, "<div><p>\u{fffd}ber</p></div>");
+
+        Ok(())
+    }
+
+    #[test]
+    fn t_code_block_language() -> Result<()> {
+        let t = |md| Ok::<_, anyhow::Error>(
+            html_and_plain(&MarkdownConfig::default(), md)?.0);
+        assert_eq!(t("```rust\nfn main() {}\n```\n")?,
+                   "<div><pre><code class=\"language-rust\">fn main() {}\n</code></pre></div>");
+        assert_eq!(t("``` c++ linenos\nx\n```\n")?,
+                   "<div><pre><code class=\"language-c++\">x\n</code></pre></div>");
+        assert_eq!(t("```rust,ignore\nx\n```\n")?,
+                   "<div><pre><code class=\"language-rust\">x\n</code></pre></div>");
+        // No or unusable language, and indented code blocks
+        for md in ["```\nx\n```\n", "```{.rust}\nx\n```\n", "    x\n"] {
+            assert_eq!(t(md)?, "<div><pre><code>x\n</code></pre></div>", "{md:?}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn t_smart_quotes() -> Result<()> {
+        let md = "\"Quote\" and 'single' *\"em\"*, `\"code\"` isn't -- changed...\n\n\
+                  ```\n\"block\"\n```\n";
+        let html = HtmlAllocator::new(1000, std::sync::Arc::new(""));
+        let convert = |quotes| -> Result<String> {
+            let config = MarkdownConfig { quotes, ..Default::default() };
+            MarkdownFile::process_str_to_html(md, &config, &html)?.html().unwrap()
+                .to_html_fragment_string(&html)
+        };
+        assert_eq!(convert(SmartQuotes::ENGLISH)?,
+                   "<div><p>“Quote” and ‘single’ <em>“em”</em>, \
+                    <code>&quot;code&quot;</code> isn’t – changed…</p>\
+                    <pre><code>&quot;block&quot;\n</code></pre></div>");
+        assert_eq!(convert(SmartQuotes::GERMAN)?,
+                   "<div><p>„Quote“ and ‚single‘ <em>„em“</em>, \
+                    <code>&quot;code&quot;</code> isn’t – changed…</p>\
+                    <pre><code>&quot;block&quot;\n</code></pre></div>");
+        Ok(())
+    }
+
+    #[test]
+    fn t_error_location() {
+        let html = HtmlAllocator::new(10000, std::sync::Arc::new(""));
+        let error = |md: &str| {
+            let config = MarkdownConfig { front_matter: true, ..Default::default() };
+            let e = MarkdownFile::process_str_to_html(md, &config, &html)
+                .map(|_| ()).expect_err("error"); // (no Debug for ProcessedMarkdown)
+            format!("{e:#}")
+        };
+        assert_eq!(error("a <em>b</i> c\n"),
+                   "line 1, column 8: non-balanced tags/markup: \
+                    HTML \"em\" element ending as HTML \"i\" element");
+        // Lines are counted in the whole source, including front matter
+        let e = error("---\ntitle: x\n---\n<div>\n\nText\n\n</span>\n");
+        assert!(e.starts_with("line 8, column 1: non-balanced tags/markup"), "{e}");
+    }
+
+    #[test]
+    fn t_raw_html_policy() -> Result<()> {
+        let html = HtmlAllocator::new(10000, std::sync::Arc::new(""));
+        let md = "## A <blink>B</blink>\n\nHi <blink>x</blink>.\n\n\
+                  <div>\n<marquee>m</marquee>\n</div>\n";
+        let convert = |raw_html| -> Result<String> {
+            let config = MarkdownConfig { raw_html, ..Default::default() };
+            let pmd = MarkdownFile::process_str_to_html(md, &config, &html)?;
+            pmd.html().expect("wrapper").to_html_fragment_string(&html)
+        };
+        let e = format!("{:#}", convert(RawHtmlPolicy::Validate).expect_err("unknown tag"));
+        assert!(e.starts_with("line 1, column 6: not an HTML5 tag name: \"blink\""), "{e}");
+        assert_eq!(convert(RawHtmlPolicy::PassThroughVerbatim)?,
+                   "<div><h2 id=\"a-b\">A <blink>B</blink></h2><p>Hi <blink>x</blink>.</p>\
+                    <div>\n<marquee>m</marquee>\n</div>\n</div>");
+        assert_eq!(convert(RawHtmlPolicy::Strip)?,
+                   "<div><h2 id=\"a-b\">A B</h2><p>Hi x.</p></div>");
+        assert_eq!(convert(RawHtmlPolicy::Escape)?,
+                   "<div><h2 id=\"a-_blink_b__blink_\">A &lt;blink&gt;B&lt;/blink&gt;</h2>\
+                    <p>Hi &lt;blink&gt;x&lt;/blink&gt;.</p>\
+                    &lt;div&gt;\n&lt;marquee&gt;m&lt;/marquee&gt;\n&lt;/div&gt;\n</div>");
+        Ok(())
+    }
+
+    #[test]
+    fn t_inline_html() -> Result<()> {
+        let html = HtmlAllocator::new(10000, std::sync::Arc::new(""));
+        // Markdown within HTML elements spanning several events
+        let pmd = MarkdownFile::process_str_to_html(
+            "Hi <span class=\"x\">*y*</span><br/>z.\n", &MarkdownConfig::default(), &html)?;
+        assert_eq!(pmd.html().expect("wrapper").to_html_fragment_string(&html)?,
+                   "<div><p>Hi <span class=\"x\"><em>y</em></span><br>z.</p></div>");
+        Ok(())
+    }
+
+    #[test]
+    fn t_push_text_to_anchor() {
+        let mut s = String::from("pre-");
+        push_text_to_anchor("Foo Bar", &mut s);
+        assert_eq!(s, "pre-foo-bar");
+    }
+}
@@ -0,0 +1,299 @@
+//! Convert markdown to readable plain text (e.g. for the text version
+//! of an email, or for full text indexing), keeping the structure
+//! visible: headings on their own lines (the top two levels
+//! underlined), list items with bullets or numbers, links as "text
+//! (url)", block quotes prefixed with "> ".
+
+use std::collections::HashMap;
+
+use pulldown_cmark::{Parser, Event, Tag, HeadingLevel};
+
+use crate::{markdown::{MarkdownConfig, markdown_options},
+            smart_punctuation::SmartPunctuation};
+
+/// Accumulates the output lines, taking care of the prefixes for
+/// nesting (indentation of list items, "> " for block quotes) and of
+/// blank lines between blocks.
+struct PlainWriter {
+    out: String,
+    prefixes: Vec<String>,
+    /// Written instead of the last prefix at the start of the next
+    /// line (the bullet of a list item).
+    marker: Option<String>,
+    at_line_start: bool,
+    /// A blank line is to be written before the next block.
+    need_blank: bool,
+}
+
+impl PlainWriter {
+    fn new() -> Self {
+        PlainWriter {
+            out: String::new(),
+            prefixes: Vec::new(),
+            marker: None,
+            at_line_start: true,
+            need_blank: false,
+        }
+    }
+
+    fn start_line(&mut self) {
+        if self.at_line_start {
+            if self.need_blank {
+                if ! self.out.is_empty() {
+                    self.out.push_str(self.prefixes.concat().trim_end());
+                    self.out.push('\n');
+                }
+                self.need_blank = false;
+            }
+            if let Some(marker) = self.marker.take() {
+                let n = self.prefixes.len().saturating_sub(1);
+                self.out.push_str(&self.prefixes[..n].concat());
+                self.out.push_str(&marker);
+            } else {
+                self.out.push_str(&self.prefixes.concat());
+            }
+            self.at_line_start = false;
+        }
+    }
+
+    fn text(&mut self, s: &str) {
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.newline();
+            }
+            if ! line.is_empty() {
+                self.start_line();
+                self.out.push_str(line);
+            }
+        }
+    }
+
+    fn newline(&mut self) {
+        self.start_line();
+        self.out.push('\n');
+        self.at_line_start = true;
+    }
+
+    /// Finish the current line, if any.
+    fn end_line(&mut self) {
+        if ! self.at_line_start {
+            self.newline();
+        }
+    }
+
+    fn end_block(&mut self) {
+        self.end_line();
+        self.need_blank = true;
+    }
+}
+
+/// Convert the markdown in `s` to plain text. Uses `config.quotes`
+/// for smart punctuation; raw HTML is dropped.
+pub fn markdown_to_plain(s: &str, config: &MarkdownConfig) -> String {
+    let mut w = PlainWriter::new();
+    let mut smart = SmartPunctuation::new(config.quotes);
+    // Current heading level and text, to write it with underline
+    let mut heading: Option<(HeadingLevel, String)> = None;
+    // Next item number (None for bullets) for each nested list
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    // Where the text of each open link starts in the output
+    let mut links: Vec<usize> = Vec::new();
+    let mut in_code_block = false;
+    let mut footnote_numbers: HashMap<String, usize> = HashMap::new();
+    let mut footnote_number = |label: &str| {
+        let n = footnote_numbers.len() + 1;
+        *footnote_numbers.entry(label.to_string()).or_insert(n)
+    };
+
+    macro_rules! text {
+        ($s:expr) => {
+            if let Some((_, h)) = &mut heading {
+                h.push_str($s)
+            } else {
+                w.text($s)
+            }
+        }
+    }
+
+    for event in Parser::new_ext(s, markdown_options()) {
+        match event {
+            Event::Start(tag) => {
+                if ! matches!(tag, Tag::Emphasis | Tag::Strong | Tag::Strikethrough
+                              | Tag::Link(..) | Tag::Image(..)) {
+                    smart.reset();
+                }
+                match tag {
+                    Tag::Paragraph => (),
+                    Tag::Heading(level, _, _) => heading = Some((level, String::new())),
+                    Tag::BlockQuote => {
+                        w.end_line();
+                        w.prefixes.push("> ".into());
+                    }
+                    Tag::CodeBlock(_) => {
+                        w.end_line();
+                        in_code_block = true;
+                        w.prefixes.push("    ".into());
+                    }
+                    Tag::List(first) => {
+                        w.end_line();
+                        lists.push(first);
+                    }
+                    Tag::Item => {
+                        w.end_line();
+                        let marker = match lists.last_mut() {
+                            Some(Some(n)) => {
+                                *n += 1;
+                                format!("{}. ", *n - 1)
+                            }
+                            _ => "- ".into()
+                        };
+                        w.prefixes.push(" ".repeat(marker.chars().count()));
+                        w.marker = Some(marker);
+                    }
+                    Tag::FootnoteDefinition(label) => {
+                        let marker = format!("[{}] ", footnote_number(&label));
+                        w.prefixes.push(" ".repeat(marker.len()));
+                        w.marker = Some(marker);
+                    }
+                    Tag::Table(_) => (),
+                    Tag::TableHead | Tag::TableRow => (),
+                    Tag::TableCell => {
+                        if ! w.at_line_start {
+                            w.text(" | ");
+                        }
+                    }
+                    Tag::Emphasis | Tag::Strong | Tag::Strikethrough => (),
+                    Tag::Link(..) => {
+                        if let Some((_, h)) = &heading {
+                            links.push(h.len());
+                        } else {
+                            w.start_line();
+                            links.push(w.out.len());
+                        }
+                    }
+                    Tag::Image(..) => text!("["),
+                }
+            }
+            Event::End(tag) => {
+                match tag {
+                    Tag::Paragraph => w.end_block(),
+                    Tag::Heading(..) => {
+                        if let Some((level, h)) = heading.take() {
+                            w.text(&h);
+                            let underline = match level {
+                                HeadingLevel::H1 => Some('='),
+                                HeadingLevel::H2 => Some('-'),
+                                _ => None
+                            };
+                            if let Some(c) = underline {
+                                w.newline();
+                                w.text(&c.to_string().repeat(h.chars().count()));
+                            }
+                            w.end_block();
+                        }
+                    }
+                    Tag::BlockQuote => {
+                        w.end_line();
+                        w.prefixes.pop();
+                        w.need_blank = true;
+                    }
+                    Tag::CodeBlock(_) => {
+                        in_code_block = false;
+                        w.end_line();
+                        w.prefixes.pop();
+                        w.need_blank = true;
+                    }
+                    Tag::List(_) => {
+                        w.end_line();
+                        lists.pop();
+                        // Nested lists continue the item they are in
+                        if lists.is_empty() {
+                            w.need_blank = true;
+                        }
+                    }
+                    Tag::Item | Tag::FootnoteDefinition(_) => {
+                        w.end_line();
+                        w.prefixes.pop();
+                        w.marker = None;
+                    }
+                    Tag::Table(_) => w.end_block(),
+                    Tag::TableHead | Tag::TableRow => w.end_line(),
+                    Tag::TableCell => (),
+                    Tag::Emphasis | Tag::Strong | Tag::Strikethrough => (),
+                    Tag::Link(_, url, _) => {
+                        let written = heading.as_ref().map_or(&w.out, |(_, h)| h);
+                        let start = links.pop().unwrap_or(written.len());
+                        let url = url.strip_prefix("mailto:").unwrap_or(&url);
+                        if written[start..] != *url {
+                            text!(&format!(" ({url})"));
+                        }
+                    }
+                    Tag::Image(..) => text!("]"),
+                }
+            }
+            Event::Text(s) => {
+                if in_code_block {
+                    text!(&s)
+                } else {
+                    text!(&smart.convert(&s))
+                }
+            }
+            Event::Code(s) => {
+                smart.skip(&s);
+                text!(&s)
+            }
+            Event::Html(_) => (),
+            Event::FootnoteReference(label) =>
+                text!(&format!("[{}]", footnote_number(&label))),
+            Event::SoftBreak | Event::HardBreak => {
+                if heading.is_some() {
+                    text!(" ")
+                } else {
+                    w.newline()
+                }
+            }
+            Event::Rule => {
+                w.end_line();
+                w.text("----------");
+                w.end_block();
+            }
+            Event::TaskListMarker(checked) =>
+                text!(if checked { "[x] " } else { "[ ] " }),
+        }
+    }
+    w.end_line();
+    w.out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(s: &str) -> String {
+        markdown_to_plain(s, &MarkdownConfig::default())
+    }
+
+    #[test]
+    fn t_markdown_to_plain() {
+        assert_eq!(plain("# The *Title*\n\nSome text\nwith \"quotes\".\n\n\
+                          ## Sub\n\n### Deeper\n\nEnd.\n"),
+                   "The Title\n=========\n\nSome text\nwith “quotes”.\n\n\
+                    Sub\n---\n\nDeeper\n\nEnd.\n");
+        assert_eq!(plain("Items:\n\n- a\n- b\n  - b1\n  - b2\n- c\n\n\
+                          3. three\n4. four\n   continued\n\nAfter.\n"),
+                   "Items:\n\n- a\n- b\n  - b1\n  - b2\n- c\n\n\
+                    3. three\n4. four\n   continued\n\nAfter.\n");
+        assert_eq!(plain("See [the docs](https://docs.example/a) or \
+                          <https://b.example/>, mail <me@example.com>, \
+                          ![a cat](cat.png).\n"),
+                   "See the docs (https://docs.example/a) or \
+                    https://b.example/, mail me@example.com, [a cat].\n");
+        assert_eq!(plain("> quoted\n> more\n\n```\ncode \"x\"\n```\n\nText[^n].\n\n\
+                          [^n]: The note.\n"),
+                   "> quoted\n> more\n\n    code \"x\"\n\nText[1].\n\n[1] The note.\n");
+        assert_eq!(plain("| a | b |\n|---|---|\n| 1 | 2 |\n"), "a | b\n1 | 2\n");
+        assert_eq!(plain("# [Title](http://x)\n\n## <http://y>\n"),
+                   "Title (http://x)\n================\n\nhttp://y\n--------\n");
+    }
+}
@@ -1,10 +1,10 @@
-use anyhow::Result;
+use anyhow::{Result, bail};
 use kstring::KString;
 
-use ahtml::{HtmlAllocator, AId, Node, TryCollectBody, att};
+use ahtml::{HtmlAllocator, AId, Node, TryCollectBody, att, opt_att, ClassList};
 
 use crate::{acontext::AContext,
-            ppath::PPath, language::Language};
+            ppath::PPath, language::Language, alist::AList};
 
 pub trait ToHtml {
     fn to_html<L: Language>(
@@ -19,9 +19,25 @@ pub enum SubEntries {
 }
 impl ToHtml for SubEntries {
     fn to_html<L: Language>(
-        &self, _html: &HtmlAllocator, _contex: &AContext<L>
+        &self, html: &HtmlAllocator, context: &AContext<L>
     ) -> Result<AId<Node>> {
-        todo!()
+        match self {
+            SubEntries::Static(entries) =>
+                html.ul(
+                    [att("class", "subnav")],
+                    entries.iter().map(|naventry| naventry.to_html(html, context))
+                        .try_collect_body(html)?),
+            SubEntries::MdDir(path) =>
+                bail!("SubEntries::MdDir({path:?}) is not implemented yet"),
+        }
+    }
+}
+impl SubEntries {
+    fn is_empty(&self) -> bool {
+        match self {
+            SubEntries::Static(entries) => entries.is_empty(),
+            SubEntries::MdDir(_) => false,
+        }
     }
 }
 
@@ -31,20 +47,26 @@ pub struct NavEntry {
     pub subentries: SubEntries
 }
 impl ToHtml for NavEntry {
+    /// The `li` is marked `class="active"` if it is or contains the
+    /// current page, the link to the current page itself gets
+    /// `aria-current="page"`.
     fn to_html<L: Language>(
         &self, html: &HtmlAllocator, context: &AContext<L>
     ) -> Result<AId<Node>> {
         let name = html.staticstr(self.name)?;
+        let rel = self.ppath().sub(context.path())?;
+        let classes = ClassList::new().add_if(self.contains_current(context), "active");
         html.li(
-            [],
+            [opt_att("class", classes.into_option())],
             [
-                if context.path().same_document_as_path_str(self.path) {
-                    name
+                html.a(
+                    [att("href", rel.to_string()),
+                     opt_att("aria-current", self.is_current(context).then_some("page"))],
+                    [name])?,
+                if self.subentries.is_empty() {
+                    html.empty_node()?
                 } else {
-                    let rel = self.ppath().sub(context.path())?;
-                    html.a(
-                        [att("href", rel.to_string())],
-                        [name])?
+                    self.subentries.to_html(html, context)?
                 }
             ])
     }
@@ -53,6 +75,20 @@ impl NavEntry {
     fn ppath(&self) -> PPath<KString> {
         PPath::from_str(self.path)
     }
+
+    fn is_current<L: Language>(&self, context: &AContext<L>) -> bool {
+        context.path().same_document_as_path_str(self.path)
+    }
+
+    /// Whether this entry or one of its (static) subentries is the
+    /// current page.
+    fn contains_current<L: Language>(&self, context: &AContext<L>) -> bool {
+        self.is_current(context) || match self.subentries {
+            SubEntries::Static(entries) =>
+                entries.iter().any(|entry| entry.contains_current(context)),
+            SubEntries::MdDir(_) => false,
+        }
+    }
 }
 
 pub struct Nav<'t>(pub &'t [NavEntry]);
@@ -67,3 +103,83 @@ impl<'t> ToHtml for Nav<'t> {
                 .try_collect_body(html)?)?)
     }
 }
+
+/// The `<nav>` element for the menu from `navs` for the language of
+/// the request, with the current page and its ancestors highlighted
+/// (see `NavEntry::to_html`). An empty node if there's no menu for
+/// the language.
+pub fn render_nav<L: Language>(
+    navs: &[(L, Nav)], context: &AContext<L>, html: &HtmlAllocator
+) -> Result<AId<Node>> {
+    if let Some(nav) = AList(navs).get(&context.lang()) {
+        html.nav([], [nav.to_html(html, context)?])
+    } else {
+        html.empty_node()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ahtml::Print;
+
+    use crate::{lang_en_de::Lang, test_util::fake_context};
+
+    use super::*;
+
+    const NAV: &[(Lang, Nav)] = &[
+        (Lang::En, Nav(&[
+            NavEntry {
+                name: "Home",
+                path: "/en.html",
+                subentries: SubEntries::Static(&[]),
+            },
+            NavEntry {
+                name: "Projects",
+                path: "/projects.html",
+                subentries: SubEntries::Static(&[
+                    NavEntry {
+                        name: "Website",
+                        path: "/projects/website.html",
+                        subentries: SubEntries::Static(&[]),
+                    },
+                ]),
+            },
+        ])),
+        (Lang::De, Nav(&[
+            NavEntry {
+                name: "Willkommen",
+                path: "/de.html",
+                subentries: SubEntries::Static(&[]),
+            },
+        ])),
+    ];
+
+    fn render(path: &str, headers: &[(&str, &str)]) -> Result<String> {
+        fake_context("GET", path, headers, &[]).with_context(|context: &AContext<Lang>| {
+            let html = HtmlAllocator::new(1000, std::sync::Arc::new(""));
+            render_nav(NAV, context, &html)?.to_html_fragment_string(&html)
+        })
+    }
+
+    #[test]
+    fn t_render_nav() -> Result<()> {
+        assert_eq!(render("/en.html", &[])?,
+                   "<nav><ul class=\"nav\">\
+                    <li class=\"active\"><a href=\"en.html\" aria-current=\"page\">Home</a></li>\
+                    <li><a href=\"projects.html\">Projects</a>\
+                    <ul class=\"subnav\"><li><a href=\"projects/website.html\">Website</a></li></ul>\
+                    </li></ul></nav>");
+        assert_eq!(render("/projects/website.html", &[])?,
+                   "<nav><ul class=\"nav\">\
+                    <li><a href=\"../en.html\">Home</a></li>\
+                    <li class=\"active\"><a href=\"../projects.html\">Projects</a>\
+                    <ul class=\"subnav\"><li class=\"active\">\
+                    <a href=\"website.html\" aria-current=\"page\">Website</a></li></ul>\
+                    </li></ul></nav>");
+        assert_eq!(render("/de.html", &[("Accept-Language", "de")])?,
+                   "<nav><ul class=\"nav\">\
+                    <li class=\"active\"><a href=\"de.html\" aria-current=\"page\">Willkommen</a></li>\
+                    </ul></nav>");
+        Ok(())
+    }
+}
@@ -9,7 +9,10 @@ use std::fmt::Debug;
 use ahtml::myfrom::MyFrom;
 use anyhow::{Result, bail};
 
-use crate::{path::path_segments, util::{rest, first}, myasstr::MyAsStr};
+use kstring::KString;
+
+use crate::{path::path_segments, util::{rest, first}, myasstr::MyAsStr,
+            url_encoding::url_decode_lossy};
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct PPath<Segment: Clone + Debug> {
@@ -57,6 +60,23 @@ where T: MyFrom<&'s str> + Clone + Debug + 's
     }
 }
 
+impl PPath<KString> {
+    /// Parse the (still percent-encoded) path part of a request URL:
+    /// split at `/`, then decode each segment via
+    /// `url_decode_lossy`. Thus an encoded slash (`%2F`) does not
+    /// separate segments, it remains a literal `/` in its segment.
+    pub fn from_percent_encoded(s: &str) -> Self {
+        let is_absolute = s.starts_with('/');
+        let ends_with_slash = s.ends_with('/');
+        PPath {
+            is_absolute,
+            ends_with_slash,
+            segments: path_segments(s).map(
+                |v| KString::from_ref(&url_decode_lossy(v))).collect()
+        }
+    }
+}
+
 fn repeated_dotdot<'t, T>(n: usize) -> Vec<T>
 where T: From<&'t str> + Clone
 {
@@ -389,4 +409,21 @@ mod tests {
         assert!(! canon("a//./b/c.html"));
         assert!(! canon("a//../c.html"));
     }
+
+    #[test]
+    fn t_from_percent_encoded() {
+        let segments = |s| -> Vec<KString> {
+            PPath::from_percent_encoded(s).segments().to_vec()
+        };
+        assert_eq!(segments("/caf%C3%A9.html"), ["café.html"]);
+        assert_eq!(segments("/a%2Fb/c"), ["a/b", "c"]);
+        assert_eq!(segments("/a%2fb%20c/"), ["a/b c"]);
+        // Invalid sequences are kept, invalid UTF-8 replaced
+        assert_eq!(segments("/100%/%zz%4"), ["100%", "%zz%4"]);
+        assert_eq!(segments("/%FF"), ["\u{FFFD}"]);
+        let p = PPath::from_percent_encoded("/a%2Fb/");
+        assert!(p.is_absolute());
+        assert!(p.ends_with_slash());
+        assert!(! PPath::from_percent_encoded("a").is_absolute());
+    }
 }
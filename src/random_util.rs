@@ -1,4 +1,10 @@
+use std::cell::RefCell;
 use std::fmt::Write;
+use std::time::Duration;
+
+use anyhow::Result;
+use rand::{rngs::StdRng, thread_rng, Rng, RngCore, SeedableRng};
+use rand_distr::Weibull;
 
 /// A 12 character (6 entropy bytes) long hex string useful to tag
 /// e.g. error messages for identification.
@@ -11,3 +17,59 @@ pub fn randomidstring() -> Result<String, getrandom::Error> {
     }
     Ok(s)
 }
+
+thread_local! {
+    static SEEDED_RNG: RefCell<Option<StdRng>> = const { RefCell::new(None) };
+}
+
+/// Make `with_rng` on the current thread use an RNG seeded with
+/// `seed`, for tests or to reproduce timings when debugging. `None`
+/// goes back to `thread_rng` (the default).
+pub fn set_thread_rng_seed(seed: Option<u64>) {
+    SEEDED_RNG.with(|rng| *rng.borrow_mut() = seed.map(StdRng::seed_from_u64));
+}
+
+/// Run `f` with the RNG for non-cryptographic uses like timing
+/// jitter, see `set_thread_rng_seed`.
+pub fn with_rng<R>(f: impl FnOnce(&mut dyn RngCore) -> R) -> R {
+    SEEDED_RNG.with(|rng| {
+        if let Some(rng) = &mut *rng.borrow_mut() {
+            f(rng)
+        } else {
+            f(&mut thread_rng())
+        }
+    })
+}
+
+/// A random delay drawn from a Weibull distribution with the given
+/// `scale` (in microseconds, about the median for large `shape`s)
+/// and `shape` (the higher, the narrower), e.g. to make response times
+/// not depend on the work done.
+pub fn weibull_delay(scale_micros: f64, shape: f64) -> Result<Duration> {
+    let distribution: Weibull<f64> = Weibull::new(scale_micros, shape)?;
+    let micros: f64 = with_rng(|rng| rng.sample(distribution));
+    Ok(Duration::from_micros(micros as u64))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn t_weibull_delay_seeded() -> Result<()> {
+        let delays = |seed| -> Result<Vec<Duration>> {
+            set_thread_rng_seed(Some(seed));
+            let delays = (0..3).map(|_| weibull_delay(200000., 20.)).collect();
+            set_thread_rng_seed(None);
+            delays
+        };
+        let a = delays(42)?;
+        assert_eq!(a, delays(42)?);
+        assert_ne!(a, delays(43)?);
+        for d in a {
+            assert!(d > Duration::from_millis(100) && d < Duration::from_millis(300), "{d:?}");
+        }
+        Ok(())
+    }
+}
@@ -25,27 +25,52 @@ use crate::aresponse::AResponse;
 use crate::hostrouter::HostsRouter;
 use crate::http_request_method::HttpRequestMethodGrouped;
 use crate::http_response_status_codes::HttpResponseStatusCode;
-use crate::in_threadpool::in_threadpool;
+use crate::in_threadpool::{in_threadpool, ConcurrencyLimit};
 use crate::language::Language;
 use crate::ppath::PPath;
 use crate::webutils::errorpage_from_status;
 use crate::time_util;
 
 
-/// Make a handler for Rouille's `start_server` procedure.
+/// Make a handler for Rouille's `start_server` procedure. If `limit`
+/// is given, requests arriving while all of its slots are taken (by
+/// requests being handled or waiting for a thread in `threadpool`)
+/// get a 503 response right away (which is still access logged).
 pub fn server_handler<'t, L: Language + Default>(
     listen_addr: String,
     hostsrouter: Arc<HostsRouter<L>>,
     allocatorpool: &'static HtmlAllocatorPool,
     threadpool: Arc<Pool>,
+    limit: Option<Arc<ConcurrencyLimit>>,
     sessionid_hasher: Hasher,
     lang_from_path: Arc<dyn Fn(&PPath<KString>) -> Option<L> + Send + Sync>,
 ) -> impl for<'r> Fn(&'r Request) -> Response
 {
     move |request: &Request| -> Response {
         time_guard!("server_handler"); // timings including infrastructure cost
+        // Held until the response has been made
+        let slot = limit.as_ref().map(|limit| limit.try_acquire());
         let lang_from_path = lang_from_path.clone();
         session(request, "sid", 3600 /*sec*/, |session| {
+            if let (Some(limit), Some(None)) = (&limit, &slot) {
+                warn!("all {} request slots taken, responding with 503", limit.max());
+                let too_busy = || -> AResponse {
+                    errorpage_from_status(HttpResponseStatusCode::ServiceUnavailable503)
+                        .with_unique_header("Retry-After", "1").into()
+                };
+                // On this thread, as waiting for a worker thread is
+                // what we are avoiding
+                return match AContext::new(request, &listen_addr, session, &sessionid_hasher,
+                                           lang_from_path) {
+                    Ok(context) => log_combined(
+                        &context,
+                        || (hostsrouter.logs_for(&context), Ok(too_busy()))).response,
+                    Err(e) => {
+                        warn!("{e}");
+                        too_busy().response
+                    }
+                }
+            }
             let aresponse = in_threadpool(threadpool.clone(), || -> AResponse {
                 let okhandler = |context: &AContext<L>| -> AResponse {
                     log_combined(
@@ -61,27 +86,16 @@ pub fn server_handler<'t, L: Language + Default>(
                             match method.to_grouped() {
                                 HttpRequestMethodGrouped::Simple(simplemethod) => {
                                     let allocator = allocatorpool.get();
-                                    if let Some(host) = context.host() {
-                                        let lchost = host.to_lowercase();
-                                        if let Some(hostrouter) = hostsrouter.routers.get(
-                                            &KString::from_string(lchost))
-                                        {
-                                            return hostrouter.handle_request(
-                                                context, simplemethod, &*allocator)
-                                        }
-                                    }
-                                    if let Some(fallback) = &hostsrouter.fallback {
-                                        return fallback.handle_request(
-                                            context, simplemethod, &*allocator)
-                                    }
+                                    hostsrouter.handle_request(
+                                        context, simplemethod, &*allocator)
                                 }
                                 HttpRequestMethodGrouped::Document(documentmethod) => {
-                                    return unimplemented(
+                                    unimplemented(
                                         documentmethod.to_http_request_method().as_str())
                                 }
                                 HttpRequestMethodGrouped::Special(specialmethod) =>
                                     // XX should at least implement OPTIONS, or ?
-                                    return unimplemented(
+                                    unimplemented(
                                         specialmethod.to_http_request_method().as_str())
                                     // match specialmethod {
                                     //     HttpRequestMethodSpecial::OPTIONS =>
@@ -92,9 +106,6 @@ pub fn server_handler<'t, L: Language + Default>(
                                     //         return unimplemented(),
                                     // },
                             }
-                            (hostsrouter.logs.clone(),
-                             Ok(errorpage_from_status(HttpResponseStatusCode::NotFound404)
-                                .into()))
                         })
                 };
                 match AContext::new(request, &listen_addr, session, &sessionid_hasher,
@@ -110,7 +121,12 @@ pub fn server_handler<'t, L: Language + Default>(
                             HttpResponseStatusCode::InternalServerError500).into()
                     }
                 }
-            }).expect("only ever fails if thread fails outside catch_unwind");
+            }).unwrap_or_else(|e| {
+                // A panic in the handler, don't take down the thread
+                warn!("{} {:?}: {e:#}", request.method(), request.raw_url());
+                errorpage_from_status(
+                    HttpResponseStatusCode::InternalServerError500).into()
+            });
             let AResponse { response, sleep_until } = aresponse;
             if let Some(t) = sleep_until {
                 time_util::sleep_until(t);
@@ -126,8 +142,28 @@ pub struct Tlskeys {
     pub key: Vec<u8>,
 }
 
+/// Options for `RouilleRunner::new_with_config`. (Keep-alive of
+/// connections is handled by tiny_http, and not configurable via
+/// rouille.)
+#[derive(Debug, Clone, Default)]
+pub struct RouilleRunnerConfig {
+    /// Size of the worker thread pool, which runs the handlers
+    /// (default: 8 times the number of CPU threads). Must not be 0.
+    pub worker_threads: Option<usize>,
+    /// Size of rouille's thread pool for each server, which reads
+    /// requests and writes responses (default: a new thread per
+    /// request).
+    pub http_threads: Option<usize>,
+    /// Maximum number of requests that are handled or waiting for a
+    /// worker thread at the same time, across all servers; further
+    /// requests get a 503 response (default: unlimited).
+    pub max_concurrent_requests: Option<usize>,
+}
+
 pub struct RouilleRunner<L: Language> {
     workerthreadpool: Arc<Pool>,
+    limit: Option<Arc<ConcurrencyLimit>>,
+    http_threads: Option<usize>,
     allocpool: &'static HtmlAllocatorPool,
     sessionid_hasher: Hasher,
     lang_from_path: Arc<dyn Fn(&PPath<KString>) -> Option<L> + Send + Sync>,
@@ -139,17 +175,29 @@ impl<L: Language + 'static> RouilleRunner<L> {
         sessionid_hasher: Hasher,
         lang_from_path: Arc<dyn Fn(&PPath<KString>) -> Option<L> + Send + Sync>,
     ) -> Self
+    {
+        Self::new_with_config(allocpool, sessionid_hasher, lang_from_path,
+                              RouilleRunnerConfig::default())
+    }
+
+    pub fn new_with_config(
+        allocpool: &'static HtmlAllocatorPool,
+        sessionid_hasher: Hasher,
+        lang_from_path: Arc<dyn Fn(&PPath<KString>) -> Option<L> + Send + Sync>,
+        config: RouilleRunnerConfig,
+    ) -> Self
     {
         // The worker thread pool is kept separate and much smaller, since
         // it keeps thread local state, also want CPU intensive part to
         // finish quickly.
-        let workerthreadpool_size = 8 * thread::available_parallelism()
-            .map(|n| n.get())
-            .unwrap_or_else(
-                |e| {
-                    warn!("couldn't get number of CPU threads thus falling back to 1: {e}");
-                    1
-                });
+        let workerthreadpool_size = config.worker_threads.unwrap_or_else(
+            || 8 * thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or_else(
+                    |e| {
+                        warn!("couldn't get number of CPU threads thus falling back to 1: {e}");
+                        1
+                    }));
         let workerthreadpool = {
             let cfg = scoped_thread_pool::ThreadConfig::new()
                 .prefix("scoped_website_worker");
@@ -158,6 +206,9 @@ impl<L: Language + 'static> RouilleRunner<L> {
         };
         RouilleRunner {
             workerthreadpool,
+            limit: config.max_concurrent_requests.map(
+                |max| Arc::new(ConcurrencyLimit::new(max))),
+            http_threads: config.http_threads,
             allocpool,
             sessionid_hasher,
             lang_from_path,
@@ -176,6 +227,8 @@ impl<L: Language + 'static> RouilleRunner<L> {
     {
         thread::Builder::new().name(thread_name.into()).spawn({
             let workerthreadpool = self.workerthreadpool.clone();
+            let limit = self.limit.clone();
+            let http_threads = self.http_threads;
             let sessionid_hasher = self.sessionid_hasher.clone();
             let lang_from_path = self.lang_from_path.clone();
             let allocpool = self.allocpool;
@@ -185,10 +238,11 @@ impl<L: Language + 'static> RouilleRunner<L> {
                     hostsrouter,
                     allocpool,
                     workerthreadpool,
+                    limit,
                     sessionid_hasher,
                     lang_from_path,
                 );
-                if let Some(Tlskeys { crt, key }) = tlskeys {
+                let server = if let Some(Tlskeys { crt, key }) = tlskeys {
                     Server::new_ssl(addr, handler, crt, key)
                 } else {
                     Server::new(addr, handler)
@@ -197,9 +251,106 @@ impl<L: Language + 'static> RouilleRunner<L> {
                 // and it's run in a dedicated thread where panicking
                 // will achieve the same outcome. Bad for WASM or
                 // embedded contexts though. TODO fix.
-                .expect("error starting server")
-                    .run()
+                .expect("error starting server");
+                if let Some(http_threads) = http_threads {
+                    server.pool_size(http_threads).run()
+                } else {
+                    server.run()
+                }
             }
         })
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use ahtml::HtmlAllocator;
+
+    use crate::{apachelog::Logs, handler::{Handler, FnHandler},
+                hostrouter::HostRouter, lang_en_de::Lang, test_util::SharedBuf};
+
+    use super::*;
+
+    fn test_server(
+        handler: Arc<dyn Handler<Lang>>, limit: Option<usize>, access_log: SharedBuf,
+    ) -> impl Fn(&str) -> Response + Send + Sync {
+        let logs = || Arc::new(Mutex::new(Logs {
+            access_log: Box::new(access_log.clone()),
+            error_log: Box::new(std::io::sink()),
+        }));
+        let hostsrouter = Arc::new(HostsRouter::new(
+            Some(Arc::new(HostRouter { router: None, fallback: Some(handler), logs: logs() })),
+            logs()));
+        let allocpool: &'static HtmlAllocatorPool = Box::leak(Box::new(
+            HtmlAllocatorPool::new(1000, true, Arc::new("test_server"))));
+        let server = server_handler(
+            "127.0.0.1:80".into(),
+            hostsrouter,
+            allocpool,
+            Arc::new(Pool::new(1)),
+            limit.map(|max| Arc::new(ConcurrencyLimit::new(max))),
+            Hasher::new(),
+            Arc::new(|_: &PPath<KString>| -> Option<Lang> { None }));
+        move |path| server(&Request::fake_http("GET", path, vec![], vec![]))
+    }
+
+    #[test]
+    fn t_saturated_503() {
+        let (entered_tx, entered_rx) = channel();
+        let (release_tx, release_rx) = channel();
+        let (entered_tx, release_rx) = (Mutex::new(entered_tx), Mutex::new(release_rx));
+        let handler: Arc<dyn Handler<Lang>> = Arc::new(FnHandler::new(
+            move |context: &AContext<Lang>, _method, _path: &PPath<KString>,
+                  _html: &HtmlAllocator|
+            {
+                if context.path_str() == "/slow" {
+                    entered_tx.lock().unwrap().send(())?;
+                    release_rx.lock().unwrap().recv()?;
+                }
+                Ok(Some(Response::text("hi").into()))
+            }));
+        let access_log = SharedBuf::default();
+        let get = Arc::new(test_server(handler, Some(1), access_log.clone()));
+
+        let slow = thread::spawn({
+            let get = get.clone();
+            move || get("/slow").status_code
+        });
+        entered_rx.recv_timeout(Duration::from_secs(10)).expect("slow request started");
+        let r = get("/fast");
+        assert_eq!(r.status_code, 503);
+        assert!(r.headers.iter().any(|(k, v)| k == "Retry-After" && v == "1"));
+        let log = access_log.contents();
+        assert!(log.contains("\"GET /fast\" 503 "), "{log}");
+
+        release_tx.send(()).unwrap();
+        assert_eq!(slow.join().unwrap(), 200);
+        assert_eq!(get("/fast").status_code, 200);
+    }
+
+    #[test]
+    fn t_panic_500() {
+        let handler: Arc<dyn Handler<Lang>> = Arc::new(FnHandler::new(
+            |context: &AContext<Lang>, _method, _path: &PPath<KString>,
+             _html: &HtmlAllocator|
+            {
+                if context.path_str() == "/panic" {
+                    panic!("t_panic_500 test panic");
+                }
+                Ok(Some(Response::text("hi").into()))
+            }));
+        let get = test_server(handler, Some(1), SharedBuf::default());
+        for _ in 0..2 {
+            let r = get("/panic");
+            assert_eq!(r.status_code, 500);
+            assert!(r.headers.iter().any(
+                |(k, v)| k == "Content-type" && v.starts_with("text/html")));
+            // The worker thread and the request slot are still usable
+            assert_eq!(get("/fast").status_code, 200);
+        }
+    }
+}
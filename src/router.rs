@@ -9,6 +9,14 @@ use crate::{myasstr::MyAsStr,
             trie::{Trie, TrieIter, TrieIterReportStyle},
             ppath::PPath};
 
+/// The method part of the entries returned by the `routes` methods:
+/// routers dispatch on paths only, handlers see all methods.
+pub const ANY_METHOD: &str = "*";
+
+/// Path string for the segments of a trie entry, e.g. "/foo/bar".
+pub fn route_path(segments: &[&str]) -> String {
+    format!("/{}", segments.join("/"))
+}
 
 // Allow single entries as endpoints.
 #[derive(Debug)]
@@ -93,6 +101,16 @@ impl<T> UniqueRouter<T> {
             trie_iter: self.0.iter(direction_backwards, report_style)
         }
     }
+
+    /// All mounted paths as `(method, path)`, sorted, for
+    /// diagnostics. The method is always `ANY_METHOD`.
+    pub fn routes(&self) -> Vec<(String, String)> {
+        let mut routes: Vec<_> = self.iter(false, TrieIterReportStyle::BeforeRecursing)
+            .map(|(path, _)| (ANY_METHOD.into(), route_path(&path)))
+            .collect();
+        routes.sort();
+        routes
+    }
 }
 
 pub struct UniqueRouterIter<'trie, T> {
@@ -169,6 +187,20 @@ impl<T> MultiRouter<T> {
             trie_iter: self.0.iter(Dir::new().is_backwards(), report_style),
         }
     }
+
+    /// All mounted paths as `(method, path)`, sorted, for
+    /// diagnostics. A path appears once per entry added for it, so
+    /// entries that are tried in sequence (and may shadow each
+    /// other) show up as duplicates. The method is always
+    /// `ANY_METHOD`.
+    pub fn routes(&self) -> Vec<(String, String)> {
+        let mut routes: Vec<_> = self.iter::<DirectionForward, _>(
+            TrieIterReportStyle::BeforeRecursing)
+            .map(|(path, _)| (ANY_METHOD.into(), route_path(&path)))
+            .collect();
+        routes.sort();
+        routes
+    }
 }
 
 pub trait Direction<'trie, T, VecIter> {
@@ -319,5 +351,31 @@ mod tests {
         Ok(())
     }
 
-
+    #[test]
+    fn t_routes() -> Result<()> {
+        let mut r = MultiRouter::new();
+        r
+            .add("/index.html", 1)
+            .add("/hello/world", 2)
+            .add("/hello/world/", 3)
+            .add("/", 4)
+            .add("/hello", 5)
+            ;
+        let any = |p: &str| (ANY_METHOD.to_string(), p.to_string());
+        assert_eq!(r.routes(),
+                   vec![any("/"),
+                        any("/hello"),
+                        any("/hello/world"),
+                        any("/hello/world"),
+                        any("/index.html")]);
+
+        let mut u = UniqueRouter::new(true);
+        u
+            .add("/blog/2024/post", 1)?
+            .add("/blog", 2)?
+            .add("/about", 3)?;
+        assert_eq!(u.routes(),
+                   vec![any("/about"), any("/blog"), any("/blog/2024/post")]);
+        Ok(())
+    }
 }
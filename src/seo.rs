@@ -0,0 +1,209 @@
+//! Handlers for search engines and feed readers: `/robots.txt`,
+//! `/sitemap.xml` and an Atom feed for a blog, see `seo_handlers`.
+
+use std::{borrow::Cow, sync::Arc, time::SystemTime};
+
+use anyhow::bail;
+use chrono::{DateTime, SecondsFormat, Utc};
+use rouille::{Response, ResponseBody};
+
+use ahtml::HtmlAllocator;
+
+use crate::{blog::Blog,
+            handler::{Handler, ExactFnHandler},
+            http_request_method::HttpRequestMethodSimple,
+            language::Language,
+            webparts::CacheControlled,
+            webutils::mime_with_charset};
+
+/// The `Cache-Control` value used for all of these.
+const SEO_CACHE_CONTROL: &str = "public, max-age=3600";
+
+fn response(content_type: &'static str, body: String) -> Response {
+    Response {
+        status_code: 200,
+        headers: vec![(Cow::from("Content-type"), mime_with_charset(content_type))],
+        data: ResponseBody::from_string(body),
+        upgrade: None,
+    }
+}
+
+/// Append `s` escaped for XML text or attribute values.
+fn push_xml_escaped(out: &mut String, s: &str, html: &HtmlAllocator) {
+    let escaped = html.html_escape(s.as_bytes());
+    out.push_str(std::str::from_utf8(&escaped).expect("escaping keeps UTF-8"));
+}
+
+fn datetime(t: SystemTime) -> DateTime<Utc> {
+    DateTime::<Utc>::from(t)
+}
+
+/// The blog, the path it is mounted at (e.g. "/blog"), and the name
+/// of its author, which goes into the Atom feed (RFC 4287 requires an
+/// author for the feed or for each entry).
+pub struct BlogMount<'m> {
+    pub path: &'m str,
+    pub blog: Arc<Blog>,
+    pub author: &'m str,
+}
+
+/// The URLs, titles and modification times of all blog posts,
+/// newest post first. `blog_base` is the URL of the blog index,
+/// without the trailing slash.
+fn blog_post_urls(blog_base: &str, blog: &Blog) -> Vec<(String, String, SystemTime)> {
+    let blogcache = blog.blogcache();
+    blogcache.posts_sorted().into_iter().map(|(path, post)| {
+        (format!("{blog_base}/{}", path.join("/")),
+         post.title_plain.to_string(),
+         post.modified())
+    }).collect()
+}
+
+/// Handlers for `/robots.txt` (allowing everything and referencing
+/// the sitemap), `/sitemap.xml` (listing `static_paths`, the blog
+/// index and posts) and `/feed.xml` (an Atom feed of the blog
+/// posts), with their paths, to be added to a router. `site_base` is
+/// the scheme and host part of the URLs, e.g. "https://example.com".
+pub fn seo_handlers<L: Language + 'static>(
+    site_base: &str,
+    static_paths: &[&str],
+    blog: BlogMount,
+) -> Vec<(&'static str, Arc<dyn Handler<L>>)> {
+    let site_base = site_base.trim_end_matches('/').to_string();
+    let blog_base = format!("{site_base}{}", blog.path.trim_end_matches('/'));
+    let author = blog.author.to_string();
+    let blog = blog.blog;
+
+    let robots_txt = format!("User-agent: *\nAllow: /\nSitemap: {site_base}/sitemap.xml\n");
+    let robots: Arc<dyn Handler<L>> = Arc::new(ExactFnHandler::new(
+        move |_context, method: HttpRequestMethodSimple, _html| {
+            if method.is_post() {
+                bail!("can't POST to robots.txt")
+            }
+            Ok(response("text/plain", robots_txt.clone()).into())
+        }));
+
+    let sitemap: Arc<dyn Handler<L>> = Arc::new(ExactFnHandler::new({
+        let site_base = site_base.clone();
+        let static_paths: Vec<String> = static_paths.iter().map(|s| s.to_string()).collect();
+        let blog_base = blog_base.clone();
+        let blog = blog.clone();
+        move |_context, method: HttpRequestMethodSimple, html: &HtmlAllocator| {
+            if method.is_post() {
+                bail!("can't POST to sitemap")
+            }
+            let mut s = String::from(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                 <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+            let mut url = |loc: &str, lastmod: Option<SystemTime>| {
+                s.push_str("<url><loc>");
+                push_xml_escaped(&mut s, loc, html);
+                s.push_str("</loc>");
+                if let Some(t) = lastmod {
+                    s.push_str(&format!("<lastmod>{}</lastmod>",
+                                        datetime(t).format("%Y-%m-%d")));
+                }
+                s.push_str("</url>\n");
+            };
+            for path in &static_paths {
+                url(&format!("{site_base}{path}"), None);
+            }
+            url(&format!("{blog_base}/"), None);
+            for (loc, _title, modified) in blog_post_urls(&blog_base, &blog) {
+                url(&loc, Some(modified));
+            }
+            s.push_str("</urlset>\n");
+            Ok(response("application/xml", s).into())
+        }
+    }));
+
+    let feed: Arc<dyn Handler<L>> = Arc::new(ExactFnHandler::new({
+        let site_base = site_base.clone();
+        move |_context, method: HttpRequestMethodSimple, html: &HtmlAllocator| {
+            if method.is_post() {
+                bail!("can't POST to feed")
+            }
+            let posts = blog_post_urls(&blog_base, &blog);
+            let updated = posts.iter().map(|(_, _, modified)| *modified).max()
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            let rfc3339 = |t| datetime(t).to_rfc3339_opts(SecondsFormat::Secs, true);
+            let mut s = String::from(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                 <feed xmlns=\"http://www.w3.org/2005/Atom\">\n<title>");
+            push_xml_escaped(&mut s, &site_base, html);
+            s.push_str("</title>\n<id>");
+            push_xml_escaped(&mut s, &format!("{site_base}/feed.xml"), html);
+            s.push_str(&format!("</id>\n<updated>{}</updated>\n<author><name>",
+                                rfc3339(updated)));
+            push_xml_escaped(&mut s, &author, html);
+            s.push_str("</name></author>\n");
+            for (loc, title, modified) in posts {
+                s.push_str("<entry><title>");
+                push_xml_escaped(&mut s, &title, html);
+                s.push_str("</title><id>");
+                push_xml_escaped(&mut s, &loc, html);
+                s.push_str("</id><link href=\"");
+                push_xml_escaped(&mut s, &loc, html);
+                s.push_str(&format!("\"/><updated>{}</updated></entry>\n", rfc3339(modified)));
+            }
+            s.push_str("</feed>\n");
+            Ok(response("application/atom+xml", s).into())
+        }
+    }));
+
+    vec![
+        ("/robots.txt", robots.with_cache_control(SEO_CACHE_CONTROL)),
+        ("/sitemap.xml", sitemap.with_cache_control(SEO_CACHE_CONTROL)),
+        ("/feed.xml", feed.with_cache_control(SEO_CACHE_CONTROL)),
+    ]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use ahtml::HtmlAllocatorPool;
+    use anyhow::Result;
+
+    use crate::{lang_en_de::Lang, style::footnotes::BlogStyle,
+                test_util::{fake_context, TempDir}};
+
+    use super::*;
+
+    #[test]
+    fn t_seo_handlers() -> Result<()> {
+        let dir = TempDir::new("t_seo_handlers")?;
+        std::fs::create_dir_all(dir.join("2023/10/23"))?;
+        std::fs::write(dir.join("2023/10/23/post.md"), "# Fish & Chips\n\nText.\n")?;
+        let allocpool: &'static HtmlAllocatorPool = Box::leak(Box::new(
+            HtmlAllocatorPool::new(100000, true, Arc::new("t_seo_handlers"))));
+        let blog = Blog::open(dir.to_path_buf(), allocpool, Arc::new(BlogStyle::default()))?;
+
+        let handlers = seo_handlers::<Lang>(
+            "https://example.com/", &["/", "/about.html"],
+            BlogMount { path: "/blog", blog, author: "Jane & Joe" });
+        let get = |path: &str| {
+            let (_, handler) = handlers.iter().find(|(p, _)| *p == path).expect("present");
+            fake_context("GET", path, &[], &[]).call(handler).map(|r| r.expect("handled"))
+        };
+
+        let r = get("/robots.txt")?;
+        assert_eq!(r.header("Content-type"), Some("text/plain; charset=utf-8"));
+        assert_eq!(r.header("Cache-Control"), Some(SEO_CACHE_CONTROL));
+        assert!(r.body.contains("Sitemap: https://example.com/sitemap.xml\n"));
+
+        let r = get("/sitemap.xml")?;
+        assert_eq!(r.header("Content-type"), Some("application/xml; charset=utf-8"));
+        assert!(r.body.contains("<url><loc>https://example.com/about.html</loc></url>"));
+        assert!(r.body.contains("<url><loc>https://example.com/blog/</loc></url>"));
+        assert!(r.body.contains("<url><loc>https://example.com/blog/2023/10/23/post.html</loc>\
+                                 <lastmod>"));
+
+        let r = get("/feed.xml")?;
+        assert_eq!(r.header("Content-type"), Some("application/atom+xml; charset=utf-8"));
+        assert!(r.body.contains("<author><name>Jane &amp; Joe</name></author>"));
+        assert!(r.body.contains("<entry><title>Fish &amp; Chips</title>\
+                                 <id>https://example.com/blog/2023/10/23/post.html</id>"));
+
+        Ok(())
+    }
+}
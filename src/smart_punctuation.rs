@@ -0,0 +1,165 @@
+//! Typographic ("smart") punctuation for text: straight quotes become
+//! the quotation marks of a language, `--` and `---` become en and em
+//! dashes, `...` becomes an ellipsis.
+
+use std::borrow::Cow;
+
+/// The quotation marks of a language.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SmartQuotes {
+    pub double_open: char,
+    pub double_close: char,
+    pub single_open: char,
+    pub single_close: char,
+    /// For `'` within or at the end of words (`don't`, `Hans'`).
+    pub apostrophe: char,
+}
+
+impl SmartQuotes {
+    /// “double” and ‘single’
+    pub const ENGLISH: SmartQuotes = SmartQuotes {
+        double_open: '“',
+        double_close: '”',
+        single_open: '‘',
+        single_close: '’',
+        apostrophe: '’',
+    };
+
+    /// „double“ and ‚single‘
+    pub const GERMAN: SmartQuotes = SmartQuotes {
+        double_open: '„',
+        double_close: '“',
+        single_open: '‚',
+        single_close: '‘',
+        apostrophe: '’',
+    };
+}
+
+impl Default for SmartQuotes {
+    fn default() -> Self {
+        SmartQuotes::ENGLISH
+    }
+}
+
+/// Converts a sequence of text pieces (e.g. the text events of a
+/// paragraph, which may be interrupted by markup), keeping track of
+/// the preceding character to decide between opening and closing
+/// quotes.
+#[derive(Debug, Clone)]
+pub struct SmartPunctuation {
+    quotes: SmartQuotes,
+    prev: Option<char>,
+    single_is_open: bool,
+}
+
+/// Whether a quote after `prev` opens a quotation.
+fn is_opening_position(prev: Option<char>) -> bool {
+    match prev {
+        None => true,
+        Some(c) => c.is_whitespace() || "([{<-–—/\u{a0}".contains(c),
+    }
+}
+
+impl SmartPunctuation {
+    pub fn new(quotes: SmartQuotes) -> Self {
+        SmartPunctuation { quotes, prev: None, single_is_open: false }
+    }
+
+    /// Forget the context, call at the start of a block (paragraph,
+    /// heading, list item etc.).
+    pub fn reset(&mut self) {
+        self.prev = None;
+        self.single_is_open = false;
+    }
+
+    /// Note text that is not converted but precedes the next piece
+    /// (like inline code).
+    pub fn skip(&mut self, text: &str) {
+        if let Some(c) = text.chars().last() {
+            self.prev = Some(c);
+        }
+    }
+
+    /// Convert the next piece of text.
+    pub fn convert<'s>(&mut self, text: &'s str) -> Cow<'s, str> {
+        if ! text.contains(['"', '\'', '-', '.']) {
+            self.skip(text);
+            return Cow::Borrowed(text)
+        }
+        let q = self.quotes;
+        let mut out = String::with_capacity(text.len() + 8);
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            let next = chars.peek().copied();
+            let converted = match c {
+                '"' =>
+                    if is_opening_position(self.prev) {
+                        q.double_open
+                    } else {
+                        q.double_close
+                    },
+                '\'' => {
+                    let prev_is_alnum = self.prev.is_some_and(char::is_alphanumeric);
+                    if prev_is_alnum && next.is_some_and(char::is_alphanumeric) {
+                        q.apostrophe
+                    } else if is_opening_position(self.prev) {
+                        self.single_is_open = true;
+                        q.single_open
+                    } else if self.single_is_open {
+                        self.single_is_open = false;
+                        q.single_close
+                    } else {
+                        q.apostrophe
+                    }
+                }
+                '-' if next == Some('-') => {
+                    chars.next();
+                    if chars.peek() == Some(&'-') {
+                        chars.next();
+                        '—'
+                    } else {
+                        '–'
+                    }
+                }
+                '.' if next == Some('.') => {
+                    let mut rest = chars.clone();
+                    rest.next();
+                    if rest.peek() == Some(&'.') {
+                        chars.next();
+                        chars.next();
+                        '…'
+                    } else {
+                        '.'
+                    }
+                }
+                _ => c
+            };
+            out.push(converted);
+            self.prev = Some(converted);
+        }
+        Cow::Owned(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(quotes: SmartQuotes, pieces: &[&str]) -> String {
+        let mut sp = SmartPunctuation::new(quotes);
+        pieces.iter().map(|s| sp.convert(s).into_owned()).collect()
+    }
+
+    #[test]
+    fn t_smart_punctuation() {
+        let s = r#""Hello," she said, 'don't' -- wait --- it's Hans' "x"..."#;
+        assert_eq!(convert(SmartQuotes::ENGLISH, &[s]),
+                   "“Hello,” she said, ‘don’t’ – wait — it’s Hans’ “x”…");
+        assert_eq!(convert(SmartQuotes::GERMAN, &[s]),
+                   "„Hello,“ she said, ‚don’t‘ – wait — it’s Hans’ „x“…");
+        // Context is kept across pieces
+        assert_eq!(convert(SmartQuotes::GERMAN, &["a \"", "b", "\" c"]),
+                   "a „b“ c");
+        assert_eq!(convert(SmartQuotes::GERMAN, &["a. b.."]), "a. b..");
+    }
+}
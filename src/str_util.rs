@@ -1,5 +1,7 @@
 // copies from cj-hours-parser
 
+use std::borrow::Cow;
+
 /// Drop the first `n` characters. If `s` has fewer than `n`
 /// characters, returns the empty string.
 pub fn str_drop(s: &str, n: usize) -> &str {
@@ -34,3 +36,47 @@ pub fn str_take(s: &str, n: usize) -> (&str, bool) {
     (s, ci == n)
 }
 
+/// Cut `s` off after `max_chars` characters (not bytes), appending
+/// "…" if anything was dropped (and dropping trailing whitespace
+/// before it).
+pub fn str_truncate_ellipsis(s: &str, max_chars: usize) -> Cow<'_, str> {
+    match s.char_indices().nth(max_chars) {
+        None => Cow::Borrowed(s),
+        Some((i, _)) => Cow::Owned(format!("{}…", s[0..i].trim_end())),
+    }
+}
+
+#[test]
+fn t_str_truncate_ellipsis() {
+    assert_eq!(str_truncate_ellipsis("Hello", 5), "Hello");
+    assert_eq!(str_truncate_ellipsis("Hello", 55), "Hello");
+    assert_eq!(str_truncate_ellipsis("Hello", 4), "Hell…");
+    assert_eq!(str_truncate_ellipsis("Hä lü", 3), "Hä…");
+    assert_eq!(str_truncate_ellipsis("Hä lü", 4), "Hä l…");
+    assert_eq!(str_truncate_ellipsis("Hä", 0), "…");
+}
+
+/// Like `str_truncate_ellipsis`, but cut at a word boundary, i.e. the
+/// word that would be cut into is dropped (unless it's the only one,
+/// then it is cut after `max_chars` characters anyway).
+pub fn str_truncate_words(s: &str, max_chars: usize) -> Cow<'_, str> {
+    let Some((end, _)) = s.char_indices().nth(max_chars) else {
+        return Cow::Borrowed(s)
+    };
+    let cut = if s[end..].starts_with(char::is_whitespace) {
+        end
+    } else {
+        s[..end].rfind(char::is_whitespace).unwrap_or(end)
+    };
+    Cow::Owned(format!("{}…", s[0..cut].trim_end()))
+}
+
+#[test]
+fn t_str_truncate_words() {
+    assert_eq!(str_truncate_words("Hello world", 11), "Hello world");
+    assert_eq!(str_truncate_words("Hello world", 8), "Hello…");
+    assert_eq!(str_truncate_words("Hello world", 5), "Hello…");
+    assert_eq!(str_truncate_words("Hello  world", 7), "Hello…");
+    assert_eq!(str_truncate_words("Hä lü", 4), "Hä…");
+    assert_eq!(str_truncate_words("Hello", 3), "Hel…");
+}
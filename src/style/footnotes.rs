@@ -1,20 +1,88 @@
 use anyhow::Result;
+use kstring::KString;
 
 use ahtml::{Node, ASlice, HtmlAllocator, AId, att, flat::Flat};
 
-use crate::{markdown::{StylingInterface, Footnoteref, Backref, StylingContextInterface},
+use crate::{alist::AList,
+            markdown::{StylingInterface, Footnoteref, Backref, StylingContextInterface,
+                       FootnoteMarkers, FootnoteLinks},
             util::alphanumber};
 
 // ------------------------------------------------------------------
 
+const FOOTNOTES_HEADING_FOR: &[(&str, &str)] = &[
+    ("en", "Footnotes"),
+    ("de", "Fußnoten"),
+];
+
+/// The standard footnotes heading in `lang` (English if not
+/// translated).
+pub fn footnotes_heading_for(lang: &str) -> &'static str {
+    let headings = AList(FOOTNOTES_HEADING_FOR);
+    headings.get(&lang).unwrap_or_else(
+        || headings.get(&"en").expect("en always present"))
+}
+
+/// The configuration shared by the styles. The default is numbered
+/// footnotes without a heading, with back-references separated by a
+/// space.
+#[derive(Debug, Clone)]
+pub struct FootnotesConfig {
+    /// Whether to put a heading above the footnotes, in the language
+    /// of the request (see `footnotes_heading_for`).
+    pub heading: bool,
+    /// Put between the back-references of a footnote that is cited
+    /// more than once.
+    pub separator: KString,
+    /// Also pass these in `MarkdownConfig::footnote_markers`.
+    pub markers: FootnoteMarkers,
+    /// Also pass these in `MarkdownConfig::footnote_links`.
+    pub links: FootnoteLinks,
+}
+
+impl Default for FootnotesConfig {
+    fn default() -> Self {
+        FootnotesConfig {
+            heading: false,
+            separator: KString::from_static(" "),
+            markers: FootnoteMarkers::default(),
+            links: FootnoteLinks::default(),
+        }
+    }
+}
+
+impl FootnotesConfig {
+    /// Put the heading (if enabled) in `lang` above `footnotes`.
+    fn with_heading(
+        &self,
+        footnotes: AId<Node>,
+        lang: &str,
+        html: &HtmlAllocator,
+    ) -> Result<AId<Node>> {
+        if self.heading {
+            html.section(
+                [att("class", "footnotes_section")],
+                [html.h2([], [html.staticstr(footnotes_heading_for(lang))?])?,
+                 footnotes])
+        } else {
+            Ok(footnotes)
+        }
+    }
+}
+
+// ------------------------------------------------------------------
+
 /// Footnotes in the style of Wikipedia (backreferences after the
 /// number instead of after the footnote). Although it currently
 /// doesn't use the same markup and needs tweaking.
-pub struct WikipediaStyle {}
+#[derive(Default)]
+pub struct WikipediaStyle {
+    pub config: FootnotesConfig,
+}
 pub struct WikipediaStyleContext<'c> {
-    #[allow(dead_code)]
     config: &'c WikipediaStyle,
     spacer: AId<Node>,
+    separator: AId<Node>,
     uparrow: AId<Node>,
 }
 
@@ -26,9 +94,27 @@ impl StylingInterface for WikipediaStyle {
         Ok(Box::new(WikipediaStyleContext {
             config: self,
             spacer: html.str(" ")?,
+            separator: html.kstring(self.config.separator.clone())?,
             uparrow: html.str("^")?,
         }))
     }
+
+    fn footnote_markers(&self) -> FootnoteMarkers {
+        self.config.markers
+    }
+
+    fn footnote_links(&self) -> FootnoteLinks {
+        self.config.links.clone()
+    }
+
+    fn footnotes_section(
+        &self,
+        footnotes: AId<Node>,
+        lang: &str,
+        html: &HtmlAllocator,
+    ) -> Result<AId<Node>> {
+        self.config.with_heading(footnotes, lang, html)
+    }
 }
 
 impl<'c> StylingContextInterface<'c> for WikipediaStyleContext<'c> {
@@ -39,9 +125,9 @@ impl<'c> StylingContextInterface<'c> for WikipediaStyleContext<'c> {
         backreferences: &[Backref],
         clean_slice: &ASlice<Node>,
     ) -> Result<Flat<Node>> {
-
+        let links = &self.config.config.links;
         let mut refvec = html.new_vec();
-        refvec.push(html.string(reference.0.to_string())?)?;
+        refvec.push(html.string(self.config.config.markers.marker(reference.0))?)?;
         refvec.push(self.spacer)?;
         match backreferences.len() {
             0 => {},
@@ -49,18 +135,25 @@ impl<'c> StylingContextInterface<'c> for WikipediaStyleContext<'c> {
                 let backref = backreferences.first().unwrap();
                 refvec.push(
                     html.a(
-                        [att("href", backref.to_kstring(true))],
+                        [att("href", backref.to_kstring(true)),
+                         links.role("doc-backlink"),
+                         links.rel()],
                         [
                             self.uparrow
                         ])?)?;
             }
             _ => {
                 refvec.push(self.uparrow)?;
+                refvec.push(self.spacer)?;
                 for (i, backref) in backreferences.iter().enumerate() {
-                    refvec.push(self.spacer)?;
+                    if i > 0 {
+                        refvec.push(self.separator)?;
+                    }
                     refvec.push(
                         html.a(
-                            [att("href", backref.to_kstring(true))],
+                            [att("href", backref.to_kstring(true)),
+                             links.role("doc-backlink"),
+                             links.rel()],
                             [
                                 html.string(alphanumber(i as u32))?,
                             ])?)?;
@@ -72,7 +165,7 @@ impl<'c> StylingContextInterface<'c> for WikipediaStyleContext<'c> {
                 [att("class", "footnote_reference"),
                  att("id", reference.to_kstring(false)) ],
                 refvec.as_slice())?,
-            html.dd([], clean_slice)?))
+            html.dd([links.role("doc-footnote")], clean_slice)?))
     }
 
     fn format_footnotes(
@@ -89,13 +182,16 @@ impl<'c> StylingContextInterface<'c> for WikipediaStyleContext<'c> {
 // ------------------------------------------------------------------
 
 /// Footnotes in the style typically used on blogs (backreferences
-/// after the footnote). Markup seems fine.
+/// after the footnote). Markup seems fine. A footnote cited multiple
+/// times gets a separate numbered arrow (↩¹ ↩²) per citation.
+#[derive(Default)]
 pub struct BlogStyle {
+    pub config: FootnotesConfig,
 }
 pub struct BlogStyleContext<'c> {
-    #[allow(dead_code)]
     config: &'c BlogStyle,
     spacer: AId<Node>,
+    separator: AId<Node>,
     uparrow: AId<Node>,
 }
 
@@ -107,9 +203,27 @@ impl StylingInterface for BlogStyle {
         Ok(Box::new(BlogStyleContext {
             config: self,
             spacer: html.str(" ")?,
+            separator: html.kstring(self.config.separator.clone())?,
             uparrow: html.str("↩")?,
         }))
     }
+
+    fn footnote_markers(&self) -> FootnoteMarkers {
+        self.config.markers
+    }
+
+    fn footnote_links(&self) -> FootnoteLinks {
+        self.config.links.clone()
+    }
+
+    fn footnotes_section(
+        &self,
+        footnotes: AId<Node>,
+        lang: &str,
+        html: &HtmlAllocator,
+    ) -> Result<AId<Node>> {
+        self.config.with_heading(footnotes, lang, html)
+    }
 }
 
 impl<'c> StylingContextInterface<'c> for BlogStyleContext<'c> {
@@ -120,8 +234,15 @@ impl<'c> StylingContextInterface<'c> for BlogStyleContext<'c> {
         backreferences: &[Backref],
         clean_slice: &ASlice<Node>,
     ) -> Result<Flat<Node>> {
-
+        let links = &self.config.config.links;
         let mut refvec = html.new_vec();
+        if self.config.config.markers != FootnoteMarkers::Numbers {
+            // Not numbered by the list
+            refvec.push(html.span(
+                [att("class", "footnote_marker")],
+                [html.string(self.config.config.markers.marker(reference.0))?])?)?;
+            refvec.push(self.spacer)?;
+        }
         refvec.extend_from_slice(clean_slice, html)?;
         refvec.push(self.spacer)?;
         match backreferences.len() {
@@ -130,20 +251,26 @@ impl<'c> StylingContextInterface<'c> for BlogStyleContext<'c> {
                 let backref = backreferences.first().unwrap();
                 refvec.push(
                     html.a(
-                        [att("href", backref.to_kstring(true))],
+                        [att("href", backref.to_kstring(true)),
+                         links.role("doc-backlink"),
+                         links.rel()],
                         [
                             self.uparrow
                         ])?)?;
             }
             _ => {
-                refvec.push(self.uparrow)?;
                 for (i, backref) in backreferences.iter().enumerate() {
-                    refvec.push(self.spacer)?;
+                    if i > 0 {
+                        refvec.push(self.separator)?;
+                    }
                     refvec.push(
                         html.a(
-                            [att("href", backref.to_kstring(true))],
+                            [att("href", backref.to_kstring(true)),
+                             links.role("doc-backlink"),
+                             links.rel()],
                             [
-                                html.string(alphanumber(i as u32))?,
+                                self.uparrow,
+                                html.sup([], [html.string((i + 1).to_string())?])?,
                             ])?)?;
                 }
             }
@@ -151,7 +278,8 @@ impl<'c> StylingContextInterface<'c> for BlogStyleContext<'c> {
         Ok(Flat::One(
             html.li(
                 [att("class", "footnote_definition"),
-                 att("id", reference.to_kstring(false)) ],
+                 att("id", reference.to_kstring(false)),
+                 links.role("doc-footnote")],
                 refvec.as_slice())?))
     }
 
@@ -160,8 +288,125 @@ impl<'c> StylingContextInterface<'c> for BlogStyleContext<'c> {
         body: ASlice<Node>,
         html: &HtmlAllocator,
     ) -> Result<AId<Node>> {
-        html.ol(
-            [att("class", "footnotes")],
-            body)
+        if self.config.config.markers == FootnoteMarkers::Numbers {
+            html.ol([att("class", "footnotes")], body)
+        } else {
+            html.ul([att("class", "footnotes footnotes_unnumbered")], body)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use ahtml::Print;
+
+    use crate::{lang_en_de::Lang, language::Language,
+                markdown::{MarkdownFile, MarkdownConfig}};
+
+    use super::*;
+
+    #[test]
+    fn t_blogstyle_multiple_backrefs() -> Result<()> {
+        let html = HtmlAllocator::new(1000, std::sync::Arc::new(""));
+        let pmd = MarkdownFile::process_str_to_html(
+            "One[^n] and two[^n].\n\n[^n]: The note.\n",
+            &MarkdownConfig::default(),
+            &html)?;
+        let (n, footnotes) = pmd.meta().footnotes_html_fragment(&html, &BlogStyle::default())?;
+        assert_eq!(n, 1);
+        assert_eq!(
+            footnotes.to_html_fragment_string(&html)?,
+            "<ol class=\"footnotes\">\
+             <li class=\"footnote_definition\" id=\"footnote-1\">The note. \
+             <a href=\"#footnoteref-1\">↩<sup>1</sup></a> \
+             <a href=\"#footnoteref-2\">↩<sup>2</sup></a>\
+             </li></ol>");
+
+        let style = BlogStyle {
+            config: FootnotesConfig {
+                separator: KString::from_static(", "),
+                ..Default::default()
+            }
+        };
+        let (_, footnotes) = pmd.meta().footnotes_html_fragment(&html, &style)?;
+        assert!(footnotes.to_html_fragment_string(&html)?.contains(
+            "<a href=\"#footnoteref-1\">↩<sup>1</sup></a>, \
+             <a href=\"#footnoteref-2\">↩<sup>2</sup></a>"));
+        Ok(())
+    }
+
+    #[test]
+    fn t_aria_attributes() -> Result<()> {
+        let html = HtmlAllocator::new(1000, std::sync::Arc::new(""));
+        let style = BlogStyle {
+            config: FootnotesConfig {
+                links: FootnoteLinks { aria: true, rel: Some("footnote".into()) },
+                ..Default::default()
+            }
+        };
+        let config = MarkdownConfig {
+            footnote_links: style.footnote_links(),
+            ..Default::default()
+        };
+        let pmd = MarkdownFile::process_str_to_html(
+            "One[^n].\n\n[^n]: The note.\n", &config, &html)?;
+        assert_eq!(
+            pmd.html().expect("wrapped").to_html_fragment_string(&html)?,
+            "<div><p>One<sup id=\"footnoteref-1\">\
+             <a href=\"#footnote-1\" role=\"doc-noteref\" \
+             aria-describedby=\"footnote-1\" rel=\"footnote\">1</a></sup>.</p></div>");
+        let (_, footnotes) = pmd.meta().footnotes_html_fragment(&html, &style)?;
+        assert_eq!(
+            footnotes.to_html_fragment_string(&html)?,
+            "<ol class=\"footnotes\">\
+             <li class=\"footnote_definition\" id=\"footnote-1\" role=\"doc-footnote\">\
+             The note. <a href=\"#footnoteref-1\" role=\"doc-backlink\" \
+             rel=\"footnote\">↩</a></li></ol>");
+        Ok(())
+    }
+
+    #[test]
+    fn t_localized_heading_and_symbols() -> Result<()> {
+        let html = HtmlAllocator::new(1000, std::sync::Arc::new(""));
+        let style = BlogStyle {
+            config: FootnotesConfig {
+                heading: true,
+                markers: FootnoteMarkers::Symbols,
+                ..Default::default()
+            },
+        };
+        let config = MarkdownConfig {
+            footnote_markers: style.footnote_markers(),
+            ..Default::default()
+        };
+        let pmd = MarkdownFile::process_str_to_html(
+            "Eins[^a] und zwei[^b].\n\n[^a]: Erste.\n\n[^b]: Zweite.\n",
+            &config,
+            &html)?;
+        assert!(pmd.html().expect("wrapped").to_html_fragment_string(&html)?.contains(
+            "<sup id=\"footnoteref-2\"><a href=\"#footnote-2\">†</a></sup>"));
+        let (_, footnotes) = pmd.meta().footnotes_html_fragment(&html, &style)?;
+        let section = style.footnotes_section(footnotes, Lang::De.as_str(), &html)?;
+        assert_eq!(
+            section.to_html_fragment_string(&html)?,
+            "<section class=\"footnotes_section\"><h2>Fußnoten</h2>\
+             <ul class=\"footnotes footnotes_unnumbered\">\
+             <li class=\"footnote_definition\" id=\"footnote-1\">\
+             <span class=\"footnote_marker\">*</span> Erste. \
+             <a href=\"#footnoteref-1\">↩</a></li>\
+             <li class=\"footnote_definition\" id=\"footnote-2\">\
+             <span class=\"footnote_marker\">†</span> Zweite. \
+             <a href=\"#footnoteref-2\">↩</a></li>\
+             </ul></section>");
+
+        // The same cached footnotes in English, and doubled symbols
+        // after the sixth
+        let section = style.footnotes_section(footnotes, Lang::En.as_str(), &html)?;
+        assert!(section.to_html_fragment_string(&html)?.starts_with(
+            "<section class=\"footnotes_section\"><h2>Footnotes</h2><ul "));
+        assert_eq!(footnotes_heading_for("fr"), "Footnotes");
+        assert_eq!(FootnoteMarkers::Symbols.marker(7), "**");
+        Ok(())
     }
 }
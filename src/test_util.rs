@@ -0,0 +1,315 @@
+//! Helpers for unit testing handlers without running a server:
+//! `fake_context` builds a request, `FakeRequest::call` runs a
+//! `Handler` on it with a proper `AContext` and gives back the
+//! response in an easily inspectable form. Also `assert_dom_eq` for
+//! comparing DOM trees, `TempDir` for tests needing files, and
+//! `SharedBuf` for looking at log output.
+
+use std::{borrow::Cow, io::{Read, Write}, net::SocketAddr, sync::{Arc, Mutex},
+          time::Instant, ops::Deref, path::{Path, PathBuf}};
+
+use ahtml::{HtmlAllocator, AId, Node, NodeWalker};
+use anyhow::{Result, bail};
+use blake3::Hasher;
+use rouille::{Request, Response, session::session};
+
+use crate::{acontext::AContext,
+            aresponse::AResponse,
+            auri::QueryString,
+            handler::Handler,
+            http_request_method::HttpRequestMethodGrouped,
+            language::Language,
+            ppath::PPath};
+
+/// A request to run handlers on, see `fake_context`.
+pub struct FakeRequest {
+    request: Request,
+    sessionid_hasher: Hasher,
+}
+
+/// Prepare a request with the given method (e.g. "GET"), path (without
+/// query string), headers and query parameters (these are URL-encoded
+/// here).
+pub fn fake_context(
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    query: &[(&str, &str)],
+) -> FakeRequest {
+    fake_context_from("127.0.0.1:12345".parse().expect("valid"), false,
+                      method, path, headers, query)
+}
+
+/// Like `fake_context`, but with the given client address (instead
+/// of a loopback address), and over TLS if `https` is true.
+pub fn fake_context_from(
+    remote_addr: SocketAddr,
+    https: bool,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    query: &[(&str, &str)],
+) -> FakeRequest {
+    let mut url = path.to_string();
+    if ! query.is_empty() {
+        url.push('?');
+        url.push_str(&String::from(&QueryString::new(query)));
+    }
+    let headers = headers.iter().map(
+        |(k, v)| (k.to_string(), v.to_string())).collect();
+    let request = if https {
+        Request::fake_https_from(remote_addr, method, url, headers, vec![])
+    } else {
+        Request::fake_http_from(remote_addr, method, url, headers, vec![])
+    };
+    FakeRequest {
+        request,
+        sessionid_hasher: Hasher::new(),
+    }
+}
+
+impl FakeRequest {
+    /// Run `f` with an `AContext` for this request (with a fresh
+    /// session, and no language derived from the path).
+    pub fn with_context<L: Language, R>(
+        &self,
+        f: impl FnOnce(&AContext<L>) -> Result<R>
+    ) -> Result<R> {
+        let mut result = None;
+        session(&self.request, "sid", 3600, |session| {
+            result = Some(
+                AContext::new(&self.request, "localhost:3000", session,
+                              &self.sessionid_hasher, Arc::new(|_| None))
+                    .and_then(|context| f(&context)));
+            Response::text("")
+        });
+        result.expect("session calls the closure")
+    }
+
+    /// Run `handler` on this request (with an empty path rest, like
+    /// when mounted at the request path), `None` if it declined.
+    pub fn call<L: Language>(
+        &self,
+        handler: &Arc<dyn Handler<L>>
+    ) -> Result<Option<TestResponse>> {
+        self.call_with_pathrest(handler, "")
+    }
+
+    /// Like `call` but passing `pathrest` (the part of the path below
+    /// where the handler is mounted, e.g. "2023/10/23/post.html").
+    pub fn call_with_pathrest<L: Language>(
+        &self,
+        handler: &Arc<dyn Handler<L>>,
+        pathrest: &str,
+    ) -> Result<Option<TestResponse>> {
+        let html = HtmlAllocator::new(1000000, Arc::new("test_util"));
+        self.with_context(|context: &AContext<L>| {
+            let method = match context.method().to_grouped() {
+                HttpRequestMethodGrouped::Simple(m) => m,
+                _ => bail!("method {:?} is not handled by handlers",
+                           context.method_str())
+            };
+            let r = handler.call(context, method, &PPath::from_str(pathrest), &html)?;
+            r.map(|mut aresponse| {
+                context.set_headers(&mut aresponse.response.headers);
+                TestResponse::from_aresponse(aresponse)
+            }).transpose()
+        })
+    }
+}
+
+/// A response with the body read into a string.
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status_code: u16,
+    pub headers: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+    pub body: String,
+    pub sleep_until: Option<Instant>,
+}
+
+impl TestResponse {
+    pub fn from_aresponse(aresponse: AResponse) -> Result<Self> {
+        let AResponse { response, sleep_until } = aresponse;
+        let (mut reader, _) = response.data.into_reader_and_size();
+        let mut body = String::new();
+        reader.read_to_string(&mut body)?;
+        Ok(TestResponse {
+            status_code: response.status_code,
+            headers: response.headers,
+            body,
+            sleep_until,
+        })
+    }
+
+    /// The value of the first header with the given name (compared
+    /// case-insensitively).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_ref())
+    }
+
+    /// The redirect target.
+    pub fn location(&self) -> Option<&str> {
+        self.header("Location")
+    }
+}
+
+/// One line per node, indented by depth: elements as their opening
+/// tag, text as a quoted string. Two trees are structurally equal if
+/// their outlines are.
+fn dom_outline(id: AId<Node>, html: &HtmlAllocator) -> Vec<String> {
+    NodeWalker::new(html).dfs(id).filter_map(|(_, node, depth)| {
+        let indent = "  ".repeat(depth);
+        match node {
+            Node::Element(e) => {
+                let mut line = format!("{indent}<{}", e.meta.tag_name.as_str());
+                for (key, val) in e.attr.iter_att(html) {
+                    line.push_str(&format!(" {key}={:?}", val.as_str()));
+                }
+                line.push('>');
+                Some(line)
+            }
+            Node::String(s) => Some(format!("{indent}{:?}", s.as_str())),
+            Node::Preserialized(ser) => Some(format!("{indent}{}", ser.as_str())),
+            Node::Fragment(_) => Some(format!("{indent}(fragment)")),
+            Node::None => None,
+        }
+    }).collect()
+}
+
+/// `None` if the trees `a` and `b` are structurally equal, otherwise
+/// a diff of their outlines around the first divergence: lines only
+/// in `a` are prefixed with `-`, lines only in `b` with `+`, with a
+/// few lines of context.
+pub fn dom_diff(a: AId<Node>, b: AId<Node>, html: &HtmlAllocator) -> Option<String> {
+    let a = dom_outline(a, html);
+    let b = dom_outline(b, html);
+    if a == b {
+        return None
+    }
+    const CONTEXT: usize = 3;
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y).count();
+    let mut out = format!("first difference at line {}:\n", prefix + 1);
+    let mut push = |sign: &str, lines: &[String]| {
+        for line in lines {
+            out.push_str(sign);
+            out.push_str(line);
+            out.push('\n');
+        }
+    };
+    push(" ", &a[prefix.saturating_sub(CONTEXT)..prefix]);
+    push("-", &a[prefix..a.len() - suffix]);
+    push("+", &b[prefix..b.len() - suffix]);
+    let after = a.len() - suffix;
+    push(" ", &a[after..a.len().min(after + CONTEXT)]);
+    Some(out)
+}
+
+/// Panics with a diff (see `dom_diff`) if the trees `a` and `b` are
+/// not structurally equal.
+#[track_caller]
+pub fn assert_dom_eq(a: AId<Node>, b: AId<Node>, html: &HtmlAllocator) {
+    if let Some(diff) = dom_diff(a, b, html) {
+        panic!("DOM trees differ, {diff}");
+    }
+}
+
+/// A directory below the system's temporary directory, removed with
+/// its contents when dropped (also when the test fails).
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create an empty directory named after `name` (e.g. the test
+    /// name) and the process id, removing leftovers of a previous
+    /// run first.
+    pub fn new(name: &str) -> Result<TempDir> {
+        let path = std::env::temp_dir().join(
+            format!("website-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path)?;
+        Ok(TempDir { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A log output that can be looked at after being written to.
+#[derive(Clone, Default)]
+pub struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuf {
+    /// Everything written so far.
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ahtml::att;
+
+    use super::*;
+
+    #[test]
+    fn t_assert_dom_eq() -> Result<()> {
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let tree = |last: &str| -> Result<AId<Node>> {
+            html.div([att("class", "x")],
+                     [html.p([], [html.str("a")?])?,
+                      html.p([], [html.str("b")?])?,
+                      html.ul([], [html.li([], [html.str(last)?])?])?])
+        };
+        assert_dom_eq(tree("c")?, tree("c")?, &html);
+
+        let diff = dom_diff(tree("c")?, tree("d")?, &html).expect("differ");
+        assert_eq!(diff, concat!("first difference at line 8:\n",
+                                 "     \"b\"\n",
+                                 "   <ul>\n",
+                                 "     <li>\n",
+                                 "-      \"c\"\n",
+                                 "+      \"d\"\n"));
+
+        let other = html.div([att("class", "x")],
+                             [html.p([], [html.str("a")?])?,
+                              html.ol([], [])?])?;
+        let diff = dom_diff(tree("c")?, other, &html).expect("differ");
+        assert!(diff.contains("-  <p>\n") && diff.contains("+  <ol>\n"), "{diff}");
+        Ok(())
+    }
+}
@@ -228,6 +228,12 @@ impl<T> Trie<T> {
         Ok(oldendpoint)
     }
 
+    /// The direct sub-tries with their path segment, in order.
+    pub fn children(&self) -> impl Iterator<Item = (&str, &Trie<T>)> {
+        self.branching.iter().flat_map(
+            |m| m.iter().map(|(k, v)| (k.as_str(), v)))
+    }
+
     /// Iterater over the Trie returning Trie nodes. The Iterator is
     /// not double-ended, meaning .rev() cannot be called on it; as a
     /// workaround, `direction_backwards` can be set to `true`
@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 use pct_str::{PctString, URIReserved, InvalidPctString, PctStr};
 
 pub fn url_encode(s: &str) -> String {
@@ -27,3 +29,36 @@ pub fn url_decode(s: &str) -> Result<String, UrlDecodingError> {
     Ok(p.decode())
 }
 
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None
+    }
+}
+
+/// Like `url_decode` but never fails: `%` not followed by two hex
+/// digits is kept literally, and invalid UTF-8 resulting from the
+/// decoding is replaced with U+FFFD.
+pub fn url_decode_lossy(s: &str) -> Cow<'_, str> {
+    if ! s.contains('%') {
+        return Cow::Borrowed(s)
+    }
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(h), Some(l)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                out.push(h * 16 + l);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    Cow::Owned(String::from_utf8_lossy(&out).into_owned())
+}
@@ -371,6 +371,14 @@ pub fn getenv_bool(name: &str) -> Result<bool> {
     }
 }
 
+/// Retrieve a non-negative integer from an env var, None if the var
+/// isn't set.
+pub fn getenv_usize(name: &str) -> Result<Option<usize>> {
+    getenv(name)?.map(|s| s.parse().with_context(
+        || anyhow!("env var {name:?} is not a non-negative integer: {s:?}")))
+        .transpose()
+}
+
 /// Takes a place (variable or field) holding an `Option<T>` and an
 /// expression that returns `T`; returns a `&T` to the value held by
 /// the `Option`, runs the expression and stores the result in the
@@ -2,40 +2,45 @@
 //! trait (`LayoutInterface`).
 
 use std::{path::{PathBuf, Path},
+          borrow::Cow,
           sync::Arc,
-          time::{SystemTime, Instant, Duration},
+          time::{SystemTime, Instant},
           fmt::Debug};
 
 use anyhow::{Result, Context, anyhow, bail};
 use chrono::NaiveDate;
 use kstring::KString;
-use rand::{prelude::thread_rng, Rng};
-use rand_distr::Weibull;
-use rouille::{Response, post_input};
+use rouille::{Response, ResponseBody, post_input};
 
 use ahtml::{HtmlAllocator, AId, Node, P_META, TryCollectBody,
             att, opt_att};
 use chj_util::{warn, nodt, notime};
 
 use crate::{acontext::AContext,
-            webutils::{htmlresponse, request_resolve_relative, errorpage_from_status},
+            webutils::{htmlresponse, htmlresponse_streaming, request_resolve_relative,
+                       errorpage_from_status, check_preconditions, Precondition,
+                       negotiate_content_type},
             http_response_status_codes::HttpResponseStatusCode,
-            markdown::MarkdownFile,
+            markdown::{MarkdownFile, MarkdownConfig, HeadingShift},
             handler::{Handler, ExactFnHandler, FnHandler, FileHandler},
-            blog::{Blog, BlogNode, BlogPostIndex},
+            blog::{Blog, BlogNode, BlogPost, BlogPostIndex, BlogIndexTeaser},
+            str_util::str_truncate_ellipsis,
             ppath::PPath,
-            trie::TrieIterReportStyle,
             http_request_method::HttpRequestMethodSimple,
             access_control::{check_username_password, CheckAccessErrorKind,
                              db::access_control_transaction,
-                             types::{SessionData, GroupId},
+                             transaction::Transaction,
+                             types::{SessionData, GroupId, UserId, User},
                              statements_and_methods::sessionid_hash},
-            aresponse::{AResponse, ToAResponse},
+            aresponse::{AResponse, ToAResponse, CACHE_CONTROL_NO_STORE},
             time_util::now_unixtime,
             ipaddr_util::IpAddrOctets,
+            random_util::weibull_delay,
             auri::AUriLocal,
             path::{path_append, extension_eq, base, suffix},
-            language::Language};
+            language::Language,
+            alist::AList,
+            date_format_website::date_format_naivedate_website};
 use crate::try_result;
 
 
@@ -102,6 +107,111 @@ pub fn popup_box<'a>(
     }
 }
 
+/// A `<picture>` element for art direction: one `<source>` per entry
+/// in `sources`, given as `(media, srcset, type)`, in order of
+/// preference, followed by `fallback_img` (which should be an `<img>`
+/// element, used by browsers without `<picture>` support and for
+/// alt text and sizing).
+pub fn picture(
+    html: &HtmlAllocator,
+    sources: &[(Option<&str>, &str, Option<&str>)],
+    fallback_img: AId<Node>,
+) -> Result<AId<Node>>
+{
+    let mut body = html.new_vec_with_capacity(sources.len() as u32 + 1)?;
+    for (media, srcset, type_) in sources {
+        body.push(html.source([opt_att("media", *media),
+                               att("srcset", *srcset),
+                               opt_att("type", *type_)],
+                              [])?)?;
+    }
+    body.push(fallback_img)?;
+    html.picture([], body)
+}
+
+/// A `<time>` element showing `date` formatted for `lang`, with the
+/// machine-readable `datetime` attribute (ISO 8601).
+pub fn time_element<L: Language>(
+    html: &HtmlAllocator, date: NaiveDate, lang: L
+) -> Result<AId<Node>> {
+    html.time([att("datetime", date)],
+              [html.string(date_format_naivedate_website(date, lang))?])
+}
+
+/// How many pages before and after the current one `pagination`
+/// links to directly.
+const PAGINATION_WINDOW: usize = 2;
+
+/// Previous and next link labels, and the label of the navigation.
+const PAGINATION_TEXTS_FOR: &[(&str, [&str; 3])] = &[
+    ("en", ["‹ Previous", "Next ›", "Pagination"]),
+    ("de", ["‹ Zurück", "Weiter ›", "Seitennavigation"]),
+];
+
+fn pagination_texts_for(lang: &str) -> [&'static str; 3] {
+    let texts = AList(PAGINATION_TEXTS_FOR);
+    *texts.get(&lang).unwrap_or_else(|| texts.get(&"en").expect("en always present"))
+}
+
+/// Navigation between the pages of a listing, for page `current` of
+/// `total` (counting from 1), linking to `url_for_page(n)`: previous
+/// and next links (disabled at the ends), the first and last page,
+/// and the pages around the current one, with an ellipsis for each
+/// gap. Labelled in `lang`. Empty if there is only one page.
+pub fn pagination<L: Language>(
+    html: &HtmlAllocator,
+    current: usize,
+    total: usize,
+    url_for_page: impl Fn(usize) -> String,
+    lang: L,
+) -> Result<AId<Node>>
+{
+    if total <= 1 {
+        return html.empty_node()
+    }
+    let [previous, next, nav_label] = pagination_texts_for(lang.as_str());
+    let current = current.clamp(1, total);
+    let prev_next = |page: Option<usize>, rel: &str, label: &'static str| {
+        html.li([], [
+            if let Some(page) = page {
+                html.a([att("href", url_for_page(page)), att("rel", rel)],
+                       [html.staticstr(label)?])?
+            } else {
+                html.span([att("class", "disabled"), att("aria-disabled", "true")],
+                          [html.staticstr(label)?])?
+            }])
+    };
+
+    let mut pages: Vec<usize> = vec![1];
+    pages.extend(current.saturating_sub(PAGINATION_WINDOW).max(2)
+                 ..= (current + PAGINATION_WINDOW).min(total - 1));
+    pages.push(total);
+
+    let mut items = html.new_vec_with_capacity(pages.len() as u32 + 4)?;
+    items.push(prev_next((current > 1).then(|| current - 1), "prev", previous)?)?;
+    let mut last_shown = 0;
+    for page in pages {
+        match page - last_shown {
+            1 => (),
+            // Show a single missing page instead of an ellipsis
+            2 => items.push(html.li([], [html.a([att("href", url_for_page(page - 1))],
+                                                [html.string((page - 1).to_string())?])?])?)?,
+            _ => items.push(html.li([att("class", "ellipsis")], [html.staticstr("…")?])?)?,
+        }
+        let label = html.string(page.to_string())?;
+        items.push(html.li([], [
+            if page == current {
+                html.span([att("aria-current", "page")], [label])?
+            } else {
+                html.a([att("href", url_for_page(page))], [label])?
+            }])?)?;
+        last_shown = page;
+    }
+    items.push(prev_next((current < total).then(|| current + 1), "next", next)?)?;
+    html.nav([att("class", "pagination"), att("aria-label", nav_label)],
+             [html.ul([], items.as_slice())?])
+}
+
 pub fn show_popup_box_page<L: Language>(
     context: &AContext<L>,
     html: &HtmlAllocator,
@@ -130,13 +240,25 @@ pub fn show_popup_box_page<L: Language>(
                 box_title,
                 box_body)?,
             None,
-            None)
+            PageMeta::default())
         })?))
 }
 
 // ------------------------------------------------------------------
 // The higher-level parts, building blocks
 
+/// The optional data about a page for `LayoutInterface::page`,
+/// besides its parts.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PageMeta<'a> {
+    pub last_modified: Option<SystemTime>,
+    /// The URL for `<link rel="canonical">`, if the page is
+    /// reachable under several URLs.
+    pub canonical: Option<&'a str>,
+    /// For `<meta name="description">`, plain text.
+    pub description: Option<&'a str>,
+}
+
 pub trait LayoutInterface<L: Language>: Send + Sync {
     /// Build a whole HTML page from the given parts
     fn page(
@@ -156,13 +278,20 @@ pub trait LayoutInterface<L: Language>: Send + Sync {
         lead: Option<AId<Node>>,
         main: AId<Node>,
         footnotes: Option<AId<Node>>,
-        last_modified: Option<SystemTime>,
+        meta: PageMeta,
     ) -> Result<AId<Node>>;
 
     fn blog_index_title(
         &self,
         subpath_segments: Option<&[KString]> // path segments if below main page
     ) -> String;
+
+    /// How the headings of markdown pages are adapted to the page
+    /// (by default, shifted so that the top level is H2, as `page`
+    /// shows the title as H1).
+    fn heading_shift(&self) -> HeadingShift {
+        HeadingShift::default()
+    }
 }
 
 /// This re-parses the markdown on every request.
@@ -176,7 +305,12 @@ fn markdownprocessor<L: Language>(
     htmlresponse(html, HttpResponseStatusCode::OK200, |html| {
         let stat = path.metadata().with_context(
             || anyhow!("stat on {:?}", path.to_string_lossy()))?;
-        let mdfile = MarkdownFile::new(path);
+        let mdfile = MarkdownFile::new_with_config(path, MarkdownConfig {
+            heading_shift: style.heading_shift(),
+            image_dimensions: true,
+            quotes: context.lang().quotes(),
+            ..Default::default()
+        });
         let pmd = mdfile.process_to_html(html)?;
         let title =
             if let Some(body) = pmd.meta().title() {
@@ -197,7 +331,10 @@ fn markdownprocessor<L: Language>(
             None, // lead XX?
             pmd.fixed_html(html)?,
             None, // XX
-            Some(stat.modified()?)
+            PageMeta {
+                last_modified: Some(stat.modified()?),
+                ..Default::default()
+            }
         )
     })
 }
@@ -207,6 +344,27 @@ pub fn markdownpage_handler<L: Language + 'static>(
     file_path: &str,
     style: Arc<dyn LayoutInterface<L>>
 ) -> Arc<dyn Handler<L>>
+{
+    markdownpage_handler_(file_path, style, false)
+}
+
+/// Like `markdownpage_handler`, but the page gets a `<base href>` of
+/// its URL as a directory, for documents placed at arbitrary paths
+/// whose relative links refer to things below them (see
+/// `AContext::set_base_href` for the tradeoffs).
+pub fn markdownpage_handler_with_base_href<L: Language + 'static>(
+    file_path: &str,
+    style: Arc<dyn LayoutInterface<L>>
+) -> Arc<dyn Handler<L>>
+{
+    markdownpage_handler_(file_path, style, true)
+}
+
+fn markdownpage_handler_<L: Language + 'static>(
+    file_path: &str,
+    style: Arc<dyn LayoutInterface<L>>,
+    base_href: bool,
+) -> Arc<dyn Handler<L>>
 {
     let path = PathBuf::from(file_path);
     Arc::new(ExactFnHandler::new(
@@ -217,6 +375,9 @@ pub fn markdownpage_handler<L: Language + 'static>(
             if method.is_post() {
                 bail!("can't POST to a markdownpage"); // currently, anyway
             }
+            if base_href {
+                context.set_base_href(&context.path().as_dir().to_string());
+            }
             markdownprocessor(style.clone(), context, path.clone(), html)
                 .map(AResponse::from)
         }
@@ -230,17 +391,40 @@ pub enum DirIndexMode {
     None,
     /// If the path goes to a directory, look for an `index.md` file,
     /// if present, redirect to add a trailing `/` unless already
-    /// present, then serve as markdown.
+    /// present, then serve as markdown. If there is no `index.md`
+    /// but an `index.html` file, do the same but serve that as a
+    /// static file.
     IndexMd,
     // Future?: `Dirlisting`, show a directory listing. Or provide
     // that in a different type?
 }
 
+/// Whether `path` is a file; logs a warning if it exists but isn't
+/// one, or can't be checked.
+fn is_file_or_warn(path: &Path) -> bool {
+    match path.metadata() {
+        Ok(stat) =>
+            if stat.is_file() {
+                true
+            } else {
+                warn!("found item {path:?} but it's not a file, ignoring");
+                false
+            },
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Error getting metadata: {e:?} for path {path:?}");
+            }
+            false
+        }
+    }
+}
+
 /// Generate a markdown page from a file with suffix `.md` if
 /// `path_rest` ends with suffix `.html`, or if `indexing_mode` is
 /// `IndexMd` and `path_rest` goes to a directory then looks for an
-/// `index.md` file and serves that "after" doing a redirect to add a
-/// `/`, or otherwise decline via `Ok(None)`.
+/// `index.md` (or failing that, `index.html`) file and serves that
+/// "after" doing a redirect to add a `/`, or otherwise decline via
+/// `Ok(None)`.
 // Mess. Probably did some other versions with similar code, todo:
 // proper factoring.
 fn generate_markdown_page<L: Language + 'static>(
@@ -248,6 +432,7 @@ fn generate_markdown_page<L: Language + 'static>(
     path_rest: &PPath<KString>,
     indexing_mode: DirIndexMode,
     context: &AContext<L>,
+    method: HttpRequestMethodSimple,
     style: Arc<dyn LayoutInterface<L>>,
     html: &HtmlAllocator,
 ) -> Result<Option<Response>> {
@@ -295,35 +480,39 @@ fn generate_markdown_page<L: Language + 'static>(
         match indexing_mode {
             DirIndexMode::None => Ok(None),
             DirIndexMode::IndexMd => {
-                let mut fspath = path_append(base_path, &path_rest_string);
+                let fspath = path_append(base_path, &path_rest_string);
                 match fspath.metadata() {
                     Ok(stat) =>
                         if stat.is_file() {
                             // path is not .html nor directory, so we won't serve it
                             Ok(None)
                         } else if stat.is_dir() {
-                            fspath.push("index.md");
-                            match fspath.metadata() {
-                                Ok(stat_index_md) =>
-                                    if stat_index_md.is_file() {
-                                        if path_rest.ends_with_slash() {
-                                            Ok(Some(markdownprocessor(
-                                                style, context, fspath, html)?))
-                                        } else {
-                                            Ok(Some(
-                                                context.redirect_302_with_query(
-                                                    &context.path().as_dir())))
-                                        }
-                                    } else {
-                                        warn!("found item {:?} but it's not a file, ignoring",
-                                              &fspath);
-                                        Ok(None)
-                                    },
-                                Err(e) => {
-                                    warn!("Error getting metadata: {e:?} \
-                                           for path {:?}", &fspath);
-                                    Ok(None)
+                            // Redirect to the directory path first,
+                            // so that relative links in the page work
+                            let redirect = || Ok(Some(
+                                context.redirect_302_with_query(
+                                    &context.path().as_dir())));
+                            let index_md = fspath.join("index.md");
+                            let index_html = fspath.join("index.html");
+                            if is_file_or_warn(&index_md) {
+                                if path_rest.ends_with_slash() {
+                                    Ok(Some(markdownprocessor(
+                                        style, context, index_md, html)?))
+                                } else {
+                                    redirect()
+                                }
+                            } else if is_file_or_warn(&index_html) {
+                                if path_rest.ends_with_slash() {
+                                    let index_path = path_rest.add_segments(
+                                        &[KString::from_static("index.html")], false);
+                                    Ok(FileHandler::new(base_path)
+                                       .call(context, method, &index_path, html)?
+                                       .map(|aresponse| aresponse.response))
+                                } else {
+                                    redirect()
                                 }
+                            } else {
+                                Ok(None)
                             }
                         } else {
                             warn!("not a file nor dir: {:?}", &fspath);
@@ -386,9 +575,7 @@ pub fn unlisted_markdowndir_handler<L: Language + 'static>(
             let start: Instant = Instant::now();
             let delayed = |response: Result<Option<Response>>| -> Result<Option<AResponse>>
             {
-                let _micros: Weibull<f64> = Weibull::new(200000., 20.)?;
-                let micros: f64 = thread_rng().sample(_micros);
-                let target = start.checked_add(Duration::from_micros(micros as u64))
+                let target = start.checked_add(weibull_delay(200000., 20.)?)
                     .expect("does not fail (overflow) because we only add a second");
                 response.map(|v| v.map(|r| r.to_aresponse(Some(target))))
             };
@@ -397,6 +584,7 @@ pub fn unlisted_markdowndir_handler<L: Language + 'static>(
                                            path_rest,
                                            DirIndexMode::None,
                                            context,
+                                           method,
                                            style.clone(),
                                            html))
         }
@@ -410,7 +598,7 @@ pub fn unlisted_markdowndir_handler<L: Language + 'static>(
 /// files.
 
 /// There is no directory listing (but it does try to serve `index.md`
-/// for directories), but also no delivery delay, thus this handler is
+/// or `index.html` for directories), but also no delivery delay, thus this handler is
 /// *not* suitable for serving unlisted files!
 pub fn mixed_dir_handler<L: Language + 'static>(
     dir_path: &str,
@@ -438,6 +626,7 @@ pub fn mixed_dir_handler<L: Language + 'static>(
                                                      path_rest,
                                                      DirIndexMode::IndexMd,
                                                      context,
+                                                     method,
                                                      style.clone(),
                                                      html)?;
             Ok(match optresponse {
@@ -447,22 +636,428 @@ pub fn mixed_dir_handler<L: Language + 'static>(
         }))
 }
 
-
-fn format_naivedate(nd: NaiveDate) -> String {
-    format!("{}", nd)
-}
-
 #[cfg(test)]
 mod tests {
+    use ahtml::{flat::Flat, HtmlAllocatorPool};
+
+    use crate::{lang_en_de::Lang, website_layout::WebsiteLayout,
+                style::footnotes::BlogStyle, blog::BlogConfig,
+                test_util::{fake_context, TestResponse, TempDir}};
+
     use super::*;
 
     #[test]
-    fn t_format_naivedate() {
-        assert_eq!(format_naivedate(NaiveDate::from_ymd_opt(2022, 10, 31).unwrap()),
-                   "2022-10-31");
+    fn t_time_element() -> Result<()> {
+        use ahtml::Print;
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let t = |y, m, d, lang| time_element(
+            &html, NaiveDate::from_ymd_opt(y, m, d).unwrap(), lang)?
+            .to_html_fragment_string(&html);
+        assert_eq!(t(2022, 10, 31, Lang::En)?,
+                   r#"<time datetime="2022-10-31">October 31, 2022</time>"#);
+        assert_eq!(t(2022, 10, 31, Lang::De)?,
+                   r#"<time datetime="2022-10-31">31. Oktober 2022</time>"#);
+        assert_eq!(t(987, 1, 2, Lang::En)?,
+                   r#"<time datetime="0987-01-02">January 2, 987</time>"#);
+        Ok(())
+    }
+
+    #[test]
+    fn t_pagination() -> Result<()> {
+        use ahtml::Print;
+        let html = HtmlAllocator::new(10000, Arc::new(""));
+        let p = |current, total| pagination(&html, current, total,
+                                            |n| format!("/blog/page/{n}"), Lang::En)?
+            .to_html_fragment_string(&html);
+        let link = |n: usize| format!("<li><a href=\"/blog/page/{n}\">{n}</a></li>");
+        let current = |n: usize| format!("<li><span aria-current=\"page\">{n}</span></li>");
+        let ellipsis = "<li class=\"ellipsis\">…</li>";
+        let nav = |items: Vec<String>| format!(
+            "<nav class=\"pagination\" aria-label=\"Pagination\"><ul>{}</ul></nav>",
+            items.concat());
+
+        assert_eq!(p(1, 1)?, "");
+        assert_eq!(p(1, 0)?, "");
+        assert_eq!(
+            p(1, 10)?,
+            nav(vec![
+                "<li><span class=\"disabled\" aria-disabled=\"true\">‹ Previous</span></li>"
+                    .into(),
+                current(1), link(2), link(3), ellipsis.into(), link(10),
+                "<li><a href=\"/blog/page/2\" rel=\"next\">Next ›</a></li>".into()]));
+        assert_eq!(
+            p(6, 10)?,
+            nav(vec![
+                "<li><a href=\"/blog/page/5\" rel=\"prev\">‹ Previous</a></li>".into(),
+                link(1), ellipsis.into(), link(4), link(5), current(6), link(7), link(8),
+                link(9), link(10),
+                "<li><a href=\"/blog/page/7\" rel=\"next\">Next ›</a></li>".into()]));
+        assert!(p(3, 3)?.ends_with(
+            "<li><span class=\"disabled\" aria-disabled=\"true\">Next ›</span></li>\
+             </ul></nav>"));
+
+        let de = pagination(&html, 2, 3, |n| format!("/blog/page/{n}"), Lang::De)?
+            .to_html_fragment_string(&html)?;
+        assert!(de.starts_with(
+            "<nav class=\"pagination\" aria-label=\"Seitennavigation\"><ul>\
+             <li><a href=\"/blog/page/1\" rel=\"prev\">‹ Zurück</a></li>"), "{de}");
+        assert!(de.contains("<a href=\"/blog/page/3\" rel=\"next\">Weiter ›</a>"), "{de}");
+        Ok(())
+    }
+
+    #[test]
+    fn t_picture() -> Result<()> {
+        use ahtml::Print;
+        let html = HtmlAllocator::new(1000, Arc::new(""));
+        let img = html.img([att("src", "/a.jpg"), att("alt", "A")], [])?;
+        let pic = picture(
+            &html,
+            &[(Some("(min-width: 800px)"), "/a-wide.avif", Some("image/avif")),
+              (None, "/a.webp 1x, /a@2x.webp 2x", Some("image/webp"))],
+            img)?;
+        assert_eq!(
+            pic.to_html_fragment_string(&html)?,
+            concat!(r#"<picture><source media="(min-width: 800px)" srcset="/a-wide.avif" type="image/avif">"#,
+                    r#"<source srcset="/a.webp 1x, /a@2x.webp 2x" type="image/webp">"#,
+                    r#"<img src="/a.jpg" alt="A"></picture>"#));
+        Ok(())
+    }
+
+    fn test_layout() -> Arc<dyn LayoutInterface<Lang>> {
+        Arc::new(WebsiteLayout {
+            site_name: "Test",
+            copyright_owner: "Test",
+            nav: &[],
+            preloads: &[],
+            header_contents: Box::new(|_html| Ok(Flat::None)),
+            sibling_from_path: Box::new(|_path| None),
+        })
+    }
+
+    /// Run `handler` for a GET request to `path`.
+    fn get(handler: &Arc<dyn Handler<Lang>>, path: &str) -> Result<Option<TestResponse>> {
+        fake_context("GET", path, &[], &[]).call(handler)
+    }
+
+    #[test]
+    fn t_cache_control() -> Result<()> {
+        let public = "public, max-age=300, stale-while-revalidate=60";
+
+        let dir = TempDir::new("t_cache_control")?;
+        let mdpath = dir.join("page.md");
+        std::fs::write(&mdpath, "# Hello\n\nWorld\n")?;
+        let page = markdownpage_handler(mdpath.to_str().unwrap(), test_layout())
+            .with_cache_control(public);
+        let r = get(&page, "/page")?.expect("handled");
+        assert_eq!(r.status_code, 200);
+        assert_eq!(r.header("Cache-Control"), Some(public));
+
+        // Pages depending on the session are not stored, even when
+        // mounted below a handler with a public cache setting
+        let login = login_handler(test_layout());
+        let r = get(&login, "/login")?.expect("handled");
+        assert_eq!(r.status_code, 200);
+        assert_eq!(r.header("Cache-Control"), Some(CACHE_CONTROL_NO_STORE));
+        let r = get(&login.with_cache_control(public), "/login")?.expect("handled");
+        assert_eq!(r.header("Cache-Control"), Some(CACHE_CONTROL_NO_STORE));
+
+        // `no_store` (as used by `restricted_to_group`) replaces a
+        // public setting from further inside
+        let restricted = markdownpage_handler("/nonexistent.md", test_layout())
+            .with_cache_control(public)
+            .no_store();
+        assert!(get(&restricted, "/x").is_err());
+        let restricted: Arc<dyn Handler<Lang>> = Arc::new(FnHandler::new(
+            |_context, _method, _path, _html| -> Result<Option<AResponse>> {
+                Ok(Some(Response::text("secret").into()))
+            }));
+        let r = get(&restricted.with_cache_control(public).no_store(), "/x")?
+            .expect("handled");
+        assert_eq!(r.headers.iter()
+                   .filter(|(k, _)| k == "Cache-Control").count(),
+                   1);
+        assert_eq!(r.header("Cache-Control"), Some(CACHE_CONTROL_NO_STORE));
+        Ok(())
+    }
+
+    #[test]
+    fn t_base_href() -> Result<()> {
+        let dir = TempDir::new("t_base_href")?;
+        let mdpath = dir.join("page.md");
+        std::fs::write(&mdpath, "# Hello\n\n![Photo](photo.jpg)\n")?;
+        let mdpath = mdpath.to_str().unwrap();
+
+        let page = markdownpage_handler_with_base_href(mdpath, test_layout());
+        let body = get(&page, "/docs/a/b/page")?.expect("handled").body;
+        assert!(body.contains("<head><base href=\"/docs/a/b/page/\"><title>"), "{body}");
+        assert!(body.contains("<img src=\"photo.jpg\""), "{body}");
+
+        let page = markdownpage_handler(mdpath, test_layout());
+        let body = get(&page, "/docs/a/b/page")?.expect("handled").body;
+        assert!(! body.contains("<base"), "{body}");
+        Ok(())
+    }
+
+    #[test]
+    fn t_strict_accept() -> Result<()> {
+        let dir = TempDir::new("t_strict_accept")?;
+        let mdpath = dir.join("page.md");
+        std::fs::write(&mdpath, "# Hello\n")?;
+        let page = markdownpage_handler(mdpath.to_str().unwrap(), test_layout())
+            .strict_accept(&["text/html"]);
+        let status = |accept: &[(&str, &str)]| -> Result<u16> {
+            Ok(fake_context("GET", "/page", accept, &[]).call(&page)?
+               .expect("handled").status_code)
+        };
+        assert_eq!(status(&[("Accept", "application/json")])?, 406);
+        assert_eq!(status(&[("Accept", "text/html,application/xhtml+xml,*/*;q=0.8")])?, 200);
+        assert_eq!(status(&[("Accept", "*/*")])?, 200);
+        assert_eq!(status(&[])?, 200);
+        Ok(())
+    }
+
+    #[test]
+    fn t_account_handler() -> Result<()> {
+        use crate::access_control::{db::set_thread_db, statements_and_methods::Db};
+
+        let dir = TempDir::new("t_account_handler")?;
+        let path = dir.join("accounts.db");
+        sqlite::open(&path)?.execute(include_str!("../accounts-schema.sql"))?;
+        set_thread_db(Arc::new(Db::new(path.to_str().expect("temp dir is UTF-8"))));
+        access_control_transaction(true, |trans| -> Result<()> {
+            trans.insert_user(&User {
+                id: None,
+                username: "alice".try_into()?,
+                email: Some("alice@example.com".into()),
+                name: "Alice".into(),
+                surname: "Smith".into(),
+                hashed_pass: "x".into(),
+            })?;
+            let user = trans.get_user_by_username("alice")?.expect("inserted");
+            trans.insert_sessiondata(&SessionData::new(
+                None, "alicesession", now_unixtime(), user.id, None, blake3::Hasher::new()))?;
+            Ok(())
+        })?;
+
+        let account = account_handler(test_layout());
+        let r = fake_context("GET", "/account", &[], &[]).call(&account)?.expect("handled");
+        assert_eq!(r.status_code, 302);
+        assert_eq!(r.header("Location"), Some("/login?return_path=%2Faccount"));
+        assert_eq!(r.header("Cache-Control"), Some(CACHE_CONTROL_NO_STORE));
+
+        let r = fake_context("GET", "/account", &[("Cookie", "sid=alicesession")], &[])
+            .call(&account)?.expect("handled");
+        assert_eq!(r.status_code, 200);
+        assert!(r.body.contains("<tr><th>Username:</th><td>alice</td></tr>\
+                                 <tr><th>Name:</th><td>Alice Smith</td></tr>\
+                                 <tr><th>Email:</th><td>alice@example.com</td></tr>"),
+                "{}", r.body);
+        assert_eq!(r.header("Cache-Control"), Some(CACHE_CONTROL_NO_STORE));
+
+        let r = fake_context("GET", "/account", &[("Cookie", "sid=alicesession; lang=de")], &[])
+            .call(&account)?.expect("handled");
+        assert!(r.body.contains("<tr><th>Benutzername:</th><td>alice</td></tr>"),
+                "{}", r.body);
+        Ok(())
+    }
+
+    #[test]
+    fn t_language_handler() -> Result<()> {
+        let handler = language_handler::<Lang>();
+        let r = fake_context("GET", "/", &[], &[]).call(&handler)?.expect("handled");
+        assert_eq!(r.status_code, 302);
+        assert_eq!(r.location(), Some("/en.html"));
+
+        // Language from the browser, query string kept
+        let r = fake_context("GET", "/", &[("Accept-Language", "de-CH, de;q=0.9")],
+                             &[("q", "a b")])
+            .call(&handler)?.expect("handled");
+        assert_eq!(r.location(), Some("/de.html?q=a%20b"));
+
+        // The cookie overrides the browser setting
+        let r = fake_context("GET", "/", &[("Accept-Language", "de"),
+                                           ("Cookie", "lang=en")],
+                             &[])
+            .call(&handler)?.expect("handled");
+        assert_eq!(r.location(), Some("/en.html"));
+        Ok(())
+    }
+
+    #[test]
+    fn t_mixed_dir_index_html() -> Result<()> {
+        let dir = TempDir::new("t_mixed_dir_index_html")?;
+        std::fs::create_dir_all(dir.join("static"))?;
+        std::fs::create_dir_all(dir.join("empty"))?;
+        std::fs::write(dir.join("static/index.html"), "<p>static</p>")?;
+        let handler = mixed_dir_handler(dir.to_str().unwrap(), test_layout());
+        let get = |path: &str| {
+            fake_context("GET", &format!("/{path}"), &[], &[])
+                .call_with_pathrest(&handler, path)
+        };
+
+        let r = get("static")?.expect("handled");
+        assert_eq!(r.status_code, 302);
+        assert_eq!(r.location(), Some("/static/"));
+        let r = get("static/")?.expect("handled");
+        assert_eq!(r.status_code, 200);
+        assert_eq!(r.body, "<p>static</p>");
+        assert!(r.header("Content-type").unwrap().starts_with("text/html"));
+
+        assert!(get("empty")?.is_none());
+        assert!(get("empty/")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn t_blog_index_teaser() -> Result<()> {
+        let dir = TempDir::new("t_blog_index_teaser")?;
+        std::fs::create_dir_all(dir.join("2023/10/23"))?;
+        std::fs::write(dir.join("2023/10/23/post.md"),
+                       "# Post\n\nThe lead of the post, über *long*.\n\n## Main\n\nText.\n")?;
+        let allocpool: &'static HtmlAllocatorPool = Box::leak(Box::new(
+            HtmlAllocatorPool::new(100000, true, Arc::new("t_blog_index_teaser"))));
+        let index = |index_teaser| -> Result<String> {
+            let blog = Blog::open_with_config(
+                dir.to_path_buf(), allocpool, Arc::new(BlogStyle::default()),
+                BlogConfig { index_teaser, ..Default::default() })?;
+            let handler = blog_handler(blog, test_layout());
+            Ok(get(&handler, "/blog/")?.expect("handled").body)
+        };
+
+        let body = index(BlogIndexTeaser::PlainText(26))?;
+        assert!(body.contains("<p class=\"teaser\">The lead of the post, über…</p>"),
+                "{body}");
+        let body = index(BlogIndexTeaser::PlainText(100))?;
+        assert!(body.contains("<p class=\"teaser\">The lead of the post, über long.</p>"),
+                "{body}");
+        let body = index(BlogIndexTeaser::Lead)?;
+        assert!(body.contains("<div class=\"teaser\"><div><p>The lead of the post, \
+                               über <em>long</em>.</p></div></div>"),
+                "{body}");
+        let body = index(BlogIndexTeaser::None)?;
+        assert!(! body.contains("teaser"), "{body}");
+        Ok(())
+    }
+
+    #[test]
+    fn t_blog_post_etag() -> Result<()> {
+        let dir = TempDir::new("t_blog_post_etag")?;
+        std::fs::create_dir_all(dir.join("2023/10/23"))?;
+        std::fs::write(dir.join("2023/10/23/post.md"), "# Post\n\nText.\n")?;
+        let allocpool: &'static HtmlAllocatorPool = Box::leak(Box::new(
+            HtmlAllocatorPool::new(100000, true, Arc::new("t_blog_post_etag"))));
+        let blog = Blog::open(dir.to_path_buf(), allocpool, Arc::new(BlogStyle::default()))?;
+        let handler = blog_handler(blog, test_layout());
+        let get = |headers: &[(&str, &str)]| {
+            fake_context("GET", "/blog/2023/10/23/post.html", headers, &[])
+                .call_with_pathrest(&handler, "2023/10/23/post.html")
+                .map(|r| r.expect("handled"))
+        };
+
+        let r = get(&[])?;
+        assert_eq!(r.status_code, 200);
+        let etag = r.header("ETag").expect("has ETag").to_string();
+        assert!(etag.starts_with("W/\"") && etag.ends_with("-en\""), "{etag}");
+
+        let r = get(&[("If-None-Match", &etag)])?;
+        assert_eq!(r.status_code, 304);
+        assert_eq!(r.body, "");
+        assert_eq!(r.header("ETag"), Some(etag.as_str()));
+
+        let r = get(&[("If-None-Match", "W/\"other\"")])?;
+        assert_eq!(r.status_code, 200);
+        assert!(r.body.contains(">October 23, 2023</time>"), "{}", r.body);
+
+        let r = get(&[("Accept-Language", "de")])?;
+        assert_eq!(r.status_code, 200);
+        assert!(r.body.contains(">23. Oktober 2023</time>"), "{}", r.body);
+        assert_ne!(r.header("ETag"), Some(etag.as_str()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn t_blog_post_aliases() -> Result<()> {
+        let dir = TempDir::new("t_blog_post_aliases")?;
+        std::fs::create_dir_all(dir.join("2023/10/23"))?;
+        std::fs::write(dir.join("2023/10/23/post.md"),
+                       "---\npermalink: /old/name.html\naliases: [2023/10/23/x.html]\n---\n\
+                        # Post\n\nText.\n")?;
+        let allocpool: &'static HtmlAllocatorPool = Box::leak(Box::new(
+            HtmlAllocatorPool::new(100000, true, Arc::new("t_blog_post_aliases"))));
+        let blog = Blog::open(dir.to_path_buf(), allocpool, Arc::new(BlogStyle::default()))?;
+        let handler = blog_handler(blog, test_layout());
+        let get = |pathrest: &str| {
+            fake_context("GET", &format!("/blog/{pathrest}"), &[], &[])
+                .call_with_pathrest(&handler, pathrest)
+        };
+
+        for alias in ["old/name.html", "2023/10/23/x.html"] {
+            let r = get(alias)?.expect("handled");
+            assert_eq!(r.status_code, 301);
+            assert_eq!(r.location(), Some("/blog/2023/10/23/post.html"));
+        }
+        let r = get("2023/10/23/post.html")?.expect("handled");
+        assert_eq!(r.status_code, 200);
+        assert!(! r.body.contains("permalink"), "{}", r.body);
+        assert!(get("old")?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn t_blog_post_canonical() -> Result<()> {
+        let dir = TempDir::new("t_blog_post_canonical")?;
+        std::fs::create_dir_all(dir.join("2023/10/23"))?;
+        std::fs::write(dir.join("2023/10/23/post.md"), "# Post\n\nText.\n")?;
+        let allocpool: &'static HtmlAllocatorPool = Box::leak(Box::new(
+            HtmlAllocatorPool::new(100000, true, Arc::new("t_blog_post_canonical"))));
+        let blog = Blog::open_with_config(
+            dir.to_path_buf(), allocpool, Arc::new(BlogStyle::default()),
+            BlogConfig {
+                site_base: Some("https://example.com/".into()),
+                ..Default::default()
+            })?;
+        let handler = blog_handler(blog, test_layout());
+        let canonical = "<link rel=\"canonical\" \
+                         href=\"https://example.com/blog/2023/10/23/post.html\">";
+        for pathrest in ["2023/10/23/post.html", "2023/10/23/post.html/"] {
+            let r = fake_context("GET", &format!("/blog/{pathrest}"), &[], &[])
+                .call_with_pathrest(&handler, pathrest)?.expect("handled");
+            assert_eq!(r.status_code, 200);
+            assert!(r.body.contains(canonical), "{}", r.body);
+        }
+
+        Ok(())
     }
 }
 
+/// The teaser for `blogpost` in the blog index, as configured.
+fn blog_index_teaser(
+    html: &HtmlAllocator, teaser: BlogIndexTeaser, blogpost: &BlogPost
+) -> Result<AId<Node>> {
+    match (teaser, &blogpost.lead, &blogpost.lead_plain) {
+        (BlogIndexTeaser::Lead, Some(lead), _) =>
+            html.div([att("class", "teaser")], [html.preserialized(lead)?]),
+        (BlogIndexTeaser::PlainText(max_chars), _, Some(lead_plain)) =>
+            html.p([att("class", "teaser")],
+                   [html.string(str_truncate_ellipsis(lead_plain, max_chars).into_owned())?]),
+        _ => html.empty_node(),
+    }
+}
+
+/// The absolute path of `segments` below the path the blog is
+/// mounted at, for a request with `pathrest` below the mount point.
+fn blog_url<L: Language>(
+    context: &AContext<L>, pathrest: &PPath<KString>, segments: &[KString]
+) -> String {
+    let request_segments = context.path().segments();
+    let mut path: Vec<KString> =
+        request_segments[..request_segments.len() - pathrest.segments().len()].to_vec();
+    path.extend(segments.iter().cloned());
+    PPath::new(true, false, path).to_string()
+}
+
 pub fn blog_handler<L: Language + 'static>(
     blog: Arc<Blog>, style: Arc<dyn LayoutInterface<L>>
 ) -> Arc<dyn Handler<L>>
@@ -482,31 +1077,76 @@ pub fn blog_handler<L: Language + 'static>(
             }
             let with_slash = context.path().ends_with_slash();
             let blogcache = blog.blogcache();
-            if let Some(trie) = blogcache.router.get_trie(path) {
-                let blognode = trie.endpoint().expect(
-                    "every trie node in a blog trie has an endpoint");
+            // (Nodes in the middle of alias paths have no endpoint.)
+            if let Some(blognode) = blogcache.router.get_trie(path).and_then(
+                |trie| trie.endpoint())
+            {
                 match blognode {
+                    BlogNode::Redirect(target) => {
+                        Ok(Some(Response::redirect_301(
+                            blog_url(context, path, target)).into()))
+                    }
                     BlogNode::BlogPost(blogpost) => {
                         nodt!("blogpost", pathrest);
                         
                         // an individual post; XX check that the part of
                         // the path used contains the date?
+
+                        // Weak since the layout around the post could
+                        // differ (e.g. the copyright year); it
+                        // depends on the language, too.
+                        let etag = format!("W/\"{}-{}\"",
+                                           blogpost.etag, context.lang().as_str());
+                        let etag_header = || (Cow::from("ETag"), Cow::from(etag.clone()));
+                        match check_preconditions(context, method, Some(&etag),
+                                                  Some(blogpost.modified())) {
+                            Precondition::Satisfied => (),
+                            Precondition::NotModified => {
+                                return Ok(Some(Response {
+                                    status_code: HttpResponseStatusCode::NotModified304.code(),
+                                    headers: vec![etag_header()],
+                                    data: ResponseBody::empty(),
+                                    upgrade: None,
+                                }.into()))
+                            }
+                            Precondition::Failed => {
+                                return Ok(Some(errorpage_from_status(
+                                    HttpResponseStatusCode::PreconditionFailed412).into()))
+                            }
+                        }
+
                         let head_title = html.kstring(blogpost.title_plain.clone())?;
                         let title = html.preserialized(&blogpost.title_html)?;
                         let toc = html.preserialized(&blogpost.toc)?;
-                        let lead = blogpost.lead.as_ref()
-                            .map(|a| html.preserialized(a)).transpose()?;
+                        let date = html.p([att("class", "publish_date")],
+                                          [time_element(html, blogpost.publish_date,
+                                                        context.lang())?])?;
+                        let lead = Some(
+                            if let Some(lead) = &blogpost.lead {
+                                html.div([], [date, html.preserialized(lead)?])?
+                            } else {
+                                date
+                            });
                         let main = html.preserialized(&blogpost.main)?;
                         let opt_footnotes =
                             if blogpost.num_footnotes > 0 {
-                                Some(html.preserialized(&blogpost.footnotes)?)
+                                Some(blog.style().footnotes_section(
+                                    html.preserialized(&blogpost.footnotes)?,
+                                    context.lang().as_str(),
+                                    html)?)
                             } else {
                                 None
                             };
                         let breadcrumb =
                             html.preserialized(blogpost.breadcrumb.with_slash(
                                 with_slash))?;
-                        let resp =
+                        // Without trailing slash, regardless of the request
+                        let canonical = format!(
+                            "{}{}",
+                            blog.config().site_base.as_deref().unwrap_or("")
+                                .trim_end_matches('/'),
+                            blog_url(context, path, path.segments()));
+                        let mut resp =
                             htmlresponse(html, HttpResponseStatusCode::OK200, |html| {
                                 Ok(style.page(
                                     context,
@@ -518,17 +1158,25 @@ pub fn blog_handler<L: Language + 'static>(
                                     lead,
                                     main,
                                     opt_footnotes,
-                                    Some(blogpost.modified())
+                                    PageMeta {
+                                        last_modified: Some(blogpost.modified()),
+                                        canonical: Some(&canonical),
+                                        description: (! blogpost.description.is_empty())
+                                            .then_some(&*blogpost.description),
+                                    }
                                 )?)
                             })?;
+                        resp.headers.push(etag_header());
                         Ok(Some(resp.into()))
                     }
-                    BlogNode::BlogPostIndex(BlogPostIndex { breadcrumb }) => {
+                    BlogNode::BlogPostIndex(BlogPostIndex { breadcrumb, .. }) => {
                         nodt!("blog index");
-                        let iter = trie.iter(true,
-                                             TrieIterReportStyle::BeforeRecursing);
-                        let resp =
-                            htmlresponse(html, HttpResponseStatusCode::OK200, |html| {
+                        let prefix = path.segments();
+                        // Can be large, hence streamed, from an
+                        // allocator of our own as the response
+                        // outlives `html`
+                        let resp = htmlresponse_streaming(
+                            blog.allocpool().get(), HttpResponseStatusCode::OK200, |html| {
                                 let (archivetitle, breadcrumb) =
                                     if let Some(breadcrumb) = breadcrumb {
                                         (
@@ -554,46 +1202,44 @@ pub fn blog_handler<L: Language + 'static>(
                                     None, // lead
                                     html.ul(
                                         [],
-                                        iter.filter_map(
-                                            |(path1, trie)| -> Option<Result<AId<Node>>> {
-                                                let r: Result<Option<AId<Node>>> = try_result!{
-                                                    let blognode =
-                                                        trie.endpoint().expect(
-                                                            "every trie node in a blog trie \
-                                                             has an endpoint");
-                                                    let blogpost =
-                                                        match blognode {
-                                                            BlogNode::BlogPost(p) => p,
-                                                            BlogNode::BlogPostIndex(_) => {
-                                                                return Ok(None)
-                                                            }
-                                                       };
-
-                                                    let datestr =
-                                                        format_naivedate(
-                                                            blogpost.publish_date);
-                                                    let url =
-                                                        request_resolve_relative(
-                                                            context,
-                                                            PPath::new(false, false,
-                                                                       path1));
-                                                    Ok(Some(html.li(
+                                        blogcache.posts_sorted().into_iter().filter_map(
+                                            |(path1, blogpost)| -> Option<Result<AId<Node>>> {
+                                                // Only the posts below this index
+                                                if ! (path1.len() > prefix.len()
+                                                      && path1.iter().zip(prefix).all(
+                                                          |(a, b)| *a == b.as_str())) {
+                                                    return None
+                                                }
+                                                let url =
+                                                    request_resolve_relative(
+                                                        context,
+                                                        PPath::new(false, false,
+                                                                   path1[prefix.len()..]
+                                                                   .to_vec()));
+                                                Some(try_result!{
+                                                    html.li(
                                                         [],
                                                         [
-                                                            html.str(&datestr)?,
+                                                            time_element(
+                                                                html,
+                                                                blogpost.publish_date,
+                                                                context.lang())?,
                                                             html.str(" - ")?,
                                                             html.a(
                                                                 [att("href", &url)],
                                                                 [
                                                                     html.preserialized(
                                                                         &blogpost.title_html)?
-                                                                ])?
-                                                        ])?))
-                                                };
-                                                r.transpose()
+                                                                ])?,
+                                                            blog_index_teaser(
+                                                                html,
+                                                                blog.config().index_teaser,
+                                                                blogpost)?
+                                                        ])
+                                                })
                                             }).try_collect_body(html)?)?,
                                     None,
-                                    None)
+                                    PageMeta::default())
                             })?;
                         Ok(Some(resp.into()))
                     }
@@ -655,11 +1301,11 @@ fn show_login_form<L: Language>(
 /// username/password, verifying that securely (allowing for retries)
 /// and storing a successful login in the database, then redirecting
 /// back to the path that's stored in the `return_path` query string
-/// parameter.
+/// parameter. Responses are `Cache-Control: private, no-store`.
 pub fn login_handler<L: Language + 'static>(
     style: Arc<dyn LayoutInterface<L>>
 ) -> Arc<dyn Handler<L>> {
-    Arc::new(FnHandler::new(
+    let handler: Arc<dyn Handler<L>> = Arc::new(FnHandler::new(
         move |
         context: &AContext<L>,
         method: HttpRequestMethodSimple,
@@ -696,9 +1342,7 @@ pub fn login_handler<L: Language + 'static>(
                 let start: Instant = Instant::now();
                 let delayed = |response: Result<Option<Response>>| -> Result<Option<AResponse>>
                 {
-                    let _micros: Weibull<f64> = Weibull::new(1100000., 20.)?;
-                    let micros: f64 = thread_rng().sample(_micros);
-                    let target = start.checked_add(Duration::from_micros(micros as u64))
+                    let target = start.checked_add(weibull_delay(1100000., 20.)?)
                         .expect("does not fail (overflow) because we only add a second");
                     response.map(|v| v.map(|r| r.to_aresponse(Some(target))))
                 };
@@ -785,18 +1429,89 @@ pub fn login_handler<L: Language + 'static>(
                                     context.get_param("username"),
                                     context.get_param("return_path")))
             }
+        }));
+    handler.no_store()
+}
+
+
+/// Setting the `Cache-Control` header on the responses of a handler.
+pub trait CacheControlled<L: Language> {
+    /// Set `Cache-Control` to `value` (e.g. `public, max-age=300,
+    /// stale-while-revalidate=60`) on successful responses (2xx,
+    /// 304). Responses that already carry the header are left alone,
+    /// so that e.g. a restricted handler mounted below stays
+    /// `no-store`.
+    fn with_cache_control(self, value: &str) -> Self;
+
+    /// Set `Cache-Control: private, no-store` on all responses,
+    /// replacing any other value. For pages depending on the session.
+    fn no_store(self) -> Self;
+}
+
+impl<L: Language + 'static> CacheControlled<L> for Arc<dyn Handler<L>> {
+    fn with_cache_control(self, value: &str) -> Self {
+        let value = value.to_string();
+        Arc::new(FnHandler::new(move |context, method, path, html| -> Result<Option<AResponse>> {
+            let mut r = self.call(context, method, path, html)?;
+            if let Some(aresponse) = &mut r {
+                if aresponse.is_success() && aresponse.cache_control().is_none() {
+                    aresponse.set_cache_control(&value);
+                }
+            }
+            Ok(r)
         }))
+    }
+
+    fn no_store(self) -> Self {
+        Arc::new(FnHandler::new(move |context, method, path, html| -> Result<Option<AResponse>> {
+            let mut r = self.call(context, method, path, html)?;
+            if let Some(aresponse) = &mut r {
+                aresponse.set_cache_control(CACHE_CONTROL_NO_STORE);
+            }
+            Ok(r)
+        }))
+    }
 }
 
 
-/// Tie via GroupId: requires that Ids are never re-used in the
-/// database! XX double-check sqlite.
+/// Strict content negotiation for a handler.
+pub trait AcceptChecked<L: Language> {
+    /// Respond with `406 Not Acceptable` to requests whose `Accept`
+    /// header excludes all of the `producible` media types (see
+    /// `negotiate_content_type`), instead of calling the handler.
+    /// Without this, clients get whatever the handler produces.
+    fn strict_accept(self, producible: &[&str]) -> Self;
+}
+
+impl<L: Language + 'static> AcceptChecked<L> for Arc<dyn Handler<L>> {
+    fn strict_accept(self, producible: &[&str]) -> Self {
+        let producible: Vec<String> = producible.iter().map(|s| s.to_string()).collect();
+        Arc::new(FnHandler::new(move |context, method, path, html| -> Result<Option<AResponse>> {
+            let producible: Vec<&str> = producible.iter().map(|s| s.as_str()).collect();
+            if negotiate_content_type(context.header("Accept"), &producible).is_none() {
+                return Ok(Some(errorpage_from_status(
+                    HttpResponseStatusCode::NotAcceptable406).into()))
+            }
+            self.call(context, method, path, html)
+        }))
+    }
+}
+
+
+/// Access control for a handler, based on the logged-in user. Not
+/// logged-in users are redirected to `/login`. The resulting
+/// handler's responses are `Cache-Control: private, no-store`.
 pub trait Restricted<L: Language> {
+    /// Only for members of the group. Tie via GroupId: requires that
+    /// Ids are never re-used in the database! XX double-check sqlite.
     fn restricted_to_group(
         self,
         group: GroupId,
         style: Arc<dyn LayoutInterface<L>>,
     ) -> Self;
+
+    /// For any logged-in user.
+    fn require_login(self) -> Self;
 }
 
 enum LoginState {
@@ -805,61 +1520,140 @@ enum LoginState {
     Allowed
 }
 
-impl<L: Language + 'static> Restricted<L> for Arc<dyn Handler<L>> {
-    fn restricted_to_group(
-        self,
-        group_id: GroupId,
-        style: Arc<dyn LayoutInterface<L>>,
-    ) -> Self {
+/// Redirect to `/login`, coming back to the current path and query
+/// afterwards.
+fn login_redirect<L: Language>(context: &AContext<L>) -> Result<Response> {
+    let mut params = context.params()?;
+    params.push(("return_path".into(),
+                 KString::from_ref(context.path_str())));
+    let target = AUriLocal::from_str(
+        "/login",
+        Some(params));
+    Ok(Response::redirect_302(String::from(target)))
+}
+
+/// Call `handler` if the session's user is `allowed`. `style` is for
+/// the page shown otherwise.
+fn restricted<L: Language + 'static>(
+    handler: Arc<dyn Handler<L>>,
+    style: Option<Arc<dyn LayoutInterface<L>>>,
+    allowed: impl Fn(&mut Transaction, UserId) -> Result<bool> + Send + Sync + 'static,
+) -> Arc<dyn Handler<L>> {
+    let restricted: Arc<dyn Handler<L>> =
         Arc::new(FnHandler::new(move |context, method, path, html| -> Result<Option<AResponse>> {
-            let session = context.session();
-            // if ! session.client_has_sid() {
-            //     todo!()
-            // }
-            let hash = sessionid_hash(context.sessionid_hasher(), session.id());
-            let state = access_control_transaction(true, move |trans| -> Result<_> {
-                if let Some(mut sessiondata) = notime!{
-                    "get_sessiondata_by_sessionid";
-                    trans.get_sessiondata_by_sessionid_hash(&hash)}?
-                {
-                    if let Some(user_id) = sessiondata.user_id {
-                        if trans.user_in_group(user_id, group_id)? {
-                            // Update timestamp; OK to only update it here?
-                            sessiondata.last_request_time = now_unixtime();
-                            trans.update_sessiondata(&sessiondata)?;
-                            Ok(LoginState::Allowed)
-                        } else {
-                            Ok(LoginState::NotAllowed)
-                        }
+        let session = context.session();
+        // if ! session.client_has_sid() {
+        //     todo!()
+        // }
+        let hash = sessionid_hash(context.sessionid_hasher(), session.id());
+        let state = access_control_transaction(true, |trans| -> Result<_> {
+            if let Some(mut sessiondata) = notime!{
+                "get_sessiondata_by_sessionid";
+                trans.get_sessiondata_by_sessionid_hash(&hash)}?
+            {
+                if let Some(user_id) = sessiondata.user_id {
+                    if allowed(trans, user_id)? {
+                        // Update timestamp; OK to only update it here?
+                        sessiondata.last_request_time = now_unixtime();
+                        trans.update_sessiondata(&sessiondata)?;
+                        Ok(LoginState::Allowed)
                     } else {
-                        Ok(LoginState::NeedLogin)
+                        Ok(LoginState::NotAllowed)
                     }
                 } else {
                     Ok(LoginState::NeedLogin)
                 }
-            })?;
-            match state {
-                LoginState::NeedLogin => {
-                    let mut params = context.params()?;
-                    params.push(("return_path".into(),
-                                 KString::from_ref(context.path_str())));
-                    let target = AUriLocal::from_str(
-                        "/login",
-                        Some(params));
-                    Ok(Some(Response::redirect_302(String::from(target)).into()))
-                }
-                LoginState::NotAllowed => {
+            } else {
+                Ok(LoginState::NeedLogin)
+            }
+        })?;
+        match state {
+            LoginState::NeedLogin => Ok(Some(login_redirect(context)?.into())),
+            LoginState::NotAllowed => {
+                if let Some(style) = &style {
                     show_popup_box_page(
-                        context, html, &style,
+                        context, html, style,
                         PopupBoxKind::Error(HttpResponseStatusCode::Forbidden403),
                         html.str("Permission denied")?,
                         html.str("You are not allowed to access this resource.")?,
                     ).map(|o| o.map(AResponse::from))
+                } else {
+                    Ok(Some(errorpage_from_status(
+                        HttpResponseStatusCode::Forbidden403).into()))
                 }
-                LoginState::Allowed => self.call(context, method, path, html)
             }
-        }))
+            LoginState::Allowed => handler.call(context, method, path, html)
+        }
+    }));
+    restricted.no_store()
+}
+
+impl<L: Language + 'static> Restricted<L> for Arc<dyn Handler<L>> {
+    fn restricted_to_group(
+        self,
+        group_id: GroupId,
+        style: Arc<dyn LayoutInterface<L>>,
+    ) -> Self {
+        restricted(self, Some(style),
+                   move |trans, user_id| trans.user_in_group(user_id, group_id))
     }
+
+    fn require_login(self) -> Self {
+        restricted(self, None, |_trans, _user_id| Ok(true))
+    }
+}
+
+/// The texts of the account page: title, and the labels for
+/// username, name and email.
+const ACCOUNT_TEXTS_FOR: &[(&str, [&str; 4])] = &[
+    ("en", ["Your account", "Username:", "Name:", "Email:"]),
+    ("de", ["Ihr Konto", "Benutzername:", "Name:", "E-Mail:"]),
+];
+
+fn account_texts_for(lang: &str) -> [&'static str; 4] {
+    let texts = AList(ACCOUNT_TEXTS_FOR);
+    *texts.get(&lang).unwrap_or_else(|| texts.get(&"en").expect("en always present"))
+}
+
+/// Handler showing the logged-in user's account data (usually at
+/// "/account"); redirects to the login page if not logged in.
+/// Responses are `Cache-Control: private, no-store`.
+pub fn account_handler<L: Language + 'static>(
+    style: Arc<dyn LayoutInterface<L>>
+) -> Arc<dyn Handler<L>> {
+    let handler: Arc<dyn Handler<L>> = Arc::new(ExactFnHandler::new(
+        move |
+        context: &AContext<L>,
+        _method: HttpRequestMethodSimple,
+        html: &HtmlAllocator
+            | -> Result<AResponse>
+        {
+            let hash = sessionid_hash(context.sessionid_hasher(), context.session().id());
+            // The session may have been logged out since
+            // `require_login` checked it
+            let Some(User { username, email, name, surname, .. }) =
+                access_control_transaction(false, |trans| {
+                    trans.get_user_by_sessionid_hash(&hash)
+                })? else {
+                    return Ok(login_redirect(context)?.into())
+                };
+            let [title, username_label, name_label, email_label] =
+                account_texts_for(context.lang().as_str());
+            let row = |label: &'static str, value: String| {
+                html.tr([], [html.th([], label)?, html.td([], [html.string(value)?])?])
+            };
+            Ok(htmlresponse(html, HttpResponseStatusCode::OK200, |html| {
+                let title = html.str(title)?;
+                let main = html.table(
+                    [att("class", "account")],
+                    [row(username_label, username.to_string())?,
+                     row(name_label, format!("{name} {surname}").trim().into())?,
+                     row(email_label, email.unwrap_or_default())?])?;
+                style.page(context, html, Some(title), Some(title),
+                           None, None, None, main, None, PageMeta::default())
+            })?.into())
+        }));
+    handler.require_login()
 }
 
 
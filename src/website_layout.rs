@@ -1,18 +1,16 @@
 //! Concrete layout for bin/website.rs (implements webparts' `LayoutInterface`)
 
-use std::time::SystemTime;
-
 use anyhow::Result;
 use chrono::Utc;
 use kstring::KString;
 
-use ahtml::{HtmlAllocator, AId, Node, ToASlice, att, flat::Flat};
+use ahtml::{HtmlAllocator, AId, Node, ToASlice, AVec, att, opt_att, flat::Flat};
 use chj_util::warn;
 
-use crate::{webparts::LayoutInterface,
+use crate::{webparts::{LayoutInterface, PageMeta},
             acontext::AContext,
             
-            nav::{Nav, ToHtml},
+            nav::{Nav, render_nav},
             time_util::LocalYear,
             alist::AList,
             ppath::PPath,
@@ -38,14 +36,237 @@ fn last_modified_for(lang: &str) -> &'static str {
     lmf.get(&lang).unwrap_or_else(|| lmf.get(&"en").expect("en always present"))
 }
 
+/// The kind of a preloaded resource, for the `as` attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreloadAs {
+    Style,
+    /// Also sets `crossorigin`, as fonts are always fetched in CORS
+    /// mode (the preload is not used otherwise).
+    Font,
+    Script,
+    Image,
+}
+
+impl PreloadAs {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PreloadAs::Style => "style",
+            PreloadAs::Font => "font",
+            PreloadAs::Script => "script",
+            PreloadAs::Image => "image",
+        }
+    }
+}
+
+/// A resource for a `<link rel="preload">` hint, see
+/// `HeadBuilder::preload`.
+#[derive(Debug, Clone, Copy)]
+pub struct Preload<'p> {
+    pub href: &'p str,
+    pub as_: PreloadAs,
+    /// MIME type (e.g. "font/woff2"), lets browsers skip resources
+    /// in formats they don't support.
+    pub type_: Option<&'p str>,
+}
+
+impl<'p> Preload<'p> {
+    /// The parameters for the equivalent `Link` header entry, see
+    /// `AContext::add_link`.
+    pub fn link_params(&self) -> Vec<(&'p str, &'p str)> {
+        let mut params = vec![("rel", "preload"), ("as", self.as_.as_str())];
+        if let Some(type_) = self.type_ {
+            params.push(("type", type_));
+        }
+        if self.as_ == PreloadAs::Font {
+            params.push(("crossorigin", "anonymous"));
+        }
+        params
+    }
+}
+
+/// Accumulates the contents of a `<head>` element. Regardless of the
+/// order of the calls, the output is ordered as: meta tags, base URL,
+/// title, preload hints, other links, stylesheets, scripts, then anything
+/// added via `push`.
+pub struct HeadBuilder<'a> {
+    html: &'a HtmlAllocator,
+    metas: AVec<'a, Node>,
+    base: Option<AId<Node>>,
+    title: Option<AId<Node>>,
+    preloads: AVec<'a, Node>,
+    links: AVec<'a, Node>,
+    stylesheets: AVec<'a, Node>,
+    scripts: AVec<'a, Node>,
+    other: AVec<'a, Node>,
+    /// CSP nonce added to scripts
+    nonce: Option<KString>,
+}
+
+impl<'a> HeadBuilder<'a> {
+    pub fn new(html: &'a HtmlAllocator) -> Self {
+        HeadBuilder {
+            html,
+            metas: html.new_vec(),
+            base: None,
+            title: None,
+            preloads: html.new_vec(),
+            links: html.new_vec(),
+            stylesheets: html.new_vec(),
+            scripts: html.new_vec(),
+            other: html.new_vec(),
+            nonce: None,
+        }
+    }
+
+    /// Set the CSP nonce for scripts added afterwards.
+    pub fn set_nonce(&mut self, nonce: Option<KString>) -> &mut Self {
+        self.nonce = nonce;
+        self
+    }
+
+    /// Set the `<title>` element body (replaces a previously set one).
+    pub fn title(&mut self, body: impl ToASlice<Node>) -> Result<&mut Self> {
+        self.title = Some(self.html.title([], body)?);
+        Ok(self)
+    }
+
+    /// `<base href=..>`, the URL that relative URLs in the document
+    /// are resolved against (replaces a previously set one), see
+    /// `AContext::set_base_href`.
+    pub fn base(&mut self, href: &str) -> Result<&mut Self> {
+        self.base = Some(self.html.base([att("href", href)], [])?);
+        Ok(self)
+    }
+
+    /// `<meta name=.. content=..>`
+    pub fn meta_name(&mut self, name: &str, content: &str) -> Result<&mut Self> {
+        self.metas.push(self.html.meta([att("name", name),
+                                        att("content", content)], [])?)?;
+        Ok(self)
+    }
+
+    /// `<link rel="preload">` hint.
+    pub fn preload(&mut self, preload: &Preload) -> Result<&mut Self> {
+        let Preload { href, as_, type_ } = *preload;
+        self.preloads.push(self.html.link([att("rel", "preload"),
+                                           att("href", href),
+                                           att("as", as_.as_str()),
+                                           opt_att("type", type_),
+                                           opt_att("crossorigin",
+                                                   (as_ == PreloadAs::Font)
+                                                   .then_some("anonymous"))],
+                                          [])?)?;
+        Ok(self)
+    }
+
+    pub fn preloads(&mut self, preloads: &[Preload]) -> Result<&mut Self> {
+        for preload in preloads {
+            self.preload(preload)?;
+        }
+        Ok(self)
+    }
+
+    /// Other `<link>` elements, e.g. `canonical` or `alternate`.
+    pub fn link(&mut self, rel: &str, href: &str, hreflang: Option<&str>) -> Result<&mut Self> {
+        self.links.push(self.html.link([att("rel", rel),
+                                        att("href", href),
+                                        opt_att("hreflang", hreflang)], [])?)?;
+        Ok(self)
+    }
+
+    pub fn stylesheet(&mut self, href: &str) -> Result<&mut Self> {
+        self.stylesheets.push(self.html.link([att("rel", "stylesheet"),
+                                              att("href", href)], [])?)?;
+        Ok(self)
+    }
+
+    /// `<script src=..>`, with the nonce if set.
+    pub fn script(&mut self, src: &str) -> Result<&mut Self> {
+        self.scripts.push(self.html.script([att("src", src),
+                                            opt_att("nonce", self.nonce.clone())],
+                                           [])?)?;
+        Ok(self)
+    }
+
+    /// Add any other element, appended after all the above.
+    pub fn push(&mut self, node: AId<Node>) -> Result<&mut Self> {
+        self.other.push(node)?;
+        Ok(self)
+    }
+
+    /// Produce the `<head>` element.
+    pub fn build(self) -> Result<AId<Node>> {
+        let html = self.html;
+        let mut body = html.new_vec();
+        body.extend_from_slice(&self.metas.as_slice(), html)?;
+        for node in [self.base, self.title].into_iter().flatten() {
+            body.push(node)?;
+        }
+        for v in [&self.preloads, &self.links, &self.stylesheets, &self.scripts,
+                  &self.other] {
+            body.extend_from_slice(&v.as_slice(), html)?;
+        }
+        html.head([], body)
+    }
+}
+
+
 pub struct WebsiteLayout<L: Language + 'static> {
     pub site_name: &'static str,
     pub copyright_owner: &'static str,
     pub nav: &'static [(L, Nav<'static>)],
+    /// Preload hints added to every page, for resources the browser
+    /// would only discover late, e.g. web fonts referenced from the
+    /// stylesheet. Not for the stylesheets in the head, which are
+    /// fetched right away anyway.
+    pub preloads: &'static [Preload<'static>],
     pub header_contents: Box<dyn Fn(&HtmlAllocator) -> Result<Flat<Node>> + Send + Sync>,
     pub sibling_from_path: Box<dyn Fn(&PPath<KString>) -> Option<String> + Send + Sync>,
 }
 
+impl<L: Language> WebsiteLayout<L> {
+    /// The head as used by `page`, for further extension.
+    pub fn head_builder<'a>(
+        &self,
+        html: &'a HtmlAllocator,
+        // See `LayoutInterface::page`
+        head_title: Option<AId<Node>>,
+        canonical: Option<&str>,
+        description: Option<&str>,
+    ) -> Result<HeadBuilder<'a>>
+    {
+        let mut head = HeadBuilder::new(html);
+        if let Some(canonical) = canonical {
+            head.link("canonical", canonical, None)?;
+        }
+        if let Some(description) = description {
+            head.meta_name("description", description)?;
+        }
+        head.preloads(self.preloads)?;
+        head.stylesheet("/static/main.css")?;
+        head.title(
+            if let Some(head_title) = head_title {
+                let head_title_string = html.to_plain_string(head_title)?;
+                Flat::Two(
+                    html.to_plain_string_aid(head_title)?,
+                    // Do not show the title if it's
+                    // also the site name
+                    if &head_title_string == self.site_name {
+                        html.empty_node()?
+                    } else {
+                        html.string(format!(" | {}",
+                                            self.site_name))?
+                    }
+                )
+            } else {
+                Flat::One(
+                    html.staticstr(self.site_name)?
+                )
+            })?;
+        Ok(head)
+    }
+}
+
 impl<L: Language> LayoutInterface<L> for WebsiteLayout<L> {
     fn page(
         &self,
@@ -61,7 +282,7 @@ impl<L: Language> LayoutInterface<L> for WebsiteLayout<L> {
         lead: Option<AId<Node>>,
         main: AId<Node>,
         footnotes: Option<AId<Node>>,
-        last_modified: Option<SystemTime>,
+        PageMeta { last_modified, canonical, description }: PageMeta,
     ) -> Result<AId<Node>>
     {
         let tocbox =
@@ -83,13 +304,14 @@ impl<L: Language> LayoutInterface<L> for WebsiteLayout<L> {
                 html.div([att("class", "no_breadcrumb")], [])?
             };
 
+        // Also as `Link` header, so that they can be acted upon
+        // before the document arrives
+        for preload in self.preloads {
+            context.add_link(preload.href, &preload.link_params());
+        }
+
         let lang: L = context.lang();
-        let nav_html =
-            if let Some(nav) = AList(self.nav).get(&lang) {
-                nav.to_html(&html, context)?
-            } else {
-                html.empty_node()?
-            };
+        let nav_html = render_nav(self.nav, context, html)?;
         let langswitcher_html = {
             let mut items = html.new_vec_with_capacity(L::strs().len() as u32)?;
             for l in L::members() {
@@ -121,34 +343,13 @@ impl<L: Language> LayoutInterface<L> for WebsiteLayout<L> {
         html.html(
             [],
             [
-                html.head(
-                    [],
-                    [
-                        html.link(
-                            [att("rel", "stylesheet"),
-                             att("href", "/static/main.css")],
-                            [])?,
-                        html.title(
-                            [],
-                            if let Some(head_title) = head_title {
-                                let head_title_string = html.to_plain_string(head_title)?;
-                                Flat::Two(
-                                    html.to_plain_string_aid(head_title)?,
-                                    // Do not show the title if it's
-                                    // also the site name
-                                    if &head_title_string == self.site_name {
-                                        html.empty_node()?
-                                    } else {
-                                        html.string(format!(" | {}",
-                                                            self.site_name))?
-                                    }
-                                )
-                            } else {
-                                Flat::One(
-                                    html.staticstr(self.site_name)?
-                                )
-                            })?,
-                    ])?,
+                {
+                    let mut head = self.head_builder(html, head_title, canonical, description)?;
+                    if let Some(base_href) = context.base_href() {
+                        head.base(&base_href)?;
+                    }
+                    head.build()?
+                },
                 html.body(
                     [],
                     [
@@ -253,3 +454,38 @@ impl<L: Language> LayoutInterface<L> for WebsiteLayout<L> {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use ahtml::Print;
+
+    use super::*;
+
+    #[test]
+    fn t_head_builder() -> Result<()> {
+        let html = HtmlAllocator::new(1000, std::sync::Arc::new(""));
+        let mut head = HeadBuilder::new(&html);
+        head.set_nonce(Some(KString::from_static("abc")));
+        head.script("/static/app.js")?
+            .stylesheet("/static/main.css")?
+            .title([html.str("Hello")?])?
+            .meta_name("description", "Greeting")?
+            .preloads(&[
+                Preload { href: "/static/font.woff2", as_: PreloadAs::Font,
+                          type_: Some("font/woff2") },
+                Preload { href: "/static/bg.jpg", as_: PreloadAs::Image, type_: None },
+            ])?;
+        assert_eq!(
+            head.build()?.to_html_fragment_string(&html)?,
+            "<head>\
+             <meta name=\"description\" content=\"Greeting\">\
+             <title>Hello</title>\
+             <link rel=\"preload\" href=\"/static/font.woff2\" as=\"font\" \
+             type=\"font/woff2\" crossorigin=\"anonymous\">\
+             <link rel=\"preload\" href=\"/static/bg.jpg\" as=\"image\">\
+             <link rel=\"stylesheet\" href=\"/static/main.css\">\
+             <script src=\"/static/app.js\" nonce=\"abc\"></script>\
+             </head>");
+        Ok(())
+    }
+}
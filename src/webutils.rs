@@ -1,11 +1,16 @@
 use std::borrow::Cow;
-use anyhow::{Result, Error};
-use rouille::{Response, ResponseBody};
+use std::ops::Deref;
+use std::time::{Duration, SystemTime};
+use anyhow::{Result, Error, anyhow};
+use httpdate::parse_http_date;
+use rouille::{Response, ResponseBody, input::parse_priority_header};
 
-use ahtml::{Node, AId, HtmlAllocator};
+use ahtml::{Node, AId, HtmlAllocator, HtmlReader};
 use chj_util::{nopp as pp, nodt as dt, warn};
 
 use crate::acontext::AContext;
+use crate::handler::HandlerError;
+use crate::http_request_method::HttpRequestMethodSimple;
 use crate::http_response_status_codes::HttpResponseStatusCode;
 use crate::language::Language;
 use crate::ppath::PPath;
@@ -42,20 +47,49 @@ pub fn errorpage_from_status(status: HttpResponseStatusCode) -> Response {
                         <p>{explanation}</p></body></html>\n");
     Response {
         status_code: status.code(),
-        headers: vec![(Cow::from("Content-type"), Cow::from("text/html"))],
+        headers: vec![(Cow::from("Content-type"), Cow::from("text/html; charset=utf-8"))],
         data: ResponseBody::from_string(resp),
         upgrade: None, // XX? aha https?
     }
 }
 
 pub fn errorpage_from_error(err: Error) -> Response {
-    // XX: make status possibly dependent on e instead!
-    let status = HttpResponseStatusCode::InternalServerError500;
+    let status = HandlerError::status_of(&err);
     // XX show context of course. This MUST provided ALREADY
     eprintln!("ERROR in page (return {status:?}): {err:#}");
     errorpage_from_status(status)
 }
 
+/// Whether content of type `mime` (without parameters) is text, for
+/// which a charset should be given. (Not JSON, which is always UTF-8
+/// and has no charset parameter.)
+fn mime_is_textual(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || matches!(mime, "application/javascript" | "application/xml")
+        || (mime.starts_with("application/") && mime.ends_with("+xml"))
+}
+
+/// `mime` (e.g. from `rouille::extension_to_mime`) with `charset=utf-8`
+/// if it is a textual type (replacing a charset given already, as
+/// rouille uses the non-standard spelling `utf8`), unchanged
+/// otherwise.
+pub fn mime_with_charset(mime: &'static str) -> Cow<'static, str> {
+    let (base, params) = mime.split_once(';').unwrap_or((mime, ""));
+    let base = base.trim();
+    if ! mime_is_textual(base) {
+        return Cow::Borrowed(mime)
+    }
+    let mut result = String::from(base);
+    for param in params.split(';').map(str::trim) {
+        if ! (param.is_empty() || param.to_ascii_lowercase().starts_with("charset=")) {
+            result.push_str("; ");
+            result.push_str(param);
+        }
+    }
+    result.push_str("; charset=utf-8");
+    Cow::Owned(result)
+}
+
 pub fn htmlresponse(
     html: &HtmlAllocator,
     status: HttpResponseStatusCode,
@@ -71,6 +105,37 @@ pub fn htmlresponse(
     })
 }
 
+/// A response body with the document `id`, serialized while it is
+/// being sent instead of first as a whole into memory. Takes
+/// ownership of the allocator (e.g. an `HtmlAllocatorGuard` from a
+/// pool), which is released when the body is dropped.
+pub fn html_response_body<A>(html: A, id: AId<Node>, want_doctype: bool) -> ResponseBody
+where A: Deref<Target = HtmlAllocator> + Send + 'static
+{
+    ResponseBody::from_reader(HtmlReader::new(html, id, want_doctype))
+}
+
+
+/// Like `htmlresponse`, but the page is built in `html`, which is
+/// taken ownership of (e.g. a `HtmlAllocatorGuard` from a pool), and
+/// serialized while it is being sent (see `html_response_body`),
+/// for large pages.
+pub fn htmlresponse_streaming<A>(
+    html: A,
+    status: HttpResponseStatusCode,
+    produce: impl FnOnce(&HtmlAllocator) -> Result<AId<Node>>
+) -> Result<Response>
+where A: Deref<Target = HtmlAllocator> + Send + 'static
+{
+    let id = produce(&html)?;
+    Ok(Response {
+        status_code: status.code(),
+        headers: vec![(Cow::from("Content-type"),
+                       Cow::from("text/html; charset=utf-8"))],
+        data: html_response_body(html, id, true),
+        upgrade: None,
+    })
+}
 
 /// Resolve a relative path from the current location but fix it up
 /// with regards to slash or not slash.  Request `/blog` resolves the
@@ -110,3 +175,314 @@ pub fn email_url(s: &str) -> String {
         format!("mailto:{s}")
     }
 }
+
+
+// Can't just check `mtime > modsince` since that's ~always true
+// because mtime has a nsec value, where modsince has 0
+// there. Sigh. fmt_http_date should not accept SystemTime please, and
+// SystemTime should have a second accessor or something; huh. And
+// can't get at contents at all directly, so can't write tv_sec()
+// accessor either.  `modsince.checked_sub(mtime)` doesn't work
+// either, takes a Duration as argument. duration_since it is, but
+// then have to do it both ways if wanting to know how far off it
+// is. Sick.
+// fn file_mtime_indicates_file_has_changed(mtime: SystemTime, modsince: SystemTime) -> bool {
+// }
+
+// But then, if just wanting to know if the file is *newer* than snapshot time:
+fn file_is_newer_than_snapshot_time(mtime: SystemTime, modsince: SystemTime) -> bool {
+    match mtime.duration_since(modsince) {
+        Err(_e) => {
+            // file is older than snapshot time; client is cheating,
+            // or file has been restored to an older version; in any
+            // case, it is not newer, so say no
+            false
+        }
+        Ok(secsnewer) => {
+            // Make sure it is at least a second newer, due to the
+            // rounding issue. Otherwise it would report a fake newer.
+            secsnewer >= Duration::from_secs(1)
+        }
+    }
+}
+
+/// Whether the entity tag `etag` (with quotes, e.g. `"123"` or
+/// `W/"123"`) is in the list `header` as given in `If-Match` or
+/// `If-None-Match` (`*` matches any). With `weak`, `W/` prefixes are
+/// ignored, otherwise weak tags never match.
+fn etag_list_matches(header: &str, etag: &str, weak: bool) -> bool {
+    if header.trim() == "*" {
+        return true
+    }
+    let opaque = |tag: &str| -> Option<String> {
+        match tag.strip_prefix("W/") {
+            Some(t) => if weak { Some(t.to_string()) } else { None },
+            None => Some(tag.to_string())
+        }
+    };
+    let etag = match opaque(etag) {
+        Some(e) => e,
+        None => return false
+    };
+    let mut rest = header;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_ascii_whitespace());
+        if rest.is_empty() {
+            return false
+        }
+        let prefix_len = if rest.starts_with("W/") { 2 } else { 0 };
+        let tag_len =
+            if rest[prefix_len..].starts_with('"') {
+                match rest[prefix_len + 1..].find('"') {
+                    Some(i) => prefix_len + i + 2,
+                    None => rest.len()
+                }
+            } else {
+                // Invalid, skip up to the next comma
+                rest.find(',').unwrap_or(rest.len())
+            };
+        if opaque(&rest[..tag_len]).as_ref() == Some(&etag) {
+            return true
+        }
+        rest = &rest[tag_len..];
+    }
+}
+
+/// A date header, `None` if missing or invalid (invalid dates are to
+/// be ignored, RFC 9110).
+fn date_header<L: Language>(context: &AContext<L>, name: &str) -> Option<SystemTime> {
+    let s = context.header(name)?;
+    match parse_http_date(s) {
+        Ok(t) => Some(t),
+        Err(e) => {
+            warn!("ignoring invalid {name} header {s:?}: {e}");
+            None
+        }
+    }
+}
+
+/// The outcome of evaluating the conditional request headers, see
+/// `check_preconditions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precondition {
+    /// Proceed with the request.
+    Satisfied,
+    /// Respond with `304 Not Modified` (only happens for GET and HEAD).
+    NotModified,
+    /// Respond with `412 Precondition Failed`; e.g. the client wants
+    /// to update a version that was changed in the meantime.
+    Failed,
+}
+
+impl Precondition {
+    /// The status to respond with instead of proceeding.
+    pub fn status(self) -> Option<HttpResponseStatusCode> {
+        match self {
+            Precondition::Satisfied => None,
+            Precondition::NotModified => Some(HttpResponseStatusCode::NotModified304),
+            Precondition::Failed => Some(HttpResponseStatusCode::PreconditionFailed412),
+        }
+    }
+
+    /// For write endpoints: an error carrying the status (as a
+    /// `HandlerError`) unless satisfied.
+    pub fn require_satisfied(self) -> Result<()> {
+        match self.status() {
+            None => Ok(()),
+            Some(status) => Err(HandlerError::new(
+                status, anyhow!("precondition not satisfied: {self:?}")).into())
+        }
+    }
+}
+
+/// Evaluate `If-Match`, `If-Unmodified-Since`, `If-None-Match` and
+/// `If-Modified-Since` (in this order, as per RFC 9110 section
+/// 13.2.2) against the current `etag` (with quotes) and
+/// `last_modified` time of the resource.
+pub fn check_preconditions<L: Language>(
+    context: &AContext<L>,
+    method: HttpRequestMethodSimple,
+    etag: Option<&str>,
+    last_modified: Option<SystemTime>,
+) -> Precondition {
+    if let Some(if_match) = context.header("If-Match") {
+        if ! etag.is_some_and(|etag| etag_list_matches(if_match, etag, false)) {
+            return Precondition::Failed
+        }
+    } else if let Some(unmodsince) = date_header(context, "If-Unmodified-Since") {
+        if last_modified.is_some_and(|t| file_is_newer_than_snapshot_time(t, unmodsince)) {
+            return Precondition::Failed
+        }
+    }
+    let is_get = ! method.is_post();
+    if let Some(if_none_match) = context.header("If-None-Match") {
+        if etag.is_some_and(|etag| etag_list_matches(if_none_match, etag, true)) {
+            return if is_get { Precondition::NotModified } else { Precondition::Failed }
+        }
+    } else if is_get {
+        if let (Some(modsince), Some(t)) = (date_header(context, "If-Modified-Since"),
+                                            last_modified) {
+            if ! file_is_newer_than_snapshot_time(t, modsince) {
+                return Precondition::NotModified
+            }
+        }
+    }
+    Precondition::Satisfied
+}
+
+/// The media type out of `producible` (in the server's order of
+/// preference) that the `Accept` header value `accept` rates best, or
+/// None if it excludes all of them (then respond with `406 Not
+/// Acceptable`). Without the header, anything is acceptable. The
+/// most specific matching media range applies (`text/html` over
+/// `text/*` over `*/*`); `q=0` excludes.
+pub fn negotiate_content_type<'t>(accept: Option<&str>, producible: &[&'t str]) -> Option<&'t str> {
+    let Some(accept) = accept else {
+        return producible.first().copied()
+    };
+    let ranges: Vec<(&str, f32)> = parse_priority_header(accept).collect();
+    let quality = |mime: &str| -> f32 {
+        let type_ = mime.split('/').next().unwrap_or(mime);
+        // (specificity, q)
+        let mut best: Option<(u8, f32)> = None;
+        for (range, q) in &ranges {
+            let specificity =
+                if range.eq_ignore_ascii_case(mime) {
+                    2
+                } else if range.strip_suffix("/*").is_some_and(|t| t.eq_ignore_ascii_case(type_)) {
+                    1
+                } else if *range == "*/*" {
+                    0
+                } else {
+                    continue
+                };
+            if best.is_none_or(|(s, _)| specificity > s) {
+                best = Some((specificity, *q));
+            }
+        }
+        best.map_or(0., |(_, q)| q)
+    };
+    let mut result = None;
+    let mut result_q = 0.;
+    for mime in producible {
+        let q = quality(mime);
+        if q > result_q {
+            result = Some(*mime);
+            result_q = q;
+        }
+    }
+    result
+}
+
+/// Whether the `Accept` header value `accept` names `mime` explicitly
+/// (wildcards don't count) with a non-zero `q`. For formats that not
+/// all clients support even though they send `*/*`.
+pub fn accept_lists(accept: Option<&str>, mime: &str) -> bool {
+    accept.is_some_and(|accept| parse_priority_header(accept).any(
+        |(range, q)| q > 0. && range.eq_ignore_ascii_case(mime)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use httpdate::fmt_http_date;
+
+    use crate::{lang_en_de::Lang, test_util::fake_context};
+
+    use super::*;
+
+    #[test]
+    fn t_mime_with_charset() {
+        assert_eq!(mime_with_charset("text/html; charset=utf8"), "text/html; charset=utf-8");
+        assert_eq!(mime_with_charset("text/plain"), "text/plain; charset=utf-8");
+        assert_eq!(mime_with_charset("application/javascript"),
+                   "application/javascript; charset=utf-8");
+        assert_eq!(mime_with_charset("application/rss+xml"),
+                   "application/rss+xml; charset=utf-8");
+        assert_eq!(mime_with_charset("image/png"), "image/png");
+        assert_eq!(mime_with_charset("application/json"), "application/json");
+        assert_eq!(mime_with_charset("application/octet-stream"), "application/octet-stream");
+    }
+
+    #[test]
+    fn t_negotiate_content_type() {
+        let html = ["text/html"];
+        let both = ["text/html", "application/json"];
+        assert_eq!(negotiate_content_type(None, &both), Some("text/html"));
+        assert_eq!(negotiate_content_type(Some("application/json"), &html), None);
+        assert_eq!(negotiate_content_type(Some("application/json"), &both),
+                   Some("application/json"));
+        assert_eq!(negotiate_content_type(Some("text/*, */*;q=0.1"), &both), Some("text/html"));
+        assert_eq!(negotiate_content_type(Some("*/*"), &both), Some("text/html"));
+        assert_eq!(negotiate_content_type(
+            Some("text/html;q=0.5, application/json"), &both), Some("application/json"));
+        assert_eq!(negotiate_content_type(Some("*/*, text/html;q=0"), &html), None);
+        assert_eq!(negotiate_content_type(Some("TEXT/HTML; charset=utf-8"), &html),
+                   Some("text/html"));
+    }
+
+    #[test]
+    fn t_check_preconditions() -> Result<()> {
+        use HttpRequestMethodSimple::{GET, POST};
+        use Precondition::*;
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let check = |method, headers: &[(&str, &str)]| {
+            fake_context("POST", "/", headers, &[]).with_context(
+                |context: &AContext<Lang>| {
+                    Ok(check_preconditions(context, method, Some("\"v2\""), Some(mtime)))
+                })
+        };
+        let before = fmt_http_date(mtime - Duration::from_secs(60));
+        let at = fmt_http_date(mtime);
+
+        assert_eq!(check(POST, &[])?, Satisfied);
+
+        // If-Match
+        assert_eq!(check(POST, &[("If-Match", "\"v2\"")])?, Satisfied);
+        assert_eq!(check(POST, &[("If-Match", "\"v1\", \"v2\"")])?, Satisfied);
+        assert_eq!(check(POST, &[("If-Match", "*")])?, Satisfied);
+        assert_eq!(check(POST, &[("If-Match", "\"v1\"")])?, Failed);
+        // weak tags don't match strongly
+        assert_eq!(check(POST, &[("If-Match", "W/\"v2\"")])?, Failed);
+
+        // If-Unmodified-Since
+        assert_eq!(check(POST, &[("If-Unmodified-Since", &at)])?, Satisfied);
+        assert_eq!(check(POST, &[("If-Unmodified-Since", &before)])?, Failed);
+        assert_eq!(check(POST, &[("If-Unmodified-Since", "garbage")])?, Satisfied);
+        // If-Match takes precedence
+        assert_eq!(check(POST, &[("If-Match", "\"v2\""),
+                                 ("If-Unmodified-Since", &before)])?, Satisfied);
+
+        // If-None-Match and If-Modified-Since
+        assert_eq!(check(GET, &[("If-None-Match", "W/\"v2\"")])?, NotModified);
+        assert_eq!(check(GET, &[("If-None-Match", "\"v1\"")])?, Satisfied);
+        assert_eq!(check(POST, &[("If-None-Match", "*")])?, Failed);
+        assert_eq!(check(GET, &[("If-Modified-Since", &at)])?, NotModified);
+        assert_eq!(check(GET, &[("If-Modified-Since", &before)])?, Satisfied);
+
+        let err = Failed.require_satisfied().unwrap_err();
+        assert_eq!(HandlerError::status_of(&err).code(), 412);
+        assert!(Satisfied.require_satisfied().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn t_html_response_body() -> Result<()> {
+        use std::io::Read;
+        let pool: &'static ahtml::HtmlAllocatorPool = Box::leak(Box::new(
+            ahtml::HtmlAllocatorPool::new(1000, true, std::sync::Arc::new("t_html_response_body"))));
+        let html = pool.get();
+        let page = html.html([], [html.body([], [html.p([], [html.str("a & b")?])?])?])?;
+        let expected = html.to_html_string(page, true);
+        let (mut reader, _) = html_response_body(html, page, true).into_reader_and_size();
+        // The allocator is still in use
+        assert_eq!(pool.idle_count(), 0);
+        let mut s = String::new();
+        reader.read_to_string(&mut s)?;
+        assert_eq!(s, expected);
+        drop(reader);
+        assert_eq!(pool.idle_count(), 1);
+        Ok(())
+    }
+}